@@ -1,5 +1,6 @@
 mod batched_p384;
 mod batched_ristretto255;
+mod nonce_store;
 mod private;
 mod public;
 
@@ -7,6 +8,7 @@ use criterion::{criterion_group, criterion_main};
 
 use batched_p384::criterion_batched_p384_benchmark;
 use batched_ristretto255::criterion_batched_ristretto255_benchmark;
+use nonce_store::criterion_nonce_store_benchmark;
 use private::criterion_private_benchmark;
 use public::criterion_public_benchmark;
 
@@ -15,6 +17,7 @@ criterion_group!(
     criterion_private_benchmark,
     criterion_public_benchmark,
     criterion_batched_ristretto255_benchmark,
-    criterion_batched_p384_benchmark
+    criterion_batched_p384_benchmark,
+    criterion_nonce_store_benchmark
 );
 criterion_main!(benches);
@@ -0,0 +1,55 @@
+use std::thread;
+
+use criterion::{BatchSize, Criterion};
+use futures::executor::block_on;
+
+use privacypass::{
+    memory_stores::{MemoryNonceStore, ShardedMemoryNonceStore},
+    Nonce, NonceStore,
+};
+
+const THREADS: u32 = 8;
+const NONCES_PER_THREAD: u32 = 10_000;
+
+/// Redeems distinct nonces from several threads at once, the way a
+/// multi-threaded origin records redemptions.
+fn redeem_concurrently<NS: NonceStore>(nonce_store: &NS) {
+    thread::scope(|scope| {
+        for worker in 0..THREADS {
+            scope.spawn(move || {
+                for i in 0..NONCES_PER_THREAD {
+                    let mut nonce: Nonce = [0; 32];
+                    nonce[..4].copy_from_slice(&worker.to_be_bytes());
+                    nonce[4..8].copy_from_slice(&i.to_be_bytes());
+                    assert!(block_on(nonce_store.try_insert(nonce)));
+                }
+            });
+        }
+    });
+}
+
+pub fn criterion_nonce_store_benchmark(c: &mut Criterion) {
+    let total = THREADS * NONCES_PER_THREAD;
+
+    c.bench_function(
+        &format!("MEMORY NONCE STORE: Redeem {total} nonces on {THREADS} threads"),
+        |b| {
+            b.iter_batched(
+                MemoryNonceStore::new,
+                |nonce_store| redeem_concurrently(&nonce_store),
+                BatchSize::SmallInput,
+            );
+        },
+    );
+
+    c.bench_function(
+        &format!("SHARDED MEMORY NONCE STORE: Redeem {total} nonces on {THREADS} threads"),
+        |b| {
+            b.iter_batched(
+                ShardedMemoryNonceStore::new,
+                |nonce_store| redeem_concurrently(&nonce_store),
+                BatchSize::SmallInput,
+            );
+        },
+    );
+}
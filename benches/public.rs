@@ -168,7 +168,8 @@ pub fn criterion_public_benchmark(c: &mut Criterion) {
                             public_key_to_truncated_token_key_id(&key_pair.pk),
                             key_pair.pk.clone(),
                         )
-                        .await;
+                        .await
+                        .unwrap();
                     key_pair
                 });
 
@@ -0,0 +1,27 @@
+CREATE TABLE IF NOT EXISTS privacypass_keys (
+    key_set TEXT NOT NULL,
+    key_id SMALLINT NOT NULL,
+    key_material BYTEA NOT NULL,
+    PRIMARY KEY (key_set, key_id)
+);
+
+-- Kept apart from the keys, so that a validity period can be recorded before
+-- its key is inserted
+CREATE TABLE IF NOT EXISTS privacypass_key_validities (
+    key_set TEXT NOT NULL,
+    key_id SMALLINT NOT NULL,
+    -- Seconds since the Unix epoch, NULL if the key is valid without bound
+    not_before BIGINT,
+    not_after BIGINT,
+    redeemable_until BIGINT,
+    PRIMARY KEY (key_set, key_id)
+);
+
+CREATE TABLE IF NOT EXISTS privacypass_nonces (
+    nonce BYTEA PRIMARY KEY,
+    -- Milliseconds since the Unix epoch, NULL if the nonce never expires
+    expires_at BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS privacypass_nonces_expires_at
+    ON privacypass_nonces (expires_at);
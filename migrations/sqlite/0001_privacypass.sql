@@ -0,0 +1,27 @@
+CREATE TABLE IF NOT EXISTS privacypass_keys (
+    key_set TEXT NOT NULL,
+    key_id INTEGER NOT NULL,
+    key_material BLOB NOT NULL,
+    PRIMARY KEY (key_set, key_id)
+);
+
+-- Kept apart from the keys, so that a validity period can be recorded before
+-- its key is inserted
+CREATE TABLE IF NOT EXISTS privacypass_key_validities (
+    key_set TEXT NOT NULL,
+    key_id INTEGER NOT NULL,
+    -- Seconds since the Unix epoch, NULL if the key is valid without bound
+    not_before INTEGER,
+    not_after INTEGER,
+    redeemable_until INTEGER,
+    PRIMARY KEY (key_set, key_id)
+);
+
+CREATE TABLE IF NOT EXISTS privacypass_nonces (
+    nonce BLOB PRIMARY KEY,
+    -- Milliseconds since the Unix epoch, NULL if the nonce never expires
+    expires_at INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS privacypass_nonces_expires_at
+    ON privacypass_nonces (expires_at);
@@ -0,0 +1,169 @@
+//! # Arbitrary batched tokens
+//!
+//! Wire format of the generalized batched issuance protocol, in which a
+//! single request carries token requests of different token types, e.g. a
+//! privately verifiable token request next to a batched Ristretto255 token
+//! request. Every entry of a [`BatchTokenRequest`] is a complete serialized
+//! token request of its token type, and the [`BatchTokenResponse`] holds
+//! the serialized token response for each entry in the same order, or
+//! nothing for entries the issuer did not answer.
+//!
+//! Issuers answer batch token requests with
+//! [`MultiTypeServer::issue_batch_token_response`](crate::dispatch::MultiTypeServer::issue_batch_token_response),
+//! which dispatches every entry to the handler of its token type. Clients
+//! create the entries and finalize the responses with the client of each
+//! token type, e.g. a [`DynClient`](crate::dynamic::DynClient).
+
+use tls_codec::VLBytes;
+use tls_codec_derive::{TlsDeserialize, TlsSerialize, TlsSize};
+
+use crate::batched_tokens::from_bytes_strict;
+pub use crate::batched_tokens::SerializationError;
+
+/// Batch token request as specified in the spec:
+///
+/// ```c
+/// struct {
+///     TokenRequest token_requests<V>;
+/// } BatchTokenRequest;
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, TlsSize, TlsSerialize, TlsDeserialize)]
+pub struct BatchTokenRequest {
+    token_requests: Vec<VLBytes>,
+}
+
+impl BatchTokenRequest {
+    /// Creates a batch token request from serialized token requests.
+    #[must_use]
+    pub fn new(token_requests: Vec<Vec<u8>>) -> Self {
+        Self {
+            token_requests: token_requests.into_iter().map(VLBytes::new).collect(),
+        }
+    }
+
+    /// Returns the serialized token requests.
+    pub fn token_requests(&self) -> impl Iterator<Item = &[u8]> {
+        self.token_requests.iter().map(VLBytes::as_slice)
+    }
+
+    /// Returns the number of token requests.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.token_requests.len()
+    }
+
+    /// Returns `true` if the batch contains no token requests.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.token_requests.is_empty()
+    }
+
+    /// Create a new `BatchTokenRequest` from a byte slice.
+    ///
+    /// # Errors
+    /// Returns an error if the byte slice is not a valid `BatchTokenRequest`
+    /// or continues after it.
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, SerializationError> {
+        from_bytes_strict(bytes)
+    }
+}
+
+/// Optional token response as specified in the spec. An empty token
+/// response means that the issuer did not answer the token request.
+///
+/// ```c
+/// struct {
+///     TokenResponse token_response<V>;
+/// } OptionalTokenResponse;
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, TlsSize, TlsSerialize, TlsDeserialize)]
+pub struct OptionalTokenResponse {
+    token_response: VLBytes,
+}
+
+impl OptionalTokenResponse {
+    /// Returns the serialized token response, or `None` if the issuer did
+    /// not answer the token request.
+    #[must_use]
+    pub fn token_response(&self) -> Option<&[u8]> {
+        Some(self.token_response.as_slice()).filter(|token_response| !token_response.is_empty())
+    }
+}
+
+impl From<Option<Vec<u8>>> for OptionalTokenResponse {
+    fn from(token_response: Option<Vec<u8>>) -> Self {
+        Self {
+            token_response: VLBytes::new(token_response.unwrap_or_default()),
+        }
+    }
+}
+
+/// Batch token response as specified in the spec:
+///
+/// ```c
+/// struct {
+///     OptionalTokenResponse token_responses<V>;
+/// } BatchTokenResponse;
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, TlsSize, TlsSerialize, TlsDeserialize)]
+pub struct BatchTokenResponse {
+    token_responses: Vec<OptionalTokenResponse>,
+}
+
+impl BatchTokenResponse {
+    /// Creates a batch token response from the serialized token responses
+    /// to the entries of a batch token request.
+    #[must_use]
+    pub fn new(token_responses: Vec<Option<Vec<u8>>>) -> Self {
+        Self {
+            token_responses: token_responses.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Returns the serialized token responses in the order of the token
+    /// requests, with `None` for token requests the issuer did not answer.
+    pub fn token_responses(&self) -> impl Iterator<Item = Option<&[u8]>> {
+        self.token_responses
+            .iter()
+            .map(OptionalTokenResponse::token_response)
+    }
+
+    /// Returns the number of token responses.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.token_responses.len()
+    }
+
+    /// Returns `true` if the batch contains no token responses.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.token_responses.is_empty()
+    }
+
+    /// Create a new `BatchTokenResponse` from a byte slice.
+    ///
+    /// # Errors
+    /// Returns an error if the byte slice is not a valid `BatchTokenResponse`
+    /// or continues after it.
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, SerializationError> {
+        from_bytes_strict(bytes)
+    }
+}
+
+#[test]
+fn batch_token_response_serialization() {
+    use tls_codec::Serialize;
+
+    let batch_token_response = BatchTokenResponse::new(vec![Some(vec![1, 2, 3]), None]);
+    let serialized = batch_token_response.tls_serialize_detached().unwrap();
+    // Length of the vector, then one length-prefixed response and an empty one
+    assert_eq!(serialized, [5, 3, 1, 2, 3, 0]);
+
+    let deserialized = BatchTokenResponse::try_from_bytes(&serialized).unwrap();
+    assert_eq!(deserialized, batch_token_response);
+    assert_eq!(
+        deserialized.token_responses().collect::<Vec<_>>(),
+        [Some([1, 2, 3].as_slice()), None]
+    );
+    assert!(BatchTokenResponse::try_from_bytes(&serialized[..4]).is_err());
+}
@@ -0,0 +1,148 @@
+//! # Attested token requests
+//!
+//! In deployments where a separate attester authorizes issuance over a
+//! private channel, the token request is forwarded to the issuer together
+//! with an attestation and the attester's signature:
+//!
+//! ```text
+//! struct {
+//!     TokenRequest token_request;
+//!     opaque attestation<0..2^16-1>;
+//!     opaque signature<0..2^16-1>;
+//! } AttestedTokenRequest;
+//! ```
+//!
+//! The issuer checks the envelope with an [`AttestationVerifier`] before
+//! passing the inner token request to its server.
+
+use std::io::{Read, Write};
+
+use async_trait::async_trait;
+use thiserror::Error;
+use tls_codec::{Deserialize, Error, Serialize, Size, TlsByteVecU16};
+
+use crate::MaybeSendSync;
+
+/// Errors that can occur when verifying an attested token request.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AttestationError {
+    #[error("Invalid TokenRequest")]
+    /// The inner token request cannot be serialized.
+    InvalidTokenRequest,
+    #[error("Invalid attestation")]
+    /// The attestation is not acceptable.
+    InvalidAttestation,
+    #[error("Invalid attestation signature")]
+    /// The signature over the token request and attestation is invalid.
+    InvalidSignature,
+}
+
+/// A token request together with an attestation and a signature.
+#[derive(Debug)]
+pub struct AttestedTokenRequest<R> {
+    token_request: R,
+    attestation: TlsByteVecU16,
+    signature: TlsByteVecU16,
+}
+
+impl<R> AttestedTokenRequest<R> {
+    /// Creates a new attested token request.
+    pub fn new(token_request: R, attestation: &[u8], signature: &[u8]) -> Self {
+        Self {
+            token_request,
+            attestation: attestation.into(),
+            signature: signature.into(),
+        }
+    }
+
+    /// Returns the inner token request.
+    pub const fn token_request(&self) -> &R {
+        &self.token_request
+    }
+
+    /// Returns the attestation.
+    pub fn attestation(&self) -> &[u8] {
+        self.attestation.as_slice()
+    }
+
+    /// Returns the signature.
+    pub fn signature(&self) -> &[u8] {
+        self.signature.as_slice()
+    }
+}
+
+impl<R: Serialize> AttestedTokenRequest<R> {
+    /// Verifies the envelope with the given verifier and returns the inner
+    /// token request on success.
+    ///
+    /// # Errors
+    /// Returns an error if the token request cannot be serialized or if the
+    /// verifier rejects the attestation or the signature.
+    pub async fn verify<V: AttestationVerifier>(self, verifier: &V) -> Result<R, AttestationError> {
+        let token_request = self
+            .token_request
+            .tls_serialize_detached()
+            .map_err(|_| AttestationError::InvalidTokenRequest)?;
+        verifier
+            .verify(
+                &token_request,
+                self.attestation.as_slice(),
+                self.signature.as_slice(),
+            )
+            .await?;
+        Ok(self.token_request)
+    }
+}
+
+impl<R: Size> Size for AttestedTokenRequest<R> {
+    fn tls_serialized_len(&self) -> usize {
+        self.token_request.tls_serialized_len()
+            + self.attestation.tls_serialized_len()
+            + self.signature.tls_serialized_len()
+    }
+}
+
+impl<R: Serialize> Serialize for AttestedTokenRequest<R> {
+    fn tls_serialize<W: Write>(&self, writer: &mut W) -> Result<usize, Error> {
+        Ok(self.token_request.tls_serialize(writer)?
+            + self.attestation.tls_serialize(writer)?
+            + self.signature.tls_serialize(writer)?)
+    }
+}
+
+impl<R: Deserialize> Deserialize for AttestedTokenRequest<R> {
+    fn tls_deserialize<B: Read>(bytes: &mut B) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        let token_request = R::tls_deserialize(bytes)?;
+        let attestation = TlsByteVecU16::tls_deserialize(bytes)?;
+        let signature = TlsByteVecU16::tls_deserialize(bytes)?;
+        Ok(Self {
+            token_request,
+            attestation,
+            signature,
+        })
+    }
+}
+
+/// Server-side hook that verifies attestations. Implementations typically
+/// check the attestation against the deployment's policy and the signature
+/// against the attester's public key.
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+pub trait AttestationVerifier: MaybeSendSync {
+    /// Verifies the attestation and the signature over a serialized token
+    /// request.
+    ///
+    /// # Errors
+    /// Returns an error if the attestation or the signature is not
+    /// acceptable.
+    async fn verify(
+        &self,
+        token_request: &[u8],
+        attestation: &[u8],
+        signature: &[u8],
+    ) -> Result<(), AttestationError>;
+}
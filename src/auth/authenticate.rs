@@ -9,15 +9,21 @@ use tls_codec::{Deserialize, Serialize, TlsByteVecU16, TlsByteVecU8};
 use tls_codec_derive::{TlsDeserialize, TlsSerialize, TlsSize};
 
 use nom::{
-    bytes::complete::{tag, tag_no_case},
+    bytes::complete::tag,
     character::complete::digit1,
     multi::{many1, separated_list1},
     IResult,
 };
 
-use crate::{ChallengeDigest, TokenType};
+use crate::{
+    issuer_directory::{decode_token_key, encode_token_key},
+    ChallengeDigest, CodePoints, TokenType,
+};
 
-use super::{base64_char, key_name, opt_spaces, parse_u32, space};
+use super::{
+    base64_char, bounded_list, check_parameter_length, equals, format_value, key_name, known_value,
+    opt_spaces, parse_u32, scheme, space, unknown_value, Limit, ParseConfig, URL_SAFE_LENIENT,
+};
 
 /// Redemption context filed of a ``TokenChallenge
 pub type RedemptionContext = [u8; 32];
@@ -74,11 +80,7 @@ impl TokenChallenge {
     /// Returns the redemption context.
     #[must_use]
     pub fn redemption_context(&self) -> Option<RedemptionContext> {
-        if self.redemption_context.is_empty() {
-            None
-        } else {
-            Some(self.redemption_context.as_slice().try_into().unwrap())
-        }
+        self.redemption_context.as_slice().try_into().ok()
     }
 
     /// Returns the origin info.
@@ -104,7 +106,13 @@ impl TokenChallenge {
     /// # Errors
     /// Returns an error if the `TokenChallenge` cannot be deserialized.
     pub fn deserialize(mut data: &[u8]) -> Result<Self, SerializationError> {
-        Self::tls_deserialize(&mut data).map_err(|_| SerializationError::InvalidTokenChallenge)
+        let challenge = Self::tls_deserialize(&mut data)
+            .map_err(|_| SerializationError::InvalidTokenChallenge)?;
+        // The redemption context is either empty or 32 bytes long
+        if !matches!(challenge.redemption_context.len(), 0 | 32) {
+            return Err(SerializationError::InvalidTokenChallenge);
+        }
+        Ok(challenge)
     }
 
     /// Serializes the `TokenChallenge` as a base64 encoded string.
@@ -115,12 +123,13 @@ impl TokenChallenge {
         Ok(URL_SAFE.encode(self.serialize()?))
     }
 
-    /// Deserializes a `TokenChallenge` from a base64 encoded string.
+    /// Deserializes a `TokenChallenge` from a base64 encoded string. Both
+    /// padded and unpadded input is accepted.
     ///
     /// # Errors
     /// Returns an error if the `TokenChallenge` cannot be deserialized.
     pub fn from_base64(s: &str) -> Result<Self, SerializationError> {
-        URL_SAFE
+        URL_SAFE_LENIENT
             .decode(s)
             .map_err(|_| SerializationError::InvalidTokenChallenge)
             .and_then(|data| Self::deserialize(&data))
@@ -137,6 +146,7 @@ impl TokenChallenge {
 
 /// An error that occurred during serialization or deserialization.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum SerializationError {
     #[error("Invalid TokenChallenge")]
     /// Invalid TokenChallenge
@@ -157,39 +167,94 @@ pub fn build_www_authenticate_header(
     token_key: &[u8],
     max_age: Option<u32>,
 ) -> Result<(HeaderName, HeaderValue), BuildError> {
-    let challenge_value = token_challenge
-        .to_base64()
-        .map_err(|_| BuildError::InvalidTokenChallenge)?;
-    let token_key_value = URL_SAFE.encode(token_key);
-    let max_age_string =
-        max_age.map_or_else(|| "".to_string(), |max_age| format!(", max-age={max_age}"));
+    build_www_authenticate_header_with_code_points(
+        token_challenge,
+        token_key,
+        max_age,
+        CodePoints::Draft,
+    )
+}
+
+/// Builds a `WWW-Authenticate` header like [`build_www_authenticate_header`]
+/// in the parameter spelling of `code_points`: bare values for
+/// [`CodePoints::Draft`], the quoted values of RFC 9577 otherwise.
+///
+/// # Errors
+/// Returns an error if the `TokenChallenge` cannot be serialized.
+pub fn build_www_authenticate_header_with_code_points(
+    token_challenge: &TokenChallenge,
+    token_key: &[u8],
+    max_age: Option<u32>,
+    code_points: CodePoints,
+) -> Result<(HeaderName, HeaderValue), BuildError> {
+    let challenge_value = format_value(
+        &token_challenge
+            .to_base64()
+            .map_err(|_| BuildError::InvalidTokenChallenge)?,
+        code_points,
+    );
+    let token_key_value = format_value(&encode_token_key(token_key), code_points);
+    let max_age_string = max_age.map_or_else(
+        || "".to_string(),
+        |max_age| {
+            format!(
+                ", max-age={}",
+                format_value(&max_age.to_string(), code_points)
+            )
+        },
+    );
 
     let value = format!(
         "PrivateToken challenge={challenge_value}, token-key={token_key_value}{max_age_string}"
     );
     let header_name = http::header::WWW_AUTHENTICATE;
-    let header_value =
-        HeaderValue::from_str(&value).map_err(|_| BuildError::InvalidTokenChallenge)?;
+    let header_value = HeaderValue::from_str(&value)?;
     Ok((header_name, header_value))
 }
 
 /// Building error for the `Authorization` header values
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum BuildError {
     #[error("Invalid TokenChallenge")]
     /// Invalid TokenChallenge
     InvalidTokenChallenge,
+    #[error("Invalid header value")]
+    /// The header value contains invalid characters
+    InvalidHeaderValue(#[from] http::header::InvalidHeaderValue),
 }
 
 /// Parses a `WWW-Authenticate` header according to the following scheme:
 ///
 /// `PrivateToken challenge=... token-key=... [max-age=...]`
 ///
+/// The lenient default [`ParseConfig`] is used.
+///
 /// # Errors
 /// Returns an error if the `WWW-Authenticate` header cannot be parsed.
 pub fn parse_www_authenticate_header(value: &HeaderValue) -> Result<Vec<Challenge>, ParseError> {
+    parse_www_authenticate_header_with_config(value, &ParseConfig::default())
+}
+
+/// Parses a `WWW-Authenticate` header like [`parse_www_authenticate_header`]
+/// with an explicit parser configuration.
+///
+/// # Errors
+/// Returns an error if the `WWW-Authenticate` header cannot be parsed.
+pub fn parse_www_authenticate_header_with_config(
+    value: &HeaderValue,
+    config: &ParseConfig,
+) -> Result<Vec<Challenge>, ParseError> {
+    if value.len() > config.max_header_length {
+        return Err(ParseError::HeaderTooLong);
+    }
     let s = value.to_str().map_err(|_| ParseError::InvalidInput)?;
-    let (_, challenges) = parse_private_tokens(s).map_err(|_| ParseError::InvalidChallenge)?;
+    let (_, challenges) =
+        parse_private_tokens(s, config).map_err(|e| match Limit::from_error(&e) {
+            Some(Limit::Challenges) => ParseError::TooManyChallenges,
+            Some(Limit::ParameterLength) => ParseError::ParameterTooLong,
+            None => ParseError::InvalidChallenge,
+        })?;
 
     Ok(challenges)
 }
@@ -224,6 +289,7 @@ impl Challenge {
 
 /// Parsing error for the `WWW-Authenticate` header values
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum ParseError {
     /// Invalid challenge
     #[error("Invalid challenge")]
@@ -231,32 +297,39 @@ pub enum ParseError {
     /// Invalid input string
     #[error("Invalid token key")]
     InvalidInput,
+    /// The header value exceeds the maximum length
+    #[error("Header value too long")]
+    HeaderTooLong,
+    /// The header value contains too many challenges
+    #[error("Too many challenges")]
+    TooManyChallenges,
+    /// A parameter value exceeds the maximum length
+    #[error("Parameter value too long")]
+    ParameterTooLong,
 }
 
-fn parse_key_value(input: &str) -> IResult<&str, (&str, &str)> {
+fn parse_key_value<'a>(
+    input: &'a str,
+    config: &ParseConfig,
+) -> IResult<&'a str, (&'a str, &'a str)> {
     let (input, _) = opt_spaces(input)?;
     let (input, key) = key_name(input)?;
-    let (input, _) = opt_spaces(input)?;
-    let (input, _) = tag("=")(input)?;
-    let (input, _) = opt_spaces(input)?;
+    let (input, _) = equals(input, config)?;
     let (input, value) = match key.to_lowercase().as_str() {
-        "challenge" | "token-key" => base64_char(input)?,
-        "max-age" => digit1(input)?,
-        _ => {
-            return Err(nom::Err::Failure(nom::error::make_error(
-                input,
-                nom::error::ErrorKind::Tag,
-            )));
-        }
+        "challenge" | "token-key" => known_value(input, config, base64_char)?,
+        "max-age" => known_value(input, config, digit1)?,
+        _ => unknown_value(input, config)?,
     };
+    check_parameter_length(input, value, config)?;
     Ok((input, (key, value)))
 }
 
-fn parse_private_token(input: &str) -> IResult<&str, Challenge> {
+fn parse_private_token<'a>(input: &'a str, config: &ParseConfig) -> IResult<&'a str, Challenge> {
     let (input, _) = opt_spaces(input)?;
-    let (input, _) = tag_no_case("PrivateToken")(input)?;
+    let (input, _) = scheme(input, config)?;
     let (input, _) = many1(space)(input)?;
-    let (input, key_values) = separated_list1(tag(","), parse_key_value)(input)?;
+    let (input, key_values) =
+        separated_list1(tag(","), |i: &'a str| parse_key_value(i, config))(input)?;
 
     let mut challenge = None;
     let mut token_key = None;
@@ -266,12 +339,13 @@ fn parse_private_token(input: &str) -> IResult<&str, Challenge> {
         let err = nom::Err::Failure(nom::error::make_error(input, nom::error::ErrorKind::Tag));
         match key.to_lowercase().as_str() {
             "challenge" => challenge = Some(TokenChallenge::from_base64(value).map_err(|_| err)?),
-            "token-key" => token_key = Some(URL_SAFE.decode(value).map_err(|_| err)?),
+            "token-key" => token_key = Some(decode_token_key(value).map_err(|_| err)?),
             "max-age" => {
                 let parsed_max_age = parse_u32(value).map_err(|_| err)?;
                 max_age = Some(parsed_max_age);
             }
-            _ => return Err(err),
+            // Unknown parameters only get here if they are to be ignored
+            _ => {}
         }
     }
 
@@ -292,8 +366,11 @@ fn parse_private_token(input: &str) -> IResult<&str, Challenge> {
     }
 }
 
-fn parse_private_tokens(input: &str) -> IResult<&str, Vec<Challenge>> {
-    separated_list1(tag(","), parse_private_token)(input)
+fn parse_private_tokens<'a>(
+    input: &'a str,
+    config: &ParseConfig,
+) -> IResult<&'a str, Vec<Challenge>> {
+    bounded_list(input, config, |i: &'a str| parse_private_token(i, config))
 }
 
 #[test]
@@ -348,7 +425,8 @@ fn parser_test() {
             URL_SAFE.encode(&token_key2)))
         .unwrap();
 
-    let (_, challenge_list) = parse_private_tokens(input.to_str().unwrap()).unwrap();
+    let (_, challenge_list) =
+        parse_private_tokens(input.to_str().unwrap(), &ParseConfig::default()).unwrap();
 
     assert_eq!(
         challenge_list,
@@ -367,6 +445,7 @@ fn parser_test() {
     );
 }
 
+#[cfg(feature = "ristretto255")]
 #[test]
 fn builder_parser_test() {
     use crate::batched_tokens_ristretto255::server::{
@@ -404,3 +483,184 @@ fn builder_parser_test() {
     let deserialized_public_key = deserialize_public_key(challenge.token_key()).unwrap();
     assert_eq!(deserialized_public_key, public_key);
 }
+
+#[test]
+fn unpadded_parser_test() {
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+    let token_key = b"sample token key".to_vec();
+    let challenge = TokenChallenge::new(
+        TokenType::PrivateToken,
+        "issuer",
+        None,
+        &["origin".to_string()],
+    );
+    let serialized_challenge = URL_SAFE_NO_PAD.encode(challenge.serialize().unwrap());
+
+    let input = HeaderValue::from_str(&format!(
+        "PrivateToken challenge={}, token-key={}",
+        serialized_challenge,
+        URL_SAFE_NO_PAD.encode(&token_key)
+    ))
+    .unwrap();
+
+    let challenges = parse_www_authenticate_header(&input).unwrap();
+    assert_eq!(challenges.len(), 1);
+    assert_eq!(challenges[0].token_challenge(), &challenge);
+    assert_eq!(challenges[0].token_key(), token_key.as_slice());
+}
+
+#[test]
+fn parse_config_test() {
+    let challenge = TokenChallenge::new(
+        TokenType::PrivateToken,
+        "issuer",
+        None,
+        &["origin".to_string()],
+    );
+    let token_key = URL_SAFE.encode(b"sample token key");
+    let challenge = challenge.to_base64().unwrap();
+
+    let lenient_only = [
+        format!("privatetoken challenge={challenge}, token-key={token_key}"),
+        format!("PrivateToken challenge = {challenge}, token-key={token_key}"),
+        format!("PrivateToken challenge={challenge}, token-key={token_key}, realm=\"a b\""),
+        format!("PrivateToken challenge={challenge}, foo=bar, token-key={token_key}"),
+    ];
+
+    for value in lenient_only {
+        let value = HeaderValue::from_str(&value).unwrap();
+        assert_eq!(parse_www_authenticate_header(&value).unwrap().len(), 1);
+        assert!(parse_www_authenticate_header_with_config(&value, &ParseConfig::strict()).is_err());
+    }
+
+    let value = HeaderValue::from_str(&format!(
+        "PrivateToken challenge={challenge}, token-key={token_key}"
+    ))
+    .unwrap();
+    assert!(parse_www_authenticate_header_with_config(&value, &ParseConfig::strict()).is_ok());
+}
+
+#[test]
+fn parse_limits_test() {
+    let challenge = TokenChallenge::new(
+        TokenType::PrivateToken,
+        "issuer",
+        None,
+        &["origin".to_string()],
+    )
+    .to_base64()
+    .unwrap();
+    let token_key = URL_SAFE.encode(b"sample token key");
+    let single = format!("PrivateToken challenge={challenge}, token-key={token_key}");
+
+    let config = ParseConfig {
+        max_challenges: 2,
+        ..ParseConfig::default()
+    };
+    let value = HeaderValue::from_str(&[single.as_str(); 2].join(", ")).unwrap();
+    assert_eq!(
+        parse_www_authenticate_header_with_config(&value, &config)
+            .unwrap()
+            .len(),
+        2
+    );
+    let value = HeaderValue::from_str(&[single.as_str(); 3].join(", ")).unwrap();
+    assert!(matches!(
+        parse_www_authenticate_header_with_config(&value, &config),
+        Err(ParseError::TooManyChallenges)
+    ));
+
+    let config = ParseConfig {
+        max_header_length: single.len() - 1,
+        ..ParseConfig::default()
+    };
+    let value = HeaderValue::from_str(&single).unwrap();
+    assert!(matches!(
+        parse_www_authenticate_header_with_config(&value, &config),
+        Err(ParseError::HeaderTooLong)
+    ));
+
+    let config = ParseConfig {
+        max_parameter_length: token_key.len() - 1,
+        ..ParseConfig::default()
+    };
+    assert!(matches!(
+        parse_www_authenticate_header_with_config(&value, &config),
+        Err(ParseError::ParameterTooLong)
+    ));
+}
+
+#[test]
+fn redemption_context_length_test() {
+    let challenge = TokenChallenge::new(
+        TokenType::PrivateToken,
+        "issuer",
+        Some([7; 32]),
+        &["origin".to_string()],
+    );
+    let bytes = challenge.serialize().unwrap();
+    assert_eq!(
+        TokenChallenge::deserialize(&bytes)
+            .unwrap()
+            .redemption_context(),
+        Some([7; 32])
+    );
+
+    // A redemption context of any other length is rejected instead of
+    // panicking later on
+    let mut truncated = challenge.clone();
+    truncated.redemption_context = vec![7; 5].into();
+    assert_eq!(truncated.redemption_context(), None);
+    let bytes = truncated.serialize().unwrap();
+    assert!(TokenChallenge::deserialize(&bytes).is_err());
+}
+
+#[test]
+fn code_points_spelling_test() {
+    let token_key = b"sample token key".to_vec();
+    let challenge = TokenChallenge::new(
+        TokenType::PrivateToken,
+        "issuer",
+        None,
+        &["origin".to_string()],
+    );
+    let (_, draft) = build_www_authenticate_header(&challenge, &token_key, Some(10)).unwrap();
+    let (_, quoted) = build_www_authenticate_header_with_code_points(
+        &challenge,
+        &token_key,
+        Some(10),
+        CodePoints::Final,
+    )
+    .unwrap();
+    assert_eq!(
+        quoted.to_str().unwrap(),
+        format!(
+            "PrivateToken challenge=\"{}\", token-key=\"{}\", max-age=\"10\"",
+            challenge.to_base64().unwrap(),
+            URL_SAFE.encode(&token_key)
+        )
+    );
+
+    let expected = vec![Challenge {
+        challenge,
+        token_key,
+        max_age: Some(10),
+    }];
+    // The default configuration accepts both spellings
+    for value in [&draft, &quoted] {
+        assert_eq!(parse_www_authenticate_header(value).unwrap(), expected);
+    }
+
+    // Strict parsing requires bare values unless final spellings are enabled
+    assert!(parse_www_authenticate_header_with_config(&quoted, &ParseConfig::strict()).is_err());
+    let config = ParseConfig {
+        code_points: CodePoints::Final,
+        ..ParseConfig::strict()
+    };
+    assert_eq!(
+        parse_www_authenticate_header_with_config(&quoted, &config).unwrap(),
+        expected
+    );
+    assert!(parse_www_authenticate_header_with_config(&draft, &config).is_ok());
+}
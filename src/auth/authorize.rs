@@ -5,7 +5,7 @@ use base64::{engine::general_purpose::URL_SAFE, Engine as _};
 use generic_array::{ArrayLength, GenericArray};
 use http::{header::HeaderName, HeaderValue};
 use nom::{
-    bytes::complete::{tag, tag_no_case},
+    bytes::complete::tag,
     multi::{many1, separated_list1},
     IResult,
 };
@@ -13,9 +13,12 @@ use std::io::Write;
 use thiserror::Error;
 use tls_codec::{Deserialize, Error, Serialize, Size};
 
-use crate::{ChallengeDigest, Nonce, TokenKeyId, TokenType};
+use crate::{ChallengeDigest, CodePoints, Nonce, TokenKeyId, TokenType};
 
-use super::{base64_char, key_name, opt_spaces, space};
+use super::{
+    base64_char, bounded_list, check_parameter_length, equals, format_value, key_name, known_value,
+    opt_spaces, scheme, space, unknown_value, Limit, ParseConfig, URL_SAFE_LENIENT,
+};
 
 /// A Token as defined in The Privacy Pass HTTP Authentication Scheme:
 ///
@@ -100,6 +103,37 @@ impl<Nk: ArrayLength<u8>> Token<Nk> {
         }
     }
 
+    /// Deserializes a token of `token_type`, e.g. one whose type was
+    /// peeked from the message, before any redemption logic sees it. The
+    /// token must be exactly as long as tokens of that type and `Nk` must be
+    /// the authenticator size of the type.
+    ///
+    /// # Errors
+    /// Returns an error if the token has a different type or length, or if
+    /// `Nk` does not match the token type.
+    pub fn from_bytes_for_type(
+        token_type: TokenType,
+        mut bytes: &[u8],
+    ) -> Result<Self, DecodeTokenError> {
+        if Nk::to_usize() != token_type.nk() {
+            return Err(DecodeTokenError::AuthenticatorSize {
+                expected: token_type.nk(),
+                actual: Nk::to_usize(),
+            });
+        }
+        let invalid_length = DecodeTokenError::InvalidLength {
+            expected: token_type.token_len(),
+            actual: bytes.len(),
+        };
+        if bytes.len() != token_type.token_len() {
+            return Err(invalid_length);
+        }
+        if bytes[..2] != (token_type as u16).to_be_bytes() {
+            return Err(DecodeTokenError::TokenTypeMismatch);
+        }
+        Self::tls_deserialize(&mut bytes).map_err(|_| invalid_length)
+    }
+
     /// Returns the token type.
     pub const fn token_type(&self) -> TokenType {
         self.token_type
@@ -124,6 +158,51 @@ impl<Nk: ArrayLength<u8>> Token<Nk> {
     pub fn authenticator(&self) -> &[u8] {
         self.authenticator.as_ref()
     }
+
+    /// Returns the value of an `Authorization` header that carries the
+    /// token, see [`build_authorization_header`].
+    ///
+    /// # Errors
+    /// Returns an error if the token is not valid.
+    pub fn to_authorization_header(&self) -> Result<HeaderValue, BuildError> {
+        build_authorization_header(self).map(|(_, value)| value)
+    }
+
+    /// Parses the token from the value of an `Authorization` header, see
+    /// [`parse_authorization_header`].
+    ///
+    /// # Errors
+    /// Returns an error if the header value is not valid.
+    pub fn from_authorization_header(value: &HeaderValue) -> Result<Self, ParseError> {
+        parse_authorization_header(value)
+    }
+}
+
+/// Errors that can occur when deserializing a token of a given type.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DecodeTokenError {
+    #[error("The token has a different token type")]
+    /// The token has a different token type than expected.
+    TokenTypeMismatch,
+    #[error("Invalid token length {actual}, expected {expected} bytes")]
+    /// The token is longer or shorter than tokens of its type.
+    InvalidLength {
+        /// Length of tokens of the type.
+        expected: usize,
+        /// Length of the token.
+        actual: usize,
+    },
+    #[error(
+        "Authenticator size {actual} does not match the token type, expected {expected} bytes"
+    )]
+    /// The authenticator size of the token is not the one of the token type.
+    AuthenticatorSize {
+        /// Authenticator size of the token type.
+        expected: usize,
+        /// Authenticator size of the token.
+        actual: usize,
+    },
 }
 
 /// Builds a `Authorize` header according to the following scheme:
@@ -135,44 +214,100 @@ impl<Nk: ArrayLength<u8>> Token<Nk> {
 pub fn build_authorization_header<Nk: ArrayLength<u8>>(
     token: &Token<Nk>,
 ) -> Result<(HeaderName, HeaderValue), BuildError> {
-    let value = format!(
-        "PrivateToken token={}",
-        URL_SAFE.encode(
-            token
-                .tls_serialize_detached()
-                .map_err(|_| BuildError::InvalidToken)?
-        ),
+    build_authorization_header_with_code_points(token, CodePoints::Draft)
+}
+
+/// Builds an `Authorization` header like [`build_authorization_header`] in
+/// the parameter spelling of `code_points`: a bare value for
+/// [`CodePoints::Draft`], the quoted value of RFC 9577 otherwise.
+///
+/// # Errors
+/// Returns an error if the token is not valid.
+pub fn build_authorization_header_with_code_points<Nk: ArrayLength<u8>>(
+    token: &Token<Nk>,
+    code_points: CodePoints,
+) -> Result<(HeaderName, HeaderValue), BuildError> {
+    let token = URL_SAFE.encode(
+        token
+            .tls_serialize_detached()
+            .map_err(|_| BuildError::InvalidToken)?,
     );
+    let value = format!("PrivateToken token={}", format_value(&token, code_points));
     let header_name = http::header::AUTHORIZATION;
-    let header_value = HeaderValue::from_str(&value).map_err(|_| BuildError::InvalidToken)?;
+    let header_value = HeaderValue::from_str(&value)?;
     Ok((header_name, header_value))
 }
 
 /// Building error for the `Authorization` header values
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum BuildError {
     #[error("Invalid token")]
     /// Invalid token
     InvalidToken,
+    #[error("Invalid header value")]
+    /// The header value contains invalid characters
+    InvalidHeaderValue(#[from] http::header::InvalidHeaderValue),
 }
 
 /// Parses an `Authorization` header according to the following scheme:
 ///
 /// `PrivateToken token=...`
 ///
+/// The lenient default [`ParseConfig`] is used.
+///
 /// # Errors
 /// Returns an error if the header value is not valid.
 pub fn parse_authorization_header<Nk: ArrayLength<u8>>(
     value: &HeaderValue,
 ) -> Result<Token<Nk>, ParseError> {
+    parse_authorization_header_with_config(value, &ParseConfig::default())
+}
+
+/// Parses an `Authorization` header like [`parse_authorization_header`] with
+/// an explicit parser configuration.
+///
+/// # Errors
+/// Returns an error if the header value is not valid.
+pub fn parse_authorization_header_with_config<Nk: ArrayLength<u8>>(
+    value: &HeaderValue,
+    config: &ParseConfig,
+) -> Result<Token<Nk>, ParseError> {
+    if value.len() > config.max_header_length {
+        return Err(ParseError::HeaderTooLong);
+    }
     let s = value.to_str().map_err(|_| ParseError::InvalidInput)?;
-    let tokens = parse_header_value(s)?;
-    let token = tokens[0].clone();
-    Ok(token)
+    parse_header_value(s, config)?
+        .into_iter()
+        .next()
+        .ok_or(ParseError::InvalidToken)
+}
+
+/// Parses an `Authorization` header like
+/// [`parse_authorization_header_with_config`], but returns the serialized
+/// token instead of deserializing it. This lets servers that handle several
+/// token types dispatch on the token type before the size of the
+/// authenticator is known.
+///
+/// # Errors
+/// Returns an error if the header value is not valid.
+pub fn parse_authorization_header_bytes(
+    value: &HeaderValue,
+    config: &ParseConfig,
+) -> Result<Vec<u8>, ParseError> {
+    if value.len() > config.max_header_length {
+        return Err(ParseError::HeaderTooLong);
+    }
+    let s = value.to_str().map_err(|_| ParseError::InvalidInput)?;
+    parse_header_token_bytes(s, config)?
+        .into_iter()
+        .next()
+        .ok_or(ParseError::InvalidToken)
 }
 
 /// Parsing error for the `WWW-Authenticate` header values
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum ParseError {
     #[error("Invalid token")]
     /// Invalid token
@@ -180,44 +315,49 @@ pub enum ParseError {
     #[error("Invalid input string")]
     /// Invalid input string
     InvalidInput,
+    #[error("Header value too long")]
+    /// The header value exceeds the maximum length
+    HeaderTooLong,
+    #[error("Too many tokens")]
+    /// The header value contains too many tokens
+    TooManyTokens,
+    #[error("Parameter value too long")]
+    /// A parameter value exceeds the maximum length
+    ParameterTooLong,
 }
 
-fn parse_key_value(input: &str) -> IResult<&str, (&str, &str)> {
+fn parse_key_value<'a>(
+    input: &'a str,
+    config: &ParseConfig,
+) -> IResult<&'a str, (&'a str, &'a str)> {
     let (input, _) = opt_spaces(input)?;
     let (input, key) = key_name(input)?;
-    let (input, _) = opt_spaces(input)?;
-    let (input, _) = tag("=")(input)?;
-    let (input, _) = opt_spaces(input)?;
+    let (input, _) = equals(input, config)?;
     let (input, value) = match key.to_lowercase().as_str() {
-        "token" => base64_char(input)?,
-        _ => {
-            return Err(nom::Err::Failure(nom::error::make_error(
-                input,
-                nom::error::ErrorKind::Tag,
-            )))
-        }
+        "token" => known_value(input, config, base64_char)?,
+        _ => unknown_value(input, config)?,
     };
+    check_parameter_length(input, value, config)?;
     Ok((input, (key, value)))
 }
 
-fn parse_private_token(input: &str) -> IResult<&str, &str> {
+fn parse_private_token<'a>(input: &'a str, config: &ParseConfig) -> IResult<&'a str, &'a str> {
     let (input, _) = opt_spaces(input)?;
-    let (input, _) = tag_no_case("PrivateToken")(input)?;
+    let (input, _) = scheme(input, config)?;
     let (input, _) = many1(space)(input)?;
-    let (input, key_values) = separated_list1(tag(","), parse_key_value)(input)?;
+    let (input, key_values) =
+        separated_list1(tag(","), |i: &'a str| parse_key_value(i, config))(input)?;
 
     let mut token = None;
     let err = nom::Err::Failure(nom::error::make_error(input, nom::error::ErrorKind::Tag));
 
+    // Unknown parameters only get here if they are to be ignored
     for (key, value) in key_values {
-        match key.to_lowercase().as_str() {
-            "token" => {
-                if token.is_some() {
-                    return Err(err);
-                }
-                token = Some(value)
+        if key.eq_ignore_ascii_case("token") {
+            if token.is_some() {
+                return Err(err);
             }
-            _ => return Err(err),
+            token = Some(value)
         }
     }
     let token = token.ok_or(err)?;
@@ -225,28 +365,43 @@ fn parse_private_token(input: &str) -> IResult<&str, &str> {
     Ok((input, token))
 }
 
-fn parse_private_tokens(input: &str) -> IResult<&str, Vec<&str>> {
-    separated_list1(tag(","), parse_private_token)(input)
+fn parse_private_tokens<'a>(
+    input: &'a str,
+    config: &ParseConfig,
+) -> IResult<&'a str, Vec<&'a str>> {
+    bounded_list(input, config, |i: &'a str| parse_private_token(i, config))
 }
 
-fn parse_header_value<Nk: ArrayLength<u8>>(input: &str) -> Result<Vec<Token<Nk>>, ParseError> {
-    let (output, tokens) = parse_private_tokens(input).map_err(|_| ParseError::InvalidInput)?;
+fn parse_header_value<Nk: ArrayLength<u8>>(
+    input: &str,
+    config: &ParseConfig,
+) -> Result<Vec<Token<Nk>>, ParseError> {
+    parse_header_token_bytes(input, config)?
+        .into_iter()
+        .map(|token| {
+            Token::tls_deserialize(&mut token.as_slice()).map_err(|_| ParseError::InvalidToken)
+        })
+        .collect()
+}
+
+fn parse_header_token_bytes(input: &str, config: &ParseConfig) -> Result<Vec<Vec<u8>>, ParseError> {
+    let (output, tokens) =
+        parse_private_tokens(input, config).map_err(|e| match Limit::from_error(&e) {
+            Some(Limit::Challenges) => ParseError::TooManyTokens,
+            Some(Limit::ParameterLength) => ParseError::ParameterTooLong,
+            None => ParseError::InvalidInput,
+        })?;
     if !output.is_empty() {
         return Err(ParseError::InvalidInput);
     }
-    let tokens = tokens
+    tokens
         .into_iter()
         .map(|token_value| {
-            Token::tls_deserialize(
-                &mut URL_SAFE
-                    .decode(token_value)
-                    .map_err(|_| ParseError::InvalidToken)?
-                    .as_slice(),
-            )
-            .map_err(|_| ParseError::InvalidToken)
+            URL_SAFE_LENIENT
+                .decode(token_value)
+                .map_err(|_| ParseError::InvalidToken)
         })
-        .collect::<Result<Vec<_>, _>>()?;
-    Ok(tokens)
+        .collect()
 }
 
 #[test]
@@ -274,4 +429,213 @@ fn builder_parser_test() {
     assert_eq!(token.challenge_digest(), &challenge_digest);
     assert_eq!(token.token_key_id(), &token_key_id);
     assert_eq!(token.authenticator(), &authenticator);
+
+    let token_bytes =
+        parse_authorization_header_bytes(&header_value, &ParseConfig::default()).unwrap();
+    assert_eq!(token_bytes, token.tls_serialize_detached().unwrap());
+}
+
+#[test]
+fn unpadded_parser_test() {
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use generic_array::typenum::U32;
+
+    let token = Token::<U32>::new(
+        TokenType::PrivateToken,
+        [1u8; 32],
+        [2u8; 32],
+        [3u8; 32],
+        GenericArray::clone_from_slice(&[4u8; 32]),
+    );
+    let value = format!(
+        "PrivateToken token={}",
+        URL_SAFE_NO_PAD.encode(token.tls_serialize_detached().unwrap())
+    );
+    let header_value = HeaderValue::from_str(&value).unwrap();
+
+    let parsed = parse_authorization_header::<U32>(&header_value).unwrap();
+    assert_eq!(parsed.nonce(), token.nonce());
+    assert_eq!(parsed.authenticator(), token.authenticator());
+}
+
+#[test]
+fn parse_config_test() {
+    use generic_array::typenum::U32;
+
+    let token = Token::<U32>::new(
+        TokenType::PrivateToken,
+        [1u8; 32],
+        [2u8; 32],
+        [3u8; 32],
+        GenericArray::clone_from_slice(&[4u8; 32]),
+    );
+    let token = URL_SAFE.encode(token.tls_serialize_detached().unwrap());
+
+    let lenient_only = [
+        format!("privatetoken token={token}"),
+        format!("PrivateToken token = {token}"),
+        format!("PrivateToken token={token}, realm=\"a b\""),
+    ];
+
+    for value in lenient_only {
+        let value = HeaderValue::from_str(&value).unwrap();
+        assert!(parse_authorization_header::<U32>(&value).is_ok());
+        assert!(
+            parse_authorization_header_with_config::<U32>(&value, &ParseConfig::strict()).is_err()
+        );
+    }
+
+    let value = HeaderValue::from_str(&format!("PrivateToken token={token}")).unwrap();
+    assert!(parse_authorization_header_with_config::<U32>(&value, &ParseConfig::strict()).is_ok());
+}
+
+#[test]
+fn parse_limits_test() {
+    use generic_array::typenum::U32;
+
+    let token = Token::<U32>::new(
+        TokenType::PrivateToken,
+        [1u8; 32],
+        [2u8; 32],
+        [3u8; 32],
+        GenericArray::clone_from_slice(&[4u8; 32]),
+    );
+    let token = URL_SAFE.encode(token.tls_serialize_detached().unwrap());
+    let single = format!("PrivateToken token={token}");
+
+    let config = ParseConfig {
+        max_challenges: 1,
+        ..ParseConfig::default()
+    };
+    let value = HeaderValue::from_str(&single).unwrap();
+    assert!(parse_authorization_header_with_config::<U32>(&value, &config).is_ok());
+    let value = HeaderValue::from_str(&[single.as_str(); 2].join(", ")).unwrap();
+    assert!(matches!(
+        parse_authorization_header_with_config::<U32>(&value, &config),
+        Err(ParseError::TooManyTokens)
+    ));
+
+    let config = ParseConfig {
+        max_header_length: single.len() - 1,
+        ..ParseConfig::default()
+    };
+    let value = HeaderValue::from_str(&single).unwrap();
+    assert!(matches!(
+        parse_authorization_header_with_config::<U32>(&value, &config),
+        Err(ParseError::HeaderTooLong)
+    ));
+
+    let config = ParseConfig {
+        max_parameter_length: token.len() - 1,
+        ..ParseConfig::default()
+    };
+    assert!(matches!(
+        parse_authorization_header_with_config::<U32>(&value, &config),
+        Err(ParseError::ParameterTooLong)
+    ));
+}
+
+#[test]
+fn authorization_header_methods_test() {
+    use generic_array::typenum::U32;
+
+    let token = Token::<U32>::new(
+        TokenType::PrivateToken,
+        [1u8; 32],
+        [2u8; 32],
+        [3u8; 32],
+        GenericArray::clone_from_slice(&[4u8; 32]),
+    );
+    let value = token.to_authorization_header().unwrap();
+    assert!(value.to_str().unwrap().starts_with("PrivateToken token="));
+
+    let parsed = Token::<U32>::from_authorization_header(&value).unwrap();
+    assert_eq!(
+        parsed.tls_serialize_detached().unwrap(),
+        token.tls_serialize_detached().unwrap()
+    );
+}
+
+#[test]
+fn from_bytes_for_type_test() {
+    use generic_array::typenum::{U32, U48};
+
+    let token = Token::<U48>::new(
+        TokenType::PrivateToken,
+        [1u8; 32],
+        [2u8; 32],
+        [3u8; 32],
+        GenericArray::clone_from_slice(&[4u8; 48]),
+    );
+    let bytes = token.tls_serialize_detached().unwrap();
+    assert_eq!(bytes.len(), TokenType::PrivateToken.token_len());
+    let parsed = Token::<U48>::from_bytes_for_type(TokenType::PrivateToken, &bytes).unwrap();
+    assert_eq!(parsed.tls_serialize_detached().unwrap(), bytes);
+
+    // Trailing and missing bytes are rejected
+    let mut long = bytes.clone();
+    long.push(0);
+    assert_eq!(
+        Token::<U48>::from_bytes_for_type(TokenType::PrivateToken, &long).unwrap_err(),
+        DecodeTokenError::InvalidLength {
+            expected: 146,
+            actual: 147
+        }
+    );
+    assert!(matches!(
+        Token::<U48>::from_bytes_for_type(TokenType::PrivateToken, &bytes[..145]),
+        Err(DecodeTokenError::InvalidLength { .. })
+    ));
+
+    // Tokens of another type of the same size are rejected
+    assert_eq!(
+        Token::<U48>::from_bytes_for_type(TokenType::BatchedTokenP384, &bytes).unwrap_err(),
+        DecodeTokenError::TokenTypeMismatch
+    );
+
+    // Authenticators of the wrong size are rejected before the bytes are read
+    assert_eq!(
+        Token::<U32>::from_bytes_for_type(TokenType::PrivateToken, &bytes).unwrap_err(),
+        DecodeTokenError::AuthenticatorSize {
+            expected: 48,
+            actual: 32
+        }
+    );
+}
+
+#[test]
+fn code_points_spelling_test() {
+    use generic_array::typenum::U32;
+
+    let token = Token::<U32>::new(
+        TokenType::PrivateToken,
+        [1u8; 32],
+        [2u8; 32],
+        [3u8; 32],
+        GenericArray::clone_from_slice(&[4u8; 32]),
+    );
+    let (_, draft) = build_authorization_header(&token).unwrap();
+    let (_, quoted) =
+        build_authorization_header_with_code_points(&token, CodePoints::Final).unwrap();
+    assert!(quoted
+        .to_str()
+        .unwrap()
+        .starts_with("PrivateToken token=\""));
+
+    // The default configuration accepts both spellings
+    for value in [&draft, &quoted] {
+        let parsed = parse_authorization_header::<U32>(value).unwrap();
+        assert_eq!(parsed.nonce(), token.nonce());
+    }
+
+    // Strict parsing requires bare values unless final spellings are enabled
+    assert!(
+        parse_authorization_header_with_config::<U32>(&quoted, &ParseConfig::strict()).is_err()
+    );
+    let config = ParseConfig {
+        code_points: CodePoints::Final,
+        ..ParseConfig::strict()
+    };
+    assert!(parse_authorization_header_with_config::<U32>(&quoted, &config).is_ok());
+    assert!(parse_authorization_header_with_config::<U32>(&draft, &config).is_ok());
 }
@@ -1,16 +1,249 @@
 //! Privacy Pass HTTP Authentication Scheme
 
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+};
 use nom::{
-    bytes::complete::{is_a, take_while1},
+    branch::alt,
+    bytes::complete::{is_a, is_not, tag, tag_no_case, take_till, take_while1},
+    character::complete::char,
     combinator::verify,
+    error::ErrorKind,
     multi::many0,
+    sequence::{delimited, preceded},
     IResult,
 };
 use std::str::FromStr;
 
+use crate::CodePoints;
+
 pub mod authenticate;
 pub mod authorize;
 
+/// Base64url engine used to decode incoming header parameters. Clients
+/// disagree about padding, so both padded and unpadded values are accepted.
+/// Outgoing values are always encoded with padding.
+pub(crate) const URL_SAFE_LENIENT: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Name of the authentication scheme.
+pub(crate) const SCHEME: &str = "PrivateToken";
+
+/// Handling of parameters the parser does not know about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnknownParameters {
+    /// Unknown parameters are skipped.
+    Ignore,
+    /// Unknown parameters make the header invalid.
+    Reject,
+}
+
+/// Configuration of the `WWW-Authenticate` and `Authorization` header
+/// parsers. The default configuration is lenient, [`ParseConfig::strict`]
+/// is meant for conformance testing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseConfig {
+    /// Whether the `PrivateToken` scheme name is matched case-sensitively.
+    pub case_sensitive_scheme: bool,
+    /// Whether whitespace is allowed around the `=` of a parameter.
+    pub whitespace_around_equals: bool,
+    /// How unknown parameters are handled.
+    pub unknown_parameters: UnknownParameters,
+    /// Maximum length of the header value in bytes.
+    pub max_header_length: usize,
+    /// Maximum number of challenges or tokens in a single header value.
+    pub max_challenges: usize,
+    /// Maximum length of a single parameter value in bytes.
+    pub max_parameter_length: usize,
+    /// Accepted spellings of the parameter values. With
+    /// [`CodePoints::Draft`] values must be bare, the other modes also accept
+    /// the quoted values of RFC 9577.
+    pub code_points: CodePoints,
+}
+
+/// Default maximum length of a header value in bytes.
+pub const DEFAULT_MAX_HEADER_LENGTH: usize = 16 * 1024;
+/// Default maximum number of challenges or tokens in a header value.
+pub const DEFAULT_MAX_CHALLENGES: usize = 16;
+/// Default maximum length of a parameter value in bytes.
+pub const DEFAULT_MAX_PARAMETER_LENGTH: usize = 8 * 1024;
+
+impl ParseConfig {
+    /// Lenient configuration: the scheme name is case-insensitive, whitespace
+    /// around `=` is tolerated, unknown parameters are ignored, and parameter
+    /// values may be bare or quoted.
+    #[must_use]
+    pub const fn lenient() -> Self {
+        Self {
+            case_sensitive_scheme: false,
+            whitespace_around_equals: true,
+            unknown_parameters: UnknownParameters::Ignore,
+            max_header_length: DEFAULT_MAX_HEADER_LENGTH,
+            max_challenges: DEFAULT_MAX_CHALLENGES,
+            max_parameter_length: DEFAULT_MAX_PARAMETER_LENGTH,
+            code_points: CodePoints::Transitional,
+        }
+    }
+
+    /// Strict configuration: the scheme name must match exactly, no
+    /// whitespace is allowed around `=`, unknown parameters are rejected, and
+    /// parameter values must be bare as in the drafts. Set
+    /// [`code_points`](Self::code_points) to test final spellings.
+    #[must_use]
+    pub const fn strict() -> Self {
+        Self {
+            case_sensitive_scheme: true,
+            whitespace_around_equals: false,
+            unknown_parameters: UnknownParameters::Reject,
+            max_header_length: DEFAULT_MAX_HEADER_LENGTH,
+            max_challenges: DEFAULT_MAX_CHALLENGES,
+            max_parameter_length: DEFAULT_MAX_PARAMETER_LENGTH,
+            code_points: CodePoints::Draft,
+        }
+    }
+}
+
+impl Default for ParseConfig {
+    fn default() -> Self {
+        Self::lenient()
+    }
+}
+
+pub(crate) fn scheme<'a>(input: &'a str, config: &ParseConfig) -> IResult<&'a str, &'a str> {
+    if config.case_sensitive_scheme {
+        tag(SCHEME)(input)
+    } else {
+        tag_no_case(SCHEME)(input)
+    }
+}
+
+pub(crate) fn equals<'a>(input: &'a str, config: &ParseConfig) -> IResult<&'a str, &'a str> {
+    if config.whitespace_around_equals {
+        let (input, _) = opt_spaces(input)?;
+        let (input, equals) = tag("=")(input)?;
+        let (input, _) = opt_spaces(input)?;
+        Ok((input, equals))
+    } else {
+        tag("=")(input)
+    }
+}
+
+/// Parses the value of an unknown parameter, which is either a token or a
+/// quoted string.
+pub(crate) fn unknown_value<'a>(input: &'a str, config: &ParseConfig) -> IResult<&'a str, &'a str> {
+    match config.unknown_parameters {
+        UnknownParameters::Ignore => alt((
+            delimited(char('"'), take_till(|c| c == '"'), char('"')),
+            is_not(", \t"),
+        ))(input),
+        UnknownParameters::Reject => Err(nom::Err::Failure(nom::error::make_error(
+            input,
+            nom::error::ErrorKind::Tag,
+        ))),
+    }
+}
+
+/// Parses the value of a known parameter with `value`. The drafts spell
+/// values bare, RFC 9577 quotes them.
+pub(crate) fn known_value<'a, F>(
+    input: &'a str,
+    config: &ParseConfig,
+    value: F,
+) -> IResult<&'a str, &'a str>
+where
+    F: Fn(&'a str) -> IResult<&'a str, &'a str> + Copy,
+{
+    match config.code_points {
+        CodePoints::Draft => value(input),
+        CodePoints::Final | CodePoints::Transitional => {
+            alt((delimited(char('"'), value, char('"')), value))(input)
+        }
+    }
+}
+
+/// Formats a parameter value in the spelling of `code_points`.
+pub(crate) fn format_value(value: &str, code_points: CodePoints) -> String {
+    match code_points {
+        CodePoints::Draft => value.to_string(),
+        CodePoints::Final | CodePoints::Transitional => format!("\"{value}\""),
+    }
+}
+
+/// Limit of the parser configuration that a header value exceeds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Limit {
+    Challenges,
+    ParameterLength,
+}
+
+impl Limit {
+    const fn error_kind(self) -> ErrorKind {
+        match self {
+            Self::Challenges => ErrorKind::ManyMN,
+            Self::ParameterLength => ErrorKind::TooLarge,
+        }
+    }
+
+    fn failure(self, input: &str) -> nom::Err<nom::error::Error<&str>> {
+        nom::Err::Failure(nom::error::make_error(input, self.error_kind()))
+    }
+
+    /// Returns the limit a parser error was caused by, if any.
+    pub(crate) fn from_error(err: &nom::Err<nom::error::Error<&str>>) -> Option<Self> {
+        match err {
+            nom::Err::Failure(e) if e.code == Self::Challenges.error_kind() => {
+                Some(Self::Challenges)
+            }
+            nom::Err::Failure(e) if e.code == Self::ParameterLength.error_kind() => {
+                Some(Self::ParameterLength)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Fails if a parsed parameter value exceeds the configured maximum length.
+pub(crate) fn check_parameter_length<'a>(
+    input: &'a str,
+    value: &str,
+    config: &ParseConfig,
+) -> Result<(), nom::Err<nom::error::Error<&'a str>>> {
+    if value.len() > config.max_parameter_length {
+        return Err(Limit::ParameterLength.failure(input));
+    }
+    Ok(())
+}
+
+/// Parses a comma-separated list of challenges or tokens and fails as soon as
+/// the configured maximum number of elements is exceeded.
+pub(crate) fn bounded_list<'a, O, F>(
+    input: &'a str,
+    config: &ParseConfig,
+    mut parser: F,
+) -> IResult<&'a str, Vec<O>>
+where
+    F: FnMut(&'a str) -> IResult<&'a str, O>,
+{
+    let (mut input, first) = parser(input)?;
+    let mut list = vec![first];
+    loop {
+        match preceded(tag(","), &mut parser)(input) {
+            Ok((rest, element)) => {
+                if list.len() >= config.max_challenges {
+                    return Err(Limit::Challenges.failure(input));
+                }
+                list.push(element);
+                input = rest;
+            }
+            Err(nom::Err::Error(_)) => return Ok((input, list)),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 pub(crate) fn space(input: &str) -> IResult<&str, &str> {
     is_a(" \t")(input)
 }
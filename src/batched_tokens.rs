@@ -0,0 +1,450 @@
+//! # Batched token wire format
+//!
+//! The structs of the batched token protocol are the same for every VOPRF
+//! ciphersuite, only the sizes of elements and scalars differ. They are
+//! therefore generic over the prime-order group of the ciphersuite and take
+//! their sizes from [`Group::ElemLen`] and [`Group::ScalarLen`]. The
+//! [`batched_tokens_ristretto255`](crate::batched_tokens_ristretto255) and
+//! [`batched_tokens_p384`](crate::batched_tokens_p384) modules instantiate
+//! them for their groups, and batched token types for other ciphersuites,
+//! e.g. P-256, reuse them the same way.
+
+#[cfg(feature = "experimental-unblinding")]
+use std::ops::Deref;
+use std::{
+    fmt,
+    io::{Read, Write},
+};
+
+use generic_array::GenericArray;
+use thiserror::Error;
+use tls_codec::{Deserialize, Serialize, Size, TlsVecU16};
+use typenum::Unsigned;
+use voprf::Group;
+
+use crate::{TokenType, TruncatedTokenKeyId};
+
+/// Serialization error
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum SerializationError {
+    #[error("Invalid serialized data")]
+    /// Invalid serialized data
+    InvalidData,
+    #[error("Serialized data ends early")]
+    /// The byte slice ends before the structure is complete.
+    Truncated,
+    #[error("Trailing bytes after serialized data")]
+    /// The byte slice continues after the structure.
+    TrailingBytes,
+    #[error("Length of the elements is not a multiple of the element size")]
+    /// The length prefix of the elements does not describe a whole number of
+    /// elements.
+    InvalidElementsLength,
+}
+
+impl From<tls_codec::Error> for SerializationError {
+    fn from(error: tls_codec::Error) -> Self {
+        match error {
+            tls_codec::Error::EndOfStream => Self::Truncated,
+            tls_codec::Error::InvalidVectorLength => Self::InvalidElementsLength,
+            _ => Self::InvalidData,
+        }
+    }
+}
+
+/// Deserializes a structure from a byte slice that must contain nothing
+/// else.
+pub(crate) fn from_bytes_strict<T: Deserialize>(mut bytes: &[u8]) -> Result<T, SerializationError> {
+    let value = T::tls_deserialize(&mut bytes)?;
+    if !bytes.is_empty() {
+        return Err(SerializationError::TrailingBytes);
+    }
+    Ok(value)
+}
+
+/// Deserializes a `uint16_t`-prefixed vector of elements of `G::ElemLen`
+/// bytes each. Unlike `TlsVecU16`, a length that is not a multiple of the
+/// element size is rejected instead of being read past.
+fn deserialize_elements<G: Group, T, R: Read>(
+    bytes: &mut R,
+    element: impl Fn(GenericArray<u8, G::ElemLen>) -> T,
+) -> Result<TlsVecU16<T>, tls_codec::Error> {
+    let length = usize::from(u16::tls_deserialize(bytes)?);
+    if length % G::ElemLen::USIZE != 0 {
+        return Err(tls_codec::Error::InvalidVectorLength);
+    }
+    let mut elements = Vec::with_capacity(length / G::ElemLen::USIZE);
+    for _ in 0..length / G::ElemLen::USIZE {
+        let mut bytes_of_element = GenericArray::default();
+        bytes.read_exact(&mut bytes_of_element)?;
+        elements.push(element(bytes_of_element));
+    }
+    Ok(elements.into())
+}
+
+/// Blinded element as specified in the spec:
+///
+/// ```c
+/// struct {
+///     uint8_t blinded_element[Ne];
+/// } BlindedElement;
+/// ```
+pub struct BlindedElement<G: Group> {
+    pub(crate) blinded_element: GenericArray<u8, G::ElemLen>,
+}
+
+impl<G: Group> fmt::Debug for BlindedElement<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlindedElement")
+            .field("blinded_element", &self.blinded_element)
+            .finish()
+    }
+}
+
+impl<G: Group> Size for BlindedElement<G> {
+    fn tls_serialized_len(&self) -> usize {
+        G::ElemLen::USIZE
+    }
+}
+
+impl<G: Group> Serialize for BlindedElement<G> {
+    fn tls_serialize<W: Write>(&self, writer: &mut W) -> Result<usize, tls_codec::Error> {
+        writer.write_all(&self.blinded_element)?;
+        Ok(G::ElemLen::USIZE)
+    }
+}
+
+impl<G: Group> Deserialize for BlindedElement<G> {
+    fn tls_deserialize<R: Read>(bytes: &mut R) -> Result<Self, tls_codec::Error> {
+        let mut blinded_element = GenericArray::default();
+        bytes.read_exact(&mut blinded_element)?;
+        Ok(Self { blinded_element })
+    }
+}
+
+/// Token request as specified in the spec:
+///
+/// ```c
+/// struct {
+///     uint16_t token_type;
+///     uint8_t truncated_token_key_id;
+///     BlindedElement blinded_element[Nr];
+/// } TokenRequest;
+/// ```
+pub struct TokenRequest<G: Group> {
+    pub(crate) token_type: TokenType,
+    pub(crate) truncated_token_key_id: TruncatedTokenKeyId,
+    pub(crate) blinded_elements: TlsVecU16<BlindedElement<G>>,
+}
+
+impl<G: Group> TokenRequest<G> {
+    /// Returns the number of blinded elements
+    #[must_use]
+    pub fn nr(&self) -> usize {
+        self.blinded_elements.len()
+    }
+
+    /// Create a new `TokenRequest` from a byte slice.
+    ///
+    /// # Errors
+    /// Returns an error if the byte slice is not a valid `TokenRequest` or
+    /// continues after it.
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, SerializationError> {
+        from_bytes_strict(bytes)
+    }
+}
+
+impl<G: Group> fmt::Debug for TokenRequest<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenRequest")
+            .field("token_type", &self.token_type)
+            .field("truncated_token_key_id", &self.truncated_token_key_id)
+            .field("blinded_elements", &self.blinded_elements)
+            .finish()
+    }
+}
+
+impl<G: Group> Size for TokenRequest<G> {
+    fn tls_serialized_len(&self) -> usize {
+        self.token_type.tls_serialized_len()
+            + self.truncated_token_key_id.tls_serialized_len()
+            + self.blinded_elements.tls_serialized_len()
+    }
+}
+
+impl<G: Group> Serialize for TokenRequest<G> {
+    fn tls_serialize<W: Write>(&self, writer: &mut W) -> Result<usize, tls_codec::Error> {
+        Ok(self.token_type.tls_serialize(writer)?
+            + self.truncated_token_key_id.tls_serialize(writer)?
+            + self.blinded_elements.tls_serialize(writer)?)
+    }
+}
+
+impl<G: Group> Deserialize for TokenRequest<G> {
+    fn tls_deserialize<R: Read>(bytes: &mut R) -> Result<Self, tls_codec::Error> {
+        Ok(Self {
+            token_type: TokenType::tls_deserialize(bytes)?,
+            truncated_token_key_id: TruncatedTokenKeyId::tls_deserialize(bytes)?,
+            blinded_elements: deserialize_elements::<G, _, _>(bytes, |blinded_element| {
+                BlindedElement { blinded_element }
+            })?,
+        })
+    }
+}
+
+/// Evaluated element as specified in the spec:
+///
+/// ```c
+/// struct {
+///     uint8_t evaluated_element[Ne];
+/// } EvaluatedElement;
+/// ```
+pub struct EvaluatedElement<G: Group> {
+    pub(crate) evaluated_element: GenericArray<u8, G::ElemLen>,
+}
+
+impl<G: Group> fmt::Debug for EvaluatedElement<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EvaluatedElement")
+            .field("evaluated_element", &self.evaluated_element)
+            .finish()
+    }
+}
+
+impl<G: Group> Size for EvaluatedElement<G> {
+    fn tls_serialized_len(&self) -> usize {
+        G::ElemLen::USIZE
+    }
+}
+
+impl<G: Group> Serialize for EvaluatedElement<G> {
+    fn tls_serialize<W: Write>(&self, writer: &mut W) -> Result<usize, tls_codec::Error> {
+        writer.write_all(&self.evaluated_element)?;
+        Ok(G::ElemLen::USIZE)
+    }
+}
+
+impl<G: Group> Deserialize for EvaluatedElement<G> {
+    fn tls_deserialize<R: Read>(bytes: &mut R) -> Result<Self, tls_codec::Error> {
+        let mut evaluated_element = GenericArray::default();
+        bytes.read_exact(&mut evaluated_element)?;
+        Ok(Self { evaluated_element })
+    }
+}
+
+/// Token response as specified in the spec:
+///
+/// ```c
+/// struct {
+///     EvaluatedElement evaluated_elements[Nr];
+///     uint8_t evaluated_proof[Ns + Ns];
+///  } TokenResponse;
+/// ```
+pub struct TokenResponse<G: Group> {
+    pub(crate) evaluated_elements: TlsVecU16<EvaluatedElement<G>>,
+    // Always `2 * Ns` bytes
+    pub(crate) evaluated_proof: Vec<u8>,
+}
+
+/// Size of a serialized proof.
+const fn proof_len<G: Group>() -> usize {
+    2 * G::ScalarLen::USIZE
+}
+
+impl<G: Group> TokenResponse<G> {
+    /// Create a new `TokenResponse` from a byte slice.
+    ///
+    /// # Errors
+    /// Returns an error if the byte slice is not a valid `TokenResponse` or
+    /// continues after it.
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, SerializationError> {
+        from_bytes_strict(bytes)
+    }
+
+    /// Splits the token response into the parts in which it is streamed:
+    /// the length of the evaluated elements, the evaluated elements one by
+    /// one, and the proof.
+    pub fn into_parts(self) -> impl Iterator<Item = TokenResponsePart<G>> {
+        // The elements come from a `TlsVecU16`, so their length fits
+        let length = (self.evaluated_elements.len() * G::ElemLen::USIZE) as u16;
+        let proof = self.evaluated_proof;
+        std::iter::once(TokenResponsePart::ElementsLength(length))
+            .chain(
+                Vec::from(self.evaluated_elements)
+                    .into_iter()
+                    .map(TokenResponsePart::EvaluatedElement),
+            )
+            .chain(std::iter::once(TokenResponsePart::Proof(proof)))
+    }
+}
+
+impl<G: Group> fmt::Debug for TokenResponse<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenResponse")
+            .field("evaluated_elements", &self.evaluated_elements)
+            .field("evaluated_proof", &self.evaluated_proof)
+            .finish()
+    }
+}
+
+impl<G: Group> Size for TokenResponse<G> {
+    fn tls_serialized_len(&self) -> usize {
+        self.evaluated_elements.tls_serialized_len() + proof_len::<G>()
+    }
+}
+
+impl<G: Group> Serialize for TokenResponse<G> {
+    fn tls_serialize<W: Write>(&self, writer: &mut W) -> Result<usize, tls_codec::Error> {
+        if self.evaluated_proof.len() != proof_len::<G>() {
+            return Err(tls_codec::Error::InvalidVectorLength);
+        }
+        let written = self.evaluated_elements.tls_serialize(writer)?;
+        writer.write_all(&self.evaluated_proof)?;
+        Ok(written + proof_len::<G>())
+    }
+}
+
+impl<G: Group> Deserialize for TokenResponse<G> {
+    fn tls_deserialize<R: Read>(bytes: &mut R) -> Result<Self, tls_codec::Error> {
+        let evaluated_elements = deserialize_elements::<G, _, _>(bytes, |evaluated_element| {
+            EvaluatedElement { evaluated_element }
+        })?;
+        let mut evaluated_proof = vec![0; proof_len::<G>()];
+        bytes.read_exact(&mut evaluated_proof)?;
+        Ok(Self {
+            evaluated_elements,
+            evaluated_proof,
+        })
+    }
+}
+
+/// Part of a streamed token response. Concatenating the serialized parts in
+/// stream order yields the serialized `TokenResponse`.
+pub enum TokenResponsePart<G: Group> {
+    /// Length in bytes of the evaluated elements that follow.
+    ElementsLength(u16),
+    /// A single evaluated element.
+    EvaluatedElement(EvaluatedElement<G>),
+    /// The proof over all evaluated elements, `2 * Ns` bytes.
+    Proof(Vec<u8>),
+}
+
+impl<G: Group> fmt::Debug for TokenResponsePart<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ElementsLength(length) => f.debug_tuple("ElementsLength").field(length).finish(),
+            Self::EvaluatedElement(element) => {
+                f.debug_tuple("EvaluatedElement").field(element).finish()
+            }
+            Self::Proof(proof) => f.debug_tuple("Proof").field(proof).finish(),
+        }
+    }
+}
+
+impl<G: Group> TokenResponsePart<G> {
+    /// Serializes the part.
+    #[must_use]
+    pub fn serialize(&self) -> Vec<u8> {
+        match self {
+            Self::ElementsLength(length) => length.to_be_bytes().to_vec(),
+            Self::EvaluatedElement(element) => element.evaluated_element.to_vec(),
+            Self::Proof(proof) => proof.clone(),
+        }
+    }
+}
+
+/// Client token states that are either borrowed from the caller or owned
+/// after an issuance state has been consumed.
+#[cfg(feature = "experimental-unblinding")]
+#[derive(Debug)]
+pub(crate) enum TokenStates<'a, T> {
+    Borrowed(&'a [T]),
+    Owned(Vec<T>),
+}
+
+#[cfg(feature = "experimental-unblinding")]
+impl<T> Deref for TokenStates<'_, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            Self::Borrowed(token_states) => token_states,
+            Self::Owned(token_states) => token_states,
+        }
+    }
+}
+
+#[cfg(feature = "ristretto255")]
+#[test]
+fn token_response_parts() {
+    let token_response = TokenResponse::<voprf::Ristretto255> {
+        evaluated_elements: vec![
+            EvaluatedElement {
+                evaluated_element: GenericArray::from([1; 32]),
+            },
+            EvaluatedElement {
+                evaluated_element: GenericArray::from([2; 32]),
+            },
+        ]
+        .into(),
+        evaluated_proof: vec![3; 64],
+    };
+    let serialized = token_response.tls_serialize_detached().unwrap();
+    assert_eq!(serialized.len(), 2 + 2 * 32 + 64);
+    assert_eq!(serialized.len(), token_response.tls_serialized_len());
+
+    let token_response = TokenResponse::<voprf::Ristretto255>::try_from_bytes(&serialized).unwrap();
+    let streamed = token_response
+        .into_parts()
+        .flat_map(|part| part.serialize())
+        .collect::<Vec<_>>();
+    assert_eq!(streamed, serialized);
+    assert!(TokenResponse::<voprf::Ristretto255>::try_from_bytes(&serialized[..100]).is_err());
+}
+
+#[cfg(feature = "ristretto255")]
+#[test]
+fn strict_try_from_bytes() {
+    type Request = TokenRequest<voprf::Ristretto255>;
+    type Response = TokenResponse<voprf::Ristretto255>;
+
+    // Token type, truncated key ID, two blinded elements
+    let mut request = vec![0xF9, 0x1A, 0x01, 0x00, 0x40];
+    request.extend_from_slice(&[1; 64]);
+    assert_eq!(Request::try_from_bytes(&request).unwrap().nr(), 2);
+    assert!(matches!(
+        Request::try_from_bytes(&[request.as_slice(), &[0]].concat()),
+        Err(SerializationError::TrailingBytes)
+    ));
+    assert!(matches!(
+        Request::try_from_bytes(&request[..40]),
+        Err(SerializationError::Truncated)
+    ));
+    request[4] = 0x3F;
+    assert!(matches!(
+        Request::try_from_bytes(&request),
+        Err(SerializationError::InvalidElementsLength)
+    ));
+
+    // One evaluated element and the proof. Without the length check, a
+    // length of 48 would read half of the proof as a second element.
+    let mut response = vec![0x00, 0x20];
+    response.extend_from_slice(&[2; 32 + 64]);
+    assert_eq!(
+        Response::try_from_bytes(&response)
+            .unwrap()
+            .evaluated_elements
+            .len(),
+        1
+    );
+    assert!(matches!(
+        Response::try_from_bytes(&[response.as_slice(), &[0]].concat()),
+        Err(SerializationError::TrailingBytes)
+    ));
+    response[1] = 0x30;
+    assert!(matches!(
+        Response::try_from_bytes(&response),
+        Err(SerializationError::InvalidElementsLength)
+    ));
+}
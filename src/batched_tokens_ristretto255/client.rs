@@ -1,29 +1,57 @@
 //! Client-side implementation of the Batched Tokens protocol.
 
-use rand::{rngs::OsRng, Rng};
+use std::io::{Read, Write};
+
+use generic_array::GenericArray;
+use rand::{rngs::OsRng, CryptoRng, Rng, RngCore};
+#[cfg(feature = "experimental-unblinding")]
+use sha2::{digest::Output, Sha512};
 use thiserror::Error;
-use voprf::{EvaluationElement, Proof, Result, Ristretto255, VoprfClient};
+use tls_codec::{Deserialize, Serialize, Size};
+use voprf::{EvaluationElement, Group, Proof, Result, Ristretto255, VoprfClient};
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
 use crate::{
     auth::{authenticate::TokenChallenge, authorize::Token},
-    ChallengeDigest, TokenInput, TokenKeyId, TokenType,
+    batched_tokens::from_bytes_strict,
+    issuer_directory::TokenKeyDirectory,
+    ChallengeDigest, CodePoints, FinalizeObserver, FinalizeTimings, Instant, Nonce,
+    ProofVerification, TokenInput, TokenKeyId, TokenType, VoprfError, TOKEN_INPUT_LEN,
+};
+#[cfg(feature = "experimental-unblinding")]
+use crate::{
+    batched_tokens::TokenStates,
+    unblinding::{finalize_unverified, verify_proof},
 };
 
+#[cfg(feature = "experimental-unblinding")]
+use super::TokenResponsePart;
 use super::{
-    public_key_to_token_key_id, truncate_token_key_id, BatchedToken, Nonce, PublicKey,
-    TokenRequest, TokenResponse,
+    public_key_to_token_key_id, truncate_token_key_id, BatchedToken, PublicKey, SerializationError,
+    TokenRequest, TokenResponse, NE, NS,
 };
 
 /// Client-side state that is kept between the token requests and token responses.
 #[derive(Debug)]
 pub struct TokenState {
     client: VoprfClient<Ristretto255>,
+    blinded_element: GenericArray<u8, <Ristretto255 as Group>::ElemLen>,
     token_input: TokenInput,
     challenge_digest: ChallengeDigest,
 }
 
+impl Drop for TokenState {
+    fn drop(&mut self) {
+        // The VOPRF client state and the token input wipe themselves.
+        self.challenge_digest.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for TokenState {}
+
 /// Errors that can occur when issuing token requests.
 #[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum IssueTokenRequestError {
     #[error("Token blinding error")]
     /// Error when blinding the token.
@@ -31,14 +59,30 @@ pub enum IssueTokenRequestError {
     #[error("Invalid TokenChallenge")]
     /// Error when the token challenge is invalid.
     InvalidTokenChallenge,
+    #[error("Token key does not match a current issuer directory entry")]
+    /// Error when the public key is not listed as current in the issuer
+    /// directory.
+    KeyNotInDirectory,
+    #[error("Token request for zero tokens")]
+    /// Error when zero tokens are requested.
+    EmptyBatch,
+    #[error("Number of nonces does not match the number of tokens")]
+    /// Error when the precomputed nonces of a [`TokenRequestBuilder`] do not
+    /// match the number of tokens requested.
+    NonceCountMismatch,
 }
 
 /// Errors that can occur when issuing tokens.
 #[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum IssueTokenError {
     #[error("Invalid TokenResponse")]
     /// Error when the token response is invalid.
     InvalidTokenResponse,
+    #[error("VOPRF finalization failed")]
+    /// Error when the VOPRF implementation rejects the token response, e.g.
+    /// because the proof does not verify.
+    Voprf(#[from] VoprfError),
 }
 
 /// The client side of the batched token issuance protocol.
@@ -46,20 +90,65 @@ pub enum IssueTokenError {
 pub struct Client {
     token_key_id: TokenKeyId,
     public_key: PublicKey,
+    code_points: CodePoints,
+    proof_verification: ProofVerification,
+    finalize_observer: Option<FinalizeObserver>,
+    directory: Option<TokenKeyDirectory>,
 }
 
 impl Client {
     /// Create a new client from a public key.
     #[must_use]
     pub fn new(public_key: PublicKey) -> Self {
+        Self::with_code_points(public_key, CodePoints::default())
+    }
+
+    /// Create a new client from a public key that uses the given token type
+    /// code points.
+    #[must_use]
+    pub fn with_code_points(public_key: PublicKey, code_points: CodePoints) -> Self {
         let token_key_id = public_key_to_token_key_id(&public_key);
 
         Self {
             token_key_id,
             public_key,
+            code_points,
+            proof_verification: ProofVerification::default(),
+            finalize_observer: None,
+            directory: None,
         }
     }
 
+    /// Sets the issuer directory the public key is checked against before
+    /// blinding. Token requests are refused if the directory does not list
+    /// the key as current, so that an issuance endpoint cannot single out a
+    /// client by serving it a unique key.
+    #[must_use]
+    pub fn with_directory(mut self, directory: TokenKeyDirectory) -> Self {
+        self.directory = Some(directory);
+        self
+    }
+
+    /// Sets whether the proof of token responses is verified. See
+    /// [`ProofVerification`] for the security implications of skipping
+    /// verification.
+    #[must_use]
+    pub const fn with_proof_verification(mut self, proof_verification: ProofVerification) -> Self {
+        self.proof_verification = proof_verification;
+        self
+    }
+
+    /// Sets a callback that receives the timings of every token finalization.
+    #[must_use]
+    pub fn with_finalize_observer(mut self, finalize_observer: FinalizeObserver) -> Self {
+        self.finalize_observer = Some(finalize_observer);
+        self
+    }
+
+    fn token_type(&self) -> TokenType {
+        self.code_points.emit(TokenType::BatchedTokenRistretto255)
+    }
+
     /// Issue a token request.
     ///
     /// # Errors
@@ -68,28 +157,64 @@ impl Client {
         &self,
         challenge: &TokenChallenge,
         nr: u16,
+    ) -> Result<(TokenRequest, Vec<TokenState>), IssueTokenRequestError> {
+        self.issue_token_request_with_rng(&mut OsRng, challenge, nr)
+    }
+
+    /// Issue a token request with nonces and blinds drawn from `rng`, for
+    /// platforms without an OS random number generator.
+    ///
+    /// # Errors
+    /// Returns an error if the token blinding fails.
+    pub fn issue_token_request_with_rng<R: RngCore + CryptoRng>(
+        &self,
+        rng: &mut R,
+        challenge: &TokenChallenge,
+        nr: u16,
     ) -> Result<(TokenRequest, Vec<TokenState>), IssueTokenRequestError> {
         let mut nonces = Vec::with_capacity(nr as usize);
 
         for _ in 0..nr {
-            let nonce: Nonce = OsRng.gen();
+            let nonce: Nonce = rng.gen();
             nonces.push(nonce);
         }
 
-        self.issue_token_request_internal(challenge, nonces, None)
+        self.issue_token_request_internal(rng, challenge, nonces, None)
     }
 
     /// Issue a token request.
-    fn issue_token_request_internal(
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                truncated_token_key_id = truncate_token_key_id(&self.token_key_id),
+                batch_size = nonces.len(),
+            ),
+            err(level = "debug")
+        )
+    )]
+    fn issue_token_request_internal<R: RngCore + CryptoRng>(
         &self,
+        rng: &mut R,
         challenge: &TokenChallenge,
         nonces: Vec<Nonce>,
         _blinds: Option<Vec<<Ristretto255 as voprf::Group>::Scalar>>,
     ) -> Result<(TokenRequest, Vec<TokenState>), IssueTokenRequestError> {
+        if nonces.is_empty() {
+            return Err(IssueTokenRequestError::EmptyBatch);
+        }
         let challenge_digest = challenge
             .digest()
             .map_err(|_| IssueTokenRequestError::InvalidTokenChallenge)?;
 
+        if let Some(directory) = &self.directory {
+            directory
+                .check_key(self.token_type(), &self.token_key_id)
+                .map_err(|_| IssueTokenRequestError::KeyNotInDirectory)?;
+        }
+
         let mut blinded_elements = Vec::new();
         let mut token_states = Vec::new();
 
@@ -103,21 +228,23 @@ impl Client {
             // blind, blinded_element = client_context.Blind(token_input)
 
             let token_input = TokenInput::new(
-                TokenType::BatchedTokenRistretto255,
+                self.token_type(),
                 nonce,
                 challenge_digest,
                 self.token_key_id,
             );
 
             let blinded_element =
-                VoprfClient::<Ristretto255>::blind(&token_input.serialize(), &mut OsRng)
+                VoprfClient::<Ristretto255>::blind(token_input.to_bytes().as_slice(), rng)
                     .map_err(|_| IssueTokenRequestError::BlindingError)?;
 
             #[cfg(feature = "kat")]
             let blinded_element = if _blinds.is_some() {
                 VoprfClient::<Ristretto255>::deterministic_blind_unchecked(
-                    &token_input.serialize(),
-                    *blinds_iter.next().unwrap(),
+                    token_input.to_bytes().as_slice(),
+                    *blinds_iter
+                        .next()
+                        .ok_or(IssueTokenRequestError::BlindingError)?,
                 )
                 .map_err(|_| IssueTokenRequestError::BlindingError)?
             } else {
@@ -126,12 +253,13 @@ impl Client {
 
             let token_state = TokenState {
                 client: blinded_element.state,
+                blinded_element: blinded_element.message.serialize(),
                 token_input,
                 challenge_digest,
             };
 
             let blinded_element = super::BlindedElement {
-                blinded_element: blinded_element.message.serialize().into(),
+                blinded_element: token_state.blinded_element,
             };
 
             blinded_elements.push(blinded_element);
@@ -139,7 +267,7 @@ impl Client {
         }
 
         let token_request = TokenRequest {
-            token_type: TokenType::BatchedTokenRistretto255,
+            token_type: self.token_type(),
             truncated_token_key_id: truncate_token_key_id(&self.token_key_id),
             blinded_elements: blinded_elements.into(),
         };
@@ -155,45 +283,89 @@ impl Client {
         nonces: Vec<Nonce>,
         blind: Vec<<Ristretto255 as voprf::Group>::Scalar>,
     ) -> Result<(TokenRequest, Vec<TokenState>), IssueTokenRequestError> {
-        self.issue_token_request_internal(challenge, nonces, Some(blind))
+        self.issue_token_request_internal(&mut OsRng, challenge, nonces, Some(blind))
     }
 
     /// Issue a token.
     ///
     /// # Errors
     /// Returns an error if the token response is invalid.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                truncated_token_key_id = truncate_token_key_id(&self.token_key_id),
+                batch_size = token_states.len(),
+            ),
+            err(level = "debug")
+        )
+    )]
     pub fn issue_tokens(
         &self,
         token_response: &TokenResponse,
         token_states: &[TokenState],
     ) -> Result<Vec<BatchedToken>, IssueTokenError> {
+        let start = Instant::now();
         let mut evaluated_elements = Vec::new();
         for element in token_response.evaluated_elements.iter() {
             let evaluated_element =
                 EvaluationElement::<Ristretto255>::deserialize(&element.evaluated_element)
-                    .map_err(|_| IssueTokenError::InvalidTokenResponse)?;
+                    .map_err(VoprfError::from)?;
             evaluated_elements.push(evaluated_element);
         }
 
-        let proof = Proof::deserialize(&token_response.evaluated_proof)
-            .map_err(|_| IssueTokenError::InvalidTokenResponse)?;
-
-        let client_batch_finalize_result = VoprfClient::batch_finalize(
-            &token_states
-                .iter()
-                .map(|token_state| token_state.token_input.serialize())
-                .collect::<Vec<_>>(),
-            &token_states
-                .iter()
-                .map(|token_state| token_state.client.clone())
-                .collect::<Vec<_>>(),
-            &evaluated_elements,
-            &proof,
-            self.public_key,
-        )
-        .map_err(|_| IssueTokenError::InvalidTokenResponse)?
-        .collect::<Result<Vec<_>>>()
-        .map_err(|_| IssueTokenError::InvalidTokenResponse)?;
+        let proof =
+            Proof::deserialize(&token_response.evaluated_proof).map_err(VoprfError::from)?;
+
+        let decoded = Instant::now();
+
+        let client_batch_finalize_result = match self.proof_verification {
+            ProofVerification::Verify => VoprfClient::batch_finalize(
+                &token_states
+                    .iter()
+                    .map(|token_state| token_state.token_input.to_bytes())
+                    .collect::<Vec<_>>(),
+                &token_states
+                    .iter()
+                    .map(|token_state| token_state.client.clone())
+                    .collect::<Vec<_>>(),
+                &evaluated_elements,
+                &proof,
+                self.public_key,
+            )
+            .map_err(VoprfError::from)?
+            .collect::<Result<Vec<_>>>()
+            .map_err(VoprfError::from)?,
+            #[cfg(feature = "experimental-unblinding")]
+            ProofVerification::SkipForTrustedIssuer => {
+                if token_response.evaluated_elements.len() != token_states.len() {
+                    return Err(IssueTokenError::InvalidTokenResponse);
+                }
+                token_response
+                    .evaluated_elements
+                    .iter()
+                    .zip(token_states.iter())
+                    .map(|(element, token_state)| {
+                        finalize_unverified::<Ristretto255, Sha512>(
+                            &token_state.client.serialize(),
+                            token_state.token_input.to_bytes().as_slice(),
+                            &element.evaluated_element,
+                        )
+                        .ok_or(IssueTokenError::InvalidTokenResponse)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+        };
+
+        if let Some(finalize_observer) = &self.finalize_observer {
+            finalize_observer.observe(&FinalizeTimings {
+                decode: decoded - start,
+                finalize: decoded.elapsed(),
+                proof_verified: self.proof_verification == ProofVerification::Verify,
+            });
+        }
 
         let mut tokens = Vec::new();
 
@@ -201,7 +373,7 @@ impl Client {
             client_batch_finalize_result.iter().zip(token_states.iter())
         {
             let token = Token::new(
-                TokenType::BatchedTokenRistretto255,
+                token_state.token_input.token_type,
                 token_state.token_input.nonce,
                 token_state.challenge_digest,
                 token_state.token_input.token_key_id,
@@ -212,4 +384,375 @@ impl Client {
 
         Ok(tokens)
     }
+
+    /// Starts the incremental finalization of a streamed token response.
+    /// See [`IncrementalFinalization`].
+    #[must_use]
+    #[cfg(feature = "experimental-unblinding")]
+    pub fn begin_issue_tokens<'a>(
+        &'a self,
+        token_states: &'a [TokenState],
+    ) -> IncrementalFinalization<'a> {
+        self.begin_incremental_finalization(TokenStates::Borrowed(token_states))
+    }
+
+    /// Starts the incremental finalization of a streamed token response to a
+    /// request built by a [`TokenRequestBuilder`]. The state is consumed, so
+    /// that it finalizes exactly one response.
+    #[must_use]
+    #[cfg(feature = "experimental-unblinding")]
+    pub fn begin_issue_tokens_with_state(
+        &self,
+        issuance_state: IssuanceState,
+    ) -> IncrementalFinalization<'_> {
+        self.begin_incremental_finalization(TokenStates::Owned(issuance_state.token_states))
+    }
+
+    #[cfg(feature = "experimental-unblinding")]
+    fn begin_incremental_finalization<'a>(
+        &'a self,
+        token_states: TokenStates<'a, TokenState>,
+    ) -> IncrementalFinalization<'a> {
+        let count = token_states.len();
+        IncrementalFinalization {
+            client: self,
+            token_states,
+            blinded_elements: Vec::with_capacity(count),
+            evaluated_elements: Vec::with_capacity(count),
+            authenticators: Vec::with_capacity(count),
+            proof: None,
+        }
+    }
+
+    /// Starts building a token request for `challenge`. See
+    /// [`TokenRequestBuilder`].
+    pub const fn token_request_builder<'a>(
+        &'a self,
+        challenge: &'a TokenChallenge,
+    ) -> TokenRequestBuilder<'a> {
+        TokenRequestBuilder {
+            client: self,
+            challenge,
+            count: None,
+            nonces: None,
+        }
+    }
+
+    /// Issue tokens from the response to a request built by a
+    /// [`TokenRequestBuilder`]. The state is consumed, so that it finalizes
+    /// exactly one response.
+    ///
+    /// # Errors
+    /// Returns an error if the token response is invalid.
+    pub fn issue_tokens_with_state(
+        &self,
+        token_response: &TokenResponse,
+        issuance_state: IssuanceState,
+    ) -> Result<Vec<BatchedToken>, IssueTokenError> {
+        self.issue_tokens(token_response, &issuance_state.token_states)
+    }
+}
+
+/// Opaque client state of a token request built by a [`TokenRequestBuilder`].
+///
+/// The state owns everything that is needed to finalize the token response
+/// and does not borrow the client, so it can be kept across the request to
+/// the issuer, e.g. an HTTP call on another task.
+///
+/// The state can be persisted, e.g. while a mobile app is suspended, with its
+/// TLS encoding or, with the `serde-wire` feature, with serde:
+///
+/// ```c
+/// struct {
+///     uint8_t blind[Ns];
+///     uint8_t blinded_element[Ne];
+///     uint16_t token_type;
+///     uint8_t nonce[32];
+///     uint8_t challenge_digest[32];
+///     uint8_t token_key_id[Nh];
+/// } TokenState;
+///
+/// struct {
+///     uint16_t count;
+///     TokenState token_states[count];
+/// } IssuanceState;
+/// ```
+///
+/// The blinds unlink the tokens from the token request, so the serialized
+/// state must be kept as confidential as the tokens themselves.
+#[derive(Debug)]
+pub struct IssuanceState {
+    token_states: Vec<TokenState>,
+}
+
+impl IssuanceState {
+    /// Create a new `IssuanceState` from a byte slice.
+    ///
+    /// # Errors
+    /// Returns an error if the byte slice is not a valid `IssuanceState` or
+    /// continues after it.
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, SerializationError> {
+        from_bytes_strict(bytes)
+    }
+}
+
+impl Size for TokenState {
+    fn tls_serialized_len(&self) -> usize {
+        NS + NE + TOKEN_INPUT_LEN
+    }
+}
+
+impl Serialize for TokenState {
+    fn tls_serialize<W: Write>(&self, writer: &mut W) -> Result<usize, tls_codec::Error> {
+        let mut client = self.client.serialize();
+        let written = writer.write_all(&client);
+        client.as_mut_slice().zeroize();
+        written?;
+        writer.write_all(self.token_input.to_bytes().as_slice())?;
+        Ok(self.tls_serialized_len())
+    }
+}
+
+impl Deserialize for TokenState {
+    fn tls_deserialize<R: Read>(bytes: &mut R) -> Result<Self, tls_codec::Error> {
+        let mut serialized = [0u8; NS + NE];
+        let client = bytes
+            .read_exact(&mut serialized)
+            .map_err(tls_codec::Error::from)
+            .and_then(|()| {
+                VoprfClient::<Ristretto255>::deserialize(&serialized).map_err(|_| {
+                    tls_codec::Error::DecodingError("invalid VOPRF client state".to_string())
+                })
+            });
+        let blinded_element = GenericArray::clone_from_slice(&serialized[NS..]);
+        serialized.zeroize();
+        let client = client?;
+        let token_type = TokenType::tls_deserialize(bytes)?;
+        if !matches!(
+            token_type,
+            TokenType::BatchedTokenRistretto255 | TokenType::BatchedTokenRistretto255Final
+        ) {
+            return Err(tls_codec::Error::DecodingError(format!(
+                "unexpected token type {token_type:?}"
+            )));
+        }
+        let nonce = Nonce::tls_deserialize(bytes)?;
+        let challenge_digest = ChallengeDigest::tls_deserialize(bytes)?;
+        let token_key_id = TokenKeyId::tls_deserialize(bytes)?;
+        Ok(Self {
+            client,
+            blinded_element,
+            token_input: TokenInput::new(token_type, nonce, challenge_digest, token_key_id),
+            challenge_digest,
+        })
+    }
+}
+
+impl Size for IssuanceState {
+    fn tls_serialized_len(&self) -> usize {
+        2 + self
+            .token_states
+            .iter()
+            .map(Size::tls_serialized_len)
+            .sum::<usize>()
+    }
+}
+
+impl Serialize for IssuanceState {
+    fn tls_serialize<W: Write>(&self, writer: &mut W) -> Result<usize, tls_codec::Error> {
+        let count = u16::try_from(self.token_states.len())
+            .map_err(|_| tls_codec::Error::InvalidVectorLength)?;
+        let mut written = count.tls_serialize(writer)?;
+        for token_state in &self.token_states {
+            written += token_state.tls_serialize(writer)?;
+        }
+        Ok(written)
+    }
+}
+
+impl Deserialize for IssuanceState {
+    fn tls_deserialize<R: Read>(bytes: &mut R) -> Result<Self, tls_codec::Error> {
+        let count = u16::tls_deserialize(bytes)?;
+        if count == 0 {
+            return Err(tls_codec::Error::InvalidVectorLength);
+        }
+        let token_states = (0..count)
+            .map(|_| TokenState::tls_deserialize(bytes))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { token_states })
+    }
+}
+
+/// Builder for a token request that keeps the wire message apart from the
+/// blinding state.
+///
+/// [`TokenRequestBuilder::build`] returns the [`TokenRequest`] to send to the
+/// issuer and the [`IssuanceState`] to pass to
+/// [`Client::issue_tokens_with_state`] once the token response arrives.
+#[derive(Debug)]
+#[must_use]
+pub struct TokenRequestBuilder<'a> {
+    client: &'a Client,
+    challenge: &'a TokenChallenge,
+    count: Option<u16>,
+    nonces: Option<Vec<Nonce>>,
+}
+
+impl<'a> TokenRequestBuilder<'a> {
+    /// Sets the challenge the tokens are requested for.
+    pub const fn challenge(mut self, challenge: &'a TokenChallenge) -> Self {
+        self.challenge = challenge;
+        self
+    }
+
+    /// Sets the number of tokens to request. Defaults to one token, or to one
+    /// token per nonce if nonces are supplied.
+    pub const fn count(mut self, count: u16) -> Self {
+        self.count = Some(count);
+        self
+    }
+
+    /// Supplies precomputed nonces instead of drawing them at random, one
+    /// per token.
+    pub fn nonces(mut self, nonces: Vec<Nonce>) -> Self {
+        self.nonces = Some(nonces);
+        self
+    }
+
+    /// Builds the token request.
+    ///
+    /// # Errors
+    /// Returns an error if no tokens are requested, the nonces do not match
+    /// the number of tokens, or the token blinding fails.
+    pub fn build(self) -> Result<(TokenRequest, IssuanceState), IssueTokenRequestError> {
+        self.build_with_rng(&mut OsRng)
+    }
+
+    /// Builds the token request with nonces and blinds drawn from `rng`.
+    ///
+    /// # Errors
+    /// Returns an error if no tokens are requested, the nonces do not match
+    /// the number of tokens, or the token blinding fails.
+    pub fn build_with_rng<R: RngCore + CryptoRng>(
+        self,
+        rng: &mut R,
+    ) -> Result<(TokenRequest, IssuanceState), IssueTokenRequestError> {
+        let nonces = match (self.count, self.nonces) {
+            (count, None) => (0..count.unwrap_or(1)).map(|_| rng.gen()).collect(),
+            (None, Some(nonces)) => nonces,
+            (Some(count), Some(nonces)) if nonces.len() == usize::from(count) => nonces,
+            (Some(_), Some(_)) => return Err(IssueTokenRequestError::NonceCountMismatch),
+        };
+        let (token_request, token_states) =
+            self.client
+                .issue_token_request_internal(rng, self.challenge, nonces, None)?;
+        Ok((token_request, IssuanceState { token_states }))
+    }
+}
+
+/// Incremental finalization of a token response that arrives as a stream of
+/// [`TokenResponsePart`]s.
+///
+/// Evaluated elements are unblinded as they arrive. Unless proof
+/// verification is skipped, [`IncrementalFinalization::finish`] only has to
+/// verify the proof over all of them before it releases the tokens.
+///
+/// Requires the experimental `experimental-unblinding` feature.
+#[cfg(feature = "experimental-unblinding")]
+#[derive(Debug)]
+pub struct IncrementalFinalization<'a> {
+    client: &'a Client,
+    token_states: TokenStates<'a, TokenState>,
+    // Only kept for the proof verification
+    blinded_elements: Vec<<Ristretto255 as Group>::Elem>,
+    evaluated_elements: Vec<<Ristretto255 as Group>::Elem>,
+    authenticators: Vec<Output<Sha512>>,
+    proof: Option<Proof<Ristretto255>>,
+}
+
+#[cfg(feature = "experimental-unblinding")]
+impl IncrementalFinalization<'_> {
+    /// Feeds the next part of the token response.
+    ///
+    /// # Errors
+    /// Returns an error if the part is invalid or arrives out of order.
+    pub fn push(&mut self, part: TokenResponsePart) -> Result<(), IssueTokenError> {
+        if self.proof.is_some() {
+            return Err(IssueTokenError::InvalidTokenResponse);
+        }
+        match part {
+            TokenResponsePart::ElementsLength(length) => {
+                if !self.authenticators.is_empty()
+                    || usize::from(length) != self.token_states.len() * NE
+                {
+                    return Err(IssueTokenError::InvalidTokenResponse);
+                }
+            }
+            TokenResponsePart::EvaluatedElement(element) => {
+                let token_state = self
+                    .token_states
+                    .get(self.authenticators.len())
+                    .ok_or(IssueTokenError::InvalidTokenResponse)?;
+                let evaluated_element = Ristretto255::deserialize_elem(&element.evaluated_element)
+                    .map_err(VoprfError::from)?;
+                let serialized_client = token_state.client.serialize();
+                let authenticator = finalize_unverified::<Ristretto255, Sha512>(
+                    &serialized_client,
+                    token_state.token_input.to_bytes().as_slice(),
+                    &element.evaluated_element,
+                )
+                .ok_or(IssueTokenError::InvalidTokenResponse)?;
+                if self.client.proof_verification == ProofVerification::Verify {
+                    let blinded_element =
+                        Ristretto255::deserialize_elem(&token_state.blinded_element)
+                            .map_err(VoprfError::from)?;
+                    self.blinded_elements.push(blinded_element);
+                    self.evaluated_elements.push(evaluated_element);
+                }
+                self.authenticators.push(authenticator);
+            }
+            TokenResponsePart::Proof(proof) => {
+                if self.authenticators.len() != self.token_states.len() {
+                    return Err(IssueTokenError::InvalidTokenResponse);
+                }
+                self.proof = Some(Proof::deserialize(&proof).map_err(VoprfError::from)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Verifies the proof, unless verification is skipped, and returns the
+    /// tokens.
+    ///
+    /// # Errors
+    /// Returns an error if the token response is incomplete or invalid.
+    pub fn finish(self) -> Result<Vec<BatchedToken>, IssueTokenError> {
+        let proof = self.proof.ok_or(IssueTokenError::InvalidTokenResponse)?;
+        if self.client.proof_verification == ProofVerification::Verify
+            && !verify_proof::<Ristretto255, Sha512>(
+                b"ristretto255-SHA512",
+                self.client.public_key,
+                &self.blinded_elements,
+                &self.evaluated_elements,
+                &proof.serialize(),
+            )
+        {
+            return Err(VoprfError::ProofVerification.into());
+        }
+
+        Ok(self
+            .authenticators
+            .iter()
+            .zip(self.token_states.iter())
+            .map(|(authenticator, token_state)| {
+                Token::new(
+                    token_state.token_input.token_type,
+                    token_state.token_input.nonce,
+                    token_state.challenge_digest,
+                    token_state.token_input.token_key_id,
+                    *authenticator,
+                )
+            })
+            .collect())
+    }
 }
@@ -4,13 +4,10 @@ pub mod client;
 pub mod server;
 
 use sha2::{Digest, Sha256};
-use thiserror::Error;
-use tls_codec::{Deserialize, TlsVecU16};
-use tls_codec_derive::{TlsDeserialize, TlsSerialize, TlsSize};
 use typenum::U64;
 pub use voprf::*;
 
-use crate::{auth::authorize::Token, Nonce, TokenKeyId, TokenType, TruncatedTokenKeyId};
+use crate::{auth::authorize::Token, batched_tokens, TokenKeyId, TruncatedTokenKeyId};
 
 use self::server::serialize_public_key;
 
@@ -26,7 +23,14 @@ pub type BatchedToken = Token<U64>;
 /// Public key alias
 pub type PublicKey = <Ristretto255 as Group>::Elem;
 
-fn public_key_to_token_key_id(public_key: &PublicKey) -> TokenKeyId {
+/// Convert a public key to a token key ID.
+pub fn public_key_to_truncated_token_key_id(public_key: &PublicKey) -> TruncatedTokenKeyId {
+    truncate_token_key_id(&public_key_to_token_key_id(public_key))
+}
+
+/// Convert a public key to a token key ID, the SHA-256 digest of the
+/// serialized public key.
+pub fn public_key_to_token_key_id(public_key: &PublicKey) -> TokenKeyId {
     let public_key = serialize_public_key(*public_key);
 
     Sha256::digest(public_key).into()
@@ -36,85 +40,24 @@ fn truncate_token_key_id(token_key_id: &TokenKeyId) -> TruncatedTokenKeyId {
     *token_key_id.iter().last().unwrap_or(&0)
 }
 
-/// Serialization error
-#[derive(Error, Debug)]
-pub enum SerializationError {
-    #[error("Invalid serialized data")]
-    /// Invalid serialized data
-    InvalidData,
-}
-
-/// Blinded element as specified in the spec:
-///
-/// ```c
-/// struct {
-///     uint8_t blinded_element[Ne];
-/// } BlindedElement;
-/// ```
-#[derive(Debug, TlsDeserialize, TlsSerialize, TlsSize)]
-pub struct BlindedElement {
-    blinded_element: [u8; NE],
-}
+pub use crate::batched_tokens::SerializationError;
 
-/// Token request as specified in the spec:
-///
-/// ```c
-/// struct {
-///     uint16_t token_type = 0xF91A;
-///     uint8_t truncated_token_key_id;
-///     BlindedElement blinded_element[Nr];
-/// } TokenRequest;
-/// ```
-#[derive(Debug, TlsDeserialize, TlsSerialize, TlsSize)]
-pub struct TokenRequest {
-    token_type: TokenType,
-    truncated_token_key_id: TruncatedTokenKeyId,
-    blinded_elements: TlsVecU16<BlindedElement>,
-}
+/// Blinded element of Ristretto255 batched tokens, see
+/// [`batched_tokens::BlindedElement`].
+pub type BlindedElement = batched_tokens::BlindedElement<Ristretto255>;
 
-impl TokenRequest {
-    /// Returns the number of blinded elements
-    #[must_use]
-    pub fn nr(&self) -> usize {
-        self.blinded_elements.len()
-    }
-}
+/// Token request of Ristretto255 batched tokens, with `token_type = 0xF91A`, see
+/// [`batched_tokens::TokenRequest`].
+pub type TokenRequest = batched_tokens::TokenRequest<Ristretto255>;
 
-/// Evaluated element as specified in the spec:
-///
-/// ```c
-/// struct {
-///     uint8_t evaluated_element[Ne];
-/// } EvaluatedElement;
-/// ```
-
-#[derive(Debug, TlsDeserialize, TlsSerialize, TlsSize)]
-pub struct EvaluatedElement {
-    evaluated_element: [u8; NE],
-}
+/// Evaluated element of Ristretto255 batched tokens, see
+/// [`batched_tokens::EvaluatedElement`].
+pub type EvaluatedElement = batched_tokens::EvaluatedElement<Ristretto255>;
 
-/// Token response as specified in the spec:
-///
-/// ```c
-/// struct {
-///     EvaluatedElement evaluated_elements[Nr];
-///     uint8_t evaluated_proof[Ns + Ns];
-///  } TokenResponse;
-/// ```
-#[derive(Debug, TlsDeserialize, TlsSerialize, TlsSize)]
-pub struct TokenResponse {
-    evaluated_elements: TlsVecU16<EvaluatedElement>,
-    evaluated_proof: [u8; NS + NS],
-}
+/// Token response of Ristretto255 batched tokens, see
+/// [`batched_tokens::TokenResponse`].
+pub type TokenResponse = batched_tokens::TokenResponse<Ristretto255>;
 
-impl TokenResponse {
-    /// Create a new `TokenResponse` from a byte slice.
-    ///
-    /// # Errors
-    /// Returns `SerializationError::InvalidData` if the byte slice is not a
-    /// valid `TokenResponse`.
-    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, SerializationError> {
-        let mut bytes = bytes;
-        Self::tls_deserialize(&mut bytes).map_err(|_| SerializationError::InvalidData)
-    }
-}
+/// Part of a streamed token response of Ristretto255 batched tokens, see
+/// [`batched_tokens::TokenResponsePart`].
+pub type TokenResponsePart = batched_tokens::TokenResponsePart<Ristretto255>;
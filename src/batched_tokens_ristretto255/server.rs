@@ -1,34 +1,72 @@
 //! Server-side implementation of the Batched Tokens protocol.
 
+use std::{sync::Arc, time::Duration};
+
 use async_trait::async_trait;
+use futures::{stream, Stream};
 use generic_array::GenericArray;
-use rand::{rngs::OsRng, RngCore};
+use rand::{CryptoRng, RngCore};
+use subtle::ConstantTimeEq;
 use thiserror::Error;
-use voprf::{
-    BlindedElement, Error, Group, Result, Ristretto255, VoprfServer,
-    VoprfServerBatchEvaluateFinishResult,
-};
+use voprf::{Error, Group, Result, Ristretto255, VoprfServer};
 
+#[cfg(feature = "pem")]
+use crate::private_keys::{ristretto255_from_scalar, ristretto255_to_scalar, PrivateKeyError};
+#[cfg(feature = "profiling")]
+use crate::IssuanceObserver;
 use crate::{
-    batched_tokens_ristretto255::EvaluatedElement, NonceStore, TokenInput, TokenType,
-    TruncatedTokenKeyId,
+    batched_tokens_ristretto255::EvaluatedElement,
+    clock::{Clock, ServerClock},
+    concurrency::{ConcurrencyLimit, ConcurrencyPermit},
+    config::{ConfigError, ServerConfig},
+    evaluator::{
+        blind_evaluate_ristretto255, BlindEvaluation, BlindEvaluator, BlindEvaluatorError,
+        StreamedEvaluationRistretto255,
+    },
+    issuer_directory::TokenKey,
+    key_derivation_info,
+    metrics::{Metrics, ServerMetrics},
+    policy::{check_policy, AcceptAll, PolicyRejection, RedemptionPolicy},
+    random_seed, uniform_server_ristretto255,
+    webhooks::RedemptionOutcome,
+    CodePoints, DoubleSpendEvent, DoubleSpendObserver, ExposeSecret, IssuanceProfiler,
+    IssuanceStage, KeyEpoch, KeyStoreError, KeyValidity, MaybeSendSync, NonceStore, ReadinessError,
+    RedemptionErrors, SecretVec, ServerRng, TokenInput, TokenKeyId, TokenType, TruncatedTokenKeyId,
+    VoprfError,
 };
 
 use super::{
     public_key_to_token_key_id, truncate_token_key_id, BatchedToken, PublicKey, TokenRequest,
-    TokenResponse, NK,
+    TokenResponse, TokenResponsePart, NK, NS,
 };
 
 /// Errors that can occur when creating a keypair.
 #[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum CreateKeypairError {
     #[error("Seed is too long")]
     /// Error when the seed is too long.
     SeedError,
+    #[error("Key derivation failed")]
+    /// Error when the VOPRF implementation cannot derive or decode the key.
+    Voprf(#[source] VoprfError),
+    #[error(transparent)]
+    /// Error when the key store fails.
+    KeyStore(#[from] KeyStoreError),
+}
+
+impl From<VoprfError> for CreateKeypairError {
+    fn from(error: VoprfError) -> Self {
+        match error {
+            VoprfError::DeriveKeyPair => Self::SeedError,
+            error => Self::Voprf(error),
+        }
+    }
 }
 
 /// Errors that can occur when issuing the token response.
 #[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum IssueTokenResponseError {
     #[error("Key ID not found")]
     /// Error when the key ID is not found.
@@ -39,10 +77,48 @@ pub enum IssueTokenResponseError {
     #[error("Invalid toke type")]
     /// Error when the token type is invalid.
     InvalidTokenType,
+    #[error("Too many concurrent requests for the key")]
+    /// Error when the key is at its concurrency limit.
+    TooManyRequests,
+    #[error("Blinded element {0} failed to decode")]
+    /// Error when the blinded element at the given index is not a valid
+    /// group element.
+    InvalidBlindedElement(usize),
+    #[error("Proof generation failed")]
+    /// Error when the evaluation or its proof could not be computed.
+    ProofGenerationFailed(#[source] VoprfError),
+    #[error("Token request contains no blinded elements")]
+    /// Error when the token request asks for no tokens at all.
+    EmptyBatch,
+    #[error("Batch of {0} tokens exceeds the limit")]
+    /// Error when the token request asks for more tokens than the server
+    /// issues in one batch.
+    BatchTooLarge(usize),
+    #[error(transparent)]
+    /// Error when the blind evaluator fails or returns an invalid evaluation.
+    Evaluator(BlindEvaluatorError),
+    #[error("Key is not valid at this time")]
+    /// Error when the key is used before its not-before time or from its
+    /// not-after time on.
+    KeyNotValid,
+    #[error(transparent)]
+    /// Error when the key store fails.
+    KeyStore(#[from] KeyStoreError),
+}
+
+impl From<BlindEvaluatorError> for IssueTokenResponseError {
+    fn from(error: BlindEvaluatorError) -> Self {
+        match error {
+            BlindEvaluatorError::InvalidBlindedElement(index) => Self::InvalidBlindedElement(index),
+            BlindEvaluatorError::Voprf(error) => Self::ProofGenerationFailed(error),
+            error => Self::Evaluator(error),
+        }
+    }
 }
 
 /// Errors that can occur when redeeming the token.
 #[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum RedeemTokenError {
     #[error("Key ID not found")]
     /// Error when the key ID is not found.
@@ -53,23 +129,202 @@ pub enum RedeemTokenError {
     #[error("The token is invalid")]
     /// Error when the token is invalid.
     InvalidToken,
+    #[error("Rejected by redemption policy: {0}")]
+    /// Error when the redemption policy rejects the token.
+    PolicyRejected(#[from] PolicyRejection),
+    #[error("Key expired")]
+    /// Error when the token is redeemed after the not-after time of its key
+    /// and the redemption grace period.
+    KeyExpired,
+    #[error(transparent)]
+    /// Error when the key store fails.
+    KeyStore(#[from] KeyStoreError),
+}
+
+/// Classifies the result of a redemption for metrics.
+fn redemption_outcome(result: &Result<(), RedeemTokenError>) -> RedemptionOutcome {
+    match result {
+        Ok(()) => RedemptionOutcome::Redeemed,
+        Err(RedeemTokenError::DoubleSpending) => RedemptionOutcome::DoubleSpending,
+        Err(_) => RedemptionOutcome::Rejected,
+    }
 }
 
 /// Minimal trait for a key store to store key material on the server-side. Note
 /// that the store requires inner mutability.
-#[async_trait]
-pub trait BatchedKeyStore: Send + Sync {
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+pub trait BatchedKeyStore: MaybeSendSync {
     /// Inserts a keypair with a given `truncated_token_key_id` into the key store.
     async fn insert(
         &self,
         truncated_token_key_id: TruncatedTokenKeyId,
         server: VoprfServer<Ristretto255>,
-    );
-    /// Returns a keypair with a given `truncated_token_key_id` from the key store.
+    ) -> Result<(), KeyStoreError>;
+    /// Returns a keypair with a given `truncated_token_key_id` from the key
+    /// store, or `None` if the key store does not contain it.
     async fn get(
         &self,
         truncated_token_key_id: &TruncatedTokenKeyId,
-    ) -> Option<VoprfServer<Ristretto255>>;
+    ) -> Result<Option<VoprfServer<Ristretto255>>, KeyStoreError>;
+    /// Removes the keypair with a given `truncated_token_key_id` from the key
+    /// store. Returns `true` if the key store contained it.
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError>;
+    /// Returns the truncated token key IDs of all keypairs in the key store.
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError>;
+    /// Returns `true` if the key store contains a keypair with a given
+    /// `truncated_token_key_id`.
+    async fn contains(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(self.get(truncated_token_key_id).await?.is_some())
+    }
+    /// Loads the key material ahead of the first request, e.g. from a
+    /// database or a key management service. The default does nothing.
+    async fn preload(&self) {}
+    /// Records the validity period of the keypair with a given
+    /// `truncated_token_key_id`, possibly before the keypair is inserted. The
+    /// default fails, for key stores that cannot record validity periods.
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        let _ = (truncated_token_key_id, validity);
+        Err(KeyStoreError::Backend(
+            "key validity periods are not supported".to_string(),
+        ))
+    }
+    /// Returns the validity period of the keypair with a given
+    /// `truncated_token_key_id`. Keypairs without a recorded validity period,
+    /// and all keypairs of the default, are valid without bounds.
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        let _ = truncated_token_key_id;
+        Ok(KeyValidity::default())
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<S: BatchedKeyStore + ?Sized> BatchedKeyStore for Box<S> {
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        server: VoprfServer<Ristretto255>,
+    ) -> Result<(), KeyStoreError> {
+        (**self).insert(truncated_token_key_id, server).await
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<VoprfServer<Ristretto255>>, KeyStoreError> {
+        (**self).get(truncated_token_key_id).await
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        (**self).remove(truncated_token_key_id).await
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        (**self).list_key_ids().await
+    }
+
+    async fn contains(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        (**self).contains(truncated_token_key_id).await
+    }
+
+    async fn preload(&self) {
+        (**self).preload().await
+    }
+
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        (**self)
+            .set_validity(truncated_token_key_id, validity)
+            .await
+    }
+
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        (**self).validity(truncated_token_key_id).await
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<S: BatchedKeyStore + ?Sized> BatchedKeyStore for Arc<S> {
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        server: VoprfServer<Ristretto255>,
+    ) -> Result<(), KeyStoreError> {
+        (**self).insert(truncated_token_key_id, server).await
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<VoprfServer<Ristretto255>>, KeyStoreError> {
+        (**self).get(truncated_token_key_id).await
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        (**self).remove(truncated_token_key_id).await
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        (**self).list_key_ids().await
+    }
+
+    async fn contains(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        (**self).contains(truncated_token_key_id).await
+    }
+
+    async fn preload(&self) {
+        (**self).preload().await
+    }
+
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        (**self)
+            .set_validity(truncated_token_key_id, validity)
+            .await
+    }
+
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        (**self).validity(truncated_token_key_id).await
+    }
 }
 
 /// Serializes a public key.
@@ -88,111 +343,807 @@ pub fn deserialize_public_key(slice: &[u8]) -> Result<PublicKey, Error> {
 
 /// Server-side component of the batched token issuance protocol.
 #[derive(Default, Debug)]
-pub struct Server {}
+pub struct Server {
+    code_points: CodePoints,
+    concurrency_limit: Option<ConcurrencyLimit>,
+    max_batch_size: Option<usize>,
+    redemption_errors: RedemptionErrors,
+    rng: ServerRng,
+    metrics: ServerMetrics,
+    double_spend_observer: Option<DoubleSpendObserver>,
+    clock: ServerClock,
+    redemption_grace_period: Duration,
+    #[cfg(feature = "profiling")]
+    issuance_observer: Option<IssuanceObserver>,
+}
 
 impl Server {
     /// Create a new server. The new server does not contain any key material.
     #[must_use]
     pub const fn new() -> Self {
-        Self {}
+        Self::with_code_points(CodePoints::Draft)
+    }
+
+    /// Create a new server that accepts the given token type code points. The
+    /// new server does not contain any key material.
+    #[must_use]
+    pub const fn with_code_points(code_points: CodePoints) -> Self {
+        Self {
+            code_points,
+            concurrency_limit: None,
+            max_batch_size: None,
+            redemption_errors: RedemptionErrors::Detailed,
+            rng: ServerRng::os(),
+            metrics: ServerMetrics::none(),
+            double_spend_observer: None,
+            clock: ServerClock::system(),
+            redemption_grace_period: Duration::ZERO,
+            #[cfg(feature = "profiling")]
+            issuance_observer: None,
+        }
+    }
+
+    /// Creates a new server from a configuration.
+    ///
+    /// # Errors
+    /// Returns an error if the configuration is invalid or does not accept
+    /// the batched Ristretto255 code point it emits.
+    pub fn from_config(config: &ServerConfig) -> Result<Self, ConfigError> {
+        let code_points = config.code_points();
+        config.validate_for(code_points.emit(TokenType::BatchedTokenRistretto255))?;
+        let mut server =
+            Self::with_code_points(code_points).with_redemption_errors(config.redemption_errors());
+        if let Some(max_batch_size) = config.max_batch_size() {
+            server = server.with_max_batch_size(max_batch_size);
+        }
+        if let Some(max_in_flight_per_key) = config.max_in_flight_per_key() {
+            server = server.with_concurrency_limit(ConcurrencyLimit::new(max_in_flight_per_key));
+        }
+        Ok(server)
+    }
+
+    /// Returns the token type code points the server accepts.
+    #[must_use]
+    pub const fn code_points(&self) -> CodePoints {
+        self.code_points
+    }
+
+    /// Limits the number of concurrent evaluations per key. Requests for a
+    /// key that is at its limit fail with
+    /// [`IssueTokenResponseError::TooManyRequests`].
+    #[must_use]
+    pub fn with_concurrency_limit(mut self, concurrency_limit: ConcurrencyLimit) -> Self {
+        self.concurrency_limit = Some(concurrency_limit);
+        self
+    }
+
+    /// Limits the number of tokens issued in one batch. Larger requests fail
+    /// with [`IssueTokenResponseError::BatchTooLarge`].
+    #[must_use]
+    pub const fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = Some(max_batch_size);
+        self
+    }
+
+    /// Sets how much detail redemption errors reveal.
+    #[must_use]
+    pub const fn with_redemption_errors(mut self, redemption_errors: RedemptionErrors) -> Self {
+        self.redemption_errors = redemption_errors;
+        self
+    }
+
+    /// Draws key seeds and proof randomness from `rng` instead of the OS
+    /// random number generator, e.g. to reproduce test vectors.
+    #[must_use]
+    pub fn with_rng<R: RngCore + CryptoRng + Send + 'static>(mut self, rng: R) -> Self {
+        self.rng = ServerRng::new(rng);
+        self
+    }
+
+    /// Reads the time that key validity periods are checked against from
+    /// `clock` instead of the system clock.
+    #[must_use]
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = ServerClock::new(clock);
+        self
+    }
+
+    /// Keeps redeeming tokens for `redemption_grace_period` after the
+    /// not-after time of their key, so that tokens issued shortly before a
+    /// key expired can still be spent. Defaults to no grace period.
+    #[must_use]
+    pub const fn with_redemption_grace_period(mut self, redemption_grace_period: Duration) -> Self {
+        self.redemption_grace_period = redemption_grace_period;
+        self
+    }
+
+    /// Reports every issuance and redemption to `metrics`.
+    #[must_use]
+    pub fn with_metrics<M: Metrics + 'static>(mut self, metrics: M) -> Self {
+        self.metrics = ServerMetrics::new(metrics);
+        self
+    }
+
+    /// Reports every token that is rejected as double spent to
+    /// `double_spend_observer`.
+    #[must_use]
+    pub fn with_double_spend_observer(
+        mut self,
+        double_spend_observer: DoubleSpendObserver,
+    ) -> Self {
+        self.double_spend_observer = Some(double_spend_observer);
+        self
+    }
+
+    /// Reports the time spent in each stage of every successful issuance to
+    /// `issuance_observer`.
+    #[cfg(feature = "profiling")]
+    #[must_use]
+    pub fn with_issuance_observer(mut self, issuance_observer: IssuanceObserver) -> Self {
+        self.issuance_observer = Some(issuance_observer);
+        self
     }
 
     /// Creates a new keypair and inserts it into the key store.
     ///
     /// # Errors
     /// Returns an error if the seed is too long.
-    pub async fn create_keypair<BKS: BatchedKeyStore>(
+    pub async fn create_keypair<BKS: BatchedKeyStore + ?Sized>(
+        &self,
+        key_store: &BKS,
+    ) -> Result<PublicKey, CreateKeypairError> {
+        let seed = random_seed::<Ristretto255>(&mut self.rng.clone());
+        self.create_keypair_internal(key_store, &seed, &key_derivation_info(None), None)
+            .await
+    }
+
+    /// Creates a new keypair whose derivation info is bound to an auditable
+    /// key epoch and inserts it into the key store. The epoch can be
+    /// published in the issuer directory with
+    /// [`TokenKey::with_epoch`](crate::issuer_directory::TokenKey::with_epoch).
+    ///
+    /// # Errors
+    /// Returns an error if the seed is too long.
+    pub async fn create_keypair_for_epoch<BKS: BatchedKeyStore + ?Sized>(
+        &self,
+        key_store: &BKS,
+        epoch: KeyEpoch,
+    ) -> Result<PublicKey, CreateKeypairError> {
+        let seed = random_seed::<Ristretto255>(&mut self.rng.clone());
+        self.create_keypair_internal(key_store, &seed, &key_derivation_info(Some(epoch)), None)
+            .await
+    }
+
+    /// Creates a new keypair that is only used within `validity` and inserts
+    /// it into the key store. The validity period is recorded before the
+    /// keypair, so that the keypair is never used outside of it.
+    ///
+    /// # Errors
+    /// Returns an error if creating the keypair failed or the key store
+    /// cannot record validity periods.
+    pub async fn create_keypair_with_validity<BKS: BatchedKeyStore + ?Sized>(
         &self,
         key_store: &BKS,
+        validity: KeyValidity,
     ) -> Result<PublicKey, CreateKeypairError> {
-        let mut seed = GenericArray::<_, <Ristretto255 as Group>::ScalarLen>::default();
-        OsRng.fill_bytes(&mut seed);
-        self.create_keypair_internal(key_store, &seed, b"PrivacyPass")
+        let seed = random_seed::<Ristretto255>(&mut self.rng.clone());
+        self.create_keypair_internal(key_store, &seed, &key_derivation_info(None), Some(validity))
             .await
     }
 
+    /// Creates a new keypair and makes it the primary key, see
+    /// [`Server::set_primary_key`]. Tokens issued under the previous keys are
+    /// redeemed for `redemption_window`.
+    ///
+    /// # Errors
+    /// Returns an error if creating the keypair failed or the key store
+    /// cannot record validity periods.
+    pub async fn rotate_keypair<BKS: BatchedKeyStore + ?Sized>(
+        &self,
+        key_store: &BKS,
+        redemption_window: Duration,
+    ) -> Result<PublicKey, CreateKeypairError> {
+        let seed = random_seed::<Ristretto255>(&mut self.rng.clone());
+        // The validity period is recorded first, so that key stores that
+        // cannot record it fail before the keypair is inserted
+        let public_key = self
+            .create_keypair_internal(
+                key_store,
+                &seed,
+                &key_derivation_info(None),
+                Some(KeyValidity::default()),
+            )
+            .await?;
+        self.set_primary_key(
+            key_store,
+            truncate_token_key_id(&public_key_to_token_key_id(&public_key)),
+            redemption_window,
+        )
+        .await?;
+        Ok(public_key)
+    }
+
+    /// Makes the keypair with a given `truncated_token_key_id` the primary
+    /// key, which issues tokens from now on. The other keys that currently
+    /// issue tokens stop now, and tokens issued under them are redeemed for
+    /// `redemption_window`. Keys whose not-before time lies in the future
+    /// are left alone, so that a key can be staged for the next rotation.
+    /// Returns `false` if the key store does not contain the keypair.
+    ///
+    /// # Errors
+    /// Returns an error if the key store fails or cannot record validity
+    /// periods.
+    pub async fn set_primary_key<BKS: BatchedKeyStore + ?Sized>(
+        &self,
+        key_store: &BKS,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        redemption_window: Duration,
+    ) -> Result<bool, KeyStoreError> {
+        if !key_store.contains(&truncated_token_key_id).await? {
+            return Ok(false);
+        }
+        let now = self.clock.unix_time();
+        // The primary key issues before the other keys stop, so that there
+        // is no moment without a key to issue under
+        if !key_store
+            .validity(&truncated_token_key_id)
+            .await?
+            .allows_issuance(now)
+        {
+            key_store
+                .set_validity(truncated_token_key_id, KeyValidity::default())
+                .await?;
+        }
+        for other_key_id in key_store.list_key_ids().await? {
+            if other_key_id == truncated_token_key_id {
+                continue;
+            }
+            let validity = key_store.validity(&other_key_id).await?;
+            if validity.allows_issuance(now) {
+                let redeemable_until = validity
+                    .redeemable_until
+                    .unwrap_or_default()
+                    .max(now.saturating_add(redemption_window.as_secs()));
+                key_store
+                    .set_validity(
+                        other_key_id,
+                        KeyValidity::new(validity.not_before, Some(now))
+                            .with_redeemable_until(redeemable_until),
+                    )
+                    .await?;
+            }
+        }
+        Ok(true)
+    }
+
     /// Creates a new keypair and inserts it into the key store.
-    async fn create_keypair_internal<BKS: BatchedKeyStore>(
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(truncated_token_key_id = tracing::field::Empty),
+            err(level = "debug")
+        )
+    )]
+    async fn create_keypair_internal<BKS: BatchedKeyStore + ?Sized>(
         &self,
         key_store: &BKS,
-        seed: &[u8],
+        seed: &SecretVec<u8>,
         info: &[u8],
+        validity: Option<KeyValidity>,
     ) -> Result<PublicKey, CreateKeypairError> {
-        let server = VoprfServer::<Ristretto255>::new_from_seed(seed, info)
-            .map_err(|_| CreateKeypairError::SeedError)?;
+        let server = VoprfServer::<Ristretto255>::new_from_seed(seed.expose_secret(), info)
+            .map_err(VoprfError::from)?;
         let public_key = server.get_public_key();
         let truncated_token_key_id =
             truncate_token_key_id(&public_key_to_token_key_id(&server.get_public_key()));
-        key_store.insert(truncated_token_key_id, server).await;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("truncated_token_key_id", truncated_token_key_id);
+        if let Some(validity) = validity {
+            key_store
+                .set_validity(truncated_token_key_id, validity)
+                .await?;
+        }
+        key_store.insert(truncated_token_key_id, server).await?;
         Ok(public_key)
     }
 
+    /// Derives a keypair from `seed` and `info` and inserts it into the key
+    /// store. The same seed and info always yield the same keypair, so that
+    /// keys can be derived from a master secret held elsewhere or reproduced
+    /// across replicas.
+    ///
+    /// # Errors
+    /// Returns an error if the seed is too long or the key cannot be
+    /// derived.
+    pub async fn create_keypair_with_seed<BKS: BatchedKeyStore + ?Sized>(
+        &self,
+        key_store: &BKS,
+        seed: &SecretVec<u8>,
+        info: &[u8],
+    ) -> Result<PublicKey, CreateKeypairError> {
+        self.create_keypair_internal(key_store, seed, info, None)
+            .await
+    }
+
     /// Creates a new keypair with explicit parameters and inserts it into the
-    /// key store.
+    /// key store. The seed is wrapped in a [`SecretVec`] so that it cannot be
+    /// logged or serialized by accident.
     #[cfg(feature = "kat")]
-    pub async fn create_keypair_with_params<BKS: BatchedKeyStore>(
+    pub async fn create_keypair_with_params<BKS: BatchedKeyStore + ?Sized>(
         &self,
         key_store: &BKS,
-        seed: &[u8],
+        seed: &SecretVec<u8>,
         info: &[u8],
     ) -> Result<PublicKey, CreateKeypairError> {
-        self.create_keypair_internal(key_store, seed, info).await
+        self.create_keypair_internal(key_store, seed, info, None)
+            .await
+    }
+
+    /// Preloads the key store and checks that every key in
+    /// `truncated_token_key_ids` is present and can evaluate an input, so that
+    /// deployments can hold back traffic until issuance will succeed.
+    ///
+    /// # Errors
+    /// Returns an error for the first key that is not ready.
+    pub async fn ready<BKS: BatchedKeyStore + ?Sized>(
+        &self,
+        key_store: &BKS,
+        truncated_token_key_ids: &[TruncatedTokenKeyId],
+    ) -> Result<(), ReadinessError> {
+        key_store.preload().await;
+        for truncated_token_key_id in truncated_token_key_ids {
+            key_store
+                .get(truncated_token_key_id)
+                .await?
+                .ok_or(ReadinessError::KeyIdNotFound(*truncated_token_key_id))?
+                .evaluate(b"readiness")
+                .map_err(|_| ReadinessError::InvalidKey(*truncated_token_key_id))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the issuer directory entry of the key in the key store, or
+    /// `None` if the key is not present. `not_before` is the time from which
+    /// on the key is used, in seconds since the Unix epoch.
+    ///
+    /// # Errors
+    /// Returns an error if the key store fails.
+    pub async fn token_key<BKS: BatchedKeyStore + ?Sized>(
+        &self,
+        key_store: &BKS,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        not_before: Option<u64>,
+    ) -> Result<Option<TokenKey>, KeyStoreError> {
+        let Some(server) = key_store.get(&truncated_token_key_id).await? else {
+            return Ok(None);
+        };
+        Ok(Some(TokenKey::new(
+            self.code_points.emit(TokenType::BatchedTokenRistretto255),
+            &serialize_public_key(server.get_public_key()),
+            not_before,
+        )))
     }
 
     /// Issues a token response.
     ///
     /// # Errors
     /// Returns an error if the token request is invalid.
-    pub async fn issue_token_response<BKS: BatchedKeyStore>(
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                token_type = ?token_request.token_type,
+                truncated_token_key_id = token_request.truncated_token_key_id,
+                batch_size = token_request.blinded_elements.len(),
+            ),
+            err(level = "debug")
+        )
+    )]
+    pub async fn issue_token_response<BKS: BatchedKeyStore + ?Sized>(
         &self,
         key_store: &BKS,
         token_request: TokenRequest,
     ) -> Result<TokenResponse, IssueTokenResponseError> {
-        if token_request.token_type != TokenType::BatchedTokenRistretto255 {
-            return Err(IssueTokenResponseError::InvalidTokenType);
-        }
+        let token_type = token_request.token_type;
+        let truncated_token_key_id = token_request.truncated_token_key_id;
+        let batch_size = token_request.blinded_elements.len();
+        let result = self
+            .issue_token_response_inner(key_store, token_request)
+            .await;
+        self.metrics
+            .issuance(token_type, truncated_token_key_id, batch_size, &result);
+        result
+    }
+
+    async fn issue_token_response_inner<BKS: BatchedKeyStore + ?Sized>(
+        &self,
+        key_store: &BKS,
+        token_request: TokenRequest,
+    ) -> Result<TokenResponse, IssueTokenResponseError> {
+        self.check_token_request(&token_request)?;
+        let _permit = self.try_acquire_permit(token_request.truncated_token_key_id)?;
+        let mut profiler = IssuanceProfiler::start();
         let server = key_store
             .get(&token_request.truncated_token_key_id)
-            .await
+            .await?
             .ok_or(IssueTokenResponseError::KeyIdNotFound)?;
+        if !key_store
+            .validity(&token_request.truncated_token_key_id)
+            .await?
+            .allows_issuance(self.clock.unix_time())
+        {
+            return Err(IssueTokenResponseError::KeyNotValid);
+        }
+        profiler.record(IssuanceStage::KeyFetch);
+        let token_response =
+            Self::evaluate_token_request(self.rng.clone(), &server, &token_request, &mut profiler)?;
+        #[cfg(feature = "profiling")]
+        profiler.finish(self.issuance_observer.as_ref());
+        Ok(token_response)
+    }
+
+    /// Issues a token response with a key the caller manages itself, e.g. in
+    /// a hardware security device or a static configuration, without going
+    /// through a key store.
+    ///
+    /// # Errors
+    /// Returns an error if the token request is invalid or was not made for
+    /// the key of `server`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                token_type = ?token_request.token_type,
+                truncated_token_key_id = token_request.truncated_token_key_id,
+                batch_size = token_request.blinded_elements.len(),
+            ),
+            err(level = "debug")
+        )
+    )]
+    pub fn issue_token_response_with_key(
+        &self,
+        server: &VoprfServer<Ristretto255>,
+        token_request: TokenRequest,
+    ) -> Result<TokenResponse, IssueTokenResponseError> {
+        let token_type = token_request.token_type;
+        let truncated_token_key_id = token_request.truncated_token_key_id;
+        let batch_size = token_request.blinded_elements.len();
+        let result = self.issue_token_response_with_key_inner(server, token_request);
+        self.metrics
+            .issuance(token_type, truncated_token_key_id, batch_size, &result);
+        result
+    }
+
+    fn issue_token_response_with_key_inner(
+        &self,
+        server: &VoprfServer<Ristretto255>,
+        token_request: TokenRequest,
+    ) -> Result<TokenResponse, IssueTokenResponseError> {
+        self.check_token_request(&token_request)?;
+        if truncate_token_key_id(&public_key_to_token_key_id(&server.get_public_key()))
+            != token_request.truncated_token_key_id
+        {
+            return Err(IssueTokenResponseError::KeyIdNotFound);
+        }
+        let _permit = self.try_acquire_permit(token_request.truncated_token_key_id)?;
+        let mut profiler = IssuanceProfiler::start();
+        let token_response =
+            Self::evaluate_token_request(self.rng.clone(), server, &token_request, &mut profiler)?;
+        #[cfg(feature = "profiling")]
+        profiler.finish(self.issuance_observer.as_ref());
+        Ok(token_response)
+    }
 
-        let mut blinded_elements = Vec::new();
-        for element in token_request.blinded_elements.iter() {
-            let blinded_element =
-                BlindedElement::<Ristretto255>::deserialize(&element.blinded_element)
-                    .map_err(|_| IssueTokenResponseError::InvalidTokenRequest)?;
-            blinded_elements.push(blinded_element);
-        }
-
-        let prepared_elements = server
-            .batch_blind_evaluate_prepare(blinded_elements.iter())
-            .collect::<Vec<_>>();
-        let VoprfServerBatchEvaluateFinishResult { messages, proof } = server
-            .batch_blind_evaluate_finish(&mut OsRng, blinded_elements.iter(), &prepared_elements)
-            .map_err(|_| IssueTokenResponseError::InvalidTokenRequest)?;
-        let evaluated_elements = messages
-            .map(|m| EvaluatedElement {
-                evaluated_element: m.serialize().into(),
+    /// Issues a token response with a [`BlindEvaluator`], e.g. one that keeps
+    /// the private key in a KMS or HSM, without going through a key store.
+    ///
+    /// # Errors
+    /// Returns an error if the token request is invalid, was not made for
+    /// the key of `evaluator` or the evaluation fails.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                token_type = ?token_request.token_type,
+                truncated_token_key_id = token_request.truncated_token_key_id,
+                batch_size = token_request.blinded_elements.len(),
+            ),
+            err(level = "debug")
+        )
+    )]
+    pub async fn issue_token_response_with_evaluator<E: BlindEvaluator + ?Sized>(
+        &self,
+        evaluator: &E,
+        token_request: TokenRequest,
+    ) -> Result<TokenResponse, IssueTokenResponseError> {
+        let token_type = token_request.token_type;
+        let truncated_token_key_id = token_request.truncated_token_key_id;
+        let batch_size = token_request.blinded_elements.len();
+        let result = self
+            .issue_token_response_with_evaluator_inner(evaluator, token_request)
+            .await;
+        self.metrics
+            .issuance(token_type, truncated_token_key_id, batch_size, &result);
+        result
+    }
+
+    async fn issue_token_response_with_evaluator_inner<E: BlindEvaluator + ?Sized>(
+        &self,
+        evaluator: &E,
+        token_request: TokenRequest,
+    ) -> Result<TokenResponse, IssueTokenResponseError> {
+        self.check_token_request(&token_request)?;
+        if truncate_token_key_id(&evaluator.token_key_id()) != token_request.truncated_token_key_id
+        {
+            return Err(IssueTokenResponseError::KeyIdNotFound);
+        }
+        let _permit = self.try_acquire_permit(token_request.truncated_token_key_id)?;
+        let mut profiler = IssuanceProfiler::start();
+        let blinded_elements = Self::blinded_elements(&token_request);
+        let evaluation = evaluator
+            .blind_evaluate(&mut self.rng.clone(), &blinded_elements)
+            .await?;
+        let token_response = Self::token_response(evaluation, blinded_elements.len())?;
+        profiler.record(IssuanceStage::Serialize);
+        #[cfg(feature = "profiling")]
+        profiler.finish(self.issuance_observer.as_ref());
+        Ok(token_response)
+    }
+
+    fn check_token_request(
+        &self,
+        token_request: &TokenRequest,
+    ) -> Result<(), IssueTokenResponseError> {
+        if !self.code_points.accepts(
+            TokenType::BatchedTokenRistretto255,
+            token_request.token_type,
+        ) {
+            return Err(IssueTokenResponseError::InvalidTokenType);
+        }
+        if token_request.blinded_elements.is_empty() {
+            return Err(IssueTokenResponseError::EmptyBatch);
+        }
+        if let Some(max_batch_size) = self.max_batch_size {
+            if token_request.blinded_elements.len() > max_batch_size {
+                return Err(IssueTokenResponseError::BatchTooLarge(
+                    token_request.blinded_elements.len(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn try_acquire_permit(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+    ) -> Result<Option<ConcurrencyPermit>, IssueTokenResponseError> {
+        self.concurrency_limit
+            .as_ref()
+            .map(|limit| {
+                limit
+                    .try_acquire(truncated_token_key_id)
+                    .ok_or(IssueTokenResponseError::TooManyRequests)
             })
-            .collect();
+            .transpose()
+    }
+
+    fn evaluate_token_request(
+        mut rng: ServerRng,
+        server: &VoprfServer<Ristretto255>,
+        token_request: &TokenRequest,
+        profiler: &mut IssuanceProfiler,
+    ) -> Result<TokenResponse, IssueTokenResponseError> {
+        let blinded_elements = Self::blinded_elements(token_request);
+        let evaluation =
+            blind_evaluate_ristretto255(server, &mut rng, &blinded_elements, profiler)?;
+        let token_response = Self::token_response(evaluation, blinded_elements.len())?;
+        profiler.record(IssuanceStage::Serialize);
+        Ok(token_response)
+    }
+
+    fn blinded_elements(token_request: &TokenRequest) -> Vec<&[u8]> {
+        token_request
+            .blinded_elements
+            .iter()
+            .map(|element| element.blinded_element.as_slice())
+            .collect()
+    }
 
+    fn token_response(
+        evaluation: BlindEvaluation,
+        batch_size: usize,
+    ) -> Result<TokenResponse, BlindEvaluatorError> {
+        if evaluation.evaluated_elements.len() != batch_size || evaluation.proof.len() != NS + NS {
+            return Err(BlindEvaluatorError::InvalidEvaluation);
+        }
+        let evaluated_elements = evaluation
+            .evaluated_elements
+            .into_iter()
+            .map(|evaluated_element| {
+                GenericArray::from_exact_iter(evaluated_element)
+                    .map(|evaluated_element| EvaluatedElement { evaluated_element })
+                    .ok_or(BlindEvaluatorError::InvalidEvaluation)
+            })
+            .collect::<Result<_, _>>()?;
         Ok(TokenResponse {
             evaluated_elements,
-            evaluated_proof: proof.serialize().into(),
+            evaluated_proof: evaluation.proof,
         })
     }
 
+    /// Issues a token response as a stream of parts, so that the evaluated
+    /// elements can be written to a streaming response body one by one, with
+    /// the proof at the end. The batch is evaluated once before the first
+    /// part is produced and the proof is computed when the stream is polled
+    /// for it, so streaming costs no more than `issue_token_response`. Errors
+    /// in the token request are returned before the first part is produced.
+    ///
+    /// # Errors
+    /// Returns an error if the token request is invalid. The stream yields an
+    /// error instead of the proof if the proof cannot be generated.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                token_type = ?token_request.token_type,
+                truncated_token_key_id = token_request.truncated_token_key_id,
+                batch_size = token_request.blinded_elements.len(),
+            ),
+            err(level = "debug")
+        )
+    )]
+    pub async fn issue_token_response_stream<BKS: BatchedKeyStore + ?Sized>(
+        &self,
+        key_store: &BKS,
+        token_request: TokenRequest,
+    ) -> Result<
+        impl Stream<Item = Result<TokenResponsePart, IssueTokenResponseError>>,
+        IssueTokenResponseError,
+    > {
+        let token_type = token_request.token_type;
+        let truncated_token_key_id = token_request.truncated_token_key_id;
+        let batch_size = token_request.blinded_elements.len();
+        let result = self
+            .issue_token_response_stream_inner(key_store, token_request)
+            .await;
+        self.metrics
+            .issuance(token_type, truncated_token_key_id, batch_size, &result);
+        result
+    }
+
+    async fn issue_token_response_stream_inner<BKS: BatchedKeyStore + ?Sized>(
+        &self,
+        key_store: &BKS,
+        token_request: TokenRequest,
+    ) -> Result<
+        impl Stream<Item = Result<TokenResponsePart, IssueTokenResponseError>>,
+        IssueTokenResponseError,
+    > {
+        self.check_token_request(&token_request)?;
+        let permit = self.try_acquire_permit(token_request.truncated_token_key_id)?;
+        let server = key_store
+            .get(&token_request.truncated_token_key_id)
+            .await?
+            .ok_or(IssueTokenResponseError::KeyIdNotFound)?;
+        if !key_store
+            .validity(&token_request.truncated_token_key_id)
+            .await?
+            .allows_issuance(self.clock.unix_time())
+        {
+            return Err(IssueTokenResponseError::KeyNotValid);
+        }
+        let evaluation = StreamedEvaluationRistretto255::new(
+            server,
+            self.rng.clone(),
+            &Self::blinded_elements(&token_request),
+        )?;
+        Ok(stream::iter(evaluation.map(move |part| {
+            // The permit is released once the stream is dropped
+            let _permit = &permit;
+            part.map_err(IssueTokenResponseError::from)
+        })))
+    }
+
     /// Redeems a token.
     ///
     /// # Errors
     /// Returns an error if the token is invalid.
-    pub async fn redeem_token<BKS: BatchedKeyStore, NS: NonceStore>(
+    pub async fn redeem_token<BKS: BatchedKeyStore + ?Sized, NS: NonceStore + ?Sized>(
+        &self,
+        key_store: &BKS,
+        nonce_store: &NS,
+        token: BatchedToken,
+    ) -> Result<(), RedeemTokenError> {
+        self.redeem_token_with_policy(key_store, nonce_store, token, &AcceptAll, &())
+            .await
+    }
+
+    /// Redeems a token if `policy` accepts it. The policy is consulted with
+    /// `metadata` after the token was verified and before its nonce is
+    /// recorded, so a rejected token is not spent.
+    ///
+    /// # Errors
+    /// Returns an error if the token is invalid or the policy rejects it.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                token_type = ?token.token_type(),
+                truncated_token_key_id = truncate_token_key_id(token.token_key_id()),
+            ),
+            err(level = "debug")
+        )
+    )]
+    pub async fn redeem_token_with_policy<
+        BKS: BatchedKeyStore + ?Sized,
+        NS: NonceStore + ?Sized,
+        P: RedemptionPolicy<M> + ?Sized,
+        M: Sync + ?Sized,
+    >(
         &self,
         key_store: &BKS,
         nonce_store: &NS,
         token: BatchedToken,
+        policy: &P,
+        metadata: &M,
     ) -> Result<(), RedeemTokenError> {
-        if token.token_type() != TokenType::BatchedTokenRistretto255 {
+        let token_type = token.token_type();
+        let token_key_id = *token.token_key_id();
+        let challenge_digest = *token.challenge_digest();
+        let result = self
+            .redeem_token_with_policy_inner(key_store, nonce_store, token, policy, metadata)
+            .await;
+        self.metrics.redemption(
+            token_type,
+            truncate_token_key_id(&token_key_id),
+            redemption_outcome(&result),
+        );
+        if let (Err(RedeemTokenError::DoubleSpending), Some(double_spend_observer)) =
+            (&result, &self.double_spend_observer)
+        {
+            double_spend_observer.observe(&DoubleSpendEvent {
+                token_type,
+                token_key_id,
+                challenge_digest,
+            });
+        }
+        result
+    }
+
+    async fn redeem_token_with_policy_inner<
+        BKS: BatchedKeyStore + ?Sized,
+        NS: NonceStore + ?Sized,
+        P: RedemptionPolicy<M> + ?Sized,
+        M: Sync + ?Sized,
+    >(
+        &self,
+        key_store: &BKS,
+        nonce_store: &NS,
+        token: BatchedToken,
+        policy: &P,
+        metadata: &M,
+    ) -> Result<(), RedeemTokenError> {
+        if self.redemption_errors == RedemptionErrors::Uniform {
+            return self
+                .redeem_token_uniform(key_store, nonce_store, token, policy, metadata)
+                .await;
+        }
+        if !self
+            .code_points
+            .accepts(TokenType::BatchedTokenRistretto255, token.token_type())
+        {
             return Err(RedeemTokenError::InvalidToken);
         }
         if token.authenticator().len() != (NK) {
@@ -201,41 +1152,253 @@ impl Server {
         if nonce_store.exists(&token.nonce()).await {
             return Err(RedeemTokenError::DoubleSpending);
         }
-        let token_input = TokenInput {
-            token_type: token.token_type(),
-            nonce: token.nonce(),
-            challenge_digest: *token.challenge_digest(),
-            token_key_id: *token.token_key_id(),
-        };
+        let token_input = TokenInput::new(
+            token.token_type(),
+            token.nonce(),
+            *token.challenge_digest(),
+            *token.token_key_id(),
+        );
         let server = key_store
             .get(&truncate_token_key_id(token.token_key_id()))
-            .await
+            .await?
             .ok_or(RedeemTokenError::KeyIdNotFound)?;
+        if !self
+            .allows_redemption(key_store, token.token_key_id())
+            .await?
+        {
+            return Err(RedeemTokenError::KeyExpired);
+        }
         let token_authenticator = server
-            .evaluate(&token_input.serialize())
-            .map_err(|_| RedeemTokenError::InvalidToken)?
-            .to_vec();
-        if token.authenticator() == token_authenticator {
-            nonce_store.insert(token.nonce()).await;
+            .evaluate(token_input.to_bytes().as_slice())
+            .map_err(|_| RedeemTokenError::InvalidToken)?;
+        let valid: bool = token_authenticator
+            .as_slice()
+            .ct_eq(token.authenticator())
+            .into();
+        if valid {
+            check_policy(
+                policy,
+                &token,
+                truncate_token_key_id(token.token_key_id()),
+                metadata,
+            )
+            .await?;
+            if !nonce_store.try_insert(token.nonce()).await {
+                return Err(RedeemTokenError::DoubleSpending);
+            }
+            Ok(())
+        } else {
+            Err(RedeemTokenError::InvalidToken)
+        }
+    }
+
+    /// Verifies a token with a key the caller manages itself, without going
+    /// through a key store. The nonce is neither checked nor recorded, so the
+    /// caller is responsible for detecting double spending.
+    ///
+    /// # Errors
+    /// Returns an error if the token is invalid or was not issued under the
+    /// key of `server`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                token_type = ?token.token_type(),
+                truncated_token_key_id = truncate_token_key_id(token.token_key_id()),
+            ),
+            err(level = "debug")
+        )
+    )]
+    pub fn redeem_token_with_key(
+        &self,
+        server: &VoprfServer<Ristretto255>,
+        token: BatchedToken,
+    ) -> Result<(), RedeemTokenError> {
+        let token_type = token.token_type();
+        let truncated_token_key_id = truncate_token_key_id(token.token_key_id());
+        let result = self.redeem_token_with_key_inner(server, token);
+        self.metrics.redemption(
+            token_type,
+            truncated_token_key_id,
+            redemption_outcome(&result),
+        );
+        result
+    }
+
+    fn redeem_token_with_key_inner(
+        &self,
+        server: &VoprfServer<Ristretto255>,
+        token: BatchedToken,
+    ) -> Result<(), RedeemTokenError> {
+        let well_formed = self
+            .code_points
+            .accepts(TokenType::BatchedTokenRistretto255, token.token_type())
+            && token.authenticator().len() == NK;
+        if !well_formed {
+            return Err(RedeemTokenError::InvalidToken);
+        }
+        if public_key_to_token_key_id(&server.get_public_key()) != *token.token_key_id() {
+            return Err(match self.redemption_errors {
+                RedemptionErrors::Detailed => RedeemTokenError::KeyIdNotFound,
+                RedemptionErrors::Uniform => RedeemTokenError::InvalidToken,
+            });
+        }
+        let token_input = TokenInput::new(
+            token.token_type(),
+            token.nonce(),
+            *token.challenge_digest(),
+            *token.token_key_id(),
+        );
+        let valid: bool = match server.evaluate(token_input.to_bytes().as_slice()) {
+            Ok(token_authenticator) => token_authenticator
+                .as_slice()
+                .ct_eq(token.authenticator())
+                .into(),
+            Err(_) => false,
+        };
+        if valid {
             Ok(())
         } else {
             Err(RedeemTokenError::InvalidToken)
         }
     }
 
+    /// Redeems a token without revealing the cause of a failure. Unknown key
+    /// IDs and malformed tokens go through the same nonce and key lookups and
+    /// an evaluation with a throw-away key, the authenticator is compared in
+    /// constant time, and all of them fail with
+    /// [`RedeemTokenError::InvalidToken`].
+    async fn redeem_token_uniform<
+        BKS: BatchedKeyStore + ?Sized,
+        NS: NonceStore + ?Sized,
+        P: RedemptionPolicy<M> + ?Sized,
+        M: Sync + ?Sized,
+    >(
+        &self,
+        key_store: &BKS,
+        nonce_store: &NS,
+        token: BatchedToken,
+        policy: &P,
+        metadata: &M,
+    ) -> Result<(), RedeemTokenError> {
+        // Derived ahead of the key lookup, so that the first unknown key ID
+        // does not pay for it
+        let uniform_server = uniform_server_ristretto255();
+        let well_formed = self
+            .code_points
+            .accepts(TokenType::BatchedTokenRistretto255, token.token_type())
+            && token.authenticator().len() == NK;
+        let spent = nonce_store.exists(&token.nonce()).await;
+        let token_input = TokenInput::new(
+            token.token_type(),
+            token.nonce(),
+            *token.challenge_digest(),
+            *token.token_key_id(),
+        );
+
+        let server = key_store
+            .get(&truncate_token_key_id(token.token_key_id()))
+            .await?;
+        let known = server.is_some();
+        let current = self
+            .allows_redemption(key_store, token.token_key_id())
+            .await?;
+        let server = match &server {
+            Some(server) => server,
+            None => uniform_server.ok_or(RedeemTokenError::InvalidToken)?,
+        };
+        let valid = match server.evaluate(token_input.to_bytes().as_slice()) {
+            Ok(token_authenticator) => token_authenticator
+                .as_slice()
+                .ct_eq(token.authenticator())
+                .into(),
+            Err(_) => false,
+        };
+
+        if !(well_formed && known && current && valid) {
+            return Err(RedeemTokenError::InvalidToken);
+        }
+        if spent {
+            return Err(RedeemTokenError::DoubleSpending);
+        }
+        check_policy(
+            policy,
+            &token,
+            truncate_token_key_id(token.token_key_id()),
+            metadata,
+        )
+        .await?;
+        if !nonce_store.try_insert(token.nonce()).await {
+            return Err(RedeemTokenError::DoubleSpending);
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if tokens issued under the key may be redeemed now.
+    async fn allows_redemption<BKS: BatchedKeyStore + ?Sized>(
+        &self,
+        key_store: &BKS,
+        token_key_id: &TokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(key_store
+            .validity(&truncate_token_key_id(token_key_id))
+            .await?
+            .allows_redemption(
+                self.clock.unix_time(),
+                self.redemption_grace_period.as_secs(),
+            ))
+    }
+
+    /// Exports the raw private key scalar of a key in the key store.
+    ///
+    /// # Errors
+    /// Returns an error if the key is not present or the key store fails.
+    #[cfg(feature = "pem")]
+    pub async fn export_private_key<BKS: BatchedKeyStore + ?Sized>(
+        &self,
+        key_store: &BKS,
+        truncated_token_key_id: TruncatedTokenKeyId,
+    ) -> Result<SecretVec<u8>, PrivateKeyError> {
+        let server = key_store
+            .get(&truncated_token_key_id)
+            .await?
+            .ok_or(PrivateKeyError::KeyIdNotFound)?;
+        ristretto255_to_scalar(&server)
+    }
+
+    /// Imports a raw private key scalar into the key store.
+    ///
+    /// # Errors
+    /// Returns an error if the scalar is invalid or the key store fails.
+    #[cfg(feature = "pem")]
+    pub async fn import_private_key<BKS: BatchedKeyStore + ?Sized>(
+        &self,
+        key_store: &BKS,
+        private_key: &[u8],
+    ) -> Result<PublicKey, PrivateKeyError> {
+        let server = ristretto255_from_scalar(private_key)?;
+        let public_key = server.get_public_key();
+        let truncated_token_key_id =
+            truncate_token_key_id(&public_key_to_token_key_id(&public_key));
+        key_store.insert(truncated_token_key_id, server).await?;
+        Ok(public_key)
+    }
+
     /// Sets a keypair with a given `private_key` into the key store.
     #[cfg(feature = "kat")]
-    pub async fn set_key<BKS: BatchedKeyStore>(
+    pub async fn set_key<BKS: BatchedKeyStore + ?Sized>(
         &self,
         key_store: &BKS,
         private_key: &[u8],
     ) -> Result<PublicKey, CreateKeypairError> {
-        let server = VoprfServer::<Ristretto255>::new_with_key(private_key)
-            .map_err(|_| CreateKeypairError::SeedError)?;
+        let server =
+            VoprfServer::<Ristretto255>::new_with_key(private_key).map_err(VoprfError::from)?;
         let public_key = server.get_public_key();
         let truncated_token_key_id =
             truncate_token_key_id(&public_key_to_token_key_id(&server.get_public_key()));
-        key_store.insert(truncated_token_key_id, server).await;
+        key_store.insert(truncated_token_key_id, server).await?;
         Ok(public_key)
     }
 }
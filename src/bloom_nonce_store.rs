@@ -0,0 +1,177 @@
+//! # Bloom filter nonce store
+//!
+//! [`BloomNonceStore`] puts an in-memory Bloom filter in front of another
+//! [`NonceStore`], e.g. a Redis or SQL store. Servers check whether a nonce
+//! was redeemed before they verify a token, and most nonces were not. The
+//! filter answers these checks without a round trip to the backing store,
+//! which is only asked if the filter reports a possible hit.
+//!
+//! The filter only learns nonces that are inserted through the wrapper, so
+//! in a cluster, or after a restart, it reports nonces that were redeemed
+//! elsewhere as not redeemed. This does not re-enable double spending:
+//! servers record a redeemed nonce with [`NonceStore::try_insert`], which
+//! always goes to the backing store and fails for a nonce that is already
+//! there.
+
+use std::{
+    collections::hash_map::RandomState,
+    f64::consts::LN_2,
+    fmt,
+    hash::BuildHasher,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+
+use crate::{Nonce, NonceStore};
+
+/// Lowest false positive rate a filter is sized for.
+const MIN_FALSE_POSITIVE_RATE: f64 = 1e-9;
+/// Highest number of hash functions per nonce.
+const MAX_HASHES: u32 = 32;
+
+/// Bloom filter over nonces whose bits are set atomically, so that
+/// concurrent redemptions do not contend on a lock.
+struct BloomFilter {
+    bits: Box<[AtomicU64]>,
+    hashes: u32,
+    // Randomly keyed, so that clients cannot choose nonces that collide
+    hasher1: RandomState,
+    hasher2: RandomState,
+}
+
+impl BloomFilter {
+    fn new(expected_nonces: usize, false_positive_rate: f64) -> Self {
+        // NaN is not positive either
+        let false_positive_rate = if false_positive_rate > 0.0 {
+            false_positive_rate.clamp(MIN_FALSE_POSITIVE_RATE, 0.5)
+        } else {
+            MIN_FALSE_POSITIVE_RATE
+        };
+        let expected_nonces = expected_nonces.max(1) as f64;
+        let bits = (-expected_nonces * false_positive_rate.ln() / (LN_2 * LN_2)).ceil();
+        let words = ((bits / 64.0).ceil() as usize).max(1);
+        let hashes = ((words * 64) as f64 / expected_nonces * LN_2).round() as u32;
+        Self {
+            bits: (0..words).map(|_| AtomicU64::new(0)).collect(),
+            hashes: hashes.clamp(1, MAX_HASHES),
+            hasher1: RandomState::new(),
+            hasher2: RandomState::new(),
+        }
+    }
+
+    fn len_bits(&self) -> u64 {
+        self.bits.len() as u64 * 64
+    }
+
+    /// Returns the bit positions of the nonce, using double hashing.
+    fn positions(&self, nonce: &Nonce) -> impl Iterator<Item = u64> {
+        let len_bits = self.len_bits();
+        let hash1 = self.hasher1.hash_one(nonce);
+        // Odd, so that the positions do not repeat early
+        let hash2 = self.hasher2.hash_one(nonce) | 1;
+        (0..u64::from(self.hashes))
+            .map(move |i| hash1.wrapping_add(i.wrapping_mul(hash2)) % len_bits)
+    }
+
+    fn insert(&self, nonce: &Nonce) {
+        for position in self.positions(nonce) {
+            self.bits[(position / 64) as usize].fetch_or(1 << (position % 64), Ordering::Relaxed);
+        }
+    }
+
+    fn may_contain(&self, nonce: &Nonce) -> bool {
+        self.positions(nonce).all(|position| {
+            self.bits[(position / 64) as usize].load(Ordering::Relaxed) & (1 << (position % 64))
+                != 0
+        })
+    }
+}
+
+/// Nonce store that answers most lookups from an in-memory Bloom filter and
+/// forwards everything else to the nonce store it wraps.
+///
+/// The filter is sized for a number of nonces and a false positive rate.
+/// Beyond that number, more lookups fall through to the backing store. The
+/// filter never forgets a nonce, so nonces that expire in the backing store
+/// are looked up there until the filter is rebuilt with
+/// [`clear`](Self::clear).
+pub struct BloomNonceStore<NS> {
+    inner: NS,
+    filter: BloomFilter,
+}
+
+impl<NS: fmt::Debug> fmt::Debug for BloomNonceStore<NS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BloomNonceStore")
+            .field("inner", &self.inner)
+            .field("bits", &self.filter.len_bits())
+            .field("hashes", &self.filter.hashes)
+            .finish()
+    }
+}
+
+impl<NS> BloomNonceStore<NS> {
+    /// Wraps `inner` with a filter for `expected_nonces` nonces that reports
+    /// a nonce as possibly redeemed with a probability of about
+    /// `false_positive_rate` if it was not. The rate is clamped to
+    /// `[1e-9, 0.5]`.
+    #[must_use]
+    pub fn new(inner: NS, expected_nonces: usize, false_positive_rate: f64) -> Self {
+        Self {
+            inner,
+            filter: BloomFilter::new(expected_nonces, false_positive_rate),
+        }
+    }
+
+    /// Returns the wrapped nonce store.
+    #[must_use]
+    pub const fn inner(&self) -> &NS {
+        &self.inner
+    }
+
+    /// Returns the wrapped nonce store and drops the filter.
+    #[must_use]
+    pub fn into_inner(self) -> NS {
+        self.inner
+    }
+
+    /// Empties the filter, e.g. after the nonces of retired keys were
+    /// removed from the backing store. Until nonces are inserted again,
+    /// lookups report every nonce as not redeemed.
+    pub fn clear(&self) {
+        for word in self.filter.bits.iter() {
+            word.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<NS: NonceStore> NonceStore for BloomNonceStore<NS> {
+    async fn exists(&self, nonce: &Nonce) -> bool {
+        self.filter.may_contain(nonce) && self.inner.exists(nonce).await
+    }
+
+    async fn insert(&self, nonce: Nonce) {
+        self.filter.insert(&nonce);
+        self.inner.insert(nonce).await;
+    }
+
+    async fn try_insert(&self, nonce: Nonce) -> bool {
+        // The nonce is in the backing store either way
+        self.filter.insert(&nonce);
+        self.inner.try_insert(nonce).await
+    }
+
+    async fn insert_with_ttl(&self, nonce: Nonce, ttl: Duration) {
+        self.filter.insert(&nonce);
+        self.inner.insert_with_ttl(nonce, ttl).await;
+    }
+
+    async fn try_insert_with_ttl(&self, nonce: Nonce, ttl: Duration) -> bool {
+        self.filter.insert(&nonce);
+        self.inner.try_insert_with_ttl(nonce, ttl).await
+    }
+}
@@ -0,0 +1,160 @@
+//! # Clocks
+//!
+//! Time-dependent checks, such as not-before times in the issuer directory,
+//! key epochs and event timestamps, read the time from a [`Clock`].
+//! [`SystemClock`] is the default. [`TestClock`] is set and advanced
+//! manually, so that expiry and rotation can be tested deterministically,
+//! and embedded systems can implement [`Clock`] for their own time source.
+//! A [`SystemTime`] is a clock that is fixed at that time.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Source of the current time.
+pub trait Clock: Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> SystemTime;
+
+    /// Returns the current time in seconds since the Unix epoch, or 0 if the
+    /// clock is set before the Unix epoch.
+    fn unix_time(&self) -> u64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs())
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> SystemTime {
+        (**self).now()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> SystemTime {
+        (**self).now()
+    }
+}
+
+impl Clock for SystemTime {
+    fn now(&self) -> SystemTime {
+        *self
+    }
+}
+
+/// Clock that reads the system time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    // `SystemTime::now` panics in browsers, where `web-time` reads the time
+    // from JavaScript instead.
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    fn now(&self) -> SystemTime {
+        let since_epoch = web_time::SystemTime::now()
+            .duration_since(web_time::UNIX_EPOCH)
+            .unwrap_or_default();
+        UNIX_EPOCH + since_epoch
+    }
+}
+
+/// Clock of a server: the system clock, unless one was injected.
+#[derive(Clone, Default)]
+pub(crate) struct ServerClock {
+    injected: Option<Arc<dyn Clock>>,
+}
+
+impl fmt::Debug for ServerClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerClock")
+            .field("injected", &self.injected.is_some())
+            .finish()
+    }
+}
+
+impl ServerClock {
+    /// Returns a clock that reads the system time.
+    pub(crate) const fn system() -> Self {
+        Self { injected: None }
+    }
+
+    /// Returns a clock that reads the time from `clock`.
+    pub(crate) fn new<C: Clock + 'static>(clock: C) -> Self {
+        Self {
+            injected: Some(Arc::new(clock)),
+        }
+    }
+}
+
+impl Clock for ServerClock {
+    fn now(&self) -> SystemTime {
+        match &self.injected {
+            Some(clock) => clock.now(),
+            None => SystemClock.now(),
+        }
+    }
+}
+
+/// Clock that only moves when it is told to.
+pub struct TestClock {
+    now: Mutex<SystemTime>,
+}
+
+impl fmt::Debug for TestClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestClock")
+            .field("now", &self.now())
+            .finish()
+    }
+}
+
+impl TestClock {
+    /// Creates a clock that is set to `now`.
+    #[must_use]
+    pub const fn new(now: SystemTime) -> Self {
+        Self {
+            now: Mutex::new(now),
+        }
+    }
+
+    /// Creates a clock that is set to `secs` seconds after the Unix epoch.
+    #[must_use]
+    pub fn from_unix_time(secs: u64) -> Self {
+        Self::new(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    /// Sets the clock to `now`.
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) = now;
+    }
+
+    /// Moves the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) += duration;
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[test]
+fn test_clock() {
+    let clock = TestClock::from_unix_time(100);
+    assert_eq!(clock.unix_time(), 100);
+    clock.advance(Duration::from_secs(20));
+    assert_eq!(clock.unix_time(), 120);
+    clock.set(UNIX_EPOCH);
+    assert_eq!(Clock::unix_time(&&clock), 0);
+    assert_eq!((UNIX_EPOCH + Duration::from_secs(5)).unix_time(), 5);
+}
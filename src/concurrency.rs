@@ -0,0 +1,106 @@
+//! # Per-key concurrency limits
+//!
+//! Issuance servers can be configured with a [`ConcurrencyLimit`] that caps
+//! the number of in-flight evaluations per key. A key that is hammered by an
+//! attacker then only exhausts its own budget, and issuance for other keys or
+//! tenants sharing the process continues unaffected. Requests that exceed the
+//! limit fail immediately instead of queueing.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::TruncatedTokenKeyId;
+
+/// Limits the number of concurrent evaluations per key.
+///
+/// Keys are identified by their truncated token key ID, so keys whose IDs
+/// collide share a budget.
+#[derive(Debug)]
+pub struct ConcurrencyLimit {
+    max_in_flight_per_key: usize,
+    in_flight: Mutex<HashMap<TruncatedTokenKeyId, Arc<AtomicUsize>>>,
+}
+
+impl ConcurrencyLimit {
+    /// Creates a new limit that allows `max_in_flight_per_key` concurrent
+    /// evaluations per key.
+    #[must_use]
+    pub fn new(max_in_flight_per_key: usize) -> Self {
+        Self {
+            max_in_flight_per_key,
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the maximum number of concurrent evaluations per key.
+    #[must_use]
+    pub const fn max_in_flight_per_key(&self) -> usize {
+        self.max_in_flight_per_key
+    }
+
+    /// Returns the number of evaluations that can currently start for a key.
+    #[must_use]
+    pub fn available(&self, truncated_token_key_id: TruncatedTokenKeyId) -> usize {
+        self.max_in_flight_per_key
+            .saturating_sub(self.in_flight(truncated_token_key_id).load(Ordering::Acquire))
+    }
+
+    fn in_flight(&self, truncated_token_key_id: TruncatedTokenKeyId) -> Arc<AtomicUsize> {
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        in_flight
+            .entry(truncated_token_key_id)
+            .or_insert_with(|| Arc::new(AtomicUsize::new(0)))
+            .clone()
+    }
+
+    /// Reserves an evaluation slot for a key. The slot is released when the
+    /// returned permit is dropped. Returns `None` if the key is at its limit.
+    pub(crate) fn try_acquire(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+    ) -> Option<ConcurrencyPermit> {
+        let in_flight = self.in_flight(truncated_token_key_id);
+        in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (count < self.max_in_flight_per_key).then_some(count + 1)
+            })
+            .ok()?;
+        Some(ConcurrencyPermit { in_flight })
+    }
+}
+
+/// An evaluation slot reserved with [`ConcurrencyLimit::try_acquire`].
+/// Dropping the permit releases the slot.
+#[derive(Debug)]
+pub(crate) struct ConcurrencyPermit {
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[test]
+fn concurrency_limit() {
+    let limit = ConcurrencyLimit::new(2);
+    let first = limit.try_acquire(1).unwrap();
+    let _second = limit.try_acquire(1).unwrap();
+    assert!(limit.try_acquire(1).is_none());
+
+    // Other keys are not affected
+    assert!(limit.try_acquire(2).is_some());
+
+    drop(first);
+    assert_eq!(limit.available(1), 1);
+    assert!(limit.try_acquire(1).is_some());
+}
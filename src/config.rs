@@ -0,0 +1,298 @@
+//! # Server configuration
+//!
+//! A [`ServerConfig`] collects the deployment parameters of the servers in
+//! one place: the accepted token types and code points, batch and
+//! concurrency limits, how much detail redemption errors reveal, and how
+//! often keys are rotated. [`ServerConfig::validate`] rejects inconsistent
+//! values up front, and the `from_config` constructors of the servers apply
+//! a validated configuration.
+//!
+//! The defaults match the servers' `new()` constructors, except that all
+//! token types supported by this build are accepted.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use thiserror::Error;
+
+use crate::{clock::Clock, CodePoints, KeyEpoch, RedemptionErrors, TokenType};
+
+/// Errors that can occur when validating a configuration.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConfigError {
+    #[error("No token type is accepted")]
+    /// Error when the list of accepted token types is empty.
+    NoAcceptedTokenTypes,
+    #[error("Token type {0} is not accepted")]
+    /// Error when a server is configured for a token type the configuration
+    /// does not accept.
+    TokenTypeNotAccepted(TokenType),
+    #[error("The maximum batch size must be positive")]
+    /// Error when the maximum batch size is zero.
+    InvalidMaxBatchSize,
+    #[error("The concurrency limit must be positive")]
+    /// Error when the number of concurrent evaluations per key is zero.
+    InvalidConcurrencyLimit,
+    #[error("The rotation interval must be at least one second")]
+    /// Error when the key rotation interval is shorter than one second.
+    InvalidRotationInterval,
+    #[error("The grace period must be shorter than the rotation interval")]
+    /// Error when the grace period is not shorter than the rotation interval,
+    /// or is set without a rotation interval.
+    InvalidGracePeriod,
+}
+
+/// Deployment parameters of a server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerConfig {
+    accepted_token_types: Vec<TokenType>,
+    code_points: CodePoints,
+    max_batch_size: Option<usize>,
+    max_in_flight_per_key: Option<usize>,
+    redemption_errors: RedemptionErrors,
+    rotation_interval: Option<Duration>,
+    grace_period: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            accepted_token_types: TokenType::supported().collect(),
+            code_points: CodePoints::default(),
+            max_batch_size: None,
+            max_in_flight_per_key: None,
+            redemption_errors: RedemptionErrors::default(),
+            rotation_interval: None,
+            grace_period: Duration::ZERO,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Creates the default configuration.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the token types that are accepted.
+    #[must_use]
+    pub fn with_accepted_token_types(mut self, accepted_token_types: Vec<TokenType>) -> Self {
+        self.accepted_token_types = accepted_token_types;
+        self
+    }
+
+    /// Sets the token type code points that are emitted and accepted.
+    #[must_use]
+    pub const fn with_code_points(mut self, code_points: CodePoints) -> Self {
+        self.code_points = code_points;
+        self
+    }
+
+    /// Limits the number of tokens issued in one batch.
+    #[must_use]
+    pub const fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = Some(max_batch_size);
+        self
+    }
+
+    /// Limits the number of concurrent evaluations per key.
+    #[must_use]
+    pub const fn with_max_in_flight_per_key(mut self, max_in_flight_per_key: usize) -> Self {
+        self.max_in_flight_per_key = Some(max_in_flight_per_key);
+        self
+    }
+
+    /// Sets how much detail redemption errors reveal.
+    #[must_use]
+    pub const fn with_redemption_errors(mut self, redemption_errors: RedemptionErrors) -> Self {
+        self.redemption_errors = redemption_errors;
+        self
+    }
+
+    /// Sets the interval after which a new key epoch starts.
+    #[must_use]
+    pub const fn with_rotation_interval(mut self, rotation_interval: Duration) -> Self {
+        self.rotation_interval = Some(rotation_interval);
+        self
+    }
+
+    /// Sets how long keys of the previous epoch are still redeemed after a
+    /// rotation, so that tokens issued shortly before the rotation remain
+    /// valid.
+    #[must_use]
+    pub const fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Returns the accepted token types.
+    #[must_use]
+    pub fn accepted_token_types(&self) -> &[TokenType] {
+        &self.accepted_token_types
+    }
+
+    /// Returns the token type code points.
+    #[must_use]
+    pub const fn code_points(&self) -> CodePoints {
+        self.code_points
+    }
+
+    /// Returns the maximum batch size, if any.
+    #[must_use]
+    pub const fn max_batch_size(&self) -> Option<usize> {
+        self.max_batch_size
+    }
+
+    /// Returns the maximum number of concurrent evaluations per key, if any.
+    #[must_use]
+    pub const fn max_in_flight_per_key(&self) -> Option<usize> {
+        self.max_in_flight_per_key
+    }
+
+    /// Returns how much detail redemption errors reveal.
+    #[must_use]
+    pub const fn redemption_errors(&self) -> RedemptionErrors {
+        self.redemption_errors
+    }
+
+    /// Returns the key rotation interval, if keys are rotated.
+    #[must_use]
+    pub const fn rotation_interval(&self) -> Option<Duration> {
+        self.rotation_interval
+    }
+
+    /// Returns the grace period after a key rotation.
+    #[must_use]
+    pub const fn grace_period(&self) -> Duration {
+        self.grace_period
+    }
+
+    /// Returns `true` if the token type is accepted.
+    #[must_use]
+    pub fn accepts(&self, token_type: TokenType) -> bool {
+        self.accepted_token_types.contains(&token_type)
+    }
+
+    /// Checks that the configuration is consistent.
+    ///
+    /// # Errors
+    /// Returns an error for the first inconsistent parameter.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.accepted_token_types.is_empty() {
+            return Err(ConfigError::NoAcceptedTokenTypes);
+        }
+        if self.max_batch_size == Some(0) {
+            return Err(ConfigError::InvalidMaxBatchSize);
+        }
+        if self.max_in_flight_per_key == Some(0) {
+            return Err(ConfigError::InvalidConcurrencyLimit);
+        }
+        match self.rotation_interval {
+            Some(rotation_interval) if rotation_interval < Duration::from_secs(1) => {
+                Err(ConfigError::InvalidRotationInterval)
+            }
+            Some(rotation_interval) if self.grace_period >= rotation_interval => {
+                Err(ConfigError::InvalidGracePeriod)
+            }
+            None if !self.grace_period.is_zero() => Err(ConfigError::InvalidGracePeriod),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that the configuration is consistent and accepts `token_type`.
+    pub(crate) fn validate_for(&self, token_type: TokenType) -> Result<(), ConfigError> {
+        self.validate()?;
+        if !self.accepts(token_type) {
+            return Err(ConfigError::TokenTypeNotAccepted(token_type));
+        }
+        Ok(())
+    }
+
+    /// Returns the current key epoch of `clock`, or `None` if keys are not
+    /// rotated. Epochs are counted in rotation intervals since the Unix
+    /// epoch.
+    #[must_use]
+    pub fn key_epoch<C: Clock + ?Sized>(&self, clock: &C) -> Option<KeyEpoch> {
+        let rotation_interval = self.rotation_interval?.as_secs();
+        let elapsed = clock.now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+        elapsed.checked_div(rotation_interval)
+    }
+
+    /// Returns the key epochs whose keys are currently redeemed: the current
+    /// epoch, and the previous one during the grace period after a rotation.
+    #[must_use]
+    pub fn redemption_key_epochs<C: Clock + ?Sized>(&self, clock: &C) -> Vec<KeyEpoch> {
+        let at = clock.now();
+        let Some(key_epoch) = self.key_epoch(&at) else {
+            return Vec::new();
+        };
+        let epoch_start = self
+            .rotation_interval
+            .map(|rotation_interval| rotation_interval.as_secs() * key_epoch)
+            .unwrap_or_default();
+        let in_grace_period = at
+            .duration_since(UNIX_EPOCH + Duration::from_secs(epoch_start))
+            .is_ok_and(|elapsed| elapsed < self.grace_period);
+        match key_epoch.checked_sub(1) {
+            Some(previous) if in_grace_period => vec![key_epoch, previous],
+            _ => vec![key_epoch],
+        }
+    }
+}
+
+#[test]
+fn server_config_validation() {
+    assert_eq!(ServerConfig::new().validate(), Ok(()));
+    assert_eq!(
+        ServerConfig::new()
+            .with_accepted_token_types(Vec::new())
+            .validate(),
+        Err(ConfigError::NoAcceptedTokenTypes)
+    );
+    assert_eq!(
+        ServerConfig::new().with_max_batch_size(0).validate(),
+        Err(ConfigError::InvalidMaxBatchSize)
+    );
+    assert_eq!(
+        ServerConfig::new().with_max_in_flight_per_key(0).validate(),
+        Err(ConfigError::InvalidConcurrencyLimit)
+    );
+    assert_eq!(
+        ServerConfig::new()
+            .with_rotation_interval(Duration::from_millis(10))
+            .validate(),
+        Err(ConfigError::InvalidRotationInterval)
+    );
+    assert_eq!(
+        ServerConfig::new()
+            .with_grace_period(Duration::from_secs(60))
+            .validate(),
+        Err(ConfigError::InvalidGracePeriod)
+    );
+    assert_eq!(
+        ServerConfig::new()
+            .with_accepted_token_types(vec![TokenType::PublicToken])
+            .validate_for(TokenType::PrivateToken),
+        Err(ConfigError::TokenTypeNotAccepted(TokenType::PrivateToken))
+    );
+}
+
+#[test]
+fn server_config_key_epochs() {
+    let config = ServerConfig::new()
+        .with_rotation_interval(Duration::from_secs(3600))
+        .with_grace_period(Duration::from_secs(60));
+    assert_eq!(config.validate(), Ok(()));
+
+    let clock = crate::clock::TestClock::from_unix_time(7230);
+    assert_eq!(config.key_epoch(&clock), Some(2));
+    assert_eq!(config.redemption_key_epochs(&clock), vec![2, 1]);
+    clock.advance(Duration::from_secs(30));
+    assert_eq!(config.redemption_key_epochs(&clock), vec![2]);
+    assert_eq!(
+        config.redemption_key_epochs(&(UNIX_EPOCH + Duration::from_secs(30))),
+        vec![0]
+    );
+    assert!(ServerConfig::new().redemption_key_epochs(&clock).is_empty());
+}
@@ -0,0 +1,216 @@
+//! # Configuration files
+//!
+//! Loads a [`ServerConfig`] and the connection settings of the stores from a
+//! TOML file, with environment variables taking precedence over the file:
+//!
+//! ```toml
+//! accepted-token-types = [1, 2, 5]
+//! code-points = "final"
+//! max-batch-size = 100
+//! max-in-flight-per-key = 8
+//! redemption-errors = "uniform"
+//! # Seconds
+//! rotation-interval = 86400
+//! grace-period = 300
+//!
+//! [stores]
+//! key-store-url = "postgres://issuer@db/keys"
+//! nonce-store-url = "redis://cache/0"
+//! ```
+//!
+//! Every key can be overridden by an environment variable of the same name
+//! in upper snake case, prefixed with `PRIVACYPASS_`, e.g.
+//! `PRIVACYPASS_MAX_BATCH_SIZE=50` or `PRIVACYPASS_KEY_STORE_URL=...`.
+//! Accepted token types are given as a comma-separated list of decimal or
+//! `0x`-prefixed code points.
+
+use std::{fs, io, path::Path, time::Duration};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    config::{ConfigError, ServerConfig},
+    CodePoints, RedemptionErrors, TokenType,
+};
+
+/// Prefix of the environment variables that override the configuration file.
+pub const ENV_PREFIX: &str = "PRIVACYPASS_";
+
+/// Errors that can occur when loading a configuration.
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum ConfigFileError {
+    #[error(transparent)]
+    /// Error when the configuration file cannot be read.
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    /// Error when the configuration file is not valid TOML or contains
+    /// unknown keys.
+    Toml(#[from] toml::de::Error),
+    #[error("Invalid value for {0}")]
+    /// Error when a value cannot be parsed. Contains the key.
+    InvalidValue(&'static str),
+    #[error(transparent)]
+    /// Error when the loaded configuration is inconsistent.
+    Config(#[from] ConfigError),
+}
+
+/// Connection settings of the key and nonce stores. The settings are passed
+/// through to the store implementations unchanged.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct StoreSettings {
+    /// Connection URL of the key store.
+    pub key_store_url: Option<String>,
+    /// Connection URL of the nonce store.
+    pub nonce_store_url: Option<String>,
+}
+
+/// Configuration of an issuer deployment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IssuerConfig {
+    /// Server configuration.
+    pub server: ServerConfig,
+    /// Store connection settings.
+    pub stores: StoreSettings,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct FileConfig {
+    accepted_token_types: Option<Vec<u16>>,
+    code_points: Option<String>,
+    max_batch_size: Option<usize>,
+    max_in_flight_per_key: Option<usize>,
+    redemption_errors: Option<String>,
+    rotation_interval: Option<u64>,
+    grace_period: Option<u64>,
+    #[serde(default)]
+    stores: StoreSettings,
+}
+
+impl IssuerConfig {
+    /// Loads the configuration from a TOML file and applies the overrides of
+    /// the process environment.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read, a value is invalid, or
+    /// the resulting configuration is inconsistent.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigFileError> {
+        let toml = fs::read_to_string(path)?;
+        Self::from_toml_and_env(&toml, std::env::vars())
+    }
+
+    /// Parses the configuration from a TOML string and applies the overrides
+    /// of the given environment variables. Variables without the
+    /// [`ENV_PREFIX`] are ignored.
+    ///
+    /// # Errors
+    /// Returns an error if a value is invalid or the resulting configuration
+    /// is inconsistent.
+    pub fn from_toml_and_env(
+        toml: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, ConfigFileError> {
+        let mut file: FileConfig = toml::from_str(toml)?;
+        for (name, value) in vars {
+            if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+                file.set(key, value)?;
+            }
+        }
+        file.into_config()
+    }
+}
+
+impl FileConfig {
+    fn set(&mut self, key: &str, value: String) -> Result<(), ConfigFileError> {
+        match key {
+            "ACCEPTED_TOKEN_TYPES" => {
+                self.accepted_token_types = Some(
+                    value
+                        .split(',')
+                        .map(|code_point| parse_code_point(code_point.trim()))
+                        .collect::<Option<_>>()
+                        .ok_or(ConfigFileError::InvalidValue("accepted-token-types"))?,
+                );
+            }
+            "CODE_POINTS" => self.code_points = Some(value),
+            "MAX_BATCH_SIZE" => {
+                self.max_batch_size = Some(parse(&value, "max-batch-size")?);
+            }
+            "MAX_IN_FLIGHT_PER_KEY" => {
+                self.max_in_flight_per_key = Some(parse(&value, "max-in-flight-per-key")?);
+            }
+            "REDEMPTION_ERRORS" => self.redemption_errors = Some(value),
+            "ROTATION_INTERVAL" => {
+                self.rotation_interval = Some(parse(&value, "rotation-interval")?);
+            }
+            "GRACE_PERIOD" => self.grace_period = Some(parse(&value, "grace-period")?),
+            "KEY_STORE_URL" => self.stores.key_store_url = Some(value),
+            "NONCE_STORE_URL" => self.stores.nonce_store_url = Some(value),
+            // Unrelated variables may share the prefix
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn into_config(self) -> Result<IssuerConfig, ConfigFileError> {
+        let mut server = ServerConfig::new();
+        if let Some(accepted_token_types) = self.accepted_token_types {
+            server = server.with_accepted_token_types(
+                accepted_token_types
+                    .into_iter()
+                    .map(TokenType::try_from)
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| ConfigFileError::InvalidValue("accepted-token-types"))?,
+            );
+        }
+        if let Some(code_points) = self.code_points {
+            server = server.with_code_points(match code_points.as_str() {
+                "draft" => CodePoints::Draft,
+                "final" => CodePoints::Final,
+                "transitional" => CodePoints::Transitional,
+                _ => return Err(ConfigFileError::InvalidValue("code-points")),
+            });
+        }
+        if let Some(max_batch_size) = self.max_batch_size {
+            server = server.with_max_batch_size(max_batch_size);
+        }
+        if let Some(max_in_flight_per_key) = self.max_in_flight_per_key {
+            server = server.with_max_in_flight_per_key(max_in_flight_per_key);
+        }
+        if let Some(redemption_errors) = self.redemption_errors {
+            server = server.with_redemption_errors(match redemption_errors.as_str() {
+                "detailed" => RedemptionErrors::Detailed,
+                "uniform" => RedemptionErrors::Uniform,
+                _ => return Err(ConfigFileError::InvalidValue("redemption-errors")),
+            });
+        }
+        if let Some(rotation_interval) = self.rotation_interval {
+            server = server.with_rotation_interval(Duration::from_secs(rotation_interval));
+        }
+        if let Some(grace_period) = self.grace_period {
+            server = server.with_grace_period(Duration::from_secs(grace_period));
+        }
+        server.validate()?;
+        Ok(IssuerConfig {
+            server,
+            stores: self.stores,
+        })
+    }
+}
+
+fn parse<T: std::str::FromStr>(value: &str, key: &'static str) -> Result<T, ConfigFileError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| ConfigFileError::InvalidValue(key))
+}
+
+fn parse_code_point(code_point: &str) -> Option<u16> {
+    match code_point.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => code_point.parse().ok(),
+    }
+}
@@ -0,0 +1,299 @@
+//! # Multi-type dispatch
+//!
+//! A server facade that routes serialized token requests and tokens to a
+//! handler based on the token type code point in their first two bytes.
+//! Handlers are registered at runtime, which allows applications to add
+//! experimental or greased code points next to the ones implemented by this
+//! crate. Messages with an unknown code point are rejected with an error.
+//!
+//! Handlers can also be collected in a [`HandlerRegistry`] first, so that
+//! private extensions can register their token types without touching the
+//! code that sets up the server.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use thiserror::Error;
+
+use crate::{
+    arbitrary_batched_tokens::{BatchTokenRequest, BatchTokenResponse},
+    dynamic::DynServer,
+    KeyStoreError, MaybeSendSync,
+};
+
+/// Token type code point as it appears on the wire.
+pub type CodePoint = u16;
+
+/// Errors that can occur when dispatching a message to a handler.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DispatchError {
+    #[error("Message too short to contain a token type")]
+    /// The message is too short to contain a token type.
+    MissingTokenType,
+    #[error("Unsupported token type {0:#06x}")]
+    /// No handler is registered for the token type.
+    UnsupportedTokenType(CodePoint),
+    #[error("A handler is already registered for token type {0:#06x}")]
+    /// A handler is already registered for the token type.
+    AlreadyRegistered(CodePoint),
+    #[error("Key ID not found")]
+    /// The key ID is not found.
+    KeyIdNotFound,
+    #[error("Invalid TokenRequest")]
+    /// The token request is invalid.
+    InvalidTokenRequest,
+    #[error("The token has already been redeemed")]
+    /// The token has already been redeemed.
+    DoubleSpending,
+    #[error("The token is invalid")]
+    /// The token is invalid.
+    InvalidToken,
+    #[error(transparent)]
+    /// The key store failed.
+    KeyStore(KeyStoreError),
+}
+
+/// Handler for a single token type.
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+pub trait TokenTypeHandler: MaybeSendSync {
+    /// Issues a serialized token response for a serialized token request.
+    async fn issue_token_response(&self, token_request: &[u8]) -> Result<Vec<u8>, DispatchError>;
+    /// Redeems a serialized token.
+    async fn redeem_token(&self, token: &[u8]) -> Result<(), DispatchError>;
+    /// Redeems a serialized token and records its nonce with
+    /// [`NonceStore::try_insert_with_ttl`](crate::NonceStore::try_insert_with_ttl),
+    /// so that the nonce store may forget it after `nonce_ttl`. The default
+    /// ignores the TTL and calls [`redeem_token`](Self::redeem_token).
+    async fn redeem_token_with_ttl(
+        &self,
+        token: &[u8],
+        nonce_ttl: Duration,
+    ) -> Result<(), DispatchError> {
+        let _ = nonce_ttl;
+        self.redeem_token(token).await
+    }
+}
+
+/// Returns the token type code point at the beginning of a serialized token
+/// request or token.
+///
+/// # Errors
+/// Returns an error if the message is shorter than two bytes.
+pub fn peek_code_point(message: &[u8]) -> Result<CodePoint, DispatchError> {
+    match message {
+        [high, low, ..] => Ok(u16::from_be_bytes([*high, *low])),
+        _ => Err(DispatchError::MissingTokenType),
+    }
+}
+
+/// Registry of token type handlers keyed by code point.
+///
+/// Applications and plugins can fill a registry independently of the server
+/// and hand it to [`MultiTypeServer::from_registry`].
+#[derive(Default)]
+pub struct HandlerRegistry {
+    handlers: HashMap<CodePoint, Arc<dyn TokenTypeHandler>>,
+}
+
+impl std::fmt::Debug for HandlerRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HandlerRegistry")
+            .field("code_points", &self.code_points())
+            .finish()
+    }
+}
+
+impl HandlerRegistry {
+    /// Creates an empty registry.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a handler for a token type code point.
+    ///
+    /// # Errors
+    /// Returns an error if a handler is already registered for the code point.
+    pub fn register(
+        &mut self,
+        code_point: CodePoint,
+        handler: Arc<dyn TokenTypeHandler>,
+    ) -> Result<(), DispatchError> {
+        if self.handlers.contains_key(&code_point) {
+            return Err(DispatchError::AlreadyRegistered(code_point));
+        }
+        self.handlers.insert(code_point, handler);
+        Ok(())
+    }
+
+    /// Registers a server of this crate under the code points of all token
+    /// types it accepts, e.g. both the draft and the final code point of a
+    /// server with [`CodePoints::Transitional`](crate::CodePoints).
+    ///
+    /// # Errors
+    /// Returns an error if a handler is already registered for one of the
+    /// code points. No code point is registered in that case.
+    pub fn register_server<S: DynServer + 'static>(
+        &mut self,
+        server: S,
+    ) -> Result<(), DispatchError> {
+        let code_points = server
+            .accepted_token_types()
+            .into_iter()
+            .map(|token_type| token_type as CodePoint)
+            .collect::<Vec<_>>();
+        if let Some(&code_point) = code_points
+            .iter()
+            .find(|code_point| self.handlers.contains_key(code_point))
+        {
+            return Err(DispatchError::AlreadyRegistered(code_point));
+        }
+        let server: Arc<dyn TokenTypeHandler> = Arc::new(server);
+        for code_point in code_points {
+            self.handlers.insert(code_point, server.clone());
+        }
+        Ok(())
+    }
+
+    /// Removes the handler for a token type code point and returns it.
+    pub fn unregister(&mut self, code_point: CodePoint) -> Option<Arc<dyn TokenTypeHandler>> {
+        self.handlers.remove(&code_point)
+    }
+
+    /// Returns the handler for a token type code point.
+    #[must_use]
+    pub fn get(&self, code_point: CodePoint) -> Option<&Arc<dyn TokenTypeHandler>> {
+        self.handlers.get(&code_point)
+    }
+
+    /// Returns the registered code points in ascending order.
+    #[must_use]
+    pub fn code_points(&self) -> Vec<CodePoint> {
+        let mut code_points = self.handlers.keys().copied().collect::<Vec<_>>();
+        code_points.sort_unstable();
+        code_points
+    }
+}
+
+/// Server facade that dispatches messages to handlers by token type.
+#[derive(Default)]
+pub struct MultiTypeServer {
+    registry: HandlerRegistry,
+}
+
+impl std::fmt::Debug for MultiTypeServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MultiTypeServer")
+            .field("code_points", &self.code_points())
+            .finish()
+    }
+}
+
+impl MultiTypeServer {
+    /// Creates a new server without any handlers.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new server that dispatches to the handlers of a registry.
+    #[must_use]
+    pub const fn from_registry(registry: HandlerRegistry) -> Self {
+        Self { registry }
+    }
+
+    /// Returns the registry the server dispatches to.
+    #[must_use]
+    pub const fn registry(&self) -> &HandlerRegistry {
+        &self.registry
+    }
+
+    /// Registers a handler for a token type code point.
+    ///
+    /// # Errors
+    /// Returns an error if a handler is already registered for the code point.
+    pub fn register(
+        &mut self,
+        code_point: CodePoint,
+        handler: Arc<dyn TokenTypeHandler>,
+    ) -> Result<(), DispatchError> {
+        self.registry.register(code_point, handler)
+    }
+
+    /// Removes the handler for a token type code point and returns it.
+    pub fn unregister(&mut self, code_point: CodePoint) -> Option<Arc<dyn TokenTypeHandler>> {
+        self.registry.unregister(code_point)
+    }
+
+    /// Returns the registered code points in ascending order.
+    #[must_use]
+    pub fn code_points(&self) -> Vec<CodePoint> {
+        self.registry.code_points()
+    }
+
+    fn handler(&self, message: &[u8]) -> Result<&Arc<dyn TokenTypeHandler>, DispatchError> {
+        let code_point = peek_code_point(message)?;
+        self.registry
+            .get(code_point)
+            .ok_or(DispatchError::UnsupportedTokenType(code_point))
+    }
+
+    /// Issues a serialized token response by dispatching the serialized token
+    /// request to the handler of its token type.
+    ///
+    /// # Errors
+    /// Returns an error if the token type is not supported or if the handler
+    /// fails.
+    pub async fn issue_token_response(
+        &self,
+        token_request: &[u8],
+    ) -> Result<Vec<u8>, DispatchError> {
+        self.handler(token_request)?
+            .issue_token_response(token_request)
+            .await
+    }
+
+    /// Redeems a serialized token by dispatching it to the handler of its
+    /// token type.
+    ///
+    /// # Errors
+    /// Returns an error if the token type is not supported or if the handler
+    /// fails.
+    pub async fn redeem_token(&self, token: &[u8]) -> Result<(), DispatchError> {
+        self.handler(token)?.redeem_token(token).await
+    }
+
+    /// Redeems a serialized token like [`redeem_token`](Self::redeem_token)
+    /// and lets the nonce store forget its nonce after `nonce_ttl`, see
+    /// [`TokenTypeHandler::redeem_token_with_ttl`].
+    ///
+    /// # Errors
+    /// Returns an error if the token type is not supported or if the handler
+    /// fails.
+    pub async fn redeem_token_with_ttl(
+        &self,
+        token: &[u8],
+        nonce_ttl: Duration,
+    ) -> Result<(), DispatchError> {
+        self.handler(token)?
+            .redeem_token_with_ttl(token, nonce_ttl)
+            .await
+    }
+
+    /// Issues a batch token response by dispatching every token request of
+    /// the batch to the handler of its token type. Token requests that fail
+    /// are answered with an empty token response, so that the other token
+    /// requests of the batch are still served.
+    pub async fn issue_batch_token_response(
+        &self,
+        batch_token_request: &BatchTokenRequest,
+    ) -> BatchTokenResponse {
+        let mut token_responses = Vec::with_capacity(batch_token_request.len());
+        for token_request in batch_token_request.token_requests() {
+            token_responses.push(self.issue_token_response(token_request).await.ok());
+        }
+        BatchTokenResponse::new(token_responses)
+    }
+}
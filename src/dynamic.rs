@@ -0,0 +1,666 @@
+//! # Runtime ciphersuite selection
+//!
+//! Object-safe clients and servers that exchange serialized messages, so
+//! that multi-tenant issuers can serve several VOPRF ciphersuites from one
+//! binary and pick the one to use from the token type of a tenant or key at
+//! runtime.
+//!
+//! [`dyn_client`] selects the client for a token type and a serialized
+//! public key. The [`DynServer`] implementations wrap a typed server
+//! together with its stores. They also implement
+//! [`TokenTypeHandler`](crate::dispatch::TokenTypeHandler), so they can be
+//! registered with a [`MultiTypeServer`](crate::dispatch::MultiTypeServer).
+//! Origins register a [`PublicTokensOriginServer`] to redeem publicly
+//! verifiable tokens, which they verify but do not issue.
+
+use std::{any::Any, fmt, time::Duration};
+
+use async_trait::async_trait;
+use generic_array::{typenum::U256, ArrayLength};
+use thiserror::Error;
+
+use crate::{
+    auth::{authenticate::TokenChallenge, authorize::Token},
+    dispatch::{peek_code_point, DispatchError, TokenTypeHandler},
+    protocol::{IssuanceClient, ProtocolError},
+    public_tokens, Deserialize, NonceStore, NonceStoreWithTtl, Serialize, TokenType,
+};
+#[cfg(feature = "p384")]
+use crate::{batched_tokens_p384, private_tokens};
+#[cfg(feature = "ristretto255")]
+use crate::{batched_tokens_ristretto255, CodePoints};
+
+/// Errors that can occur when selecting a ciphersuite at runtime.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DynError {
+    #[error("Unsupported token type {0:#06x}")]
+    /// The token type is not a VOPRF token type of an enabled ciphersuite.
+    UnsupportedTokenType(u16),
+    #[error("Invalid public key")]
+    /// The public key is not a valid key of the ciphersuite.
+    InvalidPublicKey,
+    #[error("Keypair creation failed")]
+    /// The keypair could not be created.
+    CreateKeypair,
+}
+
+/// Type-erased client state kept between a token request and its response.
+pub struct DynTokenState(Box<dyn Any + Send>);
+
+impl fmt::Debug for DynTokenState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DynTokenState").finish_non_exhaustive()
+    }
+}
+
+/// Object-safe client of a single token type.
+pub trait DynClient: Send + Sync {
+    /// Returns the token type the client requests.
+    fn token_type(&self) -> TokenType;
+
+    /// Creates a serialized token request for `count` tokens.
+    ///
+    /// # Errors
+    /// Returns an error if the request cannot be created.
+    fn issue_token_request(
+        &self,
+        challenge: &TokenChallenge,
+        count: u16,
+    ) -> Result<(Vec<u8>, DynTokenState), ProtocolError>;
+
+    /// Finalizes a serialized token response into serialized tokens.
+    ///
+    /// # Errors
+    /// Returns an error if the token response is invalid or the token state
+    /// was created by a client of another token type.
+    fn issue_tokens(
+        &self,
+        token_response: &[u8],
+        token_state: DynTokenState,
+    ) -> Result<Vec<Vec<u8>>, ProtocolError>;
+}
+
+/// A typed client together with the token type it requests.
+struct TypedClient<C> {
+    token_type: TokenType,
+    client: C,
+}
+
+impl<C> DynClient for TypedClient<C>
+where
+    C: IssuanceClient + Send + Sync,
+    C::TokenState: Send + 'static,
+    C::Token: Serialize,
+{
+    fn token_type(&self) -> TokenType {
+        self.token_type
+    }
+
+    fn issue_token_request(
+        &self,
+        challenge: &TokenChallenge,
+        count: u16,
+    ) -> Result<(Vec<u8>, DynTokenState), ProtocolError> {
+        let (token_request, token_state) = self.client.token_request(challenge, count)?;
+        let token_request = token_request
+            .tls_serialize_detached()
+            .map_err(|_| ProtocolError::TokenRequestFailed)?;
+        Ok((token_request, DynTokenState(Box::new(token_state))))
+    }
+
+    fn issue_tokens(
+        &self,
+        token_response: &[u8],
+        token_state: DynTokenState,
+    ) -> Result<Vec<Vec<u8>>, ProtocolError> {
+        let token_state = token_state
+            .0
+            .downcast::<C::TokenState>()
+            .map_err(|_| ProtocolError::TokenStateMismatch)?;
+        let token_response = C::TokenResponse::tls_deserialize(&mut &token_response[..])
+            .map_err(|_| ProtocolError::InvalidTokenResponse)?;
+        self.client
+            .tokens(token_response, &token_state)?
+            .iter()
+            .map(|token| {
+                token
+                    .tls_serialize_detached()
+                    .map_err(|_| ProtocolError::InvalidTokenResponse)
+            })
+            .collect()
+    }
+}
+
+/// Creates a client for a VOPRF token type from a serialized public key.
+///
+/// # Errors
+/// Returns an error if the token type is not supported by the enabled
+/// ciphersuites or the public key is invalid.
+#[cfg_attr(
+    not(any(feature = "p384", feature = "ristretto255")),
+    allow(unused_variables)
+)]
+pub fn dyn_client(
+    token_type: TokenType,
+    public_key: &[u8],
+) -> Result<Box<dyn DynClient>, DynError> {
+    match token_type {
+        #[cfg(feature = "p384")]
+        TokenType::PrivateToken => {
+            let public_key = private_tokens::server::deserialize_public_key(public_key)
+                .map_err(|_| DynError::InvalidPublicKey)?;
+            Ok(Box::new(TypedClient {
+                token_type,
+                client: private_tokens::client::Client::new(public_key),
+            }))
+        }
+        #[cfg(feature = "p384")]
+        TokenType::BatchedTokenP384 => {
+            let public_key = batched_tokens_p384::server::deserialize_public_key(public_key)
+                .map_err(|_| DynError::InvalidPublicKey)?;
+            Ok(Box::new(TypedClient {
+                token_type,
+                client: batched_tokens_p384::client::Client::new(public_key),
+            }))
+        }
+        #[cfg(feature = "ristretto255")]
+        TokenType::BatchedTokenRistretto255 | TokenType::BatchedTokenRistretto255Final => {
+            let public_key =
+                batched_tokens_ristretto255::server::deserialize_public_key(public_key)
+                    .map_err(|_| DynError::InvalidPublicKey)?;
+            let code_points = if token_type == TokenType::BatchedTokenRistretto255 {
+                CodePoints::Draft
+            } else {
+                CodePoints::Final
+            };
+            Ok(Box::new(TypedClient {
+                token_type,
+                client: batched_tokens_ristretto255::client::Client::with_code_points(
+                    public_key,
+                    code_points,
+                ),
+            }))
+        }
+        token_type => Err(DynError::UnsupportedTokenType(token_type as u16)),
+    }
+}
+
+/// Object-safe server of a single token type that owns its stores.
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+pub trait DynServer: TokenTypeHandler {
+    /// Returns the token type the server issues.
+    fn token_type(&self) -> TokenType;
+
+    /// Returns the token types the server accepts, which can include the
+    /// draft code point of its token type. Defaults to the token type it
+    /// issues.
+    fn accepted_token_types(&self) -> Vec<TokenType> {
+        vec![self.token_type()]
+    }
+
+    /// Creates a new keypair, inserts it into the key store and returns the
+    /// serialized public key.
+    ///
+    /// # Errors
+    /// Returns an error if the keypair cannot be created.
+    async fn create_keypair(&self) -> Result<Vec<u8>, DynError>;
+}
+
+/// Deserializes a token of the type of its code point, so that tokens of the
+/// wrong length never reach the server.
+fn decode_token<Nk: ArrayLength<u8>>(token: &[u8]) -> Result<Token<Nk>, DispatchError> {
+    let token_type =
+        TokenType::try_from(peek_code_point(token)?).map_err(|_| DispatchError::InvalidToken)?;
+    Token::from_bytes_for_type(token_type, token).map_err(|_| DispatchError::InvalidToken)
+}
+
+/// [`DynServer`] for privately verifiable tokens.
+#[cfg(feature = "p384")]
+#[derive(Debug)]
+pub struct PrivateTokensServer<PKS, NS> {
+    server: private_tokens::server::Server,
+    key_store: PKS,
+    nonce_store: NS,
+}
+
+#[cfg(feature = "p384")]
+impl<PKS, NS> PrivateTokensServer<PKS, NS> {
+    /// Wraps a server together with its stores.
+    pub const fn new(
+        server: private_tokens::server::Server,
+        key_store: PKS,
+        nonce_store: NS,
+    ) -> Self {
+        Self {
+            server,
+            key_store,
+            nonce_store,
+        }
+    }
+}
+
+#[cfg(feature = "p384")]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<PKS: private_tokens::server::PrivateKeyStore, NS: NonceStore> DynServer
+    for PrivateTokensServer<PKS, NS>
+{
+    fn token_type(&self) -> TokenType {
+        TokenType::PrivateToken
+    }
+
+    async fn create_keypair(&self) -> Result<Vec<u8>, DynError> {
+        let public_key = self
+            .server
+            .create_keypair(&self.key_store)
+            .await
+            .map_err(|_| DynError::CreateKeypair)?;
+        Ok(private_tokens::server::serialize_public_key(public_key))
+    }
+}
+
+#[cfg(feature = "p384")]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<PKS: private_tokens::server::PrivateKeyStore, NS: NonceStore> TokenTypeHandler
+    for PrivateTokensServer<PKS, NS>
+{
+    async fn issue_token_response(&self, token_request: &[u8]) -> Result<Vec<u8>, DispatchError> {
+        let token_request = private_tokens::TokenRequest::tls_deserialize(&mut &token_request[..])
+            .map_err(|_| DispatchError::InvalidTokenRequest)?;
+        let token_response = self
+            .server
+            .issue_token_response(&self.key_store, token_request)
+            .await
+            .map_err(|error| match error {
+                private_tokens::server::IssueTokenResponseError::KeyIdNotFound => {
+                    DispatchError::KeyIdNotFound
+                }
+                private_tokens::server::IssueTokenResponseError::KeyStore(error) => {
+                    DispatchError::KeyStore(error)
+                }
+                _ => DispatchError::InvalidTokenRequest,
+            })?;
+        token_response
+            .tls_serialize_detached()
+            .map_err(|_| DispatchError::InvalidTokenRequest)
+    }
+
+    async fn redeem_token(&self, token: &[u8]) -> Result<(), DispatchError> {
+        self.redeem_with(token, &self.nonce_store).await
+    }
+
+    async fn redeem_token_with_ttl(
+        &self,
+        token: &[u8],
+        nonce_ttl: Duration,
+    ) -> Result<(), DispatchError> {
+        self.redeem_with(token, &NonceStoreWithTtl::new(&self.nonce_store, nonce_ttl)).await
+    }
+}
+
+#[cfg(feature = "p384")]
+impl<PKS: private_tokens::server::PrivateKeyStore, NS: NonceStore> PrivateTokensServer<PKS, NS> {
+    /// Redeems a token, recording its nonce in `nonce_store`.
+    async fn redeem_with<N: NonceStore + ?Sized>(
+        &self,
+        token: &[u8],
+        nonce_store: &N,
+    ) -> Result<(), DispatchError> {
+        let token: private_tokens::PrivateToken = decode_token(token)?;
+        self.server
+            .redeem_token(&self.key_store, nonce_store, token)
+            .await
+            .map_err(|error| match error {
+                private_tokens::server::RedeemTokenError::KeyIdNotFound => {
+                    DispatchError::KeyIdNotFound
+                }
+                private_tokens::server::RedeemTokenError::DoubleSpending => {
+                    DispatchError::DoubleSpending
+                }
+                private_tokens::server::RedeemTokenError::InvalidToken
+                | private_tokens::server::RedeemTokenError::KeyExpired
+                | private_tokens::server::RedeemTokenError::PolicyRejected(_) => {
+                    DispatchError::InvalidToken
+                }
+                private_tokens::server::RedeemTokenError::KeyStore(error) => {
+                    DispatchError::KeyStore(error)
+                }
+            })
+    }
+}
+
+/// [`DynServer`] for batched P-384 tokens.
+#[cfg(feature = "p384")]
+#[derive(Debug)]
+pub struct BatchedP384Server<BKS, NS> {
+    server: batched_tokens_p384::server::Server,
+    key_store: BKS,
+    nonce_store: NS,
+}
+
+#[cfg(feature = "p384")]
+impl<BKS, NS> BatchedP384Server<BKS, NS> {
+    /// Wraps a server together with its stores.
+    pub const fn new(
+        server: batched_tokens_p384::server::Server,
+        key_store: BKS,
+        nonce_store: NS,
+    ) -> Self {
+        Self {
+            server,
+            key_store,
+            nonce_store,
+        }
+    }
+}
+
+#[cfg(feature = "p384")]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<BKS: batched_tokens_p384::server::BatchedKeyStore, NS: NonceStore> DynServer
+    for BatchedP384Server<BKS, NS>
+{
+    fn token_type(&self) -> TokenType {
+        TokenType::BatchedTokenP384
+    }
+
+    async fn create_keypair(&self) -> Result<Vec<u8>, DynError> {
+        let public_key = self
+            .server
+            .create_keypair(&self.key_store)
+            .await
+            .map_err(|_| DynError::CreateKeypair)?;
+        Ok(batched_tokens_p384::server::serialize_public_key(
+            public_key,
+        ))
+    }
+}
+
+#[cfg(feature = "p384")]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<BKS: batched_tokens_p384::server::BatchedKeyStore, NS: NonceStore> TokenTypeHandler
+    for BatchedP384Server<BKS, NS>
+{
+    async fn issue_token_response(&self, token_request: &[u8]) -> Result<Vec<u8>, DispatchError> {
+        let token_request =
+            batched_tokens_p384::TokenRequest::tls_deserialize(&mut &token_request[..])
+                .map_err(|_| DispatchError::InvalidTokenRequest)?;
+        let token_response = self
+            .server
+            .issue_token_response(&self.key_store, token_request)
+            .await
+            .map_err(|error| match error {
+                batched_tokens_p384::server::IssueTokenResponseError::KeyIdNotFound => {
+                    DispatchError::KeyIdNotFound
+                }
+                batched_tokens_p384::server::IssueTokenResponseError::KeyStore(error) => {
+                    DispatchError::KeyStore(error)
+                }
+                _ => DispatchError::InvalidTokenRequest,
+            })?;
+        token_response
+            .tls_serialize_detached()
+            .map_err(|_| DispatchError::InvalidTokenRequest)
+    }
+
+    async fn redeem_token(&self, token: &[u8]) -> Result<(), DispatchError> {
+        self.redeem_with(token, &self.nonce_store).await
+    }
+
+    async fn redeem_token_with_ttl(
+        &self,
+        token: &[u8],
+        nonce_ttl: Duration,
+    ) -> Result<(), DispatchError> {
+        self.redeem_with(token, &NonceStoreWithTtl::new(&self.nonce_store, nonce_ttl)).await
+    }
+}
+
+#[cfg(feature = "p384")]
+impl<BKS: batched_tokens_p384::server::BatchedKeyStore, NS: NonceStore> BatchedP384Server<BKS, NS> {
+    /// Redeems a token, recording its nonce in `nonce_store`.
+    async fn redeem_with<N: NonceStore + ?Sized>(
+        &self,
+        token: &[u8],
+        nonce_store: &N,
+    ) -> Result<(), DispatchError> {
+        let token: batched_tokens_p384::BatchedToken = decode_token(token)?;
+        self.server
+            .redeem_token(&self.key_store, nonce_store, token)
+            .await
+            .map_err(|error| match error {
+                batched_tokens_p384::server::RedeemTokenError::KeyIdNotFound => {
+                    DispatchError::KeyIdNotFound
+                }
+                batched_tokens_p384::server::RedeemTokenError::DoubleSpending => {
+                    DispatchError::DoubleSpending
+                }
+                batched_tokens_p384::server::RedeemTokenError::InvalidToken
+                | batched_tokens_p384::server::RedeemTokenError::KeyExpired
+                | batched_tokens_p384::server::RedeemTokenError::PolicyRejected(_) => {
+                    DispatchError::InvalidToken
+                }
+                batched_tokens_p384::server::RedeemTokenError::KeyStore(error) => {
+                    DispatchError::KeyStore(error)
+                }
+            })
+    }
+}
+
+/// [`DynServer`] for batched Ristretto255 tokens. The token type follows the
+/// code points of the server.
+#[cfg(feature = "ristretto255")]
+#[derive(Debug)]
+pub struct BatchedRistretto255Server<BKS, NS> {
+    server: batched_tokens_ristretto255::server::Server,
+    key_store: BKS,
+    nonce_store: NS,
+}
+
+#[cfg(feature = "ristretto255")]
+impl<BKS, NS> BatchedRistretto255Server<BKS, NS> {
+    /// Wraps a server together with its stores.
+    pub const fn new(
+        server: batched_tokens_ristretto255::server::Server,
+        key_store: BKS,
+        nonce_store: NS,
+    ) -> Self {
+        Self {
+            server,
+            key_store,
+            nonce_store,
+        }
+    }
+}
+
+#[cfg(feature = "ristretto255")]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<BKS: batched_tokens_ristretto255::server::BatchedKeyStore, NS: NonceStore> DynServer
+    for BatchedRistretto255Server<BKS, NS>
+{
+    fn token_type(&self) -> TokenType {
+        self.server
+            .code_points()
+            .emit(TokenType::BatchedTokenRistretto255)
+    }
+
+    fn accepted_token_types(&self) -> Vec<TokenType> {
+        let code_points = self.server.code_points();
+        TokenType::supported()
+            .filter(|&token_type| {
+                code_points.accepts(TokenType::BatchedTokenRistretto255, token_type)
+            })
+            .collect()
+    }
+
+    async fn create_keypair(&self) -> Result<Vec<u8>, DynError> {
+        let public_key = self
+            .server
+            .create_keypair(&self.key_store)
+            .await
+            .map_err(|_| DynError::CreateKeypair)?;
+        Ok(batched_tokens_ristretto255::server::serialize_public_key(
+            public_key,
+        ))
+    }
+}
+
+#[cfg(feature = "ristretto255")]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<BKS: batched_tokens_ristretto255::server::BatchedKeyStore, NS: NonceStore> TokenTypeHandler
+    for BatchedRistretto255Server<BKS, NS>
+{
+    async fn issue_token_response(&self, token_request: &[u8]) -> Result<Vec<u8>, DispatchError> {
+        let token_request =
+            batched_tokens_ristretto255::TokenRequest::tls_deserialize(&mut &token_request[..])
+                .map_err(|_| DispatchError::InvalidTokenRequest)?;
+        let token_response = self
+            .server
+            .issue_token_response(&self.key_store, token_request)
+            .await
+            .map_err(|error| match error {
+                batched_tokens_ristretto255::server::IssueTokenResponseError::KeyIdNotFound => {
+                    DispatchError::KeyIdNotFound
+                }
+                batched_tokens_ristretto255::server::IssueTokenResponseError::KeyStore(error) => {
+                    DispatchError::KeyStore(error)
+                }
+                _ => DispatchError::InvalidTokenRequest,
+            })?;
+        token_response
+            .tls_serialize_detached()
+            .map_err(|_| DispatchError::InvalidTokenRequest)
+    }
+
+    async fn redeem_token(&self, token: &[u8]) -> Result<(), DispatchError> {
+        self.redeem_with(token, &self.nonce_store).await
+    }
+
+    async fn redeem_token_with_ttl(
+        &self,
+        token: &[u8],
+        nonce_ttl: Duration,
+    ) -> Result<(), DispatchError> {
+        self.redeem_with(token, &NonceStoreWithTtl::new(&self.nonce_store, nonce_ttl)).await
+    }
+}
+
+#[cfg(feature = "ristretto255")]
+impl<BKS: batched_tokens_ristretto255::server::BatchedKeyStore, NS: NonceStore>
+    BatchedRistretto255Server<BKS, NS>
+{
+    /// Redeems a token, recording its nonce in `nonce_store`.
+    async fn redeem_with<N: NonceStore + ?Sized>(
+        &self,
+        token: &[u8],
+        nonce_store: &N,
+    ) -> Result<(), DispatchError> {
+        let token: batched_tokens_ristretto255::BatchedToken = decode_token(token)?;
+        self.server
+            .redeem_token(&self.key_store, nonce_store, token)
+            .await
+            .map_err(|error| match error {
+                batched_tokens_ristretto255::server::RedeemTokenError::KeyIdNotFound => {
+                    DispatchError::KeyIdNotFound
+                }
+                batched_tokens_ristretto255::server::RedeemTokenError::DoubleSpending => {
+                    DispatchError::DoubleSpending
+                }
+                batched_tokens_ristretto255::server::RedeemTokenError::InvalidToken
+                | batched_tokens_ristretto255::server::RedeemTokenError::KeyExpired
+                | batched_tokens_ristretto255::server::RedeemTokenError::PolicyRejected(_) => {
+                    DispatchError::InvalidToken
+                }
+                batched_tokens_ristretto255::server::RedeemTokenError::KeyStore(error) => {
+                    DispatchError::KeyStore(error)
+                }
+            })
+    }
+}
+
+/// [`TokenTypeHandler`] for publicly verifiable tokens at an origin, which
+/// redeems tokens with the public keys of its issuers but does not issue
+/// them. Register it with
+/// [`MultiTypeServer::register`](crate::dispatch::MultiTypeServer::register)
+/// under [`TokenType::PublicToken`].
+#[derive(Debug)]
+pub struct PublicTokensOriginServer<OKS, NS> {
+    server: public_tokens::server::OriginServer,
+    key_store: OKS,
+    nonce_store: NS,
+}
+
+impl<OKS, NS> PublicTokensOriginServer<OKS, NS> {
+    /// Wraps a server together with its stores.
+    pub const fn new(
+        server: public_tokens::server::OriginServer,
+        key_store: OKS,
+        nonce_store: NS,
+    ) -> Self {
+        Self {
+            server,
+            key_store,
+            nonce_store,
+        }
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<OKS: public_tokens::server::OriginKeyStore + Send + Sync, NS: NonceStore> TokenTypeHandler
+    for PublicTokensOriginServer<OKS, NS>
+{
+    async fn issue_token_response(&self, _token_request: &[u8]) -> Result<Vec<u8>, DispatchError> {
+        Err(DispatchError::UnsupportedTokenType(TokenType::PublicToken as u16))
+    }
+
+    async fn redeem_token(&self, token: &[u8]) -> Result<(), DispatchError> {
+        self.redeem_with(token, &self.nonce_store).await
+    }
+
+    async fn redeem_token_with_ttl(
+        &self,
+        token: &[u8],
+        nonce_ttl: Duration,
+    ) -> Result<(), DispatchError> {
+        self.redeem_with(token, &NonceStoreWithTtl::new(&self.nonce_store, nonce_ttl)).await
+    }
+}
+
+impl<OKS: public_tokens::server::OriginKeyStore + Send + Sync, NS: NonceStore>
+    PublicTokensOriginServer<OKS, NS>
+{
+    /// Redeems a token, recording its nonce in `nonce_store`.
+    async fn redeem_with<N: NonceStore + ?Sized>(
+        &self,
+        token: &[u8],
+        nonce_store: &N,
+    ) -> Result<(), DispatchError> {
+        let token: Token<U256> = decode_token(token)?;
+        self.server
+            .redeem_token(&self.key_store, nonce_store, token)
+            .await
+            .map_err(|error| match error {
+                public_tokens::server::RedeemTokenError::KeyIdNotFound => {
+                    DispatchError::KeyIdNotFound
+                }
+                public_tokens::server::RedeemTokenError::DoubleSpending => {
+                    DispatchError::DoubleSpending
+                }
+                public_tokens::server::RedeemTokenError::InvalidToken
+                | public_tokens::server::RedeemTokenError::KeyExpired
+                | public_tokens::server::RedeemTokenError::PolicyRejected(_) => {
+                    DispatchError::InvalidToken
+                }
+                public_tokens::server::RedeemTokenError::KeyStore(error) => {
+                    DispatchError::KeyStore(error)
+                }
+            })
+    }
+}
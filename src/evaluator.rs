@@ -0,0 +1,279 @@
+//! # Blind evaluation
+//!
+//! A [`BlindEvaluator`] evaluates the blinded elements of VOPRF token
+//! requests and proves the evaluation. [`VoprfServer`] implements it with a
+//! private key in application memory. Issuers whose private key must not
+//! live in application memory implement it on top of a KMS, an HSM or a
+//! PKCS#11 token and issue with the `issue_token_response_with_evaluator`
+//! methods of the VOPRF servers.
+//!
+//! Evaluators exchange elements and proofs in the serialization of the token
+//! requests and responses, so that backends do not depend on the VOPRF
+//! implementation of this crate.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+#[cfg(any(feature = "p384", feature = "ristretto255"))]
+use generic_array::GenericArray;
+#[cfg(feature = "p384")]
+use p384::NistP384;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+#[cfg(any(feature = "p384", feature = "ristretto255"))]
+use typenum::Unsigned;
+#[cfg(feature = "ristretto255")]
+use voprf::Ristretto255;
+#[cfg(any(feature = "p384", feature = "ristretto255"))]
+use voprf::{
+    BlindedElement, Group, PreparedEvaluationElement, VoprfServer,
+    VoprfServerBatchEvaluateFinishResult,
+};
+
+#[cfg(any(feature = "p384", feature = "ristretto255"))]
+use crate::batched_tokens::{EvaluatedElement, TokenResponsePart};
+use crate::{IssuanceProfiler, IssuanceStage, MaybeSendSync, ServerRng, TokenKeyId, VoprfError};
+
+/// Serialized result of a blind evaluation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlindEvaluation {
+    /// Evaluated elements, in the order of the blinded elements.
+    pub evaluated_elements: Vec<Vec<u8>>,
+    /// DLEQ proof over all evaluated elements.
+    pub proof: Vec<u8>,
+}
+
+/// Errors that can occur during a blind evaluation.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BlindEvaluatorError {
+    #[error("Blinded element {0} failed to decode")]
+    /// Error when the blinded element at the given index is not a valid
+    /// group element.
+    InvalidBlindedElement(usize),
+    #[error("Proof generation failed")]
+    /// Error when the evaluation or its proof could not be computed.
+    Voprf(#[source] VoprfError),
+    #[error("Evaluation does not match the blinded elements")]
+    /// Error when the evaluator returns the wrong number of evaluated
+    /// elements, or elements or a proof of the wrong size.
+    InvalidEvaluation,
+    #[error("Evaluator backend failed: {0}")]
+    /// Error when the backend fails, e.g. because the HSM cannot be reached.
+    /// Contains a description of the failure.
+    Backend(String),
+}
+
+/// Evaluates blinded elements with a private key.
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+pub trait BlindEvaluator: MaybeSendSync {
+    /// Returns the serialized public key of the private key.
+    fn public_key(&self) -> Vec<u8>;
+
+    /// Returns the token key ID of the public key.
+    fn token_key_id(&self) -> TokenKeyId {
+        Sha256::digest(self.public_key()).into()
+    }
+
+    /// Evaluates the serialized blinded elements and proves all evaluations
+    /// with a single DLEQ proof. Evaluators that compute the proof themselves
+    /// draw its randomness from `rng`.
+    async fn blind_evaluate(
+        &self,
+        rng: &mut ServerRng,
+        blinded_elements: &[&[u8]],
+    ) -> Result<BlindEvaluation, BlindEvaluatorError>;
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<T: BlindEvaluator + ?Sized> BlindEvaluator for Box<T> {
+    fn public_key(&self) -> Vec<u8> {
+        (**self).public_key()
+    }
+
+    async fn blind_evaluate(
+        &self,
+        rng: &mut ServerRng,
+        blinded_elements: &[&[u8]],
+    ) -> Result<BlindEvaluation, BlindEvaluatorError> {
+        (**self).blind_evaluate(rng, blinded_elements).await
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<T: BlindEvaluator + ?Sized> BlindEvaluator for Arc<T> {
+    fn public_key(&self) -> Vec<u8> {
+        (**self).public_key()
+    }
+
+    async fn blind_evaluate(
+        &self,
+        rng: &mut ServerRng,
+        blinded_elements: &[&[u8]],
+    ) -> Result<BlindEvaluation, BlindEvaluatorError> {
+        (**self).blind_evaluate(rng, blinded_elements).await
+    }
+}
+
+#[cfg(any(feature = "p384", feature = "ristretto255"))]
+macro_rules! impl_voprf_evaluator {
+    ($group:ty, $evaluate:ident, $streamed:ident) => {
+        /// Evaluates blinded elements with a private key in memory and
+        /// records the stages of the evaluation with `profiler`.
+        pub(crate) fn $evaluate(
+            server: &VoprfServer<$group>,
+            rng: &mut ServerRng,
+            blinded_elements: &[&[u8]],
+            profiler: &mut IssuanceProfiler,
+        ) -> Result<BlindEvaluation, BlindEvaluatorError> {
+            let blinded_elements = blinded_elements
+                .iter()
+                .enumerate()
+                .map(|(index, element)| {
+                    BlindedElement::<$group>::deserialize(element)
+                        .map_err(|_| BlindEvaluatorError::InvalidBlindedElement(index))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            profiler.record(IssuanceStage::Deserialize);
+
+            let prepared_elements = server
+                .batch_blind_evaluate_prepare(blinded_elements.iter())
+                .collect::<Vec<_>>();
+            profiler.record(IssuanceStage::Prepare);
+            let VoprfServerBatchEvaluateFinishResult { messages, proof } = server
+                .batch_blind_evaluate_finish(rng, blinded_elements.iter(), &prepared_elements)
+                .map_err(|error| BlindEvaluatorError::Voprf(error.into()))?;
+            profiler.record(IssuanceStage::Evaluate);
+            Ok(BlindEvaluation {
+                evaluated_elements: messages.map(|m| m.serialize().to_vec()).collect(),
+                proof: proof.serialize().to_vec(),
+            })
+        }
+
+        /// Evaluates blinded elements with a private key in memory as the
+        /// parts of a streamed token response, and proves all evaluations
+        /// after the last one.
+        ///
+        /// The batch is evaluated once when the stream is created and only
+        /// the serialization of the evaluated elements is streamed, so a
+        /// streamed response costs as much as a regular one.
+        pub(crate) struct $streamed {
+            server: VoprfServer<$group>,
+            rng: ServerRng,
+            blinded_elements: Vec<BlindedElement<$group>>,
+            prepared_elements: Vec<PreparedEvaluationElement<$group>>,
+            sent: usize,
+            length_sent: bool,
+            proof_sent: bool,
+        }
+
+        impl $streamed {
+            /// Decodes and evaluates all blinded elements up front, so that
+            /// invalid ones are reported before the first part.
+            pub(crate) fn new(
+                server: VoprfServer<$group>,
+                rng: ServerRng,
+                blinded_elements: &[&[u8]],
+            ) -> Result<Self, BlindEvaluatorError> {
+                let blinded_elements = blinded_elements
+                    .iter()
+                    .enumerate()
+                    .map(|(index, element)| {
+                        BlindedElement::<$group>::deserialize(element)
+                            .map_err(|_| BlindEvaluatorError::InvalidBlindedElement(index))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let prepared_elements = server
+                    .batch_blind_evaluate_prepare(blinded_elements.iter())
+                    .collect();
+                Ok(Self {
+                    server,
+                    rng,
+                    blinded_elements,
+                    prepared_elements,
+                    sent: 0,
+                    length_sent: false,
+                    proof_sent: false,
+                })
+            }
+
+            /// Serializes a prepared element. voprf only serializes
+            /// evaluated elements once the proof is computed, so the element
+            /// goes through its `serde` representation, a sequence of bytes.
+            fn serialize_prepared(
+                prepared_element: &PreparedEvaluationElement<$group>,
+            ) -> Result<EvaluatedElement<$group>, BlindEvaluatorError> {
+                serde_json::to_value(prepared_element)
+                    .and_then(serde_json::from_value::<Vec<u8>>)
+                    .ok()
+                    .and_then(GenericArray::from_exact_iter)
+                    .map(|evaluated_element| EvaluatedElement { evaluated_element })
+                    .ok_or(BlindEvaluatorError::InvalidEvaluation)
+            }
+        }
+
+        impl Iterator for $streamed {
+            type Item = Result<TokenResponsePart<$group>, BlindEvaluatorError>;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                if !self.length_sent {
+                    self.length_sent = true;
+                    // The blinded elements come from a `TlsVecU16` of
+                    // elements of the same size, so their length fits
+                    let length =
+                        (self.blinded_elements.len() * <$group as Group>::ElemLen::USIZE) as u16;
+                    return Some(Ok(TokenResponsePart::ElementsLength(length)));
+                }
+                if let Some(prepared_element) = self.prepared_elements.get(self.sent) {
+                    self.sent += 1;
+                    return Some(
+                        Self::serialize_prepared(prepared_element)
+                            .map(TokenResponsePart::EvaluatedElement),
+                    );
+                }
+                if self.proof_sent {
+                    return None;
+                }
+                self.proof_sent = true;
+                Some(
+                    self.server
+                        .batch_blind_evaluate_finish(
+                            &mut self.rng,
+                            self.blinded_elements.iter(),
+                            &self.prepared_elements,
+                        )
+                        .map(|result| TokenResponsePart::Proof(result.proof.serialize().to_vec()))
+                        .map_err(|error| BlindEvaluatorError::Voprf(error.into())),
+                )
+            }
+        }
+
+        #[cfg_attr(feature = "send", async_trait)]
+        #[cfg_attr(not(feature = "send"), async_trait(?Send))]
+        impl BlindEvaluator for VoprfServer<$group> {
+            fn public_key(&self) -> Vec<u8> {
+                <$group as Group>::serialize_elem(self.get_public_key()).to_vec()
+            }
+
+            async fn blind_evaluate(
+                &self,
+                rng: &mut ServerRng,
+                blinded_elements: &[&[u8]],
+            ) -> Result<BlindEvaluation, BlindEvaluatorError> {
+                $evaluate(self, rng, blinded_elements, &mut IssuanceProfiler::start())
+            }
+        }
+    };
+}
+
+#[cfg(feature = "p384")]
+impl_voprf_evaluator!(NistP384, blind_evaluate_p384, StreamedEvaluationP384);
+#[cfg(feature = "ristretto255")]
+impl_voprf_evaluator!(
+    Ristretto255,
+    blind_evaluate_ristretto255,
+    StreamedEvaluationRistretto255
+);
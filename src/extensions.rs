@@ -0,0 +1,297 @@
+//! # Extensions
+//!
+//! Generic type-length-value extensions for wire messages, as defined in the
+//! Privacy Pass extensions drafts:
+//!
+//! ```text
+//! struct {
+//!     ExtensionType extension_type;
+//!     opaque extension_data<0..2^16-1>;
+//! } Extension;
+//!
+//! struct {
+//!     Extension extensions<0..2^16-1>;
+//! } Extensions;
+//! ```
+//!
+//! Applications register an [`ExtensionHandler`] per extension type in an
+//! [`ExtensionRegistry`], so new protocol extensions can be supported without
+//! changing the core message definitions.
+
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+};
+
+use thiserror::Error;
+use tls_codec::{Deserialize, Error, Serialize, Size, TlsByteVecU16, TlsVecU16};
+use tls_codec_derive::{TlsDeserialize, TlsSerialize, TlsSize};
+
+/// Extension type code point
+pub type ExtensionType = u16;
+
+/// A single extension.
+#[derive(Clone, Debug, PartialEq, Eq, TlsSize, TlsSerialize, TlsDeserialize)]
+pub struct Extension {
+    extension_type: ExtensionType,
+    extension_data: TlsByteVecU16,
+}
+
+impl Extension {
+    /// Creates a new extension.
+    #[must_use]
+    pub fn new(extension_type: ExtensionType, extension_data: &[u8]) -> Self {
+        Self {
+            extension_type,
+            extension_data: extension_data.into(),
+        }
+    }
+
+    /// Returns the extension type.
+    #[must_use]
+    pub const fn extension_type(&self) -> ExtensionType {
+        self.extension_type
+    }
+
+    /// Returns the extension data.
+    #[must_use]
+    pub fn extension_data(&self) -> &[u8] {
+        self.extension_data.as_slice()
+    }
+}
+
+/// A list of extensions.
+#[derive(Clone, Debug, PartialEq, Eq, TlsSize, TlsSerialize, TlsDeserialize)]
+pub struct Extensions {
+    extensions: TlsVecU16<Extension>,
+}
+
+impl Extensions {
+    /// Creates a new list of extensions.
+    #[must_use]
+    pub fn new(extensions: Vec<Extension>) -> Self {
+        Self {
+            extensions: extensions.into(),
+        }
+    }
+
+    /// Returns the first extension of the given type, if present.
+    #[must_use]
+    pub fn get(&self, extension_type: ExtensionType) -> Option<&Extension> {
+        self.iter()
+            .find(|extension| extension.extension_type == extension_type)
+    }
+
+    /// Returns an iterator over the extensions.
+    pub fn iter(&self) -> impl Iterator<Item = &Extension> {
+        self.extensions.iter()
+    }
+
+    /// Returns the number of extensions.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.extensions.len()
+    }
+
+    /// Returns `true` if the list contains no extensions.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+
+impl Default for Extensions {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+/// A wire message together with a list of extensions:
+///
+/// ```text
+/// struct {
+///     TokenRequest request;
+///     Extensions extensions;
+/// } ExtendedTokenRequest;
+/// ```
+#[derive(Debug)]
+pub struct ExtendedTokenRequest<R> {
+    request: R,
+    extensions: Extensions,
+}
+
+impl<R> ExtendedTokenRequest<R> {
+    /// Creates a new extended token request.
+    pub const fn new(request: R, extensions: Extensions) -> Self {
+        Self {
+            request,
+            extensions,
+        }
+    }
+
+    /// Returns the inner token request.
+    pub const fn request(&self) -> &R {
+        &self.request
+    }
+
+    /// Returns the extensions.
+    pub const fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Splits the extended token request into the inner token request and the
+    /// extensions.
+    pub fn into_parts(self) -> (R, Extensions) {
+        (self.request, self.extensions)
+    }
+}
+
+impl<R: Size> Size for ExtendedTokenRequest<R> {
+    fn tls_serialized_len(&self) -> usize {
+        self.request.tls_serialized_len() + self.extensions.tls_serialized_len()
+    }
+}
+
+impl<R: Serialize> Serialize for ExtendedTokenRequest<R> {
+    fn tls_serialize<W: Write>(&self, writer: &mut W) -> Result<usize, Error> {
+        Ok(self.request.tls_serialize(writer)? + self.extensions.tls_serialize(writer)?)
+    }
+}
+
+impl<R: Deserialize> Deserialize for ExtendedTokenRequest<R> {
+    fn tls_deserialize<B: Read>(bytes: &mut B) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        let request = R::tls_deserialize(bytes)?;
+        let extensions = Extensions::tls_deserialize(bytes)?;
+        Ok(Self {
+            request,
+            extensions,
+        })
+    }
+}
+
+/// Errors that can occur when processing extensions.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ExtensionError {
+    #[error("Unsupported extension type {0:#06x}")]
+    /// No handler is registered for the extension type.
+    UnsupportedExtension(ExtensionType),
+    #[error("Duplicate extension type {0:#06x}")]
+    /// The extension type appears more than once.
+    DuplicateExtension(ExtensionType),
+    #[error("Invalid extension data for type {0:#06x}")]
+    /// The handler rejected the extension data.
+    InvalidExtensionData(ExtensionType),
+}
+
+/// Handler for a single extension type.
+pub trait ExtensionHandler: Send + Sync {
+    /// Validates the data of an extension.
+    ///
+    /// # Errors
+    /// Returns an error if the extension data is not acceptable.
+    fn handle(&self, extension_data: &[u8]) -> Result<(), ExtensionError>;
+}
+
+/// Registry of extension handlers, keyed by extension type.
+#[derive(Default)]
+pub struct ExtensionRegistry {
+    handlers: HashMap<ExtensionType, Box<dyn ExtensionHandler>>,
+}
+
+impl std::fmt::Debug for ExtensionRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExtensionRegistry")
+            .field("extension_types", &self.handlers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl ExtensionRegistry {
+    /// Creates an empty registry.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a handler for an extension type, replacing any previously
+    /// registered handler for the same type.
+    pub fn register<H: ExtensionHandler + 'static>(
+        &mut self,
+        extension_type: ExtensionType,
+        handler: H,
+    ) {
+        self.handlers.insert(extension_type, Box::new(handler));
+    }
+
+    /// Returns `true` if a handler is registered for the extension type.
+    #[must_use]
+    pub fn is_registered(&self, extension_type: ExtensionType) -> bool {
+        self.handlers.contains_key(&extension_type)
+    }
+
+    /// Runs the registered handlers over a list of extensions.
+    ///
+    /// # Errors
+    /// Returns an error if an extension type is unknown or duplicated, or if a
+    /// handler rejects the extension data.
+    pub fn process(&self, extensions: &Extensions) -> Result<(), ExtensionError> {
+        let mut seen = Vec::with_capacity(extensions.len());
+        for extension in extensions.iter() {
+            let extension_type = extension.extension_type();
+            if seen.contains(&extension_type) {
+                return Err(ExtensionError::DuplicateExtension(extension_type));
+            }
+            seen.push(extension_type);
+
+            let handler = self
+                .handlers
+                .get(&extension_type)
+                .ok_or(ExtensionError::UnsupportedExtension(extension_type))?;
+            handler.handle(extension.extension_data())?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn extensions_roundtrip() {
+    struct NonEmpty;
+
+    impl ExtensionHandler for NonEmpty {
+        fn handle(&self, extension_data: &[u8]) -> Result<(), ExtensionError> {
+            if extension_data.is_empty() {
+                Err(ExtensionError::InvalidExtensionData(0x0001))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    let extensions = Extensions::new(vec![Extension::new(0x0001, b"data")]);
+    let request = ExtendedTokenRequest::new(7u8, extensions.clone());
+    let bytes = request.tls_serialize_detached().unwrap();
+    let decoded = ExtendedTokenRequest::<u8>::tls_deserialize(&mut bytes.as_slice()).unwrap();
+    assert_eq!(decoded.request(), &7u8);
+    assert_eq!(decoded.extensions(), &extensions);
+
+    let mut registry = ExtensionRegistry::new();
+    assert_eq!(
+        registry.process(&extensions),
+        Err(ExtensionError::UnsupportedExtension(0x0001))
+    );
+    registry.register(0x0001, NonEmpty);
+    assert_eq!(registry.process(&extensions), Ok(()));
+
+    let duplicated = Extensions::new(vec![
+        Extension::new(0x0001, b"a"),
+        Extension::new(0x0001, b"b"),
+    ]);
+    assert_eq!(
+        registry.process(&duplicated),
+        Err(ExtensionError::DuplicateExtension(0x0001))
+    );
+}
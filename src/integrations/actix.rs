@@ -0,0 +1,167 @@
+//! # Actix-web integration
+//!
+//! The [`RedeemedToken`] extractor redeems the `PrivateToken` of the
+//! `Authorization` header of a request before the handler runs. Handlers
+//! that take a [`RedeemedToken`] are only called for requests with a valid,
+//! unspent token and can read its challenge digest to decide which
+//! challenge the client answered. Requests without one are answered with
+//! `401 Unauthorized` and one `WWW-Authenticate` challenge per configured
+//! [`ChallengeTemplate`].
+//!
+//! The extractor is configured with a [`RedeemedTokenConfig`] that is
+//! registered with `App::app_data`.
+
+use std::sync::Arc;
+
+use actix_web::{
+    dev::Payload,
+    http::{
+        header::{self, HeaderValue},
+        StatusCode,
+    },
+    FromRequest, HttpRequest, HttpResponse, ResponseError,
+};
+use futures::future::LocalBoxFuture;
+use thiserror::Error;
+
+use super::ChallengeTemplate;
+use crate::{
+    auth::{authorize::parse_authorization_header_bytes, ParseConfig},
+    dispatch::{CodePoint, MultiTypeServer},
+    ChallengeDigest, Nonce,
+};
+
+/// Configuration of the [`RedeemedToken`] extractor.
+#[derive(Clone, Debug)]
+pub struct RedeemedTokenConfig {
+    server: Arc<MultiTypeServer>,
+    challenges: Arc<[ChallengeTemplate]>,
+    parse_config: ParseConfig,
+}
+
+impl RedeemedTokenConfig {
+    /// Creates a configuration that redeems tokens with `server` and
+    /// challenges clients without a valid token with `challenges`.
+    #[must_use]
+    pub fn new(server: Arc<MultiTypeServer>, challenges: Vec<ChallengeTemplate>) -> Self {
+        Self {
+            server,
+            challenges: challenges.into(),
+            parse_config: ParseConfig::default(),
+        }
+    }
+
+    /// Sets the configuration of the `Authorization` header parser.
+    #[must_use]
+    pub const fn with_parse_config(mut self, parse_config: ParseConfig) -> Self {
+        self.parse_config = parse_config;
+        self
+    }
+
+    fn unauthorized(&self) -> RedeemedTokenError {
+        let challenges = self
+            .challenges
+            .iter()
+            .filter_map(|challenge| challenge.header_value().ok())
+            .filter_map(|value| HeaderValue::from_bytes(value.as_bytes()).ok())
+            .collect();
+        RedeemedTokenError::Unauthorized(challenges)
+    }
+}
+
+/// Errors of the [`RedeemedToken`] extractor.
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum RedeemedTokenError {
+    #[error("No RedeemedTokenConfig is registered as app data")]
+    /// Error when the app has no [`RedeemedTokenConfig`]. It is answered
+    /// with `500 Internal Server Error`.
+    MissingConfig,
+    #[error("Missing or invalid token")]
+    /// Error when the request carries no valid token. It is answered with
+    /// `401 Unauthorized` and the contained `WWW-Authenticate` challenges.
+    Unauthorized(Vec<HeaderValue>),
+}
+
+impl ResponseError for RedeemedTokenError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::MissingConfig => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let Self::Unauthorized(challenges) = self {
+            for challenge in challenges {
+                response.append_header((header::WWW_AUTHENTICATE, challenge.clone()));
+            }
+        }
+        response.finish()
+    }
+}
+
+/// Token that was redeemed for the request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RedeemedToken {
+    code_point: CodePoint,
+    nonce: Nonce,
+    challenge_digest: ChallengeDigest,
+}
+
+impl RedeemedToken {
+    /// Returns the token type code point.
+    #[must_use]
+    pub const fn code_point(&self) -> CodePoint {
+        self.code_point
+    }
+
+    /// Returns the nonce.
+    #[must_use]
+    pub const fn nonce(&self) -> &Nonce {
+        &self.nonce
+    }
+
+    /// Returns the digest of the challenge the token was issued for.
+    #[must_use]
+    pub const fn challenge_digest(&self) -> &ChallengeDigest {
+        &self.challenge_digest
+    }
+
+    /// Reads the fields every token starts with from a serialized token.
+    fn from_bytes(token: &[u8]) -> Option<Self> {
+        Some(Self {
+            code_point: u16::from_be_bytes(token.get(..2)?.try_into().ok()?),
+            nonce: token.get(2..34)?.try_into().ok()?,
+            challenge_digest: token.get(34..66)?.try_into().ok()?,
+        })
+    }
+}
+
+impl FromRequest for RedeemedToken {
+    type Error = RedeemedTokenError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let Some(config) = request.app_data::<RedeemedTokenConfig>().cloned() else {
+            return Box::pin(async { Err(RedeemedTokenError::MissingConfig) });
+        };
+        // The header values of actix-web are converted to those of `http`
+        let token = request
+            .headers()
+            .get_all(header::AUTHORIZATION)
+            .filter_map(|value| http::HeaderValue::from_bytes(value.as_bytes()).ok())
+            .find_map(|value| parse_authorization_header_bytes(&value, &config.parse_config).ok());
+        Box::pin(async move {
+            let token = token.ok_or_else(|| config.unauthorized())?;
+            let redeemed_token = Self::from_bytes(&token).ok_or_else(|| config.unauthorized())?;
+            config
+                .server
+                .redeem_token(&token)
+                .await
+                .map_err(|_| config.unauthorized())?;
+            Ok(redeemed_token)
+        })
+    }
+}
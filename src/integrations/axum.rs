@@ -0,0 +1,155 @@
+//! # Axum integration
+//!
+//! [`IssuerService`] builds an [`axum::Router`] with the two endpoints of an
+//! issuer:
+//!
+//! - `POST` to the path of the issuer request URI of the directory, e.g.
+//!   `/token-request`, accepts token requests of the
+//!   [`TOKEN_REQUEST_MEDIA_TYPE`] content type and answers with token
+//!   responses of the [`TOKEN_RESPONSE_MEDIA_TYPE`] content type. Token
+//!   requests are dispatched to the handler of their token type.
+//! - `GET` [`WELL_KNOWN_PATH`] serves the issuer directory.
+//!
+//! Errors are answered with problem details documents. Token requests larger
+//! than the request size limit are rejected with `413 Payload Too Large`
+//! before they reach the server.
+
+use std::sync::Arc;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{DefaultBodyLimit, State},
+    http::{
+        header::{HeaderValue, CONTENT_TYPE},
+        HeaderMap, StatusCode, Uri,
+    },
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+
+use crate::{
+    dispatch::MultiTypeServer,
+    issuer_directory::{TokenKeyDirectory, DIRECTORY_MEDIA_TYPE, WELL_KNOWN_PATH},
+    problem_details::{ProblemDetails, ToProblemDetails},
+    transport::{TOKEN_REQUEST_MEDIA_TYPE, TOKEN_RESPONSE_MEDIA_TYPE},
+};
+
+/// Path of the issuance endpoint if the issuer request URI of the directory
+/// has none.
+pub const DEFAULT_TOKEN_REQUEST_PATH: &str = "/token-request";
+
+/// Default limit of the size of a token request in bytes. It fits a batched
+/// token request for several hundred tokens.
+pub const DEFAULT_MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// HTTP service of an issuer.
+#[derive(Debug)]
+pub struct IssuerService {
+    server: Arc<MultiTypeServer>,
+    directory: TokenKeyDirectory,
+    max_request_size: usize,
+}
+
+struct IssuerState {
+    server: Arc<MultiTypeServer>,
+    directory: TokenKeyDirectory,
+}
+
+impl IssuerService {
+    /// Creates a service that issues tokens with `server` and publishes
+    /// `directory`.
+    #[must_use]
+    pub fn new(server: Arc<MultiTypeServer>, directory: TokenKeyDirectory) -> Self {
+        Self {
+            server,
+            directory,
+            max_request_size: DEFAULT_MAX_REQUEST_SIZE,
+        }
+    }
+
+    /// Sets the limit of the size of a token request in bytes.
+    #[must_use]
+    pub const fn with_max_request_size(mut self, max_request_size: usize) -> Self {
+        self.max_request_size = max_request_size;
+        self
+    }
+
+    /// Returns the path the issuance endpoint is served at, i.e. the path of
+    /// the issuer request URI of the directory.
+    #[must_use]
+    pub fn token_request_path(&self) -> String {
+        self.directory
+            .issuer_request_uri()
+            .parse::<Uri>()
+            .ok()
+            .map(|uri| uri.path().to_string())
+            .filter(|path| path.starts_with('/') && path.len() > 1)
+            .unwrap_or_else(|| DEFAULT_TOKEN_REQUEST_PATH.to_string())
+    }
+
+    /// Builds the router. It can be served on its own or nested into the
+    /// router of the application.
+    pub fn router(self) -> Router {
+        let token_request_path = self.token_request_path();
+        let state = Arc::new(IssuerState {
+            server: self.server,
+            directory: self.directory,
+        });
+        Router::new()
+            .route(
+                &token_request_path,
+                post(token_request).layer(DefaultBodyLimit::max(self.max_request_size)),
+            )
+            .route(WELL_KNOWN_PATH, get(directory))
+            .with_state(state)
+    }
+}
+
+async fn token_request(
+    State(state): State<Arc<IssuerState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let media_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(str::trim);
+    if !media_type
+        .is_some_and(|media_type| media_type.eq_ignore_ascii_case(TOKEN_REQUEST_MEDIA_TYPE))
+    {
+        return problem_response(&ProblemDetails::new(
+            "unsupported-media-type",
+            "Token requests must be sent as application/private-token-request",
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        ));
+    }
+    match state.server.issue_token_response(&body).await {
+        Ok(token_response) => (
+            [(
+                CONTENT_TYPE,
+                HeaderValue::from_static(TOKEN_RESPONSE_MEDIA_TYPE),
+            )],
+            token_response,
+        )
+            .into_response(),
+        Err(error) => problem_response(&error.to_problem_details()),
+    }
+}
+
+async fn directory(State(state): State<Arc<IssuerState>>) -> Response {
+    match serde_json::to_vec(&state.directory) {
+        Ok(directory) => (
+            [(CONTENT_TYPE, HeaderValue::from_static(DIRECTORY_MEDIA_TYPE))],
+            directory,
+        )
+            .into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+fn problem_response(problem: &ProblemDetails) -> Response {
+    let (parts, body) = problem.to_response().into_parts();
+    Response::from_parts(parts, Body::from(body))
+}
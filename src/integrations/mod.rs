@@ -0,0 +1,80 @@
+//! # Web framework integrations
+//!
+//! Ready-made HTTP glue for issuers and origins. With the `axum` feature,
+//! [`axum::IssuerService`] serves the issuance endpoint and the issuer
+//! directory of a [`MultiTypeServer`](crate::dispatch::MultiTypeServer). With
+//! the `tower` feature, [`tower::TokenAuthLayer`] requires a valid token for
+//! every request to an origin. With the `actix` feature, the
+//! [`actix::RedeemedToken`] extractor does the same for actix-web handlers.
+//! Both challenge clients without a valid token with the challenges of
+//! [`ChallengeTemplate`]s.
+
+use http::HeaderValue;
+use rand::{rngs::OsRng, Rng};
+
+use crate::{
+    auth::authenticate::{build_www_authenticate_header, BuildError, TokenChallenge},
+    TokenType,
+};
+
+#[cfg(feature = "actix")]
+pub mod actix;
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(feature = "tower")]
+pub mod tower;
+
+/// Parameters of the challenges sent to clients without a valid token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChallengeTemplate {
+    token_type: TokenType,
+    issuer_name: String,
+    origin_info: Vec<String>,
+    token_key: Vec<u8>,
+    max_age: Option<u32>,
+}
+
+impl ChallengeTemplate {
+    /// Creates a template for challenges of `token_type` that name the issuer
+    /// `issuer_name` and its serialized public key `token_key`.
+    #[must_use]
+    pub fn new(token_type: TokenType, issuer_name: &str, token_key: &[u8]) -> Self {
+        Self {
+            token_type,
+            issuer_name: issuer_name.to_string(),
+            origin_info: Vec::new(),
+            token_key: token_key.to_vec(),
+            max_age: None,
+        }
+    }
+
+    /// Sets the origin names the tokens are bound to.
+    #[must_use]
+    pub fn with_origin_info(mut self, origin_info: Vec<String>) -> Self {
+        self.origin_info = origin_info;
+        self
+    }
+
+    /// Sets the `max-age` parameter of the challenges in seconds.
+    #[must_use]
+    pub const fn with_max_age(mut self, max_age: u32) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Builds a `WWW-Authenticate` header value with a fresh redemption
+    /// context.
+    ///
+    /// # Errors
+    /// Returns an error if the challenge cannot be serialized.
+    pub fn header_value(&self) -> Result<HeaderValue, BuildError> {
+        let challenge = TokenChallenge::new(
+            self.token_type,
+            &self.issuer_name,
+            Some(OsRng.gen()),
+            &self.origin_info,
+        );
+        build_www_authenticate_header(&challenge, &self.token_key, self.max_age)
+            .map(|(_, value)| value)
+    }
+}
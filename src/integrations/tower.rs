@@ -0,0 +1,138 @@
+//! # Tower integration
+//!
+//! [`TokenAuthLayer`] puts token enforcement in front of any
+//! [`tower::Service`] that handles [`http`] requests, e.g. a hyper service
+//! or an axum router. A request passes only if its `Authorization` header
+//! carries a `PrivateToken` that the [`MultiTypeServer`] redeems. Otherwise
+//! it is answered with `401 Unauthorized` and one `WWW-Authenticate`
+//! challenge per configured [`ChallengeTemplate`].
+//!
+//! Every challenge carries a freshly generated redemption context, so that
+//! clients cannot answer it with tokens fetched ahead of time. The layer does
+//! not remember the challenges it sent: a token is accepted if it is valid
+//! and unspent, whichever challenge it was issued for. Origins that need
+//! tokens bound to their own challenges should check the challenge digest in
+//! a [`RedemptionPolicy`](crate::policy::RedemptionPolicy).
+
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::future::BoxFuture;
+use http::{
+    header::{AUTHORIZATION, WWW_AUTHENTICATE},
+    HeaderValue, Request, Response, StatusCode,
+};
+use tower::{Layer, Service};
+
+use super::ChallengeTemplate;
+use crate::{
+    auth::{authorize::parse_authorization_header_bytes, ParseConfig},
+    dispatch::MultiTypeServer,
+};
+
+/// [`Layer`] that requires a valid token for every request.
+#[derive(Clone, Debug)]
+pub struct TokenAuthLayer {
+    server: Arc<MultiTypeServer>,
+    challenges: Arc<[ChallengeTemplate]>,
+    parse_config: ParseConfig,
+}
+
+impl TokenAuthLayer {
+    /// Creates a layer that redeems tokens with `server` and challenges
+    /// clients without a valid token with `challenges`.
+    #[must_use]
+    pub fn new(server: Arc<MultiTypeServer>, challenges: Vec<ChallengeTemplate>) -> Self {
+        Self {
+            server,
+            challenges: challenges.into(),
+            parse_config: ParseConfig::default(),
+        }
+    }
+
+    /// Sets the configuration of the `Authorization` header parser.
+    #[must_use]
+    pub const fn with_parse_config(mut self, parse_config: ParseConfig) -> Self {
+        self.parse_config = parse_config;
+        self
+    }
+
+    /// Redeems the first `PrivateToken` of the `Authorization` headers.
+    async fn authorize(&self, authorization: Vec<HeaderValue>) -> bool {
+        let Some(token) = authorization
+            .iter()
+            .find_map(|value| parse_authorization_header_bytes(value, &self.parse_config).ok())
+        else {
+            return false;
+        };
+        self.server.redeem_token(&token).await.is_ok()
+    }
+
+    fn unauthorized<B: Default>(&self) -> Response<B> {
+        let mut response = Response::new(B::default());
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        for challenge in self.challenges.iter() {
+            if let Ok(value) = challenge.header_value() {
+                response.headers_mut().append(WWW_AUTHENTICATE, value);
+            }
+        }
+        response
+    }
+}
+
+impl<S> Layer<S> for TokenAuthLayer {
+    type Service = TokenAuth<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TokenAuth {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// [`Service`] created by [`TokenAuthLayer`].
+#[derive(Clone, Debug)]
+pub struct TokenAuth<S> {
+    inner: S,
+    layer: TokenAuthLayer,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for TokenAuth<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    ReqBody: Send + 'static,
+    ResBody: Default + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        // The clone is not ready yet, so the service that was polled is used
+        // and the clone stays behind for the next request.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+        let authorization = request
+            .headers()
+            .get_all(AUTHORIZATION)
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        Box::pin(async move {
+            if layer.authorize(authorization).await {
+                inner.call(request).await
+            } else {
+                Ok(layer.unauthorized())
+            }
+        })
+    }
+}
@@ -0,0 +1,273 @@
+//! # Issuer directory
+//!
+//! Types of the issuer directory that issuers serve at
+//! `/.well-known/private-token-issuer-directory`:
+//!
+//! ```json
+//! {
+//!   "issuer-request-uri": "https://issuer.example.net/request",
+//!   "token-keys": [
+//!     {
+//!       "token-type": 2,
+//!       "token-key": "MI...AB",
+//!       "not-before": 1686913811
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Clients use the directory to make sure that the key they blind against is
+//! the one the issuer publishes to everyone, and not a key that was served to
+//! them alone.
+
+use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{
+    auth::URL_SAFE_LENIENT,
+    clock::{Clock, SystemClock},
+    KeyEpoch, TokenKeyId, TokenType,
+};
+
+/// Well-known path of the issuer directory.
+pub const WELL_KNOWN_PATH: &str = "/.well-known/private-token-issuer-directory";
+
+/// Media type of the issuer directory.
+pub const DIRECTORY_MEDIA_TYPE: &str = "application/private-token-issuer-directory";
+
+/// Encodes a serialized public key as base64url, the encoding of the
+/// `token-key` fields of the directory and of `token-key` challenge
+/// attributes.
+#[must_use]
+pub fn encode_token_key(token_key: &[u8]) -> String {
+    URL_SAFE.encode(token_key)
+}
+
+/// Decodes a base64url encoded public key, with or without padding.
+///
+/// # Errors
+/// Returns an error if the value is not valid base64url.
+pub fn decode_token_key(value: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    URL_SAFE_LENIENT.decode(value)
+}
+
+/// Errors that can occur when checking a token key against the directory.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DirectoryError {
+    #[error("Token key not found in the issuer directory")]
+    /// No directory entry matches the token type and token key.
+    KeyNotFound,
+    #[error("Token key is not valid yet")]
+    /// The matching directory entry is not valid yet.
+    KeyNotYetValid,
+}
+
+/// A token key entry of the issuer directory.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TokenKey {
+    token_type: u16,
+    token_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    not_before: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_epoch: Option<KeyEpoch>,
+}
+
+impl TokenKey {
+    /// Creates a new entry from a serialized public key. `not_before` is the
+    /// time from which on the key is used, in seconds since the Unix epoch.
+    #[must_use]
+    pub fn new(token_type: TokenType, token_key: &[u8], not_before: Option<u64>) -> Self {
+        Self {
+            token_type: token_type as u16,
+            token_key: encode_token_key(token_key),
+            not_before,
+            key_epoch: None,
+        }
+    }
+
+    /// Publishes the epoch the key was created for, so that auditors can
+    /// correlate keys, epochs and issuance volumes.
+    #[must_use]
+    pub const fn with_epoch(mut self, key_epoch: KeyEpoch) -> Self {
+        self.key_epoch = Some(key_epoch);
+        self
+    }
+
+    /// Returns the token type code point.
+    #[must_use]
+    pub const fn token_type(&self) -> u16 {
+        self.token_type
+    }
+
+    /// Returns the serialized public key, or `None` if the entry does not
+    /// contain valid base64url.
+    #[must_use]
+    pub fn token_key(&self) -> Option<Vec<u8>> {
+        decode_token_key(&self.token_key).ok()
+    }
+
+    /// Returns the token key ID of the public key, or `None` if the entry
+    /// does not contain valid base64url.
+    #[must_use]
+    pub fn token_key_id(&self) -> Option<TokenKeyId> {
+        self.token_key().map(|key| Sha256::digest(key).into())
+    }
+
+    /// Returns the optional not-before time in seconds since the Unix epoch.
+    #[must_use]
+    pub const fn not_before(&self) -> Option<u64> {
+        self.not_before
+    }
+
+    /// Returns the optional key epoch.
+    #[must_use]
+    pub const fn key_epoch(&self) -> Option<KeyEpoch> {
+        self.key_epoch
+    }
+}
+
+/// The issuer directory.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TokenKeyDirectory {
+    issuer_request_uri: String,
+    token_keys: Vec<TokenKey>,
+}
+
+impl TokenKeyDirectory {
+    /// Creates a new directory.
+    #[must_use]
+    pub fn new(issuer_request_uri: &str, token_keys: Vec<TokenKey>) -> Self {
+        Self {
+            issuer_request_uri: issuer_request_uri.to_string(),
+            token_keys,
+        }
+    }
+
+    /// Returns the URI of the issuance endpoint.
+    #[must_use]
+    pub fn issuer_request_uri(&self) -> &str {
+        &self.issuer_request_uri
+    }
+
+    /// Returns the token keys.
+    #[must_use]
+    pub fn token_keys(&self) -> &[TokenKey] {
+        &self.token_keys
+    }
+
+    /// Checks that the directory lists a key with the given token type and
+    /// token key ID that is valid at `now` (in seconds since the Unix epoch).
+    ///
+    /// # Errors
+    /// Returns an error if no entry matches or the matching entry is not
+    /// valid yet.
+    pub fn check_key_at(
+        &self,
+        token_type: TokenType,
+        token_key_id: &TokenKeyId,
+        now: u64,
+    ) -> Result<&TokenKey, DirectoryError> {
+        let mut result = Err(DirectoryError::KeyNotFound);
+        for token_key in self.token_keys.iter().filter(|token_key| {
+            token_key.token_type == token_type as u16
+                && token_key.token_key_id().as_ref() == Some(token_key_id)
+        }) {
+            match token_key.not_before {
+                Some(not_before) if not_before > now => {
+                    result = Err(DirectoryError::KeyNotYetValid);
+                }
+                _ => return Ok(token_key),
+            }
+        }
+        result
+    }
+
+    /// Checks that the directory lists a key with the given token type and
+    /// token key ID that is valid now.
+    ///
+    /// # Errors
+    /// Returns an error if no entry matches or the matching entry is not
+    /// valid yet.
+    pub fn check_key(
+        &self,
+        token_type: TokenType,
+        token_key_id: &TokenKeyId,
+    ) -> Result<&TokenKey, DirectoryError> {
+        self.check_key_with_clock(token_type, token_key_id, &SystemClock)
+    }
+
+    /// Checks that the directory lists a key with the given token type and
+    /// token key ID that is valid at the current time of `clock`.
+    ///
+    /// # Errors
+    /// Returns an error if no entry matches or the matching entry is not
+    /// valid yet.
+    pub fn check_key_with_clock<C: Clock + ?Sized>(
+        &self,
+        token_type: TokenType,
+        token_key_id: &TokenKeyId,
+        clock: &C,
+    ) -> Result<&TokenKey, DirectoryError> {
+        self.check_key_at(token_type, token_key_id, clock.unix_time())
+    }
+}
+
+#[test]
+fn directory_check_key() {
+    let key = b"public key";
+    let token_key_id: TokenKeyId = Sha256::digest(key).into();
+    let directory = TokenKeyDirectory::new(
+        "https://issuer.example.net/request",
+        vec![TokenKey::new(TokenType::PrivateToken, key, Some(100)).with_epoch(7)],
+    );
+
+    let json = serde_json::to_string(&directory).unwrap();
+    assert!(json.contains("\"token-type\":1"));
+    assert!(json.contains("\"not-before\":100"));
+    assert!(json.contains("\"key-epoch\":7"));
+    assert_eq!(
+        serde_json::from_str::<TokenKeyDirectory>(&json).unwrap(),
+        directory
+    );
+
+    assert!(directory
+        .check_key_at(TokenType::PrivateToken, &token_key_id, 100)
+        .is_ok());
+    assert_eq!(
+        directory.check_key_at(TokenType::PrivateToken, &token_key_id, 99),
+        Err(DirectoryError::KeyNotYetValid)
+    );
+    assert_eq!(
+        directory.check_key_at(TokenType::PublicToken, &token_key_id, 100),
+        Err(DirectoryError::KeyNotFound)
+    );
+    assert_eq!(
+        directory.check_key_at(TokenType::PrivateToken, &[0u8; 32], 100),
+        Err(DirectoryError::KeyNotFound)
+    );
+
+    let clock = crate::clock::TestClock::from_unix_time(99);
+    assert_eq!(
+        directory.check_key_with_clock(TokenType::PrivateToken, &token_key_id, &clock),
+        Err(DirectoryError::KeyNotYetValid)
+    );
+    clock.advance(std::time::Duration::from_secs(1));
+    assert!(directory
+        .check_key_with_clock(TokenType::PrivateToken, &token_key_id, &clock)
+        .is_ok());
+}
+
+#[test]
+fn token_key_encoding() {
+    let token_key = [0xfbu8, 0xff, 0x01];
+    assert_eq!(encode_token_key(&token_key), "-_8B");
+    assert_eq!(decode_token_key("-_8B").unwrap(), token_key);
+    assert_eq!(decode_token_key("-_8").unwrap(), [0xfb, 0xff]);
+    assert!(decode_token_key("+/8B").is_err());
+}
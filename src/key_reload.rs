@@ -0,0 +1,480 @@
+//! # Hot key reload
+//!
+//! Issuers that keep their keys in external storage, such as files, a
+//! database or a key management service, can pick up key changes without a
+//! restart. A [`KeySource`] reports a version of the backing store, for
+//! example the mtime of a key file or a row version, and loads the full key
+//! set. A [`KeyWatcher`] compares the version on every [`KeyWatcher::poll`]
+//! and swaps the loaded keys into a [`ReloadableKeyStore`] in one step, so
+//! that requests either see the old or the new key set, never a mix.
+//! Sources that are notified of changes, e.g. over pub/sub, call
+//! [`KeyWatcher::reload`] instead.
+//!
+//! Every swap emits a [`KeySetChanged`] event to all subscribers, so that
+//! the issuer directory can be refreshed.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use async_trait::async_trait;
+use thiserror::Error;
+use tokio::sync::{broadcast, Mutex};
+
+use crate::{KeyStoreError, KeyValidity, MaybeSendSync, TruncatedTokenKeyId};
+
+/// Number of events that are buffered for subscribers that lag behind.
+const EVENT_CAPACITY: usize = 16;
+
+/// Errors that can occur when reloading keys.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum KeyReloadError {
+    #[error("Key source unavailable")]
+    /// Error when the backing store cannot be reached.
+    SourceUnavailable,
+    #[error("Invalid key material")]
+    /// Error when the backing store contains key material that cannot be
+    /// parsed.
+    InvalidKey,
+}
+
+/// Backing store of a key set.
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+pub trait KeySource: MaybeSendSync {
+    /// Key material of the token type, e.g. `VoprfServer<Ristretto255>`.
+    type Key: Send + Sync;
+
+    /// Returns a version that changes whenever the key set changes, e.g. the
+    /// mtime of a key file or a row version.
+    async fn version(&self) -> Result<u64, KeyReloadError>;
+
+    /// Loads the full key set.
+    async fn load(&self) -> Result<HashMap<TruncatedTokenKeyId, Self::Key>, KeyReloadError>;
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<S: KeySource + ?Sized> KeySource for Arc<S> {
+    type Key = S::Key;
+
+    async fn version(&self) -> Result<u64, KeyReloadError> {
+        (**self).version().await
+    }
+
+    async fn load(&self) -> Result<HashMap<TruncatedTokenKeyId, Self::Key>, KeyReloadError> {
+        (**self).load().await
+    }
+}
+
+/// Key store whose key set can be swapped atomically while it serves
+/// requests.
+///
+/// Validity periods are recorded through the key store traits and survive
+/// swaps, since key sources only load key material.
+pub struct ReloadableKeyStore<K> {
+    keys: RwLock<Arc<HashMap<TruncatedTokenKeyId, K>>>,
+    validities: RwLock<HashMap<TruncatedTokenKeyId, KeyValidity>>,
+}
+
+impl<K> Default for ReloadableKeyStore<K> {
+    fn default() -> Self {
+        Self {
+            keys: RwLock::new(Arc::new(HashMap::new())),
+            validities: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K> fmt::Debug for ReloadableKeyStore<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut key_ids: Vec<_> = self.keys().keys().copied().collect();
+        key_ids.sort_unstable();
+        f.debug_struct("ReloadableKeyStore")
+            .field("key_ids", &key_ids)
+            .finish()
+    }
+}
+
+impl<K> ReloadableKeyStore<K> {
+    /// Creates an empty key store.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a snapshot of the current key set.
+    #[must_use]
+    pub fn keys(&self) -> Arc<HashMap<TruncatedTokenKeyId, K>> {
+        self.keys
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Replaces the key set and returns the previous one.
+    pub fn swap(
+        &self,
+        keys: HashMap<TruncatedTokenKeyId, K>,
+    ) -> Arc<HashMap<TruncatedTokenKeyId, K>> {
+        let mut current = self.keys.write().unwrap_or_else(PoisonError::into_inner);
+        std::mem::replace(&mut *current, Arc::new(keys))
+    }
+}
+
+impl<K: Clone> ReloadableKeyStore<K> {
+    fn insert_key(&self, truncated_token_key_id: TruncatedTokenKeyId, key: K) {
+        let mut current = self.keys.write().unwrap_or_else(PoisonError::into_inner);
+        Arc::make_mut(&mut current).insert(truncated_token_key_id, key);
+    }
+
+    fn get_key(&self, truncated_token_key_id: &TruncatedTokenKeyId) -> Option<K> {
+        self.keys().get(truncated_token_key_id).cloned()
+    }
+
+    fn remove_key(&self, truncated_token_key_id: &TruncatedTokenKeyId) -> bool {
+        self.validities
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(truncated_token_key_id);
+        let mut current = self.keys.write().unwrap_or_else(PoisonError::into_inner);
+        if !current.contains_key(truncated_token_key_id) {
+            return false;
+        }
+        Arc::make_mut(&mut current).remove(truncated_token_key_id);
+        true
+    }
+
+    fn set_key_validity(&self, truncated_token_key_id: TruncatedTokenKeyId, validity: KeyValidity) {
+        self.validities
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(truncated_token_key_id, validity);
+    }
+
+    fn key_validity(&self, truncated_token_key_id: &TruncatedTokenKeyId) -> KeyValidity {
+        self.validities
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(truncated_token_key_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn key_ids(&self) -> Vec<TruncatedTokenKeyId> {
+        self.keys().keys().copied().collect()
+    }
+}
+
+/// Event emitted whenever a [`KeyWatcher`] swapped in a new key set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeySetChanged {
+    /// Version of the backing store the key set was loaded from.
+    pub version: u64,
+    /// Key IDs that were not in the previous key set.
+    pub added: Vec<TruncatedTokenKeyId>,
+    /// Key IDs that are no longer in the key set.
+    pub removed: Vec<TruncatedTokenKeyId>,
+}
+
+/// Watches a [`KeySource`] and swaps its key set into a
+/// [`ReloadableKeyStore`] when it changes.
+pub struct KeyWatcher<S: KeySource> {
+    source: S,
+    key_store: Arc<ReloadableKeyStore<S::Key>>,
+    version: Mutex<Option<u64>>,
+    events: broadcast::Sender<KeySetChanged>,
+}
+
+impl<S: KeySource> fmt::Debug for KeyWatcher<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyWatcher")
+            .field("key_store", &self.key_store)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: KeySource> KeyWatcher<S> {
+    /// Creates a new watcher. The key set is loaded on the first poll.
+    #[must_use]
+    pub fn new(source: S, key_store: Arc<ReloadableKeyStore<S::Key>>) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        Self {
+            source,
+            key_store,
+            version: Mutex::new(None),
+            events,
+        }
+    }
+
+    /// Returns the key store the watcher swaps key sets into.
+    #[must_use]
+    pub fn key_store(&self) -> &Arc<ReloadableKeyStore<S::Key>> {
+        &self.key_store
+    }
+
+    /// Subscribes to the events of future key set swaps.
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<KeySetChanged> {
+        self.events.subscribe()
+    }
+
+    /// Reloads the key set if the version of the source changed since the
+    /// last reload. Returns the emitted event, or `None` if the key set is
+    /// unchanged.
+    ///
+    /// # Errors
+    /// Returns an error if the source cannot be read. The current key set
+    /// keeps serving in that case.
+    pub async fn poll(&self) -> Result<Option<KeySetChanged>, KeyReloadError> {
+        let mut current_version = self.version.lock().await;
+        let version = self.source.version().await?;
+        if *current_version == Some(version) {
+            return Ok(None);
+        }
+        self.swap(&mut current_version, version).await.map(Some)
+    }
+
+    /// Reloads the key set regardless of its version, e.g. after a change
+    /// notification.
+    ///
+    /// # Errors
+    /// Returns an error if the source cannot be read. The current key set
+    /// keeps serving in that case.
+    pub async fn reload(&self) -> Result<KeySetChanged, KeyReloadError> {
+        let mut current_version = self.version.lock().await;
+        let version = self.source.version().await?;
+        self.swap(&mut current_version, version).await
+    }
+
+    async fn swap(
+        &self,
+        current_version: &mut Option<u64>,
+        version: u64,
+    ) -> Result<KeySetChanged, KeyReloadError> {
+        let keys = self.source.load().await?;
+        let mut added: Vec<_> = keys.keys().copied().collect();
+        let previous = self.key_store.swap(keys);
+        added.retain(|key_id| !previous.contains_key(key_id));
+        added.sort_unstable();
+        let current = self.key_store.keys();
+        let mut removed: Vec<_> = previous
+            .keys()
+            .copied()
+            .filter(|key_id| !current.contains_key(key_id))
+            .collect();
+        removed.sort_unstable();
+        *current_version = Some(version);
+
+        let event = KeySetChanged {
+            version,
+            added,
+            removed,
+        };
+        // Sending only fails if nobody is subscribed.
+        let _ = self.events.send(event.clone());
+        Ok(event)
+    }
+}
+
+#[cfg(feature = "p384")]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl crate::private_tokens::server::PrivateKeyStore
+    for ReloadableKeyStore<voprf::VoprfServer<p384::NistP384>>
+{
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        server: voprf::VoprfServer<p384::NistP384>,
+    ) -> Result<(), KeyStoreError> {
+        self.insert_key(truncated_token_key_id, server);
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<voprf::VoprfServer<p384::NistP384>>, KeyStoreError> {
+        Ok(self.get_key(truncated_token_key_id))
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(self.remove_key(truncated_token_key_id))
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        Ok(self.key_ids())
+    }
+
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        self.set_key_validity(truncated_token_key_id, validity);
+        Ok(())
+    }
+
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        Ok(self.key_validity(truncated_token_key_id))
+    }
+}
+
+#[cfg(feature = "p384")]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl crate::batched_tokens_p384::server::BatchedKeyStore
+    for ReloadableKeyStore<voprf::VoprfServer<p384::NistP384>>
+{
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        server: voprf::VoprfServer<p384::NistP384>,
+    ) -> Result<(), KeyStoreError> {
+        self.insert_key(truncated_token_key_id, server);
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<voprf::VoprfServer<p384::NistP384>>, KeyStoreError> {
+        Ok(self.get_key(truncated_token_key_id))
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(self.remove_key(truncated_token_key_id))
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        Ok(self.key_ids())
+    }
+
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        self.set_key_validity(truncated_token_key_id, validity);
+        Ok(())
+    }
+
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        Ok(self.key_validity(truncated_token_key_id))
+    }
+}
+
+#[cfg(feature = "ristretto255")]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl crate::batched_tokens_ristretto255::server::BatchedKeyStore
+    for ReloadableKeyStore<voprf::VoprfServer<voprf::Ristretto255>>
+{
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        server: voprf::VoprfServer<voprf::Ristretto255>,
+    ) -> Result<(), KeyStoreError> {
+        self.insert_key(truncated_token_key_id, server);
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<voprf::VoprfServer<voprf::Ristretto255>>, KeyStoreError> {
+        Ok(self.get_key(truncated_token_key_id))
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(self.remove_key(truncated_token_key_id))
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        Ok(self.key_ids())
+    }
+
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        self.set_key_validity(truncated_token_key_id, validity);
+        Ok(())
+    }
+
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        Ok(self.key_validity(truncated_token_key_id))
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl crate::public_tokens::server::IssuerKeyStore
+    for ReloadableKeyStore<blind_rsa_signatures::KeyPair>
+{
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        server: blind_rsa_signatures::KeyPair,
+    ) -> Result<(), KeyStoreError> {
+        self.insert_key(truncated_token_key_id, server);
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<blind_rsa_signatures::KeyPair>, KeyStoreError> {
+        Ok(self.get_key(truncated_token_key_id))
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(self.remove_key(truncated_token_key_id))
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        Ok(self.key_ids())
+    }
+
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        self.set_key_validity(truncated_token_key_id, validity);
+        Ok(())
+    }
+
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        Ok(self.key_validity(truncated_token_key_id))
+    }
+}
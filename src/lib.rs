@@ -11,25 +11,131 @@
 //!  - Publicly Verfifiable Tokens
 //!  - Batched Tokens
 //!
+//! The VOPRF ciphersuites are selected at compile time with the `p384` and
+//! `ristretto255` features, which are both enabled by default. Builds that
+//! only need batched Ristretto255 tokens can disable default features and
+//! enable `ristretto255` alone. The [`dynamic`] module selects among the
+//! enabled ciphersuites at runtime.
+//!
+//! The async traits, e.g. [`NonceStore`] and the key stores of the token
+//! types, return `Send` futures and require `Send + Sync` implementations
+//! while the default `send` feature is enabled. Single-threaded runtimes such
+//! as monoio or the browser can disable it to implement them on `Rc`,
+//! `RefCell` or `JsValue`, with futures that are not `Send`. Such
+//! implementations stop compiling once anything in the build enables `send`
+//! again, including the `axum` and `tower` integrations, which require it.
+//!
+//! With the `tracing` feature, issuance, redemption, key creation and client
+//! issuance are instrumented with `tracing` spans at the debug level. Spans
+//! record token types, truncated token key IDs and batch sizes, never nonces
+//! or other token contents.
+//!
 
 #![warn(missing_docs)]
 #![deny(unreachable_pub)]
 #![deny(missing_debug_implementations)]
 #![deny(unsafe_code)]
+// Input-dependent panics are reported as errors instead.
+#![cfg_attr(
+    not(test),
+    deny(
+        clippy::unwrap_used,
+        clippy::expect_used,
+        clippy::panic,
+        clippy::unreachable,
+        clippy::todo,
+        clippy::unimplemented
+    )
+)]
+// The shared VOPRF helpers are unused if no ciphersuite is enabled.
+#![cfg_attr(
+    not(any(feature = "p384", feature = "ristretto255")),
+    allow(dead_code, unused_imports)
+)]
 
+pub mod arbitrary_batched_tokens;
+pub mod attestation;
 pub mod auth;
+pub mod batched_tokens;
+#[cfg(feature = "p384")]
 pub mod batched_tokens_p384;
+#[cfg(feature = "ristretto255")]
 pub mod batched_tokens_ristretto255;
+pub mod bloom_nonce_store;
+pub mod clock;
+pub mod concurrency;
+pub mod config;
+#[cfg(feature = "config-file")]
+pub mod config_file;
+pub mod dispatch;
+pub mod dynamic;
+pub mod evaluator;
+pub mod extensions;
+pub mod integrations;
+pub mod issuer_directory;
+pub mod key_reload;
+#[cfg(feature = "loadgen")]
+pub mod loadgen;
+#[cfg(feature = "memory-stores")]
+pub mod memory_stores;
+pub mod metrics;
+#[cfg(feature = "mmap-nonce-store")]
+pub mod mmap_nonce_store;
+pub mod origin;
+pub mod policy;
+#[cfg(feature = "pem")]
+pub mod private_keys;
+#[cfg(feature = "p384")]
 pub mod private_tokens;
+pub mod problem_details;
+pub mod protocol;
 pub mod public_tokens;
+#[cfg(feature = "redis-challenge-store")]
+pub mod redis_challenge_store;
+#[cfg(feature = "redis-nonce-store")]
+pub mod redis_nonce_store;
+#[cfg(feature = "serde-wire")]
+pub mod serde_wire;
+#[cfg(feature = "sled-nonce-store")]
+pub mod sled_nonce_store;
+#[cfg(any(feature = "sqlx-postgres", feature = "sqlx-sqlite"))]
+pub mod sqlx_stores;
+#[cfg(feature = "kat")]
+pub mod test_vectors;
+pub mod token_store;
+pub mod transport;
+#[cfg(feature = "experimental-unblinding")]
+mod unblinding;
+pub mod webhooks;
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex, OnceLock, PoisonError},
+    time::Duration,
+};
 
 use async_trait::async_trait;
+#[cfg(any(feature = "p384", feature = "ristretto255"))]
+use generic_array::GenericArray;
+use rand::{rngs::OsRng, CryptoRng, RngCore};
+use thiserror::Error;
 use tls_codec_derive::{TlsDeserialize, TlsSerialize, TlsSize};
+use typenum::Unsigned;
+use voprf::Group;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
+pub use secrecy::{ExposeSecret, SecretString, SecretVec};
 pub use tls_codec::{Deserialize, Serialize};
 
+// `std::time::Instant` panics in browsers, where `web-time` reads the time
+// from the Performance API instead.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) use std::time::Instant;
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) use web_time::Instant;
+
 /// Token type
-#[derive(TlsSize, TlsSerialize, TlsDeserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(TlsSize, TlsSerialize, TlsDeserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
 #[repr(u16)]
 pub enum TokenType {
     /// Privately verifiable token
@@ -40,8 +146,520 @@ pub enum TokenType {
     BatchedTokenRistretto255 = 0xF91A,
     /// Batched token 2
     BatchedTokenP384 = 0xF901,
+    /// Batched token with the final code point
+    BatchedTokenRistretto255Final = 0x0005,
+}
+
+impl TokenType {
+    /// Size of a token key ID in bytes, which is the same for all token types.
+    pub const NID: usize = 32;
+
+    /// Returns the size of the token authenticator in bytes.
+    #[must_use]
+    pub const fn nk(self) -> usize {
+        match self {
+            Self::PrivateToken | Self::BatchedTokenP384 => 48,
+            Self::PublicToken => 256,
+            Self::BatchedTokenRistretto255 | Self::BatchedTokenRistretto255Final => 64,
+        }
+    }
+
+    /// Returns the size of a serialized token of this type in bytes.
+    #[must_use]
+    pub const fn token_len(self) -> usize {
+        2 + 32 + 32 + Self::NID + self.nk()
+    }
+
+    /// Returns the token types whose ciphersuites are enabled in this build.
+    pub fn supported() -> impl Iterator<Item = Self> {
+        [
+            #[cfg(feature = "p384")]
+            Self::PrivateToken,
+            Self::PublicToken,
+            #[cfg(feature = "ristretto255")]
+            Self::BatchedTokenRistretto255Final,
+            #[cfg(feature = "p384")]
+            Self::BatchedTokenP384,
+            #[cfg(feature = "ristretto255")]
+            Self::BatchedTokenRistretto255,
+        ]
+        .into_iter()
+    }
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::PrivateToken => "PrivateToken",
+            Self::PublicToken => "PublicToken",
+            Self::BatchedTokenRistretto255 => "BatchedTokenRistretto255",
+            Self::BatchedTokenP384 => "BatchedTokenP384",
+            Self::BatchedTokenRistretto255Final => "BatchedTokenRistretto255Final",
+        }
+    }
+}
+
+/// Errors that can occur when converting a code point into a token type.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TokenTypeError {
+    #[error("Unknown token type {0:#06x}")]
+    /// Error when the code point is not a known token type.
+    Unknown(u16),
+}
+
+impl TryFrom<u16> for TokenType {
+    type Error = TokenTypeError;
+
+    fn try_from(code_point: u16) -> Result<Self, Self::Error> {
+        match code_point {
+            1 => Ok(Self::PrivateToken),
+            2 => Ok(Self::PublicToken),
+            0xF91A => Ok(Self::BatchedTokenRistretto255),
+            0xF901 => Ok(Self::BatchedTokenP384),
+            0x0005 => Ok(Self::BatchedTokenRistretto255Final),
+            code_point => Err(TokenTypeError::Unknown(code_point)),
+        }
+    }
+}
+
+impl fmt::Display for TokenType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:#06x})", self.name(), *self as u16)
+    }
+}
+
+/// Selects the token type code points that are used on the wire, so that
+/// clients and servers can be moved from the draft code points to the ones of
+/// the final specifications independently of each other.
+///
+/// The header builders and [`ParseConfig`](auth::ParseConfig) use the same
+/// modes for the spelling of the `WWW-Authenticate` and `Authorization`
+/// parameters: the drafts send bare values such as `token-key=abc`, RFC 9577
+/// quotes them as in `token-key="abc"`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CodePoints {
+    /// Emit and accept the draft code points only (e.g. `0xF91A` for batched
+    /// Ristretto255 tokens), and bare parameter values.
+    #[default]
+    Draft,
+    /// Emit and accept the final code points only (e.g. `0x0005` for batched
+    /// Ristretto255 tokens), and emit quoted parameter values.
+    Final,
+    /// Emit the final code points, but also accept the draft ones.
+    Transitional,
+}
+
+impl CodePoints {
+    /// Maps a token type to the code point that is emitted on the wire.
+    #[must_use]
+    pub const fn emit(self, token_type: TokenType) -> TokenType {
+        match (self, token_type) {
+            (Self::Draft, TokenType::BatchedTokenRistretto255Final) => {
+                TokenType::BatchedTokenRistretto255
+            }
+            (Self::Final | Self::Transitional, TokenType::BatchedTokenRistretto255) => {
+                TokenType::BatchedTokenRistretto255Final
+            }
+            (_, token_type) => token_type,
+        }
+    }
+
+    /// Returns `true` if `received` is an acceptable code point for the token
+    /// type `expected`.
+    #[must_use]
+    pub fn accepts(self, expected: TokenType, received: TokenType) -> bool {
+        received == self.emit(expected)
+            || (self == Self::Transitional && received == Self::Draft.emit(expected))
+    }
+}
+
+/// Selects whether a VOPRF client verifies the DLEQ proof of a token response
+/// before unblinding the evaluated elements.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProofVerification {
+    /// Verify the proof. This is the only mode that protects the client
+    /// against an issuer that evaluates with a different key than the one it
+    /// published.
+    #[default]
+    Verify,
+    /// Skip proof verification and only unblind the evaluated elements.
+    ///
+    /// Without the proof the client cannot tell whether the issuer used the
+    /// published key. A malicious issuer can then evaluate each client's
+    /// requests with a per-client key and link tokens to clients at
+    /// redemption time. Only use this mode on very constrained devices that
+    /// talk to a fully trusted issuer over an authenticated channel.
+    ///
+    /// Issuance also no longer notices a response whose evaluated elements
+    /// were reordered or substituted: the client issues tokens without an
+    /// error, and these tokens fail to redeem.
+    ///
+    /// Requires the experimental `experimental-unblinding` feature.
+    #[cfg(feature = "experimental-unblinding")]
+    SkipForTrustedIssuer,
+}
+
+/// Durations of the stages of client finalization.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FinalizeTimings {
+    /// Time spent decoding the evaluated elements and the proof.
+    pub decode: Duration,
+    /// Time spent verifying the proof and unblinding, or only unblinding if
+    /// proof verification is skipped.
+    pub finalize: Duration,
+    /// Whether the proof was verified.
+    pub proof_verified: bool,
+}
+
+/// Callback that receives the [`FinalizeTimings`] of every successful client
+/// finalization.
+#[derive(Clone)]
+pub struct FinalizeObserver(Arc<dyn Fn(&FinalizeTimings) + Send + Sync>);
+
+impl FinalizeObserver {
+    /// Creates a new observer from a callback.
+    pub fn new<F: Fn(&FinalizeTimings) + Send + Sync + 'static>(callback: F) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    pub(crate) fn observe(&self, timings: &FinalizeTimings) {
+        (self.0)(timings);
+    }
+}
+
+impl fmt::Debug for FinalizeObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("FinalizeObserver").finish()
+    }
+}
+
+/// Token that a server rejected because its nonce was already spent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DoubleSpendEvent {
+    /// Token type of the token.
+    pub token_type: TokenType,
+    /// Key ID the token claims to be issued under.
+    pub token_key_id: TokenKeyId,
+    /// Digest of the challenge the token was issued for.
+    pub challenge_digest: ChallengeDigest,
+}
+
+/// Callback that receives a [`DoubleSpendEvent`] whenever a server rejects a
+/// token as double spent, e.g. to alert on replay attempts.
+///
+/// With [`RedemptionErrors::Detailed`], the nonce is checked before the
+/// token is verified, so the key ID and the challenge digest of an event are
+/// not necessarily authentic.
+#[derive(Clone)]
+pub struct DoubleSpendObserver(Arc<dyn Fn(&DoubleSpendEvent) + Send + Sync>);
+
+impl DoubleSpendObserver {
+    /// Creates a new observer from a callback.
+    pub fn new<F: Fn(&DoubleSpendEvent) + Send + Sync + 'static>(callback: F) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    pub(crate) fn observe(&self, event: &DoubleSpendEvent) {
+        (self.0)(event);
+    }
+}
+
+impl fmt::Debug for DoubleSpendObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DoubleSpendObserver").finish()
+    }
+}
+
+/// Durations of the stages of a VOPRF issuance.
+#[cfg(feature = "profiling")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IssuanceTimings {
+    /// Time spent fetching the key from the key store.
+    pub key_fetch: Duration,
+    /// Time spent deserializing the blinded elements.
+    pub deserialize: Duration,
+    /// Time spent preparing the evaluation, i.e. multiplying the blinded
+    /// elements with the private key.
+    pub prepare: Duration,
+    /// Time spent finishing the evaluation and generating the DLEQ proof.
+    pub evaluate: Duration,
+    /// Time spent serializing the evaluated elements and the proof.
+    pub serialize: Duration,
+}
+
+/// Callback that receives the [`IssuanceTimings`] of every successful
+/// issuance.
+#[cfg(feature = "profiling")]
+#[derive(Clone)]
+pub struct IssuanceObserver(Arc<dyn Fn(&IssuanceTimings) + Send + Sync>);
+
+#[cfg(feature = "profiling")]
+impl IssuanceObserver {
+    /// Creates a new observer from a callback.
+    pub fn new<F: Fn(&IssuanceTimings) + Send + Sync + 'static>(callback: F) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    pub(crate) fn observe(&self, timings: &IssuanceTimings) {
+        (self.0)(timings);
+    }
+}
+
+#[cfg(feature = "profiling")]
+impl fmt::Debug for IssuanceObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("IssuanceObserver").finish()
+    }
+}
+
+/// Stages of a VOPRF issuance.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum IssuanceStage {
+    KeyFetch,
+    Deserialize,
+    Prepare,
+    Evaluate,
+    Serialize,
+}
+
+/// Records the time spent in each issuance stage. Without the `profiling`
+/// feature this is a no-op.
+#[derive(Debug)]
+pub(crate) struct IssuanceProfiler {
+    #[cfg(feature = "profiling")]
+    last: Instant,
+    #[cfg(feature = "profiling")]
+    timings: IssuanceTimings,
+}
+
+impl IssuanceProfiler {
+    pub(crate) fn start() -> Self {
+        Self {
+            #[cfg(feature = "profiling")]
+            last: Instant::now(),
+            #[cfg(feature = "profiling")]
+            timings: IssuanceTimings::default(),
+        }
+    }
+
+    /// Attributes the time since the previous stage to `stage`.
+    #[cfg_attr(not(feature = "profiling"), allow(unused_variables))]
+    pub(crate) fn record(&mut self, stage: IssuanceStage) {
+        #[cfg(feature = "profiling")]
+        {
+            let now = Instant::now();
+            let elapsed = now - self.last;
+            self.last = now;
+            let duration = match stage {
+                IssuanceStage::KeyFetch => &mut self.timings.key_fetch,
+                IssuanceStage::Deserialize => &mut self.timings.deserialize,
+                IssuanceStage::Prepare => &mut self.timings.prepare,
+                IssuanceStage::Evaluate => &mut self.timings.evaluate,
+                IssuanceStage::Serialize => &mut self.timings.serialize,
+            };
+            *duration += elapsed;
+        }
+    }
+
+    #[cfg(feature = "profiling")]
+    pub(crate) fn finish(self, observer: Option<&IssuanceObserver>) {
+        if let Some(observer) = observer {
+            observer.observe(&self.timings);
+        }
+    }
+}
+
+/// Selects how much detail servers reveal about failed redemptions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RedemptionErrors {
+    /// Report the cause of a failure, e.g. an unknown key ID.
+    #[default]
+    Detailed,
+    /// Report unknown key IDs, malformed tokens and invalid authenticators
+    /// uniformly as invalid tokens, and take approximately the same time for
+    /// each of them, so that attackers cannot tell the causes apart.
+    Uniform,
+}
+
+/// Auditable identifier of the epoch a key was created for
+pub type KeyEpoch = u64;
+
+/// Validity period of a keypair, in seconds since the Unix epoch, so that
+/// keys can be rotated on a schedule.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct KeyValidity {
+    /// Time from which on tokens are issued under the key, or `None` if
+    /// they are issued from the creation of the key on.
+    pub not_before: Option<u64>,
+    /// Time from which on no tokens are issued under the key, or `None` if
+    /// the key does not expire. Tokens issued under the key are redeemed
+    /// until this time plus the redemption grace period of the server.
+    pub not_after: Option<u64>,
+    /// Time until which tokens issued under the key are redeemed even if the
+    /// redemption grace period has ended, e.g. because the key was rotated
+    /// out, or `None` if only the grace period applies.
+    pub redeemable_until: Option<u64>,
+}
+
+impl KeyValidity {
+    /// Creates a validity period.
+    #[must_use]
+    pub const fn new(not_before: Option<u64>, not_after: Option<u64>) -> Self {
+        Self {
+            not_before,
+            not_after,
+            redeemable_until: None,
+        }
+    }
+
+    /// Redeems tokens issued under the key until `redeemable_until`.
+    #[must_use]
+    pub const fn with_redeemable_until(mut self, redeemable_until: u64) -> Self {
+        self.redeemable_until = Some(redeemable_until);
+        self
+    }
+
+    /// Returns `true` if tokens may be issued under the key at `now`.
+    #[must_use]
+    pub fn allows_issuance(&self, now: u64) -> bool {
+        self.not_before.is_none_or(|not_before| now >= not_before)
+            && self.not_after.is_none_or(|not_after| now < not_after)
+    }
+
+    /// Returns `true` if tokens issued under the key may be redeemed at
+    /// `now`, which may lie up to `grace_period` seconds after `not_after`,
+    /// or before `redeemable_until`. The not-before time is not checked,
+    /// since a token can only have been issued after it.
+    #[must_use]
+    pub fn allows_redemption(&self, now: u64, grace_period: u64) -> bool {
+        self.not_after
+            .is_none_or(|not_after| now < not_after.saturating_add(grace_period))
+            || self
+                .redeemable_until
+                .is_some_and(|redeemable_until| now < redeemable_until)
+    }
+}
+
+/// Returns the derivation info used when creating VOPRF keys. If an epoch is
+/// given, it is bound into the info as `"PrivacyPass-epoch-" || I2OSP(epoch, 8)`.
+pub(crate) fn key_derivation_info(epoch: Option<KeyEpoch>) -> Vec<u8> {
+    let mut info = b"PrivacyPass".to_vec();
+    if let Some(epoch) = epoch {
+        info.extend_from_slice(b"-epoch-");
+        info.extend_from_slice(&epoch.to_be_bytes());
+    }
+    info
 }
 
+/// Returns a fresh random seed for deriving a VOPRF key. The seed is wiped
+/// when it is dropped.
+pub(crate) fn random_seed<G: Group>(rng: &mut impl RngCore) -> SecretVec<u8> {
+    let mut seed = vec![0u8; G::ScalarLen::USIZE];
+    rng.fill_bytes(&mut seed);
+    SecretVec::new(seed)
+}
+
+/// Returns the server that tokens with unknown key IDs are checked against
+/// when redemption errors are uniform, so that they take as long as tokens
+/// with known key IDs. Its key is derived on the first call only, since the
+/// derivation would otherwise add time to unknown key IDs alone.
+#[cfg(feature = "p384")]
+pub(crate) fn uniform_server_p384() -> Option<&'static voprf::VoprfServer<p384::NistP384>> {
+    static SERVER: OnceLock<Option<voprf::VoprfServer<p384::NistP384>>> = OnceLock::new();
+    SERVER
+        .get_or_init(|| {
+            voprf::VoprfServer::new_from_seed(
+                &GenericArray::<u8, <p384::NistP384 as Group>::ScalarLen>::default(),
+                b"PrivacyPass-uniform",
+            )
+            .ok()
+        })
+        .as_ref()
+}
+
+/// Returns the Ristretto255 server that tokens with unknown key IDs are
+/// checked against, see `uniform_server_p384`.
+#[cfg(feature = "ristretto255")]
+pub(crate) fn uniform_server_ristretto255(
+) -> Option<&'static voprf::VoprfServer<voprf::Ristretto255>> {
+    static SERVER: OnceLock<Option<voprf::VoprfServer<voprf::Ristretto255>>> = OnceLock::new();
+    SERVER
+        .get_or_init(|| {
+            voprf::VoprfServer::new_from_seed(
+                &GenericArray::<u8, <voprf::Ristretto255 as Group>::ScalarLen>::default(),
+                b"PrivacyPass-uniform",
+            )
+            .ok()
+        })
+        .as_ref()
+}
+
+/// Generator that can be injected into a [`ServerRng`].
+trait InjectedRng: RngCore + CryptoRng + Send {}
+
+impl<R: RngCore + CryptoRng + Send> InjectedRng for R {}
+
+/// Random number generator of the servers, used for key seeds, proofs and
+/// blind signatures.
+///
+/// By default it draws from the OS random number generator. A deterministic
+/// generator can be injected for test vectors, fuzzing and platforms without
+/// OS entropy. Clones share the injected generator.
+#[derive(Clone, Default)]
+pub struct ServerRng {
+    injected: Option<Arc<Mutex<dyn InjectedRng>>>,
+}
+
+impl fmt::Debug for ServerRng {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerRng")
+            .field("injected", &self.injected.is_some())
+            .finish()
+    }
+}
+
+impl ServerRng {
+    /// Returns a generator that draws from the OS random number generator.
+    #[must_use]
+    pub const fn os() -> Self {
+        Self { injected: None }
+    }
+
+    /// Returns a generator that draws from `rng`.
+    #[must_use]
+    pub fn new<R: RngCore + CryptoRng + Send + 'static>(rng: R) -> Self {
+        Self {
+            injected: Some(Arc::new(Mutex::new(rng))),
+        }
+    }
+
+    fn with<T>(&mut self, f: impl FnOnce(&mut dyn InjectedRng) -> T) -> T {
+        match &self.injected {
+            Some(rng) => f(&mut *rng.lock().unwrap_or_else(PoisonError::into_inner)),
+            None => f(&mut OsRng),
+        }
+    }
+}
+
+impl RngCore for ServerRng {
+    fn next_u32(&mut self) -> u32 {
+        self.with(|rng| rng.next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.with(|rng| rng.next_u64())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.with(|rng| rng.fill_bytes(dest));
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.with(|rng| rng.try_fill_bytes(dest))
+    }
+}
+
+impl CryptoRng for ServerRng {}
+
 /// Token key ID
 pub type TruncatedTokenKeyId = u8;
 /// Key ID
@@ -51,14 +669,324 @@ pub type Nonce = [u8; 32];
 /// Challenge digest
 pub type ChallengeDigest = [u8; 32];
 
+/// Errors that report why a server is not ready to issue tokens yet.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ReadinessError {
+    #[error("Key ID {0} not found")]
+    /// Error when the key store does not contain an active key.
+    KeyIdNotFound(TruncatedTokenKeyId),
+    #[error("Key ID {0} cannot be used")]
+    /// Error when an active key fails to evaluate a test input.
+    InvalidKey(TruncatedTokenKeyId),
+    #[error(transparent)]
+    /// Error when the key store fails.
+    KeyStore(#[from] KeyStoreError),
+}
+
+/// Errors that key stores report when their backend fails. A key that is
+/// not present is not an error.
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum KeyStoreError {
+    #[error("Key store is unavailable")]
+    /// Error when the backend cannot be reached. The operation can be
+    /// retried.
+    Unavailable,
+    #[error("Key store backend failed: {0}")]
+    /// Error when the backend fails, e.g. on I/O or because stored key
+    /// material cannot be decoded. Contains a description of the failure.
+    Backend(String),
+}
+
+/// Classified error of the underlying VOPRF implementation. The errors of
+/// the VOPRF-based token types carry it, so that cryptographic failures stay
+/// distinguishable from malformed messages.
+#[derive(Error, Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum VoprfError {
+    #[error("Info is too long")]
+    /// The info string is longer than `u16::MAX`.
+    Info,
+    #[error("Invalid input")]
+    /// The input is empty or longer than `u16::MAX`.
+    Input,
+    #[error("Key derivation failed")]
+    /// The key pair could not be derived from the seed and info.
+    DeriveKeyPair,
+    #[error("Deserialization failed")]
+    /// An element, scalar or proof could not be deserialized.
+    Deserialization,
+    #[error("Invalid batch")]
+    /// The batch is too large or its lengths do not match.
+    Batch,
+    #[error("Proof verification failed")]
+    /// The proof of the issuer failed to verify.
+    ProofVerification,
+    #[error("Protocol failure")]
+    /// The protocol failed and cannot be completed.
+    Protocol,
+}
+
+impl From<voprf::Error> for VoprfError {
+    fn from(error: voprf::Error) -> Self {
+        match error {
+            voprf::Error::Info => Self::Info,
+            voprf::Error::Input => Self::Input,
+            voprf::Error::DeriveKeyPair => Self::DeriveKeyPair,
+            voprf::Error::Deserialization => Self::Deserialization,
+            voprf::Error::Batch => Self::Batch,
+            voprf::Error::ProofVerification => Self::ProofVerification,
+            voprf::Error::Protocol => Self::Protocol,
+        }
+    }
+}
+
+/// Supertrait of the async traits, e.g. of the key and nonce stores. With the
+/// `send` feature it requires `Send + Sync`, so that the futures of the
+/// traits are `Send`. Without it every type implements it, so that stores on
+/// single-threaded runtimes can hold `Rc`, `RefCell` or `JsValue`.
+#[cfg(feature = "send")]
+pub trait MaybeSendSync: Send + Sync {}
+
+#[cfg(feature = "send")]
+impl<T: Send + Sync + ?Sized> MaybeSendSync for T {}
+
+/// Supertrait of the async traits, e.g. of the key and nonce stores. Without
+/// the `send` feature every type implements it.
+#[cfg(not(feature = "send"))]
+pub trait MaybeSendSync {}
+
+#[cfg(not(feature = "send"))]
+impl<T: ?Sized> MaybeSendSync for T {}
+
 /// Minimal trait for a nonce store that can be used to track redeemed tokens
 /// and prevent double spending. Note that the store requires inner mutability.
-#[async_trait]
-pub trait NonceStore: Send + Sync {
+///
+/// The store traits are object safe and are implemented for `Box` and `Arc`
+/// of any store, so that stores can be selected at runtime, e.g. as
+/// `Arc<dyn NonceStore>`.
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+pub trait NonceStore: MaybeSendSync {
     /// Returns `true` if the nonce exists in the nonce store and `false` otherwise.
     async fn exists(&self, nonce: &Nonce) -> bool;
     /// Inserts a new nonce in the nonce store.
     async fn insert(&self, nonce: Nonce);
+    /// Inserts the nonce unless it already exists, and returns `true` if it
+    /// was inserted. Servers record redeemed nonces with this method, so
+    /// that of two concurrent redemptions of the same token only one
+    /// succeeds.
+    ///
+    /// The default implementation calls [`exists`](Self::exists) and
+    /// [`insert`](Self::insert) and is not atomic. Stores that are shared by
+    /// concurrent redemptions should override it with an atomic check and
+    /// insert.
+    async fn try_insert(&self, nonce: Nonce) -> bool {
+        if self.exists(&nonce).await {
+            return false;
+        }
+        self.insert(nonce).await;
+        true
+    }
+    /// Inserts a new nonce that the store may forget after `ttl`. Once the
+    /// nonce is forgotten its token is accepted again, so the TTL must not
+    /// end before redemption rejects the token for another reason, e.g.
+    /// because the challenge it was issued for expired. The default
+    /// implementation ignores the TTL and keeps the nonce like
+    /// [`insert`](Self::insert).
+    async fn insert_with_ttl(&self, nonce: Nonce, ttl: Duration) {
+        let _ = ttl;
+        self.insert(nonce).await;
+    }
+    /// Inserts the nonce unless it already exists, like
+    /// [`try_insert`](Self::try_insert), and lets the store forget it after
+    /// `ttl`, with the same caveat as [`insert_with_ttl`](Self::insert_with_ttl).
+    /// Origins record the nonces of tokens for single-use challenges with
+    /// the time left until the challenge expires. The default implementation
+    /// ignores the TTL and calls [`try_insert`](Self::try_insert).
+    async fn try_insert_with_ttl(&self, nonce: Nonce, ttl: Duration) -> bool {
+        let _ = ttl;
+        self.try_insert(nonce).await
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<S: NonceStore + ?Sized> NonceStore for Box<S> {
+    async fn exists(&self, nonce: &Nonce) -> bool {
+        (**self).exists(nonce).await
+    }
+
+    async fn insert(&self, nonce: Nonce) {
+        (**self).insert(nonce).await
+    }
+
+    async fn try_insert(&self, nonce: Nonce) -> bool {
+        (**self).try_insert(nonce).await
+    }
+
+    async fn insert_with_ttl(&self, nonce: Nonce, ttl: Duration) {
+        (**self).insert_with_ttl(nonce, ttl).await
+    }
+
+    async fn try_insert_with_ttl(&self, nonce: Nonce, ttl: Duration) -> bool {
+        (**self).try_insert_with_ttl(nonce, ttl).await
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<S: NonceStore + ?Sized> NonceStore for Arc<S> {
+    async fn exists(&self, nonce: &Nonce) -> bool {
+        (**self).exists(nonce).await
+    }
+
+    async fn insert(&self, nonce: Nonce) {
+        (**self).insert(nonce).await
+    }
+
+    async fn try_insert(&self, nonce: Nonce) -> bool {
+        (**self).try_insert(nonce).await
+    }
+
+    async fn insert_with_ttl(&self, nonce: Nonce, ttl: Duration) {
+        (**self).insert_with_ttl(nonce, ttl).await
+    }
+
+    async fn try_insert_with_ttl(&self, nonce: Nonce, ttl: Duration) -> bool {
+        (**self).try_insert_with_ttl(nonce, ttl).await
+    }
+}
+
+/// Nonce store that records nonces with a fixed TTL, so that servers which
+/// record redeemed nonces with [`NonceStore::try_insert`] keep them as long
+/// as the caller needs.
+pub(crate) struct NonceStoreWithTtl<'a, NS: ?Sized> {
+    inner: &'a NS,
+    ttl: Duration,
+}
+
+impl<'a, NS: ?Sized> NonceStoreWithTtl<'a, NS> {
+    pub(crate) const fn new(inner: &'a NS, ttl: Duration) -> Self {
+        Self { inner, ttl }
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<NS: NonceStore + ?Sized> NonceStore for NonceStoreWithTtl<'_, NS> {
+    async fn exists(&self, nonce: &Nonce) -> bool {
+        self.inner.exists(nonce).await
+    }
+
+    async fn insert(&self, nonce: Nonce) {
+        self.inner.insert_with_ttl(nonce, self.ttl).await
+    }
+
+    async fn try_insert(&self, nonce: Nonce) -> bool {
+        self.inner.try_insert_with_ttl(nonce, self.ttl).await
+    }
+
+    async fn insert_with_ttl(&self, nonce: Nonce, ttl: Duration) {
+        self.inner.insert_with_ttl(nonce, ttl).await
+    }
+
+    async fn try_insert_with_ttl(&self, nonce: Nonce, ttl: Duration) -> bool {
+        self.inner.try_insert_with_ttl(nonce, ttl).await
+    }
+}
+
+/// Record of an outstanding challenge in a [`ChallengeStore`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChallengeRecord {
+    /// Time at which the challenge expires, in seconds since the Unix epoch.
+    pub expires_at: u64,
+    /// Whether the challenge is removed once a token was redeemed for it.
+    /// Challenges without a redemption context are shared by many clients
+    /// and kept until they expire.
+    pub single_use: bool,
+}
+
+impl ChallengeRecord {
+    /// Creates a record of a challenge.
+    #[must_use]
+    pub const fn new(expires_at: u64, single_use: bool) -> Self {
+        Self {
+            expires_at,
+            single_use,
+        }
+    }
+}
+
+/// Minimal trait for a store of the challenges an origin issued and that are
+/// still outstanding, so that it only redeems tokens for its own challenges.
+/// Replicas of an origin share a store, so that a token can be redeemed at a
+/// different replica than the one that issued its challenge.
+///
+/// Stores that cannot be read should report a challenge as unknown, so that
+/// its token is rejected.
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+pub trait ChallengeStore: MaybeSendSync {
+    /// Records a challenge. If the challenge is already recorded, the record
+    /// that expires later is kept, so that re-creating a challenge without a
+    /// redemption context never cuts short the tokens issued for it.
+    async fn insert(&self, challenge_digest: ChallengeDigest, record: ChallengeRecord);
+    /// Returns the record of a challenge, or `None` if the challenge is not
+    /// recorded.
+    async fn get(&self, challenge_digest: &ChallengeDigest) -> Option<ChallengeRecord>;
+    /// Removes a challenge and returns its record, or `None` if the
+    /// challenge is not recorded. Origins take single-use challenges once a
+    /// token for them was redeemed, so that of two concurrent redemptions
+    /// for the same challenge only one gets it.
+    async fn take(&self, challenge_digest: &ChallengeDigest) -> Option<ChallengeRecord>;
+    /// Removes the challenges that expired at `now`. The default
+    /// implementation does nothing, for stores that expire challenges
+    /// themselves.
+    async fn prune_expired(&self, now: u64) {
+        let _ = now;
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<S: ChallengeStore + ?Sized> ChallengeStore for Box<S> {
+    async fn insert(&self, challenge_digest: ChallengeDigest, record: ChallengeRecord) {
+        (**self).insert(challenge_digest, record).await
+    }
+
+    async fn get(&self, challenge_digest: &ChallengeDigest) -> Option<ChallengeRecord> {
+        (**self).get(challenge_digest).await
+    }
+
+    async fn take(&self, challenge_digest: &ChallengeDigest) -> Option<ChallengeRecord> {
+        (**self).take(challenge_digest).await
+    }
+
+    async fn prune_expired(&self, now: u64) {
+        (**self).prune_expired(now).await
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<S: ChallengeStore + ?Sized> ChallengeStore for Arc<S> {
+    async fn insert(&self, challenge_digest: ChallengeDigest, record: ChallengeRecord) {
+        (**self).insert(challenge_digest, record).await
+    }
+
+    async fn get(&self, challenge_digest: &ChallengeDigest) -> Option<ChallengeRecord> {
+        (**self).get(challenge_digest).await
+    }
+
+    async fn take(&self, challenge_digest: &ChallengeDigest) -> Option<ChallengeRecord> {
+        (**self).take(challenge_digest).await
+    }
+
+    async fn prune_expired(&self, now: u64) {
+        (**self).prune_expired(now).await
+    }
 }
 
 #[derive(Debug)]
@@ -69,6 +997,9 @@ pub(crate) struct TokenInput {
     token_key_id: TokenKeyId,
 }
 
+/// Length of a serialized token input.
+pub(crate) const TOKEN_INPUT_LEN: usize = 2 + 32 + 32 + 32;
+
 impl TokenInput {
     pub(crate) const fn new(
         token_type: TokenType,
@@ -84,13 +1015,80 @@ impl TokenInput {
         }
     }
 
-    pub(crate) fn serialize(&self) -> Vec<u8> {
+    /// Serializes the token input into a stack buffer that is wiped when it
+    /// is dropped.
+    pub(crate) fn to_bytes(&self) -> Zeroizing<[u8; TOKEN_INPUT_LEN]> {
         // token_input = concat(0xXXXX, nonce, challenge_digest, token_key_id)
-        let mut token_input: Vec<u8> = Vec::new();
-        token_input.extend_from_slice((self.token_type as u16).to_be_bytes().as_slice());
-        token_input.extend_from_slice(self.nonce.as_slice());
-        token_input.extend_from_slice(self.challenge_digest.as_slice());
-        token_input.extend_from_slice(self.token_key_id.as_slice());
+        let mut token_input = Zeroizing::new([0u8; TOKEN_INPUT_LEN]);
+        let (token_type, rest) = token_input.split_at_mut(2);
+        let (nonce, rest) = rest.split_at_mut(32);
+        let (challenge_digest, token_key_id) = rest.split_at_mut(32);
+        token_type.copy_from_slice(&(self.token_type as u16).to_be_bytes());
+        nonce.copy_from_slice(&self.nonce);
+        challenge_digest.copy_from_slice(&self.challenge_digest);
+        token_key_id.copy_from_slice(&self.token_key_id);
         token_input
     }
 }
+
+impl Zeroize for TokenInput {
+    fn zeroize(&mut self) {
+        self.nonce.zeroize();
+        self.challenge_digest.zeroize();
+        self.token_key_id.zeroize();
+    }
+}
+
+impl Drop for TokenInput {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for TokenInput {}
+
+#[test]
+fn token_input_zeroize() {
+    let mut token_input = TokenInput::new(TokenType::PrivateToken, [1; 32], [2; 32], [3; 32]);
+    token_input.zeroize();
+    assert!(token_input.to_bytes()[2..].iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn token_input_to_bytes() {
+    let token_input = TokenInput::new(TokenType::PrivateToken, [1; 32], [2; 32], [3; 32]);
+    let bytes = token_input.to_bytes();
+    assert_eq!(bytes[..2], [0x00, 0x01]);
+    assert_eq!(bytes[2..34], [1; 32]);
+    assert_eq!(bytes[34..66], [2; 32]);
+    assert_eq!(bytes[66..], [3; 32]);
+}
+
+#[test]
+fn voprf_error_conversion() {
+    assert_eq!(
+        VoprfError::from(voprf::Error::ProofVerification),
+        VoprfError::ProofVerification
+    );
+    assert_eq!(
+        VoprfError::from(voprf::Error::Deserialization),
+        VoprfError::Deserialization
+    );
+}
+
+#[test]
+fn token_type_api() {
+    for token_type in TokenType::supported() {
+        assert_eq!(TokenType::try_from(token_type as u16), Ok(token_type));
+    }
+    assert_eq!(
+        TokenType::try_from(0x7A7A),
+        Err(TokenTypeError::Unknown(0x7A7A))
+    );
+    assert_eq!(
+        TokenType::BatchedTokenRistretto255Final.to_string(),
+        "BatchedTokenRistretto255Final (0x0005)"
+    );
+    assert_eq!(TokenType::PublicToken.nk(), public_tokens::NK);
+    assert_eq!(TokenType::PrivateToken.token_len(), 146);
+}
@@ -0,0 +1,284 @@
+//! # Load generation
+//!
+//! Reproducible issuance and redemption workloads for capacity planning and
+//! for tracking performance regressions. A [`WorkloadConfig`] describes the
+//! shape of the traffic: how many keys are in use, how batch sizes are
+//! distributed and how often clients replay tokens that were already
+//! redeemed. The shape is derived from a seeded RNG, so the same
+//! configuration always produces the same sequence of rounds. The
+//! cryptographic operations themselves still use fresh randomness.
+//!
+//! [`run_batched_ristretto255`] drives a batched tokens server with such a
+//! workload and reports how much time was spent in issuance and redemption.
+
+use std::time::{Duration, Instant};
+
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    rngs::StdRng,
+    Rng, SeedableRng,
+};
+use thiserror::Error;
+
+use crate::{
+    auth::authenticate::TokenChallenge,
+    batched_tokens_ristretto255::{
+        client::Client,
+        server::{BatchedKeyStore, RedeemTokenError, Server},
+    },
+    NonceStore, TokenType,
+};
+
+/// Errors that can occur when generating or running a workload.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LoadgenError {
+    #[error("Invalid workload configuration")]
+    /// The configuration has no keys or no batch size with a positive
+    /// weight.
+    InvalidConfig,
+    #[error("Keypair creation failed")]
+    /// A keypair could not be created.
+    CreateKeypair,
+    #[error("Issuance failed")]
+    /// A token request, token response or token could not be created.
+    Issuance,
+    #[error("Redemption failed")]
+    /// A fresh token was not accepted.
+    Redemption,
+}
+
+/// Describes the shape of a workload.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkloadConfig {
+    seed: u64,
+    rounds: usize,
+    key_count: usize,
+    batch_sizes: Vec<(u16, u32)>,
+    duplicate_rate: f64,
+}
+
+impl Default for WorkloadConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            rounds: 100,
+            key_count: 1,
+            batch_sizes: vec![(1, 1)],
+            duplicate_rate: 0.0,
+        }
+    }
+}
+
+impl WorkloadConfig {
+    /// Creates a configuration of 100 rounds against a single key with a
+    /// batch size of 1 and no replayed tokens.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the seed from which the workload is derived.
+    #[must_use]
+    pub const fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Sets the number of issuance rounds.
+    #[must_use]
+    pub const fn with_rounds(mut self, rounds: usize) -> Self {
+        self.rounds = rounds;
+        self
+    }
+
+    /// Sets the number of keys the rounds are spread across.
+    #[must_use]
+    pub const fn with_key_count(mut self, key_count: usize) -> Self {
+        self.key_count = key_count;
+        self
+    }
+
+    /// Sets the batch size distribution as pairs of batch size and relative
+    /// weight.
+    #[must_use]
+    pub fn with_batch_sizes(mut self, batch_sizes: Vec<(u16, u32)>) -> Self {
+        self.batch_sizes = batch_sizes;
+        self
+    }
+
+    /// Sets the probability with which a redeemed token is replayed. The
+    /// value is clamped to `0.0..=1.0`.
+    #[must_use]
+    pub fn with_duplicate_rate(mut self, duplicate_rate: f64) -> Self {
+        self.duplicate_rate = if duplicate_rate.is_nan() {
+            0.0
+        } else {
+            duplicate_rate.clamp(0.0, 1.0)
+        };
+        self
+    }
+
+    /// Derives the sequence of rounds described by the configuration.
+    ///
+    /// # Errors
+    /// Returns an error if the configuration has no keys or no batch size
+    /// with a positive weight.
+    pub fn rounds(&self) -> Result<Vec<Round>, LoadgenError> {
+        if self.key_count == 0 {
+            return Err(LoadgenError::InvalidConfig);
+        }
+        let batch_sizes = WeightedIndex::new(self.batch_sizes.iter().map(|(_, weight)| *weight))
+            .map_err(|_| LoadgenError::InvalidConfig)?;
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        Ok((0..self.rounds)
+            .map(|_| {
+                let key_index = rng.gen_range(0..self.key_count);
+                let batch_size = self.batch_sizes[batch_sizes.sample(&mut rng)].0;
+                let duplicates = (0..batch_size)
+                    .filter(|_| rng.gen_bool(self.duplicate_rate))
+                    .count() as u16;
+                Round {
+                    key_index,
+                    batch_size,
+                    duplicates,
+                }
+            })
+            .collect())
+    }
+}
+
+/// A single round of a workload: one issuance followed by the redemption of
+/// all issued tokens.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Round {
+    /// Index of the key the tokens are issued under.
+    pub key_index: usize,
+    /// Number of tokens requested in one batch.
+    pub batch_size: u16,
+    /// Number of redeemed tokens that are replayed.
+    pub duplicates: u16,
+}
+
+/// Summary of a workload run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoadReport {
+    /// Number of issuance rounds.
+    pub rounds: usize,
+    /// Number of tokens that were issued.
+    pub tokens_issued: usize,
+    /// Number of fresh tokens that were redeemed.
+    pub redemptions: usize,
+    /// Number of replayed tokens that were rejected as double spending.
+    pub rejected_duplicates: usize,
+    /// Number of replayed tokens that were accepted. Anything but zero points
+    /// to a broken nonce store.
+    pub accepted_duplicates: usize,
+    /// Time the server spent issuing token responses.
+    pub issuance_time: Duration,
+    /// Time the server spent redeeming tokens, including replays.
+    pub redemption_time: Duration,
+}
+
+/// Runs a workload against a batched tokens server. One keypair per
+/// configured key is created in the key store before the first round.
+///
+/// # Errors
+/// Returns an error if the configuration is invalid, if a keypair cannot be
+/// created, or if issuing or redeeming a fresh token fails.
+pub async fn run_batched_ristretto255<BKS: BatchedKeyStore + ?Sized, NS: NonceStore + ?Sized>(
+    server: &Server,
+    key_store: &BKS,
+    nonce_store: &NS,
+    config: &WorkloadConfig,
+) -> Result<LoadReport, LoadgenError> {
+    let rounds = config.rounds()?;
+
+    let mut clients = Vec::with_capacity(config.key_count);
+    for _ in 0..config.key_count {
+        let public_key = server
+            .create_keypair(key_store)
+            .await
+            .map_err(|_| LoadgenError::CreateKeypair)?;
+        clients.push(Client::new(public_key));
+    }
+
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "loadgen.example",
+        None,
+        &["loadgen.example".to_string()],
+    );
+
+    let mut report = LoadReport {
+        rounds: rounds.len(),
+        ..LoadReport::default()
+    };
+    for round in rounds {
+        let client = &clients[round.key_index];
+        let (token_request, token_states) = client
+            .issue_token_request(&challenge, round.batch_size)
+            .map_err(|_| LoadgenError::Issuance)?;
+
+        let start = Instant::now();
+        let token_response = server
+            .issue_token_response(key_store, token_request)
+            .await
+            .map_err(|_| LoadgenError::Issuance)?;
+        report.issuance_time += start.elapsed();
+
+        let tokens = client
+            .issue_tokens(&token_response, &token_states)
+            .map_err(|_| LoadgenError::Issuance)?;
+        report.tokens_issued += tokens.len();
+
+        let start = Instant::now();
+        for token in &tokens {
+            server
+                .redeem_token(key_store, nonce_store, token.clone())
+                .await
+                .map_err(|_| LoadgenError::Redemption)?;
+            report.redemptions += 1;
+        }
+        for token in tokens.iter().cycle().take(usize::from(round.duplicates)) {
+            match server
+                .redeem_token(key_store, nonce_store, token.clone())
+                .await
+            {
+                Ok(()) => report.accepted_duplicates += 1,
+                Err(RedeemTokenError::DoubleSpending) => report.rejected_duplicates += 1,
+                Err(_) => return Err(LoadgenError::Redemption),
+            }
+        }
+        report.redemption_time += start.elapsed();
+    }
+
+    Ok(report)
+}
+
+#[test]
+fn workload_is_reproducible() {
+    let config = WorkloadConfig::new()
+        .with_seed(42)
+        .with_rounds(50)
+        .with_key_count(3)
+        .with_batch_sizes(vec![(1, 5), (10, 3), (100, 1)])
+        .with_duplicate_rate(0.1);
+    let rounds = config.rounds().unwrap();
+    assert_eq!(rounds.len(), 50);
+    assert_eq!(rounds, config.rounds().unwrap());
+    assert!(rounds.iter().all(|round| round.key_index < 3
+        && [1, 10, 100].contains(&round.batch_size)
+        && round.duplicates <= round.batch_size));
+
+    assert_eq!(
+        WorkloadConfig::new().with_key_count(0).rounds(),
+        Err(LoadgenError::InvalidConfig)
+    );
+    assert_eq!(
+        WorkloadConfig::new()
+            .with_batch_sizes(vec![(1, 0)])
+            .rounds(),
+        Err(LoadgenError::InvalidConfig)
+    );
+}
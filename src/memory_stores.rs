@@ -0,0 +1,729 @@
+//! # In-memory stores
+//!
+//! [`MemoryKeyStore`] and [`MemoryNonceStore`] keep keys and redeemed nonces
+//! in a `HashMap` and a `HashSet` of the process. They are meant for tests,
+//! examples and single-process deployments that can afford to lose their
+//! state on restart; the key store implements the key store trait of every
+//! token type for the matching key type, e.g.
+//! `MemoryKeyStore<VoprfServer<Ristretto255>>` for batched Ristretto255
+//! tokens.
+//!
+//! [`ExpiringMemoryNonceStore`] forgets nonces after a TTL, so that
+//! long-running origins whose challenges carry a max-age do not accumulate
+//! redeemed nonces without bound. Tokens whose nonce was forgotten can be
+//! spent again, see the type docs.
+//!
+//! [`ShardedMemoryNonceStore`] spreads the nonces over many independently
+//! locked sets, so that redemptions on many threads do not wait for each
+//! other on a single lock.
+//!
+//! [`MemoryChallengeStore`] keeps the outstanding challenges of an
+//! [`Origin`](crate::origin::Origin) that runs as a single process.
+
+use std::{
+    collections::{hash_map::RandomState, HashMap, HashSet},
+    fmt,
+    hash::BuildHasher,
+    sync::{Mutex, PoisonError, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+
+use crate::{
+    clock::{Clock, SystemClock},
+    ChallengeDigest, ChallengeRecord, ChallengeStore, KeyStoreError, KeyValidity, Nonce,
+    NonceStore, TruncatedTokenKeyId,
+};
+
+/// Key store that keeps the keys, and their validity periods, in memory.
+pub struct MemoryKeyStore<K> {
+    keys: RwLock<HashMap<TruncatedTokenKeyId, K>>,
+    validities: RwLock<HashMap<TruncatedTokenKeyId, KeyValidity>>,
+}
+
+impl<K> Default for MemoryKeyStore<K> {
+    fn default() -> Self {
+        Self {
+            keys: RwLock::new(HashMap::new()),
+            validities: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K> fmt::Debug for MemoryKeyStore<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut key_ids = self.key_ids();
+        key_ids.sort_unstable();
+        f.debug_struct("MemoryKeyStore")
+            .field("key_ids", &key_ids)
+            .finish()
+    }
+}
+
+impl<K> MemoryKeyStore<K> {
+    /// Creates an empty key store.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn insert_key(&self, truncated_token_key_id: TruncatedTokenKeyId, key: K) {
+        self.keys
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(truncated_token_key_id, key);
+    }
+
+    fn remove_key(&self, truncated_token_key_id: &TruncatedTokenKeyId) -> bool {
+        self.validities
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(truncated_token_key_id);
+        self.keys
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(truncated_token_key_id)
+            .is_some()
+    }
+
+    fn set_key_validity(&self, truncated_token_key_id: TruncatedTokenKeyId, validity: KeyValidity) {
+        self.validities
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(truncated_token_key_id, validity);
+    }
+
+    fn key_validity(&self, truncated_token_key_id: &TruncatedTokenKeyId) -> KeyValidity {
+        self.validities
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(truncated_token_key_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn key_ids(&self) -> Vec<TruncatedTokenKeyId> {
+        self.keys
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .keys()
+            .copied()
+            .collect()
+    }
+}
+
+impl<K: Clone> MemoryKeyStore<K> {
+    fn get_key(&self, truncated_token_key_id: &TruncatedTokenKeyId) -> Option<K> {
+        self.keys
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(truncated_token_key_id)
+            .cloned()
+    }
+}
+
+#[cfg(feature = "p384")]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl crate::private_tokens::server::PrivateKeyStore
+    for MemoryKeyStore<voprf::VoprfServer<p384::NistP384>>
+{
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        server: voprf::VoprfServer<p384::NistP384>,
+    ) -> Result<(), KeyStoreError> {
+        self.insert_key(truncated_token_key_id, server);
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<voprf::VoprfServer<p384::NistP384>>, KeyStoreError> {
+        Ok(self.get_key(truncated_token_key_id))
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(self.remove_key(truncated_token_key_id))
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        Ok(self.key_ids())
+    }
+
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        self.set_key_validity(truncated_token_key_id, validity);
+        Ok(())
+    }
+
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        Ok(self.key_validity(truncated_token_key_id))
+    }
+}
+
+#[cfg(feature = "p384")]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl crate::batched_tokens_p384::server::BatchedKeyStore
+    for MemoryKeyStore<voprf::VoprfServer<p384::NistP384>>
+{
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        server: voprf::VoprfServer<p384::NistP384>,
+    ) -> Result<(), KeyStoreError> {
+        self.insert_key(truncated_token_key_id, server);
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<voprf::VoprfServer<p384::NistP384>>, KeyStoreError> {
+        Ok(self.get_key(truncated_token_key_id))
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(self.remove_key(truncated_token_key_id))
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        Ok(self.key_ids())
+    }
+
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        self.set_key_validity(truncated_token_key_id, validity);
+        Ok(())
+    }
+
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        Ok(self.key_validity(truncated_token_key_id))
+    }
+}
+
+#[cfg(feature = "ristretto255")]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl crate::batched_tokens_ristretto255::server::BatchedKeyStore
+    for MemoryKeyStore<voprf::VoprfServer<voprf::Ristretto255>>
+{
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        server: voprf::VoprfServer<voprf::Ristretto255>,
+    ) -> Result<(), KeyStoreError> {
+        self.insert_key(truncated_token_key_id, server);
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<voprf::VoprfServer<voprf::Ristretto255>>, KeyStoreError> {
+        Ok(self.get_key(truncated_token_key_id))
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(self.remove_key(truncated_token_key_id))
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        Ok(self.key_ids())
+    }
+
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        self.set_key_validity(truncated_token_key_id, validity);
+        Ok(())
+    }
+
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        Ok(self.key_validity(truncated_token_key_id))
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl crate::public_tokens::server::IssuerKeyStore
+    for MemoryKeyStore<blind_rsa_signatures::KeyPair>
+{
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        key_pair: blind_rsa_signatures::KeyPair,
+    ) -> Result<(), KeyStoreError> {
+        self.insert_key(truncated_token_key_id, key_pair);
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<blind_rsa_signatures::KeyPair>, KeyStoreError> {
+        Ok(self.get_key(truncated_token_key_id))
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(self.remove_key(truncated_token_key_id))
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        Ok(self.key_ids())
+    }
+
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        self.set_key_validity(truncated_token_key_id, validity);
+        Ok(())
+    }
+
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        Ok(self.key_validity(truncated_token_key_id))
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl crate::public_tokens::server::OriginKeyStore
+    for MemoryKeyStore<blind_rsa_signatures::PublicKey>
+{
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        public_key: blind_rsa_signatures::PublicKey,
+    ) -> Result<(), KeyStoreError> {
+        self.insert_key(truncated_token_key_id, public_key);
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<blind_rsa_signatures::PublicKey>, KeyStoreError> {
+        Ok(self.get_key(truncated_token_key_id))
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(self.remove_key(truncated_token_key_id))
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        Ok(self.key_ids())
+    }
+
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        self.set_key_validity(truncated_token_key_id, validity);
+        Ok(())
+    }
+
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        Ok(self.key_validity(truncated_token_key_id))
+    }
+}
+
+/// Nonce store that keeps the redeemed nonces in memory.
+#[derive(Debug, Default)]
+pub struct MemoryNonceStore {
+    nonces: RwLock<HashSet<Nonce>>,
+}
+
+impl MemoryNonceStore {
+    /// Creates an empty nonce store.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl NonceStore for MemoryNonceStore {
+    async fn exists(&self, nonce: &Nonce) -> bool {
+        self.nonces
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains(nonce)
+    }
+
+    async fn insert(&self, nonce: Nonce) {
+        self.nonces
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(nonce);
+    }
+
+    async fn try_insert(&self, nonce: Nonce) -> bool {
+        self.nonces
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(nonce)
+    }
+}
+
+/// Number of shards of a [`ShardedMemoryNonceStore`] created with
+/// [`ShardedMemoryNonceStore::new`].
+const DEFAULT_SHARDS: usize = 256;
+
+/// Set of nonces of one shard. Shards are aligned to cache lines, so that
+/// threads that lock neighboring shards do not invalidate each other's
+/// caches.
+#[derive(Debug, Default)]
+#[repr(align(64))]
+struct Shard(RwLock<HashSet<Nonce>>);
+
+/// Nonce store that keeps the redeemed nonces in memory, split over shards
+/// with their own locks.
+///
+/// A nonce is assigned to a shard by a randomly keyed hash, so that clients
+/// cannot choose nonces that all land in the same shard.
+pub struct ShardedMemoryNonceStore {
+    shards: Box<[Shard]>,
+    hasher: RandomState,
+}
+
+impl fmt::Debug for ShardedMemoryNonceStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShardedMemoryNonceStore")
+            .field("shards", &self.shards.len())
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for ShardedMemoryNonceStore {
+    fn default() -> Self {
+        Self::with_shards(DEFAULT_SHARDS)
+    }
+}
+
+impl ShardedMemoryNonceStore {
+    /// Creates an empty nonce store with 256 shards.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty nonce store with the given number of shards, but at
+    /// least one.
+    #[must_use]
+    pub fn with_shards(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1)).map(|_| Shard::default()).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Returns the number of stored nonces.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.0.read().unwrap_or_else(PoisonError::into_inner).len())
+            .sum()
+    }
+
+    /// Returns `true` if the store holds no nonces.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn shard(&self, nonce: &Nonce) -> &RwLock<HashSet<Nonce>> {
+        let index = self.hasher.hash_one(nonce) % self.shards.len() as u64;
+        &self.shards[index as usize].0
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl NonceStore for ShardedMemoryNonceStore {
+    async fn exists(&self, nonce: &Nonce) -> bool {
+        self.shard(nonce)
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains(nonce)
+    }
+
+    async fn insert(&self, nonce: Nonce) {
+        self.shard(&nonce)
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(nonce);
+    }
+
+    async fn try_insert(&self, nonce: Nonce) -> bool {
+        self.shard(&nonce)
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(nonce)
+    }
+}
+
+/// Nonce store that keeps the redeemed nonces in memory and forgets them
+/// after a TTL.
+///
+/// **A token whose nonce has expired is accepted again.** The servers of
+/// this crate do not reject tokens by age, so the TTL only prevents double
+/// spending if the application rejects older tokens itself, e.g. through an
+/// [`Origin`](crate::origin::Origin) whose challenges expire no later than
+/// the TTL.
+///
+/// Nonces inserted by the servers expire after the TTL of the store, nonces
+/// inserted with [`NonceStore::insert_with_ttl`] or
+/// [`NonceStore::try_insert_with_ttl`], e.g. by an
+/// [`Origin`](crate::origin::Origin), after their own TTL.
+/// Expired nonces are no longer reported as redeemed and are removed by
+/// [`prune`](Self::prune), which applications call periodically, e.g. by
+/// spawning [`prune_periodically`](Self::prune_periodically).
+pub struct ExpiringMemoryNonceStore<C = SystemClock> {
+    // `None` if the TTL reaches beyond the range of `SystemTime`
+    nonces: Mutex<HashMap<Nonce, Option<SystemTime>>>,
+    ttl: Duration,
+    clock: C,
+}
+
+impl<C> fmt::Debug for ExpiringMemoryNonceStore<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExpiringMemoryNonceStore")
+            .field("len", &self.len())
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ExpiringMemoryNonceStore {
+    /// Creates an empty nonce store whose nonces expire after `ttl`.
+    #[must_use]
+    pub fn new(ttl: Duration) -> Self {
+        Self::new_with_clock(ttl, SystemClock)
+    }
+}
+
+impl<C> ExpiringMemoryNonceStore<C> {
+    /// Creates an empty nonce store whose nonces expire after `ttl` as
+    /// measured by `clock`.
+    #[must_use]
+    pub fn new_with_clock(ttl: Duration, clock: C) -> Self {
+        Self {
+            nonces: Mutex::new(HashMap::new()),
+            ttl,
+            clock,
+        }
+    }
+
+    /// Returns the number of stored nonces, including expired nonces that
+    /// have not been pruned yet.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.nonces
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// Returns `true` if the store holds no nonces.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<C: Clock> ExpiringMemoryNonceStore<C> {
+    /// Removes all expired nonces and returns how many were removed.
+    pub fn prune(&self) -> usize {
+        let now = self.clock.now();
+        let mut nonces = self.nonces.lock().unwrap_or_else(PoisonError::into_inner);
+        let len = nonces.len();
+        nonces.retain(|_, expiry| is_live(*expiry, now));
+        len - nonces.len()
+    }
+
+    /// Prunes the store every `interval`. The future never completes;
+    /// applications spawn it next to the server.
+    pub async fn prune_periodically(&self, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            self.prune();
+        }
+    }
+
+    /// Stores a nonce that expires after `ttl`. A live nonce keeps its later
+    /// expiry, so that a shorter TTL never unblocks a redeemed nonce early;
+    /// its expiry is only extended if `extend` is set.
+    fn store(&self, nonce: Nonce, ttl: Duration, extend: bool) -> bool {
+        let now = self.clock.now();
+        let expiry = now.checked_add(ttl);
+        let mut nonces = self.nonces.lock().unwrap_or_else(PoisonError::into_inner);
+        match nonces.get_mut(&nonce) {
+            Some(current) if is_live(*current, now) => {
+                // `None` never expires
+                if extend
+                    && current.is_some_and(|current| expiry.is_none_or(|expiry| expiry > current))
+                {
+                    *current = expiry;
+                }
+                false
+            }
+            Some(current) => {
+                *current = expiry;
+                true
+            }
+            None => {
+                nonces.insert(nonce, expiry);
+                true
+            }
+        }
+    }
+}
+
+fn is_live(expiry: Option<SystemTime>, now: SystemTime) -> bool {
+    !matches!(expiry, Some(expiry) if expiry <= now)
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<C: Clock> NonceStore for ExpiringMemoryNonceStore<C> {
+    async fn exists(&self, nonce: &Nonce) -> bool {
+        let now = self.clock.now();
+        self.nonces
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(nonce)
+            .is_some_and(|expiry| is_live(*expiry, now))
+    }
+
+    async fn insert(&self, nonce: Nonce) {
+        self.store(nonce, self.ttl, true);
+    }
+
+    async fn try_insert(&self, nonce: Nonce) -> bool {
+        self.store(nonce, self.ttl, false)
+    }
+
+    async fn insert_with_ttl(&self, nonce: Nonce, ttl: Duration) {
+        self.store(nonce, ttl, true);
+    }
+
+    async fn try_insert_with_ttl(&self, nonce: Nonce, ttl: Duration) -> bool {
+        self.store(nonce, ttl, false)
+    }
+}
+
+/// Challenge store that keeps the outstanding challenges in memory.
+#[derive(Debug, Default)]
+pub struct MemoryChallengeStore {
+    challenges: Mutex<HashMap<ChallengeDigest, ChallengeRecord>>,
+}
+
+impl MemoryChallengeStore {
+    /// Creates an empty challenge store.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of outstanding challenges, including expired
+    /// challenges that have not been pruned yet.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.challenges
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// Returns `true` if the store holds no challenges.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl ChallengeStore for MemoryChallengeStore {
+    async fn insert(&self, challenge_digest: ChallengeDigest, record: ChallengeRecord) {
+        self.challenges
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(challenge_digest)
+            .and_modify(|recorded| {
+                if record.expires_at > recorded.expires_at {
+                    *recorded = record;
+                }
+            })
+            .or_insert(record);
+    }
+
+    async fn get(&self, challenge_digest: &ChallengeDigest) -> Option<ChallengeRecord> {
+        self.challenges
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(challenge_digest)
+            .copied()
+    }
+
+    async fn take(&self, challenge_digest: &ChallengeDigest) -> Option<ChallengeRecord> {
+        self.challenges
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(challenge_digest)
+    }
+
+    async fn prune_expired(&self, now: u64) {
+        self.challenges
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|_, record| record.expires_at > now);
+    }
+}
@@ -0,0 +1,149 @@
+//! # Metrics
+//!
+//! Servers report issuances and redemptions to a [`Metrics`] recorder, so
+//! that operators can watch issuance volume, redemptions, double spending
+//! and rejected requests per token type and key. Recorders are set with the
+//! `with_metrics` methods of the servers. With the `prometheus` feature,
+//! [`prometheus::PrometheusMetrics`] exports the counters to a Prometheus
+//! registry.
+//!
+//! Like redemption webhooks, recorders only learn the token type, the
+//! truncated token key ID and the outcome, never the token itself.
+
+use std::{fmt, sync::Arc};
+
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+
+use crate::{webhooks::RedemptionOutcome, TokenType, TruncatedTokenKeyId};
+
+/// Records issuances and redemptions. All methods default to doing nothing,
+/// so recorders only implement the ones they are interested in. They are
+/// called on the request path and should not block.
+pub trait Metrics: Send + Sync {
+    /// Called after a token response for `count` tokens was issued under the
+    /// key.
+    fn tokens_issued(
+        &self,
+        token_type: TokenType,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        count: usize,
+    ) {
+        let _ = (token_type, truncated_token_key_id, count);
+    }
+
+    /// Called after a token request for the key was rejected or could not be
+    /// answered.
+    fn issuance_failed(&self, token_type: TokenType, truncated_token_key_id: TruncatedTokenKeyId) {
+        let _ = (token_type, truncated_token_key_id);
+    }
+
+    /// Called after a token issued under the key was redeemed, was spent
+    /// twice or was rejected for another reason.
+    fn token_redeemed(
+        &self,
+        token_type: TokenType,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        outcome: RedemptionOutcome,
+    ) {
+        let _ = (token_type, truncated_token_key_id, outcome);
+    }
+}
+
+impl<M: Metrics + ?Sized> Metrics for Box<M> {
+    fn tokens_issued(
+        &self,
+        token_type: TokenType,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        count: usize,
+    ) {
+        (**self).tokens_issued(token_type, truncated_token_key_id, count);
+    }
+
+    fn issuance_failed(&self, token_type: TokenType, truncated_token_key_id: TruncatedTokenKeyId) {
+        (**self).issuance_failed(token_type, truncated_token_key_id);
+    }
+
+    fn token_redeemed(
+        &self,
+        token_type: TokenType,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        outcome: RedemptionOutcome,
+    ) {
+        (**self).token_redeemed(token_type, truncated_token_key_id, outcome);
+    }
+}
+
+impl<M: Metrics + ?Sized> Metrics for Arc<M> {
+    fn tokens_issued(
+        &self,
+        token_type: TokenType,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        count: usize,
+    ) {
+        (**self).tokens_issued(token_type, truncated_token_key_id, count);
+    }
+
+    fn issuance_failed(&self, token_type: TokenType, truncated_token_key_id: TruncatedTokenKeyId) {
+        (**self).issuance_failed(token_type, truncated_token_key_id);
+    }
+
+    fn token_redeemed(
+        &self,
+        token_type: TokenType,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        outcome: RedemptionOutcome,
+    ) {
+        (**self).token_redeemed(token_type, truncated_token_key_id, outcome);
+    }
+}
+
+/// Recorder of a server, if one is set.
+#[derive(Clone, Default)]
+pub(crate) struct ServerMetrics(Option<Arc<dyn Metrics>>);
+
+impl fmt::Debug for ServerMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ServerMetrics")
+            .field(&self.0.is_some())
+            .finish()
+    }
+}
+
+impl ServerMetrics {
+    pub(crate) const fn none() -> Self {
+        Self(None)
+    }
+
+    pub(crate) fn new<M: Metrics + 'static>(metrics: M) -> Self {
+        Self(Some(Arc::new(metrics)))
+    }
+
+    /// Records the result of an issuance of `count` tokens.
+    pub(crate) fn issuance<T, E>(
+        &self,
+        token_type: TokenType,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        count: usize,
+        result: &Result<T, E>,
+    ) {
+        if let Some(metrics) = &self.0 {
+            match result {
+                Ok(_) => metrics.tokens_issued(token_type, truncated_token_key_id, count),
+                Err(_) => metrics.issuance_failed(token_type, truncated_token_key_id),
+            }
+        }
+    }
+
+    /// Records the outcome of a redemption.
+    pub(crate) fn redemption(
+        &self,
+        token_type: TokenType,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        outcome: RedemptionOutcome,
+    ) {
+        if let Some(metrics) = &self.0 {
+            metrics.token_redeemed(token_type, truncated_token_key_id, outcome);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! # Prometheus metrics
+//!
+//! [`PrometheusMetrics`] counts issuances and redemptions in a Prometheus
+//! registry:
+//!
+//!  - `privacypass_tokens_issued_total`: issued tokens
+//!  - `privacypass_issuance_failures_total`: rejected or failed token
+//!    requests
+//!  - `privacypass_redemptions_total`: redemptions, with the outcome
+//!    `redeemed`, `double-spending` or `rejected`
+//!
+//! All counters are labeled with the token type code point and the
+//! truncated token key ID.
+
+use std::fmt;
+
+use prometheus::{IntCounterVec, Opts, Registry};
+
+use super::Metrics;
+use crate::{webhooks::RedemptionOutcome, TokenType, TruncatedTokenKeyId};
+
+/// Metrics recorder that counts issuances and redemptions in a Prometheus
+/// registry.
+#[derive(Clone)]
+pub struct PrometheusMetrics {
+    tokens_issued: IntCounterVec,
+    issuance_failures: IntCounterVec,
+    redemptions: IntCounterVec,
+}
+
+impl fmt::Debug for PrometheusMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrometheusMetrics").finish_non_exhaustive()
+    }
+}
+
+impl PrometheusMetrics {
+    /// Creates the counters and registers them with `registry`.
+    ///
+    /// # Errors
+    /// Returns an error if the registry already contains counters of the
+    /// same names.
+    pub fn new(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let tokens_issued = IntCounterVec::new(
+            Opts::new("privacypass_tokens_issued_total", "Issued tokens"),
+            &["token_type", "key_id"],
+        )?;
+        let issuance_failures = IntCounterVec::new(
+            Opts::new(
+                "privacypass_issuance_failures_total",
+                "Rejected or failed token requests",
+            ),
+            &["token_type", "key_id"],
+        )?;
+        let redemptions = IntCounterVec::new(
+            Opts::new("privacypass_redemptions_total", "Token redemptions"),
+            &["token_type", "key_id", "outcome"],
+        )?;
+        registry.register(Box::new(tokens_issued.clone()))?;
+        registry.register(Box::new(issuance_failures.clone()))?;
+        registry.register(Box::new(redemptions.clone()))?;
+        Ok(Self {
+            tokens_issued,
+            issuance_failures,
+            redemptions,
+        })
+    }
+}
+
+const fn outcome_label(outcome: RedemptionOutcome) -> &'static str {
+    match outcome {
+        RedemptionOutcome::Redeemed => "redeemed",
+        RedemptionOutcome::DoubleSpending => "double-spending",
+        RedemptionOutcome::Rejected => "rejected",
+    }
+}
+
+impl Metrics for PrometheusMetrics {
+    fn tokens_issued(
+        &self,
+        token_type: TokenType,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        count: usize,
+    ) {
+        let token_type = (token_type as u16).to_string();
+        let key_id = truncated_token_key_id.to_string();
+        self.tokens_issued
+            .with_label_values(&[&token_type, &key_id])
+            .inc_by(count as u64);
+    }
+
+    fn issuance_failed(&self, token_type: TokenType, truncated_token_key_id: TruncatedTokenKeyId) {
+        let token_type = (token_type as u16).to_string();
+        let key_id = truncated_token_key_id.to_string();
+        self.issuance_failures
+            .with_label_values(&[&token_type, &key_id])
+            .inc();
+    }
+
+    fn token_redeemed(
+        &self,
+        token_type: TokenType,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        outcome: RedemptionOutcome,
+    ) {
+        let token_type = (token_type as u16).to_string();
+        let key_id = truncated_token_key_id.to_string();
+        self.redemptions
+            .with_label_values(&[&token_type, &key_id, outcome_label(outcome)])
+            .inc();
+    }
+}
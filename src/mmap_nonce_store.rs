@@ -0,0 +1,329 @@
+//! # Shared nonce store
+//!
+//! A [`NonceStore`] backed by a memory-mapped file. Worker processes of a
+//! prefork-style origin server on the same host can open the same file and
+//! share the set of redeemed nonces, so a token redeemed at one worker is
+//! rejected as double spending by all others.
+//!
+//! The file holds an open-addressing hash table. Lookups take a shared lock
+//! and inserts an exclusive lock on the file, so that processes never observe
+//! a partially written entry. The table doubles in size when it becomes half
+//! full, and the other processes pick up the larger mapping with their next
+//! operation.
+//!
+//! A larger table is appended to the file and filled before the header is
+//! switched over to it, so a process that crashes while growing the table
+//! leaves the smaller table intact. The smaller tables are not reclaimed,
+//! which makes the file about twice as large as its current table.
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    path::Path,
+    sync::{Mutex, PoisonError},
+};
+
+use async_trait::async_trait;
+// Called as `FileExt::...` because newer toolchains have inherent `File`
+// methods of the same names.
+use fs4::fs_std::FileExt;
+use memmap2::MmapMut;
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{Nonce, NonceStore};
+
+const MAGIC: &[u8; 8] = b"PPNONCE2";
+const CAPACITY_OFFSET: usize = 8;
+const COUNT_OFFSET: usize = 16;
+const KEY_OFFSET: usize = 24;
+const INITIAL_CAPACITY_OFFSET: usize = 56;
+const HEADER_LEN: usize = 64;
+// One occupied flag followed by the nonce, so that the all-zero nonce can be
+// stored as well.
+const SLOT_LEN: usize = 33;
+const DEFAULT_CAPACITY: u64 = 1 << 16;
+
+/// Errors that can occur when opening a shared nonce store.
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum MmapNonceStoreError {
+    #[error("I/O error")]
+    /// Error when the file cannot be opened, locked, resized or mapped.
+    Io(#[from] io::Error),
+    #[error("Not a nonce store file")]
+    /// Error when the file exists but does not contain a nonce store.
+    InvalidFile,
+}
+
+/// Nonce store that is shared between processes through a memory-mapped
+/// file.
+///
+/// The store is fail-closed: if the file cannot be locked or mapped, every
+/// nonce is reported as redeemed. Nonces that cannot be written for the same
+/// reason are lost, so the file should live on a local file system.
+///
+/// The file lock is taken with a blocking call, so an operation blocks the
+/// executor thread while another process holds the lock. The lock is only
+/// held for a single lookup or insert, or while the table grows.
+#[derive(Debug)]
+pub struct MmapNonceStore {
+    file: File,
+    map: Mutex<MmapMut>,
+}
+
+impl MmapNonceStore {
+    /// Opens the store at `path`, creating it with room for 65536 nonces if
+    /// it does not exist yet.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be opened or is not a nonce store.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, MmapNonceStoreError> {
+        Self::open_with_capacity(path, DEFAULT_CAPACITY)
+    }
+
+    /// Opens the store at `path`, creating it with room for `capacity`
+    /// nonces if it does not exist yet. The capacity of an existing store is
+    /// kept.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be opened or is not a nonce store.
+    pub fn open_with_capacity(
+        path: impl AsRef<Path>,
+        capacity: u64,
+    ) -> Result<Self, MmapNonceStoreError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let map = {
+            FileExt::lock_exclusive(&file)?;
+            let _lock = FileLock(&file);
+            if file.metadata()?.len() == 0 {
+                initialize(&file, capacity)?;
+            }
+            let map = map_file(&file)?;
+            if !is_valid(&map) {
+                return Err(MmapNonceStoreError::InvalidFile);
+            }
+            map
+        };
+        Ok(Self {
+            file,
+            map: Mutex::new(map),
+        })
+    }
+
+    fn contains(&self, nonce: &Nonce) -> io::Result<bool> {
+        let mut map = self.map.lock().unwrap_or_else(PoisonError::into_inner);
+        FileExt::lock_shared(&self.file)?;
+        let _lock = FileLock(&self.file);
+        self.remap_if_grown(&mut map)?;
+        Ok(probe(&map, nonce)?.is_ok())
+    }
+
+    /// Returns `true` if the nonce was not stored yet.
+    fn store(&self, nonce: &Nonce) -> io::Result<bool> {
+        let mut map = self.map.lock().unwrap_or_else(PoisonError::into_inner);
+        FileExt::lock_exclusive(&self.file)?;
+        let _lock = FileLock(&self.file);
+        self.remap_if_grown(&mut map)?;
+        if probe(&map, nonce)?.is_ok() {
+            return Ok(false);
+        }
+        let count = read_u64(&map, COUNT_OFFSET) + 1;
+        if count * 2 > read_u64(&map, CAPACITY_OFFSET) {
+            self.grow(&mut map)?;
+        }
+        if let Err(index) = probe(&map, nonce)? {
+            let capacity = read_u64(&map, CAPACITY_OFFSET);
+            write_slot(&mut map, capacity, index, nonce);
+            write_u64(&mut map, COUNT_OFFSET, count);
+        }
+        Ok(true)
+    }
+
+    /// Maps the file again if another process has grown it.
+    fn remap_if_grown(&self, map: &mut MmapMut) -> io::Result<()> {
+        if map.len() as u64 != self.file.metadata()?.len() {
+            let remapped = map_file(&self.file)?;
+            if !is_valid(&remapped) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "not a nonce store file",
+                ));
+            }
+            *map = remapped;
+        }
+        Ok(())
+    }
+
+    /// Doubles the capacity of the table. The nonces are re-inserted into a
+    /// new table behind the current one, and the header only refers to the
+    /// new table once it holds all of them.
+    fn grow(&self, map: &mut MmapMut) -> io::Result<()> {
+        let capacity = read_u64(map, CAPACITY_OFFSET);
+        let nonces = table(map, capacity)
+            .chunks_exact(SLOT_LEN)
+            .filter(|slot| slot[0] != 0)
+            .map(|slot| {
+                let mut nonce = [0u8; 32];
+                nonce.copy_from_slice(&slot[1..]);
+                nonce
+            })
+            .collect::<Vec<_>>();
+        let capacity = capacity * 2;
+        let len = file_len(read_u64(map, INITIAL_CAPACITY_OFFSET), capacity)
+            .ok_or_else(|| io::Error::other("nonce store too large"))?;
+        self.file.set_len(len)?;
+        *map = map_file(&self.file)?;
+        // Clears what an interrupted grow may have left behind
+        table_mut(map, capacity).fill(0);
+        for nonce in &nonces {
+            if let Err(index) = probe_table(map, capacity, nonce)? {
+                write_slot(map, capacity, index, nonce);
+            }
+        }
+        map.flush()?;
+        write_u64(map, CAPACITY_OFFSET, capacity);
+        map.flush()
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl NonceStore for MmapNonceStore {
+    async fn exists(&self, nonce: &Nonce) -> bool {
+        self.contains(nonce).unwrap_or(true)
+    }
+
+    async fn insert(&self, nonce: Nonce) {
+        let _ = self.store(&nonce);
+    }
+
+    async fn try_insert(&self, nonce: Nonce) -> bool {
+        self.store(&nonce).unwrap_or(false)
+    }
+}
+
+/// Releases the file lock when dropped.
+struct FileLock<'a>(&'a File);
+
+impl Drop for FileLock<'_> {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(self.0);
+    }
+}
+
+#[allow(unsafe_code)]
+fn map_file(file: &File) -> io::Result<MmapMut> {
+    // SAFETY: The file is only modified by this module while the file lock is
+    // held, and mappings are replaced as soon as the file has grown.
+    unsafe { MmapMut::map_mut(file) }
+}
+
+fn initialize(file: &File, capacity: u64) -> io::Result<()> {
+    let capacity = capacity.max(2).next_power_of_two();
+    let len =
+        file_len(capacity, capacity).ok_or_else(|| io::Error::other("nonce store too large"))?;
+    file.set_len(len)?;
+    let mut map = map_file(file)?;
+    map[..MAGIC.len()].copy_from_slice(MAGIC);
+    write_u64(&mut map, CAPACITY_OFFSET, capacity);
+    write_u64(&mut map, COUNT_OFFSET, 0);
+    write_u64(&mut map, INITIAL_CAPACITY_OFFSET, capacity);
+    OsRng.fill_bytes(&mut map[KEY_OFFSET..KEY_OFFSET + 32]);
+    map.flush()
+}
+
+/// The file may be longer than the current table if growing the table was
+/// interrupted.
+fn is_valid(map: &[u8]) -> bool {
+    if map.len() < HEADER_LEN || &map[..MAGIC.len()] != MAGIC {
+        return false;
+    }
+    let initial_capacity = read_u64(map, INITIAL_CAPACITY_OFFSET);
+    let capacity = read_u64(map, CAPACITY_OFFSET);
+    initial_capacity.is_power_of_two()
+        && capacity.is_power_of_two()
+        && capacity >= initial_capacity
+        && file_len(initial_capacity, capacity).is_some_and(|len| len <= map.len() as u64)
+}
+
+/// Returns the length of a file whose current table has `capacity` slots.
+/// The tables of all capacities since `initial_capacity` are laid out one
+/// after the other, so the table of `capacity` slots starts after
+/// `capacity - initial_capacity` slots.
+fn file_len(initial_capacity: u64, capacity: u64) -> Option<u64> {
+    capacity
+        .checked_mul(2)?
+        .checked_sub(initial_capacity)?
+        .checked_mul(SLOT_LEN as u64)?
+        .checked_add(HEADER_LEN as u64)
+}
+
+fn table_offset(map: &[u8], capacity: u64) -> usize {
+    let initial_capacity = read_u64(map, INITIAL_CAPACITY_OFFSET);
+    HEADER_LEN + (capacity - initial_capacity) as usize * SLOT_LEN
+}
+
+fn table(map: &[u8], capacity: u64) -> &[u8] {
+    &map[table_offset(map, capacity)..][..capacity as usize * SLOT_LEN]
+}
+
+fn table_mut(map: &mut [u8], capacity: u64) -> &mut [u8] {
+    let offset = table_offset(map, capacity);
+    &mut map[offset..][..capacity as usize * SLOT_LEN]
+}
+
+fn read_u64(map: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&map[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn write_u64(map: &mut [u8], offset: usize, value: u64) {
+    map[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Returns `Ok` with the slot of the nonce, or `Err` with the empty slot
+/// where it would be inserted. The table is at most half full, so probing
+/// ends at an empty slot unless the file is corrupted, in which case an error
+/// is returned after every slot has been visited.
+fn probe(map: &[u8], nonce: &Nonce) -> io::Result<Result<usize, usize>> {
+    probe_table(map, read_u64(map, CAPACITY_OFFSET), nonce)
+}
+
+/// Probes the table of `capacity` slots, see [`probe`].
+fn probe_table(map: &[u8], capacity: u64, nonce: &Nonce) -> io::Result<Result<usize, usize>> {
+    let mask = capacity as usize - 1;
+    // The keyed hash keeps chosen nonces from piling up in one region.
+    let digest = Sha256::new()
+        .chain_update(&map[KEY_OFFSET..KEY_OFFSET + 32])
+        .chain_update(nonce)
+        .finalize();
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    let mut index = u64::from_le_bytes(bytes) as usize & mask;
+    let table = table(map, capacity);
+    for _ in 0..capacity {
+        let slot = &table[index * SLOT_LEN..][..SLOT_LEN];
+        if slot[0] == 0 {
+            return Ok(Err(index));
+        }
+        if &slot[1..] == nonce {
+            return Ok(Ok(index));
+        }
+        index = (index + 1) & mask;
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "nonce store table is full"))
+}
+
+fn write_slot(map: &mut [u8], capacity: u64, index: usize, nonce: &Nonce) {
+    let slot = &mut table_mut(map, capacity)[index * SLOT_LEN..][..SLOT_LEN];
+    slot[0] = 1;
+    slot[1..].copy_from_slice(nonce);
+}
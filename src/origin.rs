@@ -0,0 +1,310 @@
+//! # Origin challenges
+//!
+//! An [`Origin`] issues the token challenges it sends to clients and only
+//! redeems tokens that were issued for one of them. Without this, a server
+//! verifies the token alone, and a client can redeem a token for a challenge
+//! it made up itself, e.g. one that names another origin or that was never
+//! sent.
+//!
+//! The origin records the digest of every challenge it creates in a
+//! [`ChallengeStore`] until the challenge expires. Challenges without a
+//! redemption context are the same for many clients, so they are good for
+//! any number of tokens, e.g. all tokens of a batch, and the nonce store of
+//! the server keeps each token from being redeemed twice. Interactive
+//! challenges carry a fresh redemption context and are good for one token,
+//! so a token issued for one of them cannot be replayed for another
+//! challenge or after the challenge expired. Replicas
+//! that share a store, e.g. a
+//! [`RedisChallengeStore`](crate::redis_challenge_store::RedisChallengeStore),
+//! redeem tokens for the challenges of each other. Tokens are verified by a
+//! [`MultiTypeServer`], whose handlers also detect double spending.
+//!
+//! Origins that serve several hostnames check the `Host` header or
+//! `:authority` of the request against the `origin_info` of the challenge
+//! with [`check_origin_name`], or redeem with
+//! [`Origin::redeem_token_for_authority`], so that a token minted for one
+//! hostname is not accepted by another.
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rand::{rngs::OsRng, Rng};
+use thiserror::Error;
+
+use crate::{
+    auth::authenticate::{RedemptionContext, TokenChallenge},
+    clock::{Clock, SystemClock},
+    dispatch::{CodePoint, DispatchError, MultiTypeServer},
+    ChallengeDigest, ChallengeRecord, ChallengeStore, TokenType,
+};
+
+/// Offset of the challenge digest in a serialized token, after the token type
+/// and the nonce.
+const CHALLENGE_DIGEST_OFFSET: usize = 2 + 32;
+
+/// Errors that can occur when creating a challenge or redeeming a token.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OriginError {
+    #[error("Invalid TokenChallenge")]
+    /// Error when the challenge cannot be serialized.
+    InvalidTokenChallenge,
+    #[error("The token was not issued for a challenge of this origin")]
+    /// Error when the token's challenge digest matches no outstanding
+    /// challenge, e.g. because the challenge expired or was never issued by
+    /// the origin.
+    UnknownChallenge,
+    #[error("The challenge was issued for another origin")]
+    /// Error when the challenge names origins in its `origin_info` and the
+    /// request authority is not one of them.
+    OriginNameMismatch,
+    #[error(transparent)]
+    /// Error when the token is rejected by the server.
+    Dispatch(#[from] DispatchError),
+}
+
+/// Checks that `authority`, the `Host` header or `:authority` of a request,
+/// names one of the origins in the `origin_info` of `challenge`. Names are
+/// compared case-insensitively, without userinfo and a trailing dot, and with
+/// or without the port of the authority. Challenges with an empty
+/// `origin_info` are valid for every origin.
+///
+/// # Errors
+/// Returns an error if the challenge names origins and `authority` is not
+/// one of them.
+pub fn check_origin_name(challenge: &TokenChallenge, authority: &str) -> Result<(), OriginError> {
+    let origin_info = challenge.origin_info();
+    if origin_info.iter().all(String::is_empty) {
+        return Ok(());
+    }
+    let authority = authority.trim();
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, authority)| authority);
+    let host = strip_port(authority);
+    let matches = |origin_name: &str| {
+        let origin_name = origin_name.trim().trim_end_matches('.');
+        !origin_name.is_empty()
+            && (origin_name.eq_ignore_ascii_case(authority.trim_end_matches('.'))
+                || origin_name.eq_ignore_ascii_case(host.trim_end_matches('.')))
+    };
+    if origin_info.iter().any(|origin_name| matches(origin_name)) {
+        Ok(())
+    } else {
+        Err(OriginError::OriginNameMismatch)
+    }
+}
+
+/// Returns the host of an authority without its port. IPv6 hosts keep their
+/// brackets.
+fn strip_port(authority: &str) -> &str {
+    if authority.starts_with('[') {
+        return authority
+            .find(']')
+            .map_or(authority, |end| &authority[..=end]);
+    }
+    match authority.rsplit_once(':') {
+        Some((host, port)) if port.bytes().all(|byte| byte.is_ascii_digit()) => host,
+        _ => authority,
+    }
+}
+
+/// Origin that issues token challenges and redeems the tokens issued for
+/// them.
+pub struct Origin<C = SystemClock> {
+    issuer_name: String,
+    server: Arc<MultiTypeServer>,
+    challenges: Arc<dyn ChallengeStore>,
+    clock: C,
+}
+
+impl<C> fmt::Debug for Origin<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Origin")
+            .field("issuer_name", &self.issuer_name)
+            .field("server", &self.server)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Origin {
+    /// Creates an origin whose challenges name the issuer `issuer_name`, are
+    /// recorded in `challenge_store` and whose tokens are redeemed with
+    /// `server`.
+    #[must_use]
+    pub fn new<S: ChallengeStore + 'static>(
+        issuer_name: &str,
+        server: Arc<MultiTypeServer>,
+        challenge_store: S,
+    ) -> Self {
+        Self::new_with_clock(issuer_name, server, challenge_store, SystemClock)
+    }
+}
+
+impl<C> Origin<C> {
+    /// Creates an origin whose challenges expire as measured by `clock`.
+    #[must_use]
+    pub fn new_with_clock<S: ChallengeStore + 'static>(
+        issuer_name: &str,
+        server: Arc<MultiTypeServer>,
+        challenge_store: S,
+        clock: C,
+    ) -> Self {
+        Self {
+            issuer_name: issuer_name.to_string(),
+            server,
+            challenges: Arc::new(challenge_store),
+            clock,
+        }
+    }
+}
+
+impl<C: Clock> Origin<C> {
+    /// Creates a challenge for `token_type` and records it for `max_age`
+    /// seconds, which should be sent as the `max-age` parameter of the
+    /// challenge. Challenges with a fresh random `redemption_context` are
+    /// specific to one client and good for one token, challenges without one
+    /// are good for any number of tokens until they expire. Expired
+    /// challenges are pruned.
+    ///
+    /// # Errors
+    /// Returns an error if the server has no handler for `token_type`, so
+    /// that its tokens could never be redeemed, or if the challenge cannot be
+    /// serialized.
+    pub async fn create_challenge(
+        &self,
+        token_type: TokenType,
+        redemption_context: Option<RedemptionContext>,
+        origin_info: &[String],
+        max_age: u32,
+    ) -> Result<TokenChallenge, OriginError> {
+        let expires_at = self.clock.unix_time().saturating_add(u64::from(max_age));
+        self.create_challenge_until(token_type, redemption_context, origin_info, expires_at)
+            .await
+    }
+
+    /// Creates an interactive challenge for `token_type` with a fresh random
+    /// redemption context and records it until `expires_at`, e.g. the end of
+    /// the client's session. Tokens issued for it cannot be redeemed for any
+    /// other challenge.
+    ///
+    /// # Errors
+    /// Returns an error if the server has no handler for `token_type` or if
+    /// the challenge cannot be serialized.
+    pub async fn create_challenge_with_expiry(
+        &self,
+        token_type: TokenType,
+        origin_info: &[String],
+        expires_at: SystemTime,
+    ) -> Result<TokenChallenge, OriginError> {
+        let redemption_context: RedemptionContext = OsRng.gen();
+        let expires_at = expires_at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        self.create_challenge_until(
+            token_type,
+            Some(redemption_context),
+            origin_info,
+            expires_at,
+        )
+        .await
+    }
+
+    async fn create_challenge_until(
+        &self,
+        token_type: TokenType,
+        redemption_context: Option<RedemptionContext>,
+        origin_info: &[String],
+        expires_at: u64,
+    ) -> Result<TokenChallenge, OriginError> {
+        let code_point = token_type as CodePoint;
+        if self.server.registry().get(code_point).is_none() {
+            return Err(DispatchError::UnsupportedTokenType(code_point).into());
+        }
+        let challenge = TokenChallenge::new(
+            token_type,
+            &self.issuer_name,
+            redemption_context,
+            origin_info,
+        );
+        let challenge_digest = challenge
+            .digest()
+            .map_err(|_| OriginError::InvalidTokenChallenge)?;
+        let record = ChallengeRecord::new(expires_at, redemption_context.is_some());
+        self.challenges.prune_expired(self.clock.unix_time()).await;
+        self.challenges.insert(challenge_digest, record).await;
+        Ok(challenge)
+    }
+
+    /// Redeems a serialized token if it was issued for an outstanding
+    /// challenge of the origin. A challenge with a redemption context is
+    /// taken once the server accepted the token, so a rejected token does not
+    /// use it up, and the nonce of its token is recorded until the challenge
+    /// expires, see
+    /// [`NonceStore::try_insert_with_ttl`](crate::NonceStore::try_insert_with_ttl).
+    ///
+    /// # Errors
+    /// Returns an error if the challenge of the token is unknown or expired,
+    /// or if the server rejects the token.
+    pub async fn redeem_token(&self, token: &[u8]) -> Result<(), OriginError> {
+        let challenge_digest: ChallengeDigest = token
+            .get(CHALLENGE_DIGEST_OFFSET..CHALLENGE_DIGEST_OFFSET + 32)
+            .and_then(|digest| digest.try_into().ok())
+            .ok_or(DispatchError::InvalidToken)?;
+        let now = self.clock.unix_time();
+        let record = self
+            .challenges
+            .get(&challenge_digest)
+            .await
+            .filter(|record| record.expires_at > now)
+            .ok_or(OriginError::UnknownChallenge)?;
+        if record.single_use {
+            // The challenge is taken below, so the token is rejected once
+            // the challenge would have expired even if its nonce is forgotten.
+            // Shared challenges are extended when they are created again and
+            // keep the nonce for the TTL of the nonce store.
+            let nonce_ttl = Duration::from_secs(record.expires_at - now);
+            self.server.redeem_token_with_ttl(token, nonce_ttl).await?;
+        } else {
+            self.server.redeem_token(token).await?;
+        }
+        // Of two tokens redeemed concurrently for a single-use challenge, only
+        // the one that takes it is accepted
+        if record.single_use && self.challenges.take(&challenge_digest).await.is_none() {
+            return Err(OriginError::UnknownChallenge);
+        }
+        Ok(())
+    }
+
+    /// Redeems a serialized token like [`redeem_token`](Self::redeem_token)
+    /// after checking that `challenge` is the challenge the token was issued
+    /// for and that it names `authority`, the `Host` header or `:authority`
+    /// of the request, in its `origin_info`, see [`check_origin_name`]. The
+    /// challenge is not taken if either check fails.
+    ///
+    /// # Errors
+    /// Returns an error if the token was not issued for `challenge`, the
+    /// challenge was issued for another origin, the challenge is unknown or
+    /// expired, or if the server rejects the token.
+    pub async fn redeem_token_for_authority(
+        &self,
+        token: &[u8],
+        challenge: &TokenChallenge,
+        authority: &str,
+    ) -> Result<(), OriginError> {
+        let challenge_digest = challenge
+            .digest()
+            .map_err(|_| OriginError::InvalidTokenChallenge)?;
+        let token_digest = token
+            .get(CHALLENGE_DIGEST_OFFSET..CHALLENGE_DIGEST_OFFSET + 32)
+            .ok_or(DispatchError::InvalidToken)?;
+        if token_digest != challenge_digest {
+            return Err(OriginError::UnknownChallenge);
+        }
+        check_origin_name(challenge, authority)?;
+        self.redeem_token(token).await
+    }
+}
@@ -0,0 +1,159 @@
+//! # Redemption policies
+//!
+//! Origins often accept a cryptographically valid token only under further
+//! conditions, such as the plan tier of the client, its geography or its
+//! request rate. A [`RedemptionPolicy`] enforces such rules. The servers'
+//! `redeem_token_with_policy` methods consult it after the token was
+//! verified and before its nonce is recorded, so a rejected token is not
+//! spent and an accepted one still goes through the double-spending check of
+//! the crate.
+//!
+//! The policy sees the token type, the truncated token key ID, the challenge
+//! digest and metadata of the origin's choosing, e.g. the request that
+//! carried the token.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use generic_array::ArrayLength;
+use thiserror::Error;
+
+use crate::{
+    auth::authorize::Token, ChallengeDigest, MaybeSendSync, TokenType, TruncatedTokenKeyId,
+};
+
+/// Reason a policy rejected a redemption.
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+#[error("{reason}")]
+pub struct PolicyRejection {
+    reason: String,
+}
+
+impl PolicyRejection {
+    /// Creates a rejection with a reason that is reported to the origin.
+    #[must_use]
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+        }
+    }
+
+    /// Returns the reason of the rejection.
+    #[must_use]
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+/// Inputs of a [`RedemptionPolicy`].
+#[derive(Debug)]
+pub struct RedemptionContext<'a, M: ?Sized> {
+    token_type: TokenType,
+    truncated_token_key_id: TruncatedTokenKeyId,
+    challenge_digest: &'a ChallengeDigest,
+    metadata: &'a M,
+}
+
+impl<'a, M: ?Sized> RedemptionContext<'a, M> {
+    /// Creates the context of a redemption.
+    #[must_use]
+    pub const fn new(
+        token_type: TokenType,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        challenge_digest: &'a ChallengeDigest,
+        metadata: &'a M,
+    ) -> Self {
+        Self {
+            token_type,
+            truncated_token_key_id,
+            challenge_digest,
+            metadata,
+        }
+    }
+
+    /// Returns the token type.
+    #[must_use]
+    pub const fn token_type(&self) -> TokenType {
+        self.token_type
+    }
+
+    /// Returns the truncated token key ID of the key the token was issued
+    /// under.
+    #[must_use]
+    pub const fn truncated_token_key_id(&self) -> TruncatedTokenKeyId {
+        self.truncated_token_key_id
+    }
+
+    /// Returns the digest of the challenge the token was issued for.
+    #[must_use]
+    pub const fn challenge_digest(&self) -> &ChallengeDigest {
+        self.challenge_digest
+    }
+
+    /// Returns the metadata supplied by the origin.
+    #[must_use]
+    pub const fn metadata(&self) -> &M {
+        self.metadata
+    }
+}
+
+/// Custom acceptance check for verified tokens.
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+pub trait RedemptionPolicy<M: Sync + ?Sized = ()>: MaybeSendSync {
+    /// Accepts or rejects the redemption of a verified token.
+    ///
+    /// # Errors
+    /// Returns the reason if the token must not be redeemed.
+    async fn check(&self, context: &RedemptionContext<'_, M>) -> Result<(), PolicyRejection>;
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<M: Sync + ?Sized, P: RedemptionPolicy<M> + ?Sized> RedemptionPolicy<M> for Box<P> {
+    async fn check(&self, context: &RedemptionContext<'_, M>) -> Result<(), PolicyRejection> {
+        (**self).check(context).await
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<M: Sync + ?Sized, P: RedemptionPolicy<M> + ?Sized> RedemptionPolicy<M> for Arc<P> {
+    async fn check(&self, context: &RedemptionContext<'_, M>) -> Result<(), PolicyRejection> {
+        (**self).check(context).await
+    }
+}
+
+/// Policy that accepts every verified token. Used by `redeem_token`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AcceptAll;
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<M: Sync + ?Sized> RedemptionPolicy<M> for AcceptAll {
+    async fn check(&self, _context: &RedemptionContext<'_, M>) -> Result<(), PolicyRejection> {
+        Ok(())
+    }
+}
+
+/// Consults `policy` about a verified token.
+pub(crate) async fn check_policy<P, M, Nk>(
+    policy: &P,
+    token: &Token<Nk>,
+    truncated_token_key_id: TruncatedTokenKeyId,
+    metadata: &M,
+) -> Result<(), PolicyRejection>
+where
+    P: RedemptionPolicy<M> + ?Sized,
+    M: Sync + ?Sized,
+    Nk: ArrayLength<u8>,
+{
+    policy
+        .check(&RedemptionContext::new(
+            token.token_type(),
+            truncated_token_key_id,
+            token.challenge_digest(),
+            metadata,
+        ))
+        .await
+}
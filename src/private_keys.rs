@@ -0,0 +1,136 @@
+//! # Private key import and export
+//!
+//! Issuers that provision their keys from files or secrets managers import
+//! them into a key store instead of creating them in-process, and export
+//! them again for backups or other replicas. The servers of the token types
+//! use the following formats:
+//!
+//!  - P-384 keys of privately verifiable and batched P-384 tokens: PKCS#8,
+//!    DER or PEM encoded
+//!  - Ristretto255 keys of batched Ristretto255 tokens: the raw 32 byte
+//!    scalar, as PKCS#8 has no algorithm identifier for Ristretto255
+//!  - RSA keys of publicly verifiable tokens: PKCS#8, DER or PEM encoded.
+//!    PKCS#1 is accepted on import as well.
+//!
+//! Exported keys are wrapped in [`SecretVec`] and
+//! [`SecretString`](crate::SecretString) so that they are not logged by
+//! accident.
+
+#[cfg(feature = "p384")]
+use p384::{
+    pkcs8::{DecodePrivateKey, EncodePrivateKey, LineEnding},
+    NistP384,
+};
+use thiserror::Error;
+use typenum::Unsigned;
+#[cfg(feature = "ristretto255")]
+use voprf::Ristretto255;
+use voprf::{Group, VoprfServer};
+use zeroize::{Zeroize, Zeroizing};
+
+#[cfg(feature = "p384")]
+use crate::SecretString;
+use crate::{KeyStoreError, SecretVec};
+
+/// Errors that can occur when importing or exporting a private key.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PrivateKeyError {
+    #[error("Key ID not found")]
+    /// Error when the key store does not contain the key.
+    KeyIdNotFound,
+    #[error("Invalid private key")]
+    /// Error when the private key cannot be decoded or encoded.
+    InvalidKey,
+    #[error(transparent)]
+    /// Error when the key store fails.
+    KeyStore(#[from] KeyStoreError),
+}
+
+/// Serializes the VOPRF server of the private key `scalar` in the format of
+/// `VoprfServer::deserialize`, the scalar followed by the public key.
+fn voprf_server_bytes<G: Group>(scalar: G::Scalar) -> Zeroizing<Vec<u8>> {
+    let public_key = G::base_elem() * &scalar;
+    let mut bytes = Zeroizing::new(Vec::with_capacity(G::ScalarLen::USIZE + G::ElemLen::USIZE));
+    bytes.extend_from_slice(&G::serialize_scalar(scalar));
+    bytes.extend_from_slice(&G::serialize_elem(public_key));
+    bytes
+}
+
+/// Returns the private key of a serialized VOPRF server and wipes the
+/// serialized server.
+fn voprf_private_key<G: Group>(serialized_server: &mut [u8]) -> Result<Vec<u8>, PrivateKeyError> {
+    let private_key = serialized_server
+        .get(..G::ScalarLen::USIZE)
+        .map(<[u8]>::to_vec)
+        .ok_or(PrivateKeyError::InvalidKey);
+    serialized_server.zeroize();
+    private_key
+}
+
+#[cfg(feature = "p384")]
+fn p384_secret_key(server: &VoprfServer<NistP384>) -> Result<p384::SecretKey, PrivateKeyError> {
+    let private_key = Zeroizing::new(voprf_private_key::<NistP384>(&mut server.serialize())?);
+    p384::SecretKey::from_slice(&private_key).map_err(|_| PrivateKeyError::InvalidKey)
+}
+
+#[cfg(feature = "p384")]
+fn p384_server(secret_key: &p384::SecretKey) -> Result<VoprfServer<NistP384>, PrivateKeyError> {
+    let bytes = voprf_server_bytes::<NistP384>(*secret_key.to_nonzero_scalar());
+    VoprfServer::<NistP384>::deserialize(&bytes).map_err(|_| PrivateKeyError::InvalidKey)
+}
+
+/// Encodes the private key of a P-384 VOPRF server as a DER-encoded PKCS#8
+/// document.
+#[cfg(feature = "p384")]
+pub(crate) fn p384_to_pkcs8_der(
+    server: &VoprfServer<NistP384>,
+) -> Result<SecretVec<u8>, PrivateKeyError> {
+    let document = p384_secret_key(server)?
+        .to_pkcs8_der()
+        .map_err(|_| PrivateKeyError::InvalidKey)?;
+    Ok(SecretVec::new(document.as_bytes().to_vec()))
+}
+
+/// Encodes the private key of a P-384 VOPRF server as a PEM-encoded PKCS#8
+/// document.
+#[cfg(feature = "p384")]
+pub(crate) fn p384_to_pkcs8_pem(
+    server: &VoprfServer<NistP384>,
+) -> Result<SecretString, PrivateKeyError> {
+    let pem = p384_secret_key(server)?
+        .to_pkcs8_pem(LineEnding::LF)
+        .map_err(|_| PrivateKeyError::InvalidKey)?;
+    Ok(SecretString::new(pem.to_string()))
+}
+
+/// Creates a P-384 VOPRF server from a DER-encoded PKCS#8 document.
+#[cfg(feature = "p384")]
+pub(crate) fn p384_from_pkcs8_der(der: &[u8]) -> Result<VoprfServer<NistP384>, PrivateKeyError> {
+    p384_server(&p384::SecretKey::from_pkcs8_der(der).map_err(|_| PrivateKeyError::InvalidKey)?)
+}
+
+/// Creates a P-384 VOPRF server from a PEM-encoded PKCS#8 document.
+#[cfg(feature = "p384")]
+pub(crate) fn p384_from_pkcs8_pem(pem: &str) -> Result<VoprfServer<NistP384>, PrivateKeyError> {
+    p384_server(&p384::SecretKey::from_pkcs8_pem(pem).map_err(|_| PrivateKeyError::InvalidKey)?)
+}
+
+/// Returns the raw private key scalar of a Ristretto255 VOPRF server.
+#[cfg(feature = "ristretto255")]
+pub(crate) fn ristretto255_to_scalar(
+    server: &VoprfServer<Ristretto255>,
+) -> Result<SecretVec<u8>, PrivateKeyError> {
+    voprf_private_key::<Ristretto255>(&mut server.serialize()).map(SecretVec::new)
+}
+
+/// Creates a Ristretto255 VOPRF server from a raw private key scalar.
+#[cfg(feature = "ristretto255")]
+pub(crate) fn ristretto255_from_scalar(
+    scalar: &[u8],
+) -> Result<VoprfServer<Ristretto255>, PrivateKeyError> {
+    let scalar =
+        Ristretto255::deserialize_scalar(scalar).map_err(|_| PrivateKeyError::InvalidKey)?;
+    VoprfServer::<Ristretto255>::deserialize(&voprf_server_bytes::<Ristretto255>(scalar))
+        .map_err(|_| PrivateKeyError::InvalidKey)
+}
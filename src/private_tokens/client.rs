@@ -1,13 +1,20 @@
 //! Client-side implementation of the Privately Verifiable Token protocol.
 
 use p384::NistP384;
-use rand::{rngs::OsRng, Rng};
+use rand::{rngs::OsRng, CryptoRng, Rng, RngCore};
+#[cfg(feature = "experimental-unblinding")]
+use sha2::Sha384;
 use thiserror::Error;
 use voprf::{EvaluationElement, Proof, Result, VoprfClient};
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
+#[cfg(feature = "experimental-unblinding")]
+use crate::unblinding::finalize_unverified;
 use crate::{
     auth::{authenticate::TokenChallenge, authorize::Token},
-    ChallengeDigest, TokenInput, TokenKeyId, TokenType,
+    issuer_directory::TokenKeyDirectory,
+    ChallengeDigest, FinalizeObserver, FinalizeTimings, Instant, ProofVerification, TokenInput,
+    TokenKeyId, TokenType, VoprfError,
 };
 
 use super::{
@@ -23,8 +30,18 @@ pub struct TokenState {
     client: VoprfClient<NistP384>,
 }
 
+impl Drop for TokenState {
+    fn drop(&mut self) {
+        // The VOPRF client state and the token input wipe themselves.
+        self.challenge_digest.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for TokenState {}
+
 /// Errors that can occur when issuing token requests.
 #[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum IssueTokenRequestError {
     #[error("Token blinding error")]
     /// Error when blinding the token.
@@ -32,14 +49,23 @@ pub enum IssueTokenRequestError {
     #[error("Invalid TokenChallenge")]
     /// Error when the token challenge is invalid.
     InvalidTokenChallenge,
+    #[error("Token key does not match a current issuer directory entry")]
+    /// Error when the public key is not listed as current in the issuer
+    /// directory.
+    KeyNotInDirectory,
 }
 
 /// Errors that can occur when issuing tokens.
 #[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum IssueTokenError {
     #[error("Invalid TokenResponse")]
     /// Error when the token response is invalid.
     InvalidTokenResponse,
+    #[error("VOPRF finalization failed")]
+    /// Error when the VOPRF implementation rejects the token response, e.g.
+    /// because the proof does not verify.
+    Voprf(#[from] VoprfError),
 }
 
 /// The client side of the Privately Verifiable Token protocol.
@@ -47,6 +73,9 @@ pub enum IssueTokenError {
 pub struct Client {
     token_key_id: TokenKeyId,
     public_key: PublicKey,
+    proof_verification: ProofVerification,
+    finalize_observer: Option<FinalizeObserver>,
+    directory: Option<TokenKeyDirectory>,
 }
 
 impl Client {
@@ -58,9 +87,38 @@ impl Client {
         Self {
             token_key_id,
             public_key,
+            proof_verification: ProofVerification::default(),
+            finalize_observer: None,
+            directory: None,
         }
     }
 
+    /// Sets the issuer directory the public key is checked against before
+    /// blinding. Token requests are refused if the directory does not list
+    /// the key as current, so that an issuance endpoint cannot single out a
+    /// client by serving it a unique key.
+    #[must_use]
+    pub fn with_directory(mut self, directory: TokenKeyDirectory) -> Self {
+        self.directory = Some(directory);
+        self
+    }
+
+    /// Sets whether the proof of token responses is verified. See
+    /// [`ProofVerification`] for the security implications of skipping
+    /// verification.
+    #[must_use]
+    pub const fn with_proof_verification(mut self, proof_verification: ProofVerification) -> Self {
+        self.proof_verification = proof_verification;
+        self
+    }
+
+    /// Sets a callback that receives the timings of every token finalization.
+    #[must_use]
+    pub fn with_finalize_observer(mut self, finalize_observer: FinalizeObserver) -> Self {
+        self.finalize_observer = Some(finalize_observer);
+        self
+    }
+
     /// Issue a token request.
     ///
     /// # Errors
@@ -69,14 +127,37 @@ impl Client {
         &self,
         challenge: &TokenChallenge,
     ) -> Result<(TokenRequest, TokenState), IssueTokenRequestError> {
-        let nonce: Nonce = OsRng.gen();
+        self.issue_token_request_with_rng(&mut OsRng, challenge)
+    }
 
-        self.issue_token_request_internal(challenge, nonce, None)
+    /// Issue a token request with the nonce and blind drawn from `rng`, for
+    /// platforms without an OS random number generator.
+    ///
+    /// # Errors
+    /// Returns an error if the challenge is invalid.
+    pub fn issue_token_request_with_rng<R: RngCore + CryptoRng>(
+        &self,
+        rng: &mut R,
+        challenge: &TokenChallenge,
+    ) -> Result<(TokenRequest, TokenState), IssueTokenRequestError> {
+        let nonce: Nonce = rng.gen();
+
+        self.issue_token_request_internal(rng, challenge, nonce, None)
     }
 
     /// Issue a token request.
-    fn issue_token_request_internal(
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(truncated_token_key_id = truncate_token_key_id(&self.token_key_id)),
+            err(level = "debug")
+        )
+    )]
+    fn issue_token_request_internal<R: RngCore + CryptoRng>(
         &self,
+        rng: &mut R,
         challenge: &TokenChallenge,
         nonce: Nonce,
         _blind: Option<<NistP384 as voprf::Group>::Scalar>,
@@ -85,6 +166,12 @@ impl Client {
             .digest()
             .map_err(|_| IssueTokenRequestError::InvalidTokenChallenge)?;
 
+        if let Some(directory) = &self.directory {
+            directory
+                .check_key(TokenType::PrivateToken, &self.token_key_id)
+                .map_err(|_| IssueTokenRequestError::KeyNotInDirectory)?;
+        }
+
         // nonce = random(32)
         // challenge_digest = SHA256(challenge)
         // token_input = concat(0x0001, nonce, challenge_digest, token_key_id)
@@ -97,13 +184,17 @@ impl Client {
             self.token_key_id,
         );
 
-        let blinded_element = VoprfClient::<NistP384>::blind(&token_input.serialize(), &mut OsRng)
-            .map_err(|_| IssueTokenRequestError::BlindingError)?;
+        let blinded_element =
+            VoprfClient::<NistP384>::blind(token_input.to_bytes().as_slice(), rng)
+                .map_err(|_| IssueTokenRequestError::BlindingError)?;
 
         #[cfg(feature = "kat")]
         let blinded_element = if let Some(blind) = _blind {
-            VoprfClient::<NistP384>::deterministic_blind_unchecked(&token_input.serialize(), blind)
-                .map_err(|_| IssueTokenRequestError::BlindingError)?
+            VoprfClient::<NistP384>::deterministic_blind_unchecked(
+                token_input.to_bytes().as_slice(),
+                blind,
+            )
+            .map_err(|_| IssueTokenRequestError::BlindingError)?
         } else {
             blinded_element
         };
@@ -129,28 +220,61 @@ impl Client {
         nonce: Nonce,
         blind: <NistP384 as voprf::Group>::Scalar,
     ) -> Result<(TokenRequest, TokenState), IssueTokenRequestError> {
-        self.issue_token_request_internal(challenge, nonce, Some(blind))
+        self.issue_token_request_internal(&mut OsRng, challenge, nonce, Some(blind))
     }
 
     /// Issue a token.
     ///
     /// # Errors
     /// Returns an error if the response is invalid.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(truncated_token_key_id = truncate_token_key_id(&self.token_key_id)),
+            err(level = "debug")
+        )
+    )]
     pub fn issue_token(
         &self,
         token_response: &TokenResponse,
         token_state: &TokenState,
     ) -> Result<PrivateToken, IssueTokenError> {
+        let start = Instant::now();
         let evaluation_element = EvaluationElement::deserialize(&token_response.evaluate_msg)
-            .map_err(|_| IssueTokenError::InvalidTokenResponse)?;
-        let proof = Proof::deserialize(&token_response.evaluate_proof)
-            .map_err(|_| IssueTokenError::InvalidTokenResponse)?;
-        let token_input = token_state.token_input.serialize();
-        // authenticator = client_context.Finalize(token_input, blind, evaluated_element, blinded_element, proof)
-        let authenticator = token_state
-            .client
-            .finalize(&token_input, &evaluation_element, &proof, self.public_key)
-            .map_err(|_| IssueTokenError::InvalidTokenResponse)?;
+            .map_err(VoprfError::from)?;
+        let proof = Proof::deserialize(&token_response.evaluate_proof).map_err(VoprfError::from)?;
+        let decoded = Instant::now();
+
+        let token_input = token_state.token_input.to_bytes();
+        let authenticator = match self.proof_verification {
+            // authenticator = client_context.Finalize(token_input, blind, evaluated_element, blinded_element, proof)
+            ProofVerification::Verify => token_state
+                .client
+                .finalize(
+                    token_input.as_slice(),
+                    &evaluation_element,
+                    &proof,
+                    self.public_key,
+                )
+                .map_err(VoprfError::from)?,
+            #[cfg(feature = "experimental-unblinding")]
+            ProofVerification::SkipForTrustedIssuer => finalize_unverified::<NistP384, Sha384>(
+                &token_state.client.serialize(),
+                token_input.as_slice(),
+                &token_response.evaluate_msg,
+            )
+            .ok_or(IssueTokenError::InvalidTokenResponse)?,
+        };
+
+        if let Some(finalize_observer) = &self.finalize_observer {
+            finalize_observer.observe(&FinalizeTimings {
+                decode: decoded - start,
+                finalize: decoded.elapsed(),
+                proof_verified: self.proof_verification == ProofVerification::Verify,
+            });
+        }
 
         Ok(Token::new(
             TokenType::PrivateToken,
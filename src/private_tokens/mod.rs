@@ -32,7 +32,9 @@ pub fn public_key_to_truncated_token_key_id(public_key: &PublicKey) -> Truncated
     truncate_token_key_id(&public_key_to_token_key_id(public_key))
 }
 
-fn public_key_to_token_key_id(public_key: &PublicKey) -> TokenKeyId {
+/// Convert a public key to a token key ID, the SHA-256 digest of the
+/// serialized public key.
+pub fn public_key_to_token_key_id(public_key: &PublicKey) -> TokenKeyId {
     let public_key = serialize_public_key(*public_key);
 
     Sha256::digest(public_key).into()
@@ -44,6 +46,7 @@ fn truncate_token_key_id(token_key_id: &TokenKeyId) -> TruncatedTokenKeyId {
 
 /// Serialization error
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum SerializationError {
     #[error("Invalid serialized data")]
     /// Invalid serialized data
@@ -1,30 +1,73 @@
 //! Server-side implementation of Privately Verifiable Token protocol.
 
+use std::{sync::Arc, time::Duration};
+
 use async_trait::async_trait;
 use generic_array::ArrayLength;
-use generic_array::GenericArray;
 use p384::NistP384;
-use rand::{rngs::OsRng, RngCore};
+use rand::{CryptoRng, RngCore};
+use subtle::ConstantTimeEq;
 use thiserror::Error;
-use voprf::{BlindedElement, Error, Group, Result, VoprfServer};
+use voprf::{Error, Group, Result, VoprfServer};
 
-use crate::{auth::authorize::Token, NonceStore, TokenInput, TokenType, TruncatedTokenKeyId};
+#[cfg(feature = "profiling")]
+use crate::IssuanceObserver;
+use crate::{
+    auth::authorize::Token,
+    clock::{Clock, ServerClock},
+    concurrency::{ConcurrencyLimit, ConcurrencyPermit},
+    config::{ConfigError, ServerConfig},
+    evaluator::{blind_evaluate_p384, BlindEvaluation, BlindEvaluator, BlindEvaluatorError},
+    issuer_directory::TokenKey,
+    key_derivation_info,
+    metrics::{Metrics, ServerMetrics},
+    policy::{check_policy, AcceptAll, PolicyRejection, RedemptionPolicy},
+    random_seed, uniform_server_p384,
+    webhooks::RedemptionOutcome,
+    DoubleSpendEvent, DoubleSpendObserver, ExposeSecret, IssuanceProfiler, IssuanceStage, KeyEpoch,
+    KeyStoreError, KeyValidity, MaybeSendSync, NonceStore, ReadinessError, RedemptionErrors,
+    SecretVec, ServerRng, TokenInput, TokenKeyId, TokenType, TruncatedTokenKeyId, VoprfError,
+};
+#[cfg(feature = "pem")]
+use crate::{
+    private_keys::{
+        p384_from_pkcs8_der, p384_from_pkcs8_pem, p384_to_pkcs8_der, p384_to_pkcs8_pem,
+        PrivateKeyError,
+    },
+    SecretString,
+};
 
 use super::{
     public_key_to_token_key_id, truncate_token_key_id, PublicKey, TokenRequest, TokenResponse, NK,
-    NS,
 };
 
 /// Errors that can occur when creating a keypair.
 #[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum CreateKeypairError {
     #[error("Seed is too long")]
     /// Error when the seed is too long.
     SeedError,
+    #[error("Key derivation failed")]
+    /// Error when the VOPRF implementation cannot derive or decode the key.
+    Voprf(#[source] VoprfError),
+    #[error(transparent)]
+    /// Error when the key store fails.
+    KeyStore(#[from] KeyStoreError),
+}
+
+impl From<VoprfError> for CreateKeypairError {
+    fn from(error: VoprfError) -> Self {
+        match error {
+            VoprfError::DeriveKeyPair => Self::SeedError,
+            error => Self::Voprf(error),
+        }
+    }
 }
 
 /// Errors that can occur when issuing the token response.
 #[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum IssueTokenResponseError {
     #[error("Key ID not found")]
     /// Error when the key ID is not found.
@@ -35,10 +78,41 @@ pub enum IssueTokenResponseError {
     #[error("Invalid toke type")]
     /// Error when the token type is invalid.
     InvalidTokenType,
+    #[error("Too many concurrent requests for the key")]
+    /// Error when the key is at its concurrency limit.
+    TooManyRequests,
+    #[error("Blinded element {0} failed to decode")]
+    /// Error when the blinded element at the given index is not a valid
+    /// group element.
+    InvalidBlindedElement(usize),
+    #[error("Proof generation failed")]
+    /// Error when the evaluation or its proof could not be computed.
+    ProofGenerationFailed(#[source] VoprfError),
+    #[error(transparent)]
+    /// Error when the blind evaluator fails or returns an invalid evaluation.
+    Evaluator(BlindEvaluatorError),
+    #[error("Key is not valid at this time")]
+    /// Error when the key is used before its not-before time or from its
+    /// not-after time on.
+    KeyNotValid,
+    #[error(transparent)]
+    /// Error when the key store fails.
+    KeyStore(#[from] KeyStoreError),
+}
+
+impl From<BlindEvaluatorError> for IssueTokenResponseError {
+    fn from(error: BlindEvaluatorError) -> Self {
+        match error {
+            BlindEvaluatorError::InvalidBlindedElement(index) => Self::InvalidBlindedElement(index),
+            BlindEvaluatorError::Voprf(error) => Self::ProofGenerationFailed(error),
+            error => Self::Evaluator(error),
+        }
+    }
 }
 
 /// Errors that can occur when redeeming the token.
 #[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum RedeemTokenError {
     #[error("Key ID not found")]
     /// Error when the key ID is not found.
@@ -49,23 +123,202 @@ pub enum RedeemTokenError {
     #[error("The token is invalid")]
     /// Error when the token is invalid.
     InvalidToken,
+    #[error("Rejected by redemption policy: {0}")]
+    /// Error when the redemption policy rejects the token.
+    PolicyRejected(#[from] PolicyRejection),
+    #[error("Key expired")]
+    /// Error when the token is redeemed after the not-after time of its key
+    /// and the redemption grace period.
+    KeyExpired,
+    #[error(transparent)]
+    /// Error when the key store fails.
+    KeyStore(#[from] KeyStoreError),
+}
+
+/// Classifies the result of a redemption for metrics.
+fn redemption_outcome(result: &Result<(), RedeemTokenError>) -> RedemptionOutcome {
+    match result {
+        Ok(()) => RedemptionOutcome::Redeemed,
+        Err(RedeemTokenError::DoubleSpending) => RedemptionOutcome::DoubleSpending,
+        Err(_) => RedemptionOutcome::Rejected,
+    }
 }
 
 /// Minimal trait for a key store to store key material on the server-side. Note
 /// that the store requires inner mutability.
-#[async_trait]
-pub trait PrivateKeyStore: Send + Sync {
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+pub trait PrivateKeyStore: MaybeSendSync {
     /// Inserts a keypair with a given `truncated_token_key_id` into the key store.
     async fn insert(
         &self,
         truncated_token_key_id: TruncatedTokenKeyId,
         server: VoprfServer<NistP384>,
-    );
-    /// Returns a keypair with a given `truncated_token_key_id` from the key store.
+    ) -> Result<(), KeyStoreError>;
+    /// Returns a keypair with a given `truncated_token_key_id` from the key
+    /// store, or `None` if the key store does not contain it.
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<VoprfServer<NistP384>>, KeyStoreError>;
+    /// Removes the keypair with a given `truncated_token_key_id` from the key
+    /// store. Returns `true` if the key store contained it.
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError>;
+    /// Returns the truncated token key IDs of all keypairs in the key store.
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError>;
+    /// Returns `true` if the key store contains a keypair with a given
+    /// `truncated_token_key_id`.
+    async fn contains(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(self.get(truncated_token_key_id).await?.is_some())
+    }
+    /// Loads the key material ahead of the first request, e.g. from a
+    /// database or a key management service. The default does nothing.
+    async fn preload(&self) {}
+    /// Records the validity period of the keypair with a given
+    /// `truncated_token_key_id`, possibly before the keypair is inserted. The
+    /// default fails, for key stores that cannot record validity periods.
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        let _ = (truncated_token_key_id, validity);
+        Err(KeyStoreError::Backend(
+            "key validity periods are not supported".to_string(),
+        ))
+    }
+    /// Returns the validity period of the keypair with a given
+    /// `truncated_token_key_id`. Keypairs without a recorded validity period,
+    /// and all keypairs of the default, are valid without bounds.
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        let _ = truncated_token_key_id;
+        Ok(KeyValidity::default())
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<S: PrivateKeyStore + ?Sized> PrivateKeyStore for Box<S> {
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        server: VoprfServer<NistP384>,
+    ) -> Result<(), KeyStoreError> {
+        (**self).insert(truncated_token_key_id, server).await
+    }
+
     async fn get(
         &self,
         truncated_token_key_id: &TruncatedTokenKeyId,
-    ) -> Option<VoprfServer<NistP384>>;
+    ) -> Result<Option<VoprfServer<NistP384>>, KeyStoreError> {
+        (**self).get(truncated_token_key_id).await
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        (**self).remove(truncated_token_key_id).await
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        (**self).list_key_ids().await
+    }
+
+    async fn contains(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        (**self).contains(truncated_token_key_id).await
+    }
+
+    async fn preload(&self) {
+        (**self).preload().await
+    }
+
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        (**self)
+            .set_validity(truncated_token_key_id, validity)
+            .await
+    }
+
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        (**self).validity(truncated_token_key_id).await
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<S: PrivateKeyStore + ?Sized> PrivateKeyStore for Arc<S> {
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        server: VoprfServer<NistP384>,
+    ) -> Result<(), KeyStoreError> {
+        (**self).insert(truncated_token_key_id, server).await
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<VoprfServer<NistP384>>, KeyStoreError> {
+        (**self).get(truncated_token_key_id).await
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        (**self).remove(truncated_token_key_id).await
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        (**self).list_key_ids().await
+    }
+
+    async fn contains(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        (**self).contains(truncated_token_key_id).await
+    }
+
+    async fn preload(&self) {
+        (**self).preload().await
+    }
+
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        (**self)
+            .set_validity(truncated_token_key_id, validity)
+            .await
+    }
+
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        (**self).validity(truncated_token_key_id).await
+    }
 }
 
 /// Serializes a public key.
@@ -85,81 +338,579 @@ pub fn deserialize_public_key(slice: &[u8]) -> Result<PublicKey, Error> {
 
 /// Server side implementation of Privately Verifiable Token protocol.
 #[derive(Default, Debug)]
-pub struct Server {}
+pub struct Server {
+    concurrency_limit: Option<ConcurrencyLimit>,
+    redemption_errors: RedemptionErrors,
+    rng: ServerRng,
+    metrics: ServerMetrics,
+    double_spend_observer: Option<DoubleSpendObserver>,
+    clock: ServerClock,
+    redemption_grace_period: Duration,
+    #[cfg(feature = "profiling")]
+    issuance_observer: Option<IssuanceObserver>,
+}
 
 impl Server {
     /// Creates a new server.
     #[must_use]
     pub const fn new() -> Self {
-        Self {}
+        Self {
+            concurrency_limit: None,
+            redemption_errors: RedemptionErrors::Detailed,
+            rng: ServerRng::os(),
+            metrics: ServerMetrics::none(),
+            double_spend_observer: None,
+            clock: ServerClock::system(),
+            redemption_grace_period: Duration::ZERO,
+            #[cfg(feature = "profiling")]
+            issuance_observer: None,
+        }
+    }
+
+    /// Creates a new server from a configuration.
+    ///
+    /// # Errors
+    /// Returns an error if the configuration is invalid or does not accept
+    /// privately verifiable tokens.
+    pub fn from_config(config: &ServerConfig) -> Result<Self, ConfigError> {
+        config.validate_for(TokenType::PrivateToken)?;
+        let mut server = Self::new().with_redemption_errors(config.redemption_errors());
+        if let Some(max_in_flight_per_key) = config.max_in_flight_per_key() {
+            server = server.with_concurrency_limit(ConcurrencyLimit::new(max_in_flight_per_key));
+        }
+        Ok(server)
+    }
+
+    /// Limits the number of concurrent evaluations per key. Requests for a
+    /// key that is at its limit fail with
+    /// [`IssueTokenResponseError::TooManyRequests`].
+    #[must_use]
+    pub fn with_concurrency_limit(mut self, concurrency_limit: ConcurrencyLimit) -> Self {
+        self.concurrency_limit = Some(concurrency_limit);
+        self
+    }
+
+    /// Sets how much detail redemption errors reveal.
+    #[must_use]
+    pub const fn with_redemption_errors(mut self, redemption_errors: RedemptionErrors) -> Self {
+        self.redemption_errors = redemption_errors;
+        self
+    }
+
+    /// Draws key seeds and proof randomness from `rng` instead of the OS
+    /// random number generator, e.g. to reproduce test vectors.
+    #[must_use]
+    pub fn with_rng<R: RngCore + CryptoRng + Send + 'static>(mut self, rng: R) -> Self {
+        self.rng = ServerRng::new(rng);
+        self
+    }
+
+    /// Reads the time that key validity periods are checked against from
+    /// `clock` instead of the system clock.
+    #[must_use]
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = ServerClock::new(clock);
+        self
+    }
+
+    /// Keeps redeeming tokens for `redemption_grace_period` after the
+    /// not-after time of their key, so that tokens issued shortly before a
+    /// key expired can still be spent. Defaults to no grace period.
+    #[must_use]
+    pub const fn with_redemption_grace_period(mut self, redemption_grace_period: Duration) -> Self {
+        self.redemption_grace_period = redemption_grace_period;
+        self
+    }
+
+    /// Reports every issuance and redemption to `metrics`.
+    #[must_use]
+    pub fn with_metrics<M: Metrics + 'static>(mut self, metrics: M) -> Self {
+        self.metrics = ServerMetrics::new(metrics);
+        self
+    }
+
+    /// Reports every token that is rejected as double spent to
+    /// `double_spend_observer`.
+    #[must_use]
+    pub fn with_double_spend_observer(
+        mut self,
+        double_spend_observer: DoubleSpendObserver,
+    ) -> Self {
+        self.double_spend_observer = Some(double_spend_observer);
+        self
+    }
+
+    /// Reports the time spent in each stage of every successful issuance to
+    /// `issuance_observer`.
+    #[cfg(feature = "profiling")]
+    #[must_use]
+    pub fn with_issuance_observer(mut self, issuance_observer: IssuanceObserver) -> Self {
+        self.issuance_observer = Some(issuance_observer);
+        self
     }
 
     /// Creates a new keypair and inserts it into the key store.
     ///
     /// # Errors
     /// Returns an error if creating the keypair failed.
-    pub async fn create_keypair<PKS: PrivateKeyStore>(
+    pub async fn create_keypair<PKS: PrivateKeyStore + ?Sized>(
+        &self,
+        key_store: &PKS,
+    ) -> Result<PublicKey, CreateKeypairError> {
+        let seed = random_seed::<NistP384>(&mut self.rng.clone());
+        self.create_keypair_internal(key_store, &seed, &key_derivation_info(None), None)
+            .await
+    }
+
+    /// Creates a new keypair whose derivation info is bound to an auditable
+    /// key epoch and inserts it into the key store. The epoch can be
+    /// published in the issuer directory with
+    /// [`TokenKey::with_epoch`](crate::issuer_directory::TokenKey::with_epoch).
+    ///
+    /// # Errors
+    /// Returns an error if creating the keypair failed.
+    pub async fn create_keypair_for_epoch<PKS: PrivateKeyStore + ?Sized>(
         &self,
         key_store: &PKS,
+        epoch: KeyEpoch,
     ) -> Result<PublicKey, CreateKeypairError> {
-        let mut seed = GenericArray::<_, <NistP384 as Group>::ScalarLen>::default();
-        OsRng.fill_bytes(&mut seed);
-        self.create_keypair_internal(key_store, &seed, b"PrivacyPass")
+        let seed = random_seed::<NistP384>(&mut self.rng.clone());
+        self.create_keypair_internal(key_store, &seed, &key_derivation_info(Some(epoch)), None)
             .await
     }
 
+    /// Creates a new keypair that is only used within `validity` and inserts
+    /// it into the key store. The validity period is recorded before the
+    /// keypair, so that the keypair is never used outside of it.
+    ///
+    /// # Errors
+    /// Returns an error if creating the keypair failed or the key store
+    /// cannot record validity periods.
+    pub async fn create_keypair_with_validity<PKS: PrivateKeyStore + ?Sized>(
+        &self,
+        key_store: &PKS,
+        validity: KeyValidity,
+    ) -> Result<PublicKey, CreateKeypairError> {
+        let seed = random_seed::<NistP384>(&mut self.rng.clone());
+        self.create_keypair_internal(key_store, &seed, &key_derivation_info(None), Some(validity))
+            .await
+    }
+
+    /// Creates a new keypair and makes it the primary key, see
+    /// [`Server::set_primary_key`]. Tokens issued under the previous keys are
+    /// redeemed for `redemption_window`.
+    ///
+    /// # Errors
+    /// Returns an error if creating the keypair failed or the key store
+    /// cannot record validity periods.
+    pub async fn rotate_keypair<PKS: PrivateKeyStore + ?Sized>(
+        &self,
+        key_store: &PKS,
+        redemption_window: Duration,
+    ) -> Result<PublicKey, CreateKeypairError> {
+        let seed = random_seed::<NistP384>(&mut self.rng.clone());
+        // The validity period is recorded first, so that key stores that
+        // cannot record it fail before the keypair is inserted
+        let public_key = self
+            .create_keypair_internal(
+                key_store,
+                &seed,
+                &key_derivation_info(None),
+                Some(KeyValidity::default()),
+            )
+            .await?;
+        self.set_primary_key(
+            key_store,
+            truncate_token_key_id(&public_key_to_token_key_id(&public_key)),
+            redemption_window,
+        )
+        .await?;
+        Ok(public_key)
+    }
+
+    /// Makes the keypair with a given `truncated_token_key_id` the primary
+    /// key, which issues tokens from now on. The other keys that currently
+    /// issue tokens stop now, and tokens issued under them are redeemed for
+    /// `redemption_window`. Keys whose not-before time lies in the future
+    /// are left alone, so that a key can be staged for the next rotation.
+    /// Returns `false` if the key store does not contain the keypair.
+    ///
+    /// # Errors
+    /// Returns an error if the key store fails or cannot record validity
+    /// periods.
+    pub async fn set_primary_key<PKS: PrivateKeyStore + ?Sized>(
+        &self,
+        key_store: &PKS,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        redemption_window: Duration,
+    ) -> Result<bool, KeyStoreError> {
+        if !key_store.contains(&truncated_token_key_id).await? {
+            return Ok(false);
+        }
+        let now = self.clock.unix_time();
+        // The primary key issues before the other keys stop, so that there
+        // is no moment without a key to issue under
+        if !key_store
+            .validity(&truncated_token_key_id)
+            .await?
+            .allows_issuance(now)
+        {
+            key_store
+                .set_validity(truncated_token_key_id, KeyValidity::default())
+                .await?;
+        }
+        for other_key_id in key_store.list_key_ids().await? {
+            if other_key_id == truncated_token_key_id {
+                continue;
+            }
+            let validity = key_store.validity(&other_key_id).await?;
+            if validity.allows_issuance(now) {
+                let redeemable_until = validity
+                    .redeemable_until
+                    .unwrap_or_default()
+                    .max(now.saturating_add(redemption_window.as_secs()));
+                key_store
+                    .set_validity(
+                        other_key_id,
+                        KeyValidity::new(validity.not_before, Some(now))
+                            .with_redeemable_until(redeemable_until),
+                    )
+                    .await?;
+            }
+        }
+        Ok(true)
+    }
+
     /// Creates a new keypair and inserts it into the key store.
-    async fn create_keypair_internal<PKS: PrivateKeyStore>(
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(truncated_token_key_id = tracing::field::Empty),
+            err(level = "debug")
+        )
+    )]
+    async fn create_keypair_internal<PKS: PrivateKeyStore + ?Sized>(
         &self,
         key_store: &PKS,
-        seed: &[u8],
+        seed: &SecretVec<u8>,
         info: &[u8],
+        validity: Option<KeyValidity>,
     ) -> Result<PublicKey, CreateKeypairError> {
-        let server = VoprfServer::<NistP384>::new_from_seed(seed, info)
-            .map_err(|_| CreateKeypairError::SeedError)?;
+        let server = VoprfServer::<NistP384>::new_from_seed(seed.expose_secret(), info)
+            .map_err(VoprfError::from)?;
         let public_key = server.get_public_key();
         let truncated_token_key_id =
             truncate_token_key_id(&public_key_to_token_key_id(&server.get_public_key()));
-        key_store.insert(truncated_token_key_id, server).await;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("truncated_token_key_id", truncated_token_key_id);
+        if let Some(validity) = validity {
+            key_store
+                .set_validity(truncated_token_key_id, validity)
+                .await?;
+        }
+        key_store.insert(truncated_token_key_id, server).await?;
         Ok(public_key)
     }
 
+    /// Derives a keypair from `seed` and `info` and inserts it into the key
+    /// store. The same seed and info always yield the same keypair, so that
+    /// keys can be derived from a master secret held elsewhere or reproduced
+    /// across replicas.
+    ///
+    /// # Errors
+    /// Returns an error if the seed is too long or the key cannot be
+    /// derived.
+    pub async fn create_keypair_with_seed<PKS: PrivateKeyStore + ?Sized>(
+        &self,
+        key_store: &PKS,
+        seed: &SecretVec<u8>,
+        info: &[u8],
+    ) -> Result<PublicKey, CreateKeypairError> {
+        self.create_keypair_internal(key_store, seed, info, None)
+            .await
+    }
+
     /// Creates a new keypair with explicit parameters and inserts it into the
-    /// key store.
+    /// key store. The seed is wrapped in a [`SecretVec`] so that it cannot be
+    /// logged or serialized by accident.
     #[cfg(feature = "kat")]
-    pub async fn create_keypair_with_params<PKS: PrivateKeyStore>(
+    pub async fn create_keypair_with_params<PKS: PrivateKeyStore + ?Sized>(
         &self,
         key_store: &PKS,
-        seed: &[u8],
+        seed: &SecretVec<u8>,
         info: &[u8],
     ) -> Result<PublicKey, CreateKeypairError> {
-        self.create_keypair_internal(key_store, seed, info).await
+        self.create_keypair_internal(key_store, seed, info, None)
+            .await
+    }
+
+    /// Preloads the key store and checks that every key in
+    /// `truncated_token_key_ids` is present and can evaluate an input, so that
+    /// deployments can hold back traffic until issuance will succeed.
+    ///
+    /// # Errors
+    /// Returns an error for the first key that is not ready.
+    pub async fn ready<PKS: PrivateKeyStore + ?Sized>(
+        &self,
+        key_store: &PKS,
+        truncated_token_key_ids: &[TruncatedTokenKeyId],
+    ) -> Result<(), ReadinessError> {
+        key_store.preload().await;
+        for truncated_token_key_id in truncated_token_key_ids {
+            key_store
+                .get(truncated_token_key_id)
+                .await?
+                .ok_or(ReadinessError::KeyIdNotFound(*truncated_token_key_id))?
+                .evaluate(b"readiness")
+                .map_err(|_| ReadinessError::InvalidKey(*truncated_token_key_id))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the issuer directory entry of the key in the key store, or
+    /// `None` if the key is not present. `not_before` is the time from which
+    /// on the key is used, in seconds since the Unix epoch.
+    ///
+    /// # Errors
+    /// Returns an error if the key store fails.
+    pub async fn token_key<PKS: PrivateKeyStore + ?Sized>(
+        &self,
+        key_store: &PKS,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        not_before: Option<u64>,
+    ) -> Result<Option<TokenKey>, KeyStoreError> {
+        let Some(server) = key_store.get(&truncated_token_key_id).await? else {
+            return Ok(None);
+        };
+        Ok(Some(TokenKey::new(
+            TokenType::PrivateToken,
+            &serialize_public_key(server.get_public_key()),
+            not_before,
+        )))
     }
 
     /// Issues a token response.
     ///
     /// # Errors
     /// Returns an error if the token request is invalid.
-    pub async fn issue_token_response<PKS: PrivateKeyStore>(
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                token_type = ?token_request.token_type,
+                truncated_token_key_id = token_request.truncated_token_key_id,
+            ),
+            err(level = "debug")
+        )
+    )]
+    pub async fn issue_token_response<PKS: PrivateKeyStore + ?Sized>(
         &self,
         key_store: &PKS,
         token_request: TokenRequest,
     ) -> Result<TokenResponse, IssueTokenResponseError> {
-        if token_request.token_type != TokenType::PrivateToken {
-            return Err(IssueTokenResponseError::InvalidTokenType);
-        }
+        let token_type = token_request.token_type;
+        let truncated_token_key_id = token_request.truncated_token_key_id;
+        let result = self
+            .issue_token_response_inner(key_store, token_request)
+            .await;
+        self.metrics
+            .issuance(token_type, truncated_token_key_id, 1, &result);
+        result
+    }
+
+    async fn issue_token_response_inner<PKS: PrivateKeyStore + ?Sized>(
+        &self,
+        key_store: &PKS,
+        token_request: TokenRequest,
+    ) -> Result<TokenResponse, IssueTokenResponseError> {
+        self.check_token_request(&token_request)?;
+        let _permit = self.try_acquire_permit(token_request.truncated_token_key_id)?;
+        let mut profiler = IssuanceProfiler::start();
         let server = key_store
             .get(&token_request.truncated_token_key_id)
-            .await
+            .await?
             .ok_or(IssueTokenResponseError::KeyIdNotFound)?;
-        let blinded_element = BlindedElement::<NistP384>::deserialize(&token_request.blinded_msg)
-            .map_err(|_| IssueTokenResponseError::InvalidTokenRequest)?;
-        let evaluated_result = server.blind_evaluate(&mut OsRng, &blinded_element);
-        let mut evaluate_proof = [0u8; NS + NS];
-        evaluate_proof[..(NS + NS)].copy_from_slice(&evaluated_result.proof.serialize());
+        if !key_store
+            .validity(&token_request.truncated_token_key_id)
+            .await?
+            .allows_issuance(self.clock.unix_time())
+        {
+            return Err(IssueTokenResponseError::KeyNotValid);
+        }
+        profiler.record(IssuanceStage::KeyFetch);
+        let token_response =
+            Self::evaluate_token_request(self.rng.clone(), &server, &token_request, &mut profiler)?;
+        #[cfg(feature = "profiling")]
+        profiler.finish(self.issuance_observer.as_ref());
+        Ok(token_response)
+    }
+
+    /// Issues a token response with a key the caller manages itself, e.g. in
+    /// a hardware security device or a static configuration, without going
+    /// through a key store.
+    ///
+    /// # Errors
+    /// Returns an error if the token request is invalid or was not made for
+    /// the key of `server`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                token_type = ?token_request.token_type,
+                truncated_token_key_id = token_request.truncated_token_key_id,
+            ),
+            err(level = "debug")
+        )
+    )]
+    pub fn issue_token_response_with_key(
+        &self,
+        server: &VoprfServer<NistP384>,
+        token_request: TokenRequest,
+    ) -> Result<TokenResponse, IssueTokenResponseError> {
+        let token_type = token_request.token_type;
+        let truncated_token_key_id = token_request.truncated_token_key_id;
+        let result = self.issue_token_response_with_key_inner(server, token_request);
+        self.metrics
+            .issuance(token_type, truncated_token_key_id, 1, &result);
+        result
+    }
+
+    fn issue_token_response_with_key_inner(
+        &self,
+        server: &VoprfServer<NistP384>,
+        token_request: TokenRequest,
+    ) -> Result<TokenResponse, IssueTokenResponseError> {
+        self.check_token_request(&token_request)?;
+        if truncate_token_key_id(&public_key_to_token_key_id(&server.get_public_key()))
+            != token_request.truncated_token_key_id
+        {
+            return Err(IssueTokenResponseError::KeyIdNotFound);
+        }
+        let _permit = self.try_acquire_permit(token_request.truncated_token_key_id)?;
+        let mut profiler = IssuanceProfiler::start();
+        let token_response =
+            Self::evaluate_token_request(self.rng.clone(), server, &token_request, &mut profiler)?;
+        #[cfg(feature = "profiling")]
+        profiler.finish(self.issuance_observer.as_ref());
+        Ok(token_response)
+    }
+
+    /// Issues a token response with a [`BlindEvaluator`], e.g. one that keeps
+    /// the private key in a KMS or HSM, without going through a key store.
+    ///
+    /// # Errors
+    /// Returns an error if the token request is invalid, was not made for
+    /// the key of `evaluator` or the evaluation fails.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                token_type = ?token_request.token_type,
+                truncated_token_key_id = token_request.truncated_token_key_id,
+            ),
+            err(level = "debug")
+        )
+    )]
+    pub async fn issue_token_response_with_evaluator<E: BlindEvaluator + ?Sized>(
+        &self,
+        evaluator: &E,
+        token_request: TokenRequest,
+    ) -> Result<TokenResponse, IssueTokenResponseError> {
+        let token_type = token_request.token_type;
+        let truncated_token_key_id = token_request.truncated_token_key_id;
+        let result = self
+            .issue_token_response_with_evaluator_inner(evaluator, token_request)
+            .await;
+        self.metrics
+            .issuance(token_type, truncated_token_key_id, 1, &result);
+        result
+    }
+
+    async fn issue_token_response_with_evaluator_inner<E: BlindEvaluator + ?Sized>(
+        &self,
+        evaluator: &E,
+        token_request: TokenRequest,
+    ) -> Result<TokenResponse, IssueTokenResponseError> {
+        self.check_token_request(&token_request)?;
+        if truncate_token_key_id(&evaluator.token_key_id()) != token_request.truncated_token_key_id
+        {
+            return Err(IssueTokenResponseError::KeyIdNotFound);
+        }
+        let _permit = self.try_acquire_permit(token_request.truncated_token_key_id)?;
+        let mut profiler = IssuanceProfiler::start();
+        let evaluation = evaluator
+            .blind_evaluate(
+                &mut self.rng.clone(),
+                &[token_request.blinded_msg.as_slice()],
+            )
+            .await?;
+        let token_response = Self::token_response(evaluation)?;
+        profiler.record(IssuanceStage::Serialize);
+        #[cfg(feature = "profiling")]
+        profiler.finish(self.issuance_observer.as_ref());
+        Ok(token_response)
+    }
+
+    fn check_token_request(
+        &self,
+        token_request: &TokenRequest,
+    ) -> Result<(), IssueTokenResponseError> {
+        if token_request.token_type != TokenType::PrivateToken {
+            return Err(IssueTokenResponseError::InvalidTokenType);
+        }
+        Ok(())
+    }
+
+    fn try_acquire_permit(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+    ) -> Result<Option<ConcurrencyPermit>, IssueTokenResponseError> {
+        self.concurrency_limit
+            .as_ref()
+            .map(|limit| {
+                limit
+                    .try_acquire(truncated_token_key_id)
+                    .ok_or(IssueTokenResponseError::TooManyRequests)
+            })
+            .transpose()
+    }
+
+    fn evaluate_token_request(
+        mut rng: ServerRng,
+        server: &VoprfServer<NistP384>,
+        token_request: &TokenRequest,
+        profiler: &mut IssuanceProfiler,
+    ) -> Result<TokenResponse, IssueTokenResponseError> {
+        let evaluation = blind_evaluate_p384(
+            server,
+            &mut rng,
+            &[token_request.blinded_msg.as_slice()],
+            profiler,
+        )?;
+        let token_response = Self::token_response(evaluation)?;
+        profiler.record(IssuanceStage::Serialize);
+        Ok(token_response)
+    }
+
+    fn token_response(evaluation: BlindEvaluation) -> Result<TokenResponse, BlindEvaluatorError> {
+        let [evaluate_msg] = <[Vec<u8>; 1]>::try_from(evaluation.evaluated_elements)
+            .map_err(|_| BlindEvaluatorError::InvalidEvaluation)?;
         Ok(TokenResponse {
-            evaluate_msg: evaluated_result.message.serialize().into(),
-            evaluate_proof,
+            evaluate_msg: evaluate_msg
+                .try_into()
+                .map_err(|_| BlindEvaluatorError::InvalidEvaluation)?,
+            evaluate_proof: evaluation
+                .proof
+                .try_into()
+                .map_err(|_| BlindEvaluatorError::InvalidEvaluation)?,
         })
     }
 
@@ -167,12 +918,94 @@ impl Server {
     ///
     /// # Errors
     /// Returns an error if the token is invalid.
-    pub async fn redeem_token<PKS: PrivateKeyStore, NS: NonceStore, Nk: ArrayLength<u8>>(
+    pub async fn redeem_token<
+        PKS: PrivateKeyStore + ?Sized,
+        NS: NonceStore + ?Sized,
+        Nk: ArrayLength<u8>,
+    >(
         &self,
         key_store: &PKS,
         nonce_store: &NS,
         token: Token<Nk>,
     ) -> Result<(), RedeemTokenError> {
+        self.redeem_token_with_policy(key_store, nonce_store, token, &AcceptAll, &())
+            .await
+    }
+
+    /// Redeems a token if `policy` accepts it. The policy is consulted with
+    /// `metadata` after the token was verified and before its nonce is
+    /// recorded, so a rejected token is not spent.
+    ///
+    /// # Errors
+    /// Returns an error if the token is invalid or the policy rejects it.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                token_type = ?token.token_type(),
+                truncated_token_key_id = truncate_token_key_id(token.token_key_id()),
+            ),
+            err(level = "debug")
+        )
+    )]
+    pub async fn redeem_token_with_policy<
+        PKS: PrivateKeyStore + ?Sized,
+        NS: NonceStore + ?Sized,
+        Nk: ArrayLength<u8>,
+        P: RedemptionPolicy<M> + ?Sized,
+        M: Sync + ?Sized,
+    >(
+        &self,
+        key_store: &PKS,
+        nonce_store: &NS,
+        token: Token<Nk>,
+        policy: &P,
+        metadata: &M,
+    ) -> Result<(), RedeemTokenError> {
+        let token_type = token.token_type();
+        let token_key_id = *token.token_key_id();
+        let challenge_digest = *token.challenge_digest();
+        let result = self
+            .redeem_token_with_policy_inner(key_store, nonce_store, token, policy, metadata)
+            .await;
+        self.metrics.redemption(
+            token_type,
+            truncate_token_key_id(&token_key_id),
+            redemption_outcome(&result),
+        );
+        if let (Err(RedeemTokenError::DoubleSpending), Some(double_spend_observer)) =
+            (&result, &self.double_spend_observer)
+        {
+            double_spend_observer.observe(&DoubleSpendEvent {
+                token_type,
+                token_key_id,
+                challenge_digest,
+            });
+        }
+        result
+    }
+
+    async fn redeem_token_with_policy_inner<
+        PKS: PrivateKeyStore + ?Sized,
+        NS: NonceStore + ?Sized,
+        Nk: ArrayLength<u8>,
+        P: RedemptionPolicy<M> + ?Sized,
+        M: Sync + ?Sized,
+    >(
+        &self,
+        key_store: &PKS,
+        nonce_store: &NS,
+        token: Token<Nk>,
+        policy: &P,
+        metadata: &M,
+    ) -> Result<(), RedeemTokenError> {
+        if self.redemption_errors == RedemptionErrors::Uniform {
+            return self
+                .redeem_token_uniform(key_store, nonce_store, token, policy, metadata)
+                .await;
+        }
         if token.token_type() != TokenType::PrivateToken {
             return Err(RedeemTokenError::InvalidToken);
         }
@@ -191,33 +1024,290 @@ impl Server {
 
         let server = key_store
             .get(&truncate_token_key_id(token.token_key_id()))
-            .await
+            .await?
             .ok_or(RedeemTokenError::KeyIdNotFound)?;
+        if !self
+            .allows_redemption(key_store, token.token_key_id())
+            .await?
+        {
+            return Err(RedeemTokenError::KeyExpired);
+        }
         let token_authenticator = server
-            .evaluate(&token_input.serialize())
-            .map_err(|_| RedeemTokenError::InvalidToken)?
-            .to_vec();
-        if token.authenticator() == token_authenticator {
-            nonce_store.insert(token.nonce()).await;
+            .evaluate(token_input.to_bytes().as_slice())
+            .map_err(|_| RedeemTokenError::InvalidToken)?;
+        let valid: bool = token_authenticator
+            .as_slice()
+            .ct_eq(token.authenticator())
+            .into();
+        if valid {
+            check_policy(
+                policy,
+                &token,
+                truncate_token_key_id(token.token_key_id()),
+                metadata,
+            )
+            .await?;
+            if !nonce_store.try_insert(token.nonce()).await {
+                return Err(RedeemTokenError::DoubleSpending);
+            }
             Ok(())
         } else {
             Err(RedeemTokenError::InvalidToken)
         }
     }
 
+    /// Verifies a token with a key the caller manages itself, without going
+    /// through a key store. The nonce is neither checked nor recorded, so the
+    /// caller is responsible for detecting double spending.
+    ///
+    /// # Errors
+    /// Returns an error if the token is invalid or was not issued under the
+    /// key of `server`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                token_type = ?token.token_type(),
+                truncated_token_key_id = truncate_token_key_id(token.token_key_id()),
+            ),
+            err(level = "debug")
+        )
+    )]
+    pub fn redeem_token_with_key<Nk: ArrayLength<u8>>(
+        &self,
+        server: &VoprfServer<NistP384>,
+        token: Token<Nk>,
+    ) -> Result<(), RedeemTokenError> {
+        let token_type = token.token_type();
+        let truncated_token_key_id = truncate_token_key_id(token.token_key_id());
+        let result = self.redeem_token_with_key_inner(server, token);
+        self.metrics.redemption(
+            token_type,
+            truncated_token_key_id,
+            redemption_outcome(&result),
+        );
+        result
+    }
+
+    fn redeem_token_with_key_inner<Nk: ArrayLength<u8>>(
+        &self,
+        server: &VoprfServer<NistP384>,
+        token: Token<Nk>,
+    ) -> Result<(), RedeemTokenError> {
+        let well_formed =
+            token.token_type() == TokenType::PrivateToken && token.authenticator().len() == NK;
+        if !well_formed {
+            return Err(RedeemTokenError::InvalidToken);
+        }
+        if public_key_to_token_key_id(&server.get_public_key()) != *token.token_key_id() {
+            return Err(match self.redemption_errors {
+                RedemptionErrors::Detailed => RedeemTokenError::KeyIdNotFound,
+                RedemptionErrors::Uniform => RedeemTokenError::InvalidToken,
+            });
+        }
+        let token_input = TokenInput::new(
+            token.token_type(),
+            token.nonce(),
+            *token.challenge_digest(),
+            *token.token_key_id(),
+        );
+        let valid: bool = match server.evaluate(token_input.to_bytes().as_slice()) {
+            Ok(token_authenticator) => token_authenticator
+                .as_slice()
+                .ct_eq(token.authenticator())
+                .into(),
+            Err(_) => false,
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(RedeemTokenError::InvalidToken)
+        }
+    }
+
+    /// Redeems a token without revealing the cause of a failure. Unknown key
+    /// IDs and malformed tokens go through the same nonce and key lookups and
+    /// an evaluation with a throw-away key, the authenticator is compared in
+    /// constant time, and all of them fail with
+    /// [`RedeemTokenError::InvalidToken`].
+    async fn redeem_token_uniform<
+        PKS: PrivateKeyStore + ?Sized,
+        NS: NonceStore + ?Sized,
+        Nk: ArrayLength<u8>,
+        P: RedemptionPolicy<M> + ?Sized,
+        M: Sync + ?Sized,
+    >(
+        &self,
+        key_store: &PKS,
+        nonce_store: &NS,
+        token: Token<Nk>,
+        policy: &P,
+        metadata: &M,
+    ) -> Result<(), RedeemTokenError> {
+        // Derived ahead of the key lookup, so that the first unknown key ID
+        // does not pay for it
+        let uniform_server = uniform_server_p384();
+        let well_formed =
+            token.token_type() == TokenType::PrivateToken && token.authenticator().len() == NK;
+        let spent = nonce_store.exists(&token.nonce()).await;
+        let token_input = TokenInput::new(
+            token.token_type(),
+            token.nonce(),
+            *token.challenge_digest(),
+            *token.token_key_id(),
+        );
+
+        let server = key_store
+            .get(&truncate_token_key_id(token.token_key_id()))
+            .await?;
+        let known = server.is_some();
+        let current = self
+            .allows_redemption(key_store, token.token_key_id())
+            .await?;
+        let server = match &server {
+            Some(server) => server,
+            None => uniform_server.ok_or(RedeemTokenError::InvalidToken)?,
+        };
+        let valid = match server.evaluate(token_input.to_bytes().as_slice()) {
+            Ok(token_authenticator) => token_authenticator
+                .as_slice()
+                .ct_eq(token.authenticator())
+                .into(),
+            Err(_) => false,
+        };
+
+        if !(well_formed && known && current && valid) {
+            return Err(RedeemTokenError::InvalidToken);
+        }
+        if spent {
+            return Err(RedeemTokenError::DoubleSpending);
+        }
+        check_policy(
+            policy,
+            &token,
+            truncate_token_key_id(token.token_key_id()),
+            metadata,
+        )
+        .await?;
+        if !nonce_store.try_insert(token.nonce()).await {
+            return Err(RedeemTokenError::DoubleSpending);
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if tokens issued under the key may be redeemed now.
+    async fn allows_redemption<PKS: PrivateKeyStore + ?Sized>(
+        &self,
+        key_store: &PKS,
+        token_key_id: &TokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(key_store
+            .validity(&truncate_token_key_id(token_key_id))
+            .await?
+            .allows_redemption(
+                self.clock.unix_time(),
+                self.redemption_grace_period.as_secs(),
+            ))
+    }
+
+    /// Exports the private key of a key in the key store as a DER-encoded
+    /// PKCS#8 document.
+    ///
+    /// # Errors
+    /// Returns an error if the key is not present, cannot be encoded or the
+    /// key store fails.
+    #[cfg(feature = "pem")]
+    pub async fn export_private_key<PKS: PrivateKeyStore + ?Sized>(
+        &self,
+        key_store: &PKS,
+        truncated_token_key_id: TruncatedTokenKeyId,
+    ) -> Result<SecretVec<u8>, PrivateKeyError> {
+        let server = key_store
+            .get(&truncated_token_key_id)
+            .await?
+            .ok_or(PrivateKeyError::KeyIdNotFound)?;
+        p384_to_pkcs8_der(&server)
+    }
+
+    /// Exports the private key of a key in the key store as a PEM-encoded
+    /// PKCS#8 document.
+    ///
+    /// # Errors
+    /// Returns an error if the key is not present, cannot be encoded or the
+    /// key store fails.
+    #[cfg(feature = "pem")]
+    pub async fn export_private_key_pem<PKS: PrivateKeyStore + ?Sized>(
+        &self,
+        key_store: &PKS,
+        truncated_token_key_id: TruncatedTokenKeyId,
+    ) -> Result<SecretString, PrivateKeyError> {
+        let server = key_store
+            .get(&truncated_token_key_id)
+            .await?
+            .ok_or(PrivateKeyError::KeyIdNotFound)?;
+        p384_to_pkcs8_pem(&server)
+    }
+
+    /// Imports a private key from a DER-encoded PKCS#8 document into the key
+    /// store.
+    ///
+    /// # Errors
+    /// Returns an error if the document does not contain a P-384 private key
+    /// or the key store fails.
+    #[cfg(feature = "pem")]
+    pub async fn import_private_key<PKS: PrivateKeyStore + ?Sized>(
+        &self,
+        key_store: &PKS,
+        der: &[u8],
+    ) -> Result<PublicKey, PrivateKeyError> {
+        self.insert_private_key(key_store, p384_from_pkcs8_der(der)?)
+            .await
+    }
+
+    /// Imports a private key from a PEM-encoded PKCS#8 document into the key
+    /// store.
+    ///
+    /// # Errors
+    /// Returns an error if the document does not contain a P-384 private key
+    /// or the key store fails.
+    #[cfg(feature = "pem")]
+    pub async fn import_private_key_pem<PKS: PrivateKeyStore + ?Sized>(
+        &self,
+        key_store: &PKS,
+        pem: &str,
+    ) -> Result<PublicKey, PrivateKeyError> {
+        self.insert_private_key(key_store, p384_from_pkcs8_pem(pem)?)
+            .await
+    }
+
+    #[cfg(feature = "pem")]
+    async fn insert_private_key<PKS: PrivateKeyStore + ?Sized>(
+        &self,
+        key_store: &PKS,
+        server: VoprfServer<NistP384>,
+    ) -> Result<PublicKey, PrivateKeyError> {
+        let public_key = server.get_public_key();
+        let truncated_token_key_id =
+            truncate_token_key_id(&public_key_to_token_key_id(&public_key));
+        key_store.insert(truncated_token_key_id, server).await?;
+        Ok(public_key)
+    }
+
     /// Sets a keypair with a given `private_key` into the key store.
     #[cfg(feature = "kat")]
-    pub async fn set_key<PKS: PrivateKeyStore>(
+    pub async fn set_key<PKS: PrivateKeyStore + ?Sized>(
         &self,
         key_store: &PKS,
         private_key: &[u8],
     ) -> Result<PublicKey, CreateKeypairError> {
-        let server = VoprfServer::<NistP384>::new_with_key(private_key)
-            .map_err(|_| CreateKeypairError::SeedError)?;
+        let server =
+            VoprfServer::<NistP384>::new_with_key(private_key).map_err(VoprfError::from)?;
         let public_key = server.get_public_key();
         let truncated_token_key_id =
             truncate_token_key_id(&public_key_to_token_key_id(&server.get_public_key()));
-        key_store.insert(truncated_token_key_id, server).await;
+        key_store.insert(truncated_token_key_id, server).await?;
         Ok(public_key)
     }
 }
@@ -0,0 +1,397 @@
+//! Problem details for HTTP APIs as defined in RFC 9457.
+//!
+//! Issuer and origin HTTP handlers can use this module to turn the errors of
+//! the server-side components into `application/problem+json` responses, so
+//! that clients get a machine-readable reason for a failed request.
+
+use http::{header::CONTENT_TYPE, HeaderValue, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+#[cfg(any(feature = "p384", feature = "ristretto255"))]
+use crate::evaluator::BlindEvaluatorError;
+
+/// Media type of a problem details document.
+pub const PROBLEM_JSON_MEDIA_TYPE: &str = "application/problem+json";
+
+/// Prefix of the `type` member of problem details documents. The error code
+/// is appended, e.g. `urn:privacypass:problem:double-spending`. The type is
+/// a URN and not a URL, so it is stable but not dereferenceable.
+pub const PROBLEM_TYPE_BASE_URI: &str = "urn:privacypass:problem:";
+
+/// A problem details document as defined in RFC 9457. In addition to the
+/// standard members, the document carries a stable `code` extension member
+/// that identifies the error.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: String,
+    status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    code: String,
+}
+
+impl ProblemDetails {
+    /// Creates a new problem details document. The `type` member is derived
+    /// from the error code.
+    #[must_use]
+    pub fn new(code: &str, title: &str, status: StatusCode) -> Self {
+        Self {
+            problem_type: format!("{PROBLEM_TYPE_BASE_URI}{code}"),
+            title: title.to_string(),
+            status: status.as_u16(),
+            detail: None,
+            code: code.to_string(),
+        }
+    }
+
+    /// Sets the human-readable explanation specific to this occurrence of the
+    /// problem.
+    #[must_use]
+    pub fn with_detail(mut self, detail: &str) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+
+    /// Overrides the type URI of the problem.
+    #[must_use]
+    pub fn with_type(mut self, problem_type: &str) -> Self {
+        self.problem_type = problem_type.to_string();
+        self
+    }
+
+    /// Returns the type URI.
+    #[must_use]
+    pub fn problem_type(&self) -> &str {
+        &self.problem_type
+    }
+
+    /// Returns the title.
+    #[must_use]
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// Returns the HTTP status code.
+    #[must_use]
+    pub fn status(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    /// Returns the optional detail.
+    #[must_use]
+    pub fn detail(&self) -> Option<&str> {
+        self.detail.as_deref()
+    }
+
+    /// Returns the error code.
+    #[must_use]
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Serializes the problem details document as JSON.
+    #[must_use]
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap_or_default()
+    }
+
+    /// Builds an HTTP response with the status code, the
+    /// `application/problem+json` content type, and the JSON document as the
+    /// body.
+    #[must_use]
+    pub fn to_response(&self) -> Response<Vec<u8>> {
+        let mut response = Response::new(self.to_json());
+        *response.status_mut() = self.status();
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(PROBLEM_JSON_MEDIA_TYPE),
+        );
+        response
+    }
+}
+
+/// Conversion of an error into a problem details document.
+pub trait ToProblemDetails {
+    /// Returns the problem details document describing the error.
+    fn to_problem_details(&self) -> ProblemDetails;
+}
+
+fn key_id_not_found(status: StatusCode) -> ProblemDetails {
+    ProblemDetails::new("key-id-not-found", "Key ID not found", status)
+}
+
+fn invalid_token_request() -> ProblemDetails {
+    ProblemDetails::new(
+        "invalid-token-request",
+        "Invalid TokenRequest",
+        StatusCode::BAD_REQUEST,
+    )
+}
+
+fn invalid_token_type() -> ProblemDetails {
+    ProblemDetails::new(
+        "invalid-token-type",
+        "Invalid token type",
+        StatusCode::BAD_REQUEST,
+    )
+}
+
+fn too_many_requests() -> ProblemDetails {
+    ProblemDetails::new(
+        "too-many-requests",
+        "Too many concurrent requests for the key",
+        StatusCode::TOO_MANY_REQUESTS,
+    )
+}
+
+#[cfg(any(feature = "p384", feature = "ristretto255"))]
+fn proof_generation_failed() -> ProblemDetails {
+    ProblemDetails::new(
+        "proof-generation-failed",
+        "Proof generation failed",
+        StatusCode::INTERNAL_SERVER_ERROR,
+    )
+}
+
+#[cfg(any(feature = "p384", feature = "ristretto255"))]
+fn evaluator_unavailable() -> ProblemDetails {
+    ProblemDetails::new(
+        "evaluator-unavailable",
+        "The blind evaluator is unavailable",
+        StatusCode::SERVICE_UNAVAILABLE,
+    )
+}
+
+#[cfg(any(feature = "p384", feature = "ristretto255"))]
+fn batch_too_large() -> ProblemDetails {
+    ProblemDetails::new(
+        "batch-too-large",
+        "Batch exceeds the limit",
+        StatusCode::PAYLOAD_TOO_LARGE,
+    )
+}
+
+fn key_not_valid() -> ProblemDetails {
+    ProblemDetails::new(
+        "key-not-valid",
+        "The key is not valid at this time",
+        StatusCode::BAD_REQUEST,
+    )
+}
+
+fn key_expired() -> ProblemDetails {
+    ProblemDetails::new(
+        "key-expired",
+        "The key of the token has expired",
+        StatusCode::UNAUTHORIZED,
+    )
+}
+
+fn key_store_unavailable() -> ProblemDetails {
+    ProblemDetails::new(
+        "key-store-unavailable",
+        "The key store is unavailable",
+        StatusCode::SERVICE_UNAVAILABLE,
+    )
+}
+
+fn double_spending() -> ProblemDetails {
+    ProblemDetails::new(
+        "double-spending",
+        "The token has already been redeemed",
+        StatusCode::UNAUTHORIZED,
+    )
+}
+
+fn invalid_token() -> ProblemDetails {
+    ProblemDetails::new(
+        "invalid-token",
+        "The token is invalid",
+        StatusCode::UNAUTHORIZED,
+    )
+}
+
+fn policy_rejected(rejection: &crate::policy::PolicyRejection) -> ProblemDetails {
+    ProblemDetails::new(
+        "policy-rejected",
+        "Rejected by redemption policy",
+        StatusCode::FORBIDDEN,
+    )
+    .with_detail(rejection.reason())
+}
+
+#[cfg(feature = "p384")]
+impl ToProblemDetails for crate::private_tokens::server::IssueTokenResponseError {
+    fn to_problem_details(&self) -> ProblemDetails {
+        match self {
+            Self::KeyIdNotFound => key_id_not_found(StatusCode::BAD_REQUEST),
+            Self::InvalidTokenRequest => invalid_token_request(),
+            Self::InvalidTokenType => invalid_token_type(),
+            Self::TooManyRequests => too_many_requests(),
+            Self::InvalidBlindedElement(_) => invalid_token_request(),
+            Self::ProofGenerationFailed(_) => proof_generation_failed(),
+            Self::Evaluator(BlindEvaluatorError::Backend(_)) => evaluator_unavailable(),
+            Self::Evaluator(_) => proof_generation_failed(),
+            Self::KeyNotValid => key_not_valid(),
+            Self::KeyStore(_) => key_store_unavailable(),
+        }
+    }
+}
+
+#[cfg(feature = "p384")]
+impl ToProblemDetails for crate::private_tokens::server::RedeemTokenError {
+    fn to_problem_details(&self) -> ProblemDetails {
+        match self {
+            Self::KeyIdNotFound => key_id_not_found(StatusCode::UNAUTHORIZED),
+            Self::DoubleSpending => double_spending(),
+            Self::InvalidToken => invalid_token(),
+            Self::PolicyRejected(rejection) => policy_rejected(rejection),
+            Self::KeyExpired => key_expired(),
+            Self::KeyStore(_) => key_store_unavailable(),
+        }
+    }
+}
+
+impl ToProblemDetails for crate::public_tokens::server::IssueTokenResponseError {
+    fn to_problem_details(&self) -> ProblemDetails {
+        match self {
+            Self::KeyIdNotFound => key_id_not_found(StatusCode::BAD_REQUEST),
+            Self::InvalidTokenRequest => invalid_token_request(),
+            Self::InvalidTokenType => invalid_token_type(),
+            Self::TooManyRequests => too_many_requests(),
+            Self::KeyNotValid => key_not_valid(),
+            Self::KeyStore(_) => key_store_unavailable(),
+        }
+    }
+}
+
+impl ToProblemDetails for crate::public_tokens::server::RedeemTokenError {
+    fn to_problem_details(&self) -> ProblemDetails {
+        match self {
+            Self::KeyIdNotFound => key_id_not_found(StatusCode::UNAUTHORIZED),
+            Self::DoubleSpending => double_spending(),
+            Self::InvalidToken => invalid_token(),
+            Self::PolicyRejected(rejection) => policy_rejected(rejection),
+            Self::KeyExpired => key_expired(),
+            Self::KeyStore(_) => key_store_unavailable(),
+        }
+    }
+}
+
+#[cfg(feature = "ristretto255")]
+impl ToProblemDetails for crate::batched_tokens_ristretto255::server::IssueTokenResponseError {
+    fn to_problem_details(&self) -> ProblemDetails {
+        match self {
+            Self::KeyIdNotFound => key_id_not_found(StatusCode::BAD_REQUEST),
+            Self::InvalidTokenRequest => invalid_token_request(),
+            Self::InvalidTokenType => invalid_token_type(),
+            Self::TooManyRequests => too_many_requests(),
+            Self::InvalidBlindedElement(_) => invalid_token_request(),
+            Self::ProofGenerationFailed(_) => proof_generation_failed(),
+            Self::Evaluator(BlindEvaluatorError::Backend(_)) => evaluator_unavailable(),
+            Self::Evaluator(_) => proof_generation_failed(),
+            Self::EmptyBatch => invalid_token_request(),
+            Self::BatchTooLarge(_) => batch_too_large(),
+            Self::KeyNotValid => key_not_valid(),
+            Self::KeyStore(_) => key_store_unavailable(),
+        }
+    }
+}
+
+#[cfg(feature = "ristretto255")]
+impl ToProblemDetails for crate::batched_tokens_ristretto255::server::RedeemTokenError {
+    fn to_problem_details(&self) -> ProblemDetails {
+        match self {
+            Self::KeyIdNotFound => key_id_not_found(StatusCode::UNAUTHORIZED),
+            Self::DoubleSpending => double_spending(),
+            Self::InvalidToken => invalid_token(),
+            Self::PolicyRejected(rejection) => policy_rejected(rejection),
+            Self::KeyExpired => key_expired(),
+            Self::KeyStore(_) => key_store_unavailable(),
+        }
+    }
+}
+
+#[cfg(feature = "p384")]
+impl ToProblemDetails for crate::batched_tokens_p384::server::IssueTokenResponseError {
+    fn to_problem_details(&self) -> ProblemDetails {
+        match self {
+            Self::KeyIdNotFound => key_id_not_found(StatusCode::BAD_REQUEST),
+            Self::InvalidTokenRequest => invalid_token_request(),
+            Self::InvalidTokenType => invalid_token_type(),
+            Self::TooManyRequests => too_many_requests(),
+            Self::InvalidBlindedElement(_) => invalid_token_request(),
+            Self::ProofGenerationFailed(_) => proof_generation_failed(),
+            Self::Evaluator(BlindEvaluatorError::Backend(_)) => evaluator_unavailable(),
+            Self::Evaluator(_) => proof_generation_failed(),
+            Self::EmptyBatch => invalid_token_request(),
+            Self::BatchTooLarge(_) => batch_too_large(),
+            Self::KeyNotValid => key_not_valid(),
+            Self::KeyStore(_) => key_store_unavailable(),
+        }
+    }
+}
+
+#[cfg(feature = "p384")]
+impl ToProblemDetails for crate::batched_tokens_p384::server::RedeemTokenError {
+    fn to_problem_details(&self) -> ProblemDetails {
+        match self {
+            Self::KeyIdNotFound => key_id_not_found(StatusCode::UNAUTHORIZED),
+            Self::DoubleSpending => double_spending(),
+            Self::InvalidToken => invalid_token(),
+            Self::PolicyRejected(rejection) => policy_rejected(rejection),
+            Self::KeyExpired => key_expired(),
+            Self::KeyStore(_) => key_store_unavailable(),
+        }
+    }
+}
+
+impl ToProblemDetails for crate::dispatch::DispatchError {
+    fn to_problem_details(&self) -> ProblemDetails {
+        match self {
+            Self::MissingTokenType | Self::InvalidTokenRequest => invalid_token_request(),
+            Self::UnsupportedTokenType(code_point) => ProblemDetails::new(
+                "unsupported-token-type",
+                "Unsupported token type",
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            )
+            .with_detail(&format!("{code_point:#06x}")),
+            Self::AlreadyRegistered(_) => ProblemDetails::new(
+                "already-registered",
+                "A handler is already registered for the token type",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            Self::KeyIdNotFound => key_id_not_found(StatusCode::BAD_REQUEST),
+            Self::DoubleSpending => double_spending(),
+            Self::InvalidToken => invalid_token(),
+            Self::KeyStore(_) => key_store_unavailable(),
+        }
+    }
+}
+
+#[test]
+fn problem_details_response() {
+    use crate::public_tokens::server::RedeemTokenError;
+
+    let problem = RedeemTokenError::DoubleSpending
+        .to_problem_details()
+        .with_detail("nonce already seen");
+    let response = problem.to_response();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).unwrap(),
+        PROBLEM_JSON_MEDIA_TYPE
+    );
+
+    let decoded: ProblemDetails = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(decoded, problem);
+    assert_eq!(decoded.code(), "double-spending");
+    assert_eq!(
+        decoded.problem_type(),
+        "urn:privacypass:problem:double-spending"
+    );
+    assert_eq!(decoded.detail(), Some("nonce already seen"));
+}
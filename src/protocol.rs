@@ -0,0 +1,309 @@
+//! # Issuance state machine
+//!
+//! The issuance protocol as explicit states, decoupled from any transport:
+//!
+//! ```text
+//! ChallengeReceived --send_request--> RequestSent --receive_response--> TokensReady
+//! ```
+//!
+//! Each transition that produces a message returns its serialized bytes, and
+//! each transition that consumes a message takes serialized bytes. This lets
+//! embedders with unusual I/O, such as QUIC datagrams or message buses,
+//! drive the protocol manually.
+//!
+//! The states encode the flow in the type system: a response can only be
+//! finalized by the [`RequestSent`] state that produced the request, each
+//! state is consumed by its transition so that a request cannot be
+//! finalized twice, and [`RequestSent`] borrows the client that created the
+//! request, so that a response cannot be finalized with another client.
+
+use std::fmt;
+
+use rand::rngs::OsRng;
+use thiserror::Error;
+use typenum::U256;
+
+#[cfg(feature = "ristretto255")]
+use crate::batched_tokens_ristretto255;
+use crate::{
+    auth::{authenticate::TokenChallenge, authorize::Token},
+    public_tokens, Deserialize, Serialize,
+};
+#[cfg(feature = "p384")]
+use crate::{batched_tokens_p384, private_tokens};
+
+/// Errors that can occur when driving the issuance protocol.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProtocolError {
+    #[error("Invalid token challenge")]
+    /// The token challenge could not be deserialized.
+    InvalidTokenChallenge,
+    #[error("Invalid token count")]
+    /// The token type does not support the requested number of tokens.
+    InvalidTokenCount,
+    #[error("Token request failed")]
+    /// The client could not create or serialize the token request.
+    TokenRequestFailed,
+    #[error("Invalid token response")]
+    /// The token response could not be deserialized or finalized.
+    InvalidTokenResponse,
+    #[error("Token state mismatch")]
+    /// The token state was created by a client of another token type.
+    TokenStateMismatch,
+}
+
+/// A protocol client whose issuance can be driven by the state machine.
+pub trait IssuanceClient {
+    /// Token request sent to the issuer.
+    type TokenRequest: Serialize;
+    /// Token response received from the issuer.
+    type TokenResponse: Deserialize;
+    /// Client state kept between the request and the response.
+    type TokenState;
+    /// Issued token.
+    type Token;
+
+    /// Creates a token request for `count` tokens.
+    ///
+    /// # Errors
+    /// Returns an error if the token type does not support `count` tokens
+    /// or the request cannot be created.
+    fn token_request(
+        &self,
+        challenge: &TokenChallenge,
+        count: u16,
+    ) -> Result<(Self::TokenRequest, Self::TokenState), ProtocolError>;
+
+    /// Finalizes a token response into tokens.
+    ///
+    /// # Errors
+    /// Returns an error if the token response is invalid.
+    fn tokens(
+        &self,
+        token_response: Self::TokenResponse,
+        token_state: &Self::TokenState,
+    ) -> Result<Vec<Self::Token>, ProtocolError>;
+}
+
+/// A token challenge was received.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChallengeReceived {
+    challenge: TokenChallenge,
+}
+
+impl ChallengeReceived {
+    /// Starts the protocol with a token challenge.
+    #[must_use]
+    pub const fn new(challenge: TokenChallenge) -> Self {
+        Self { challenge }
+    }
+
+    /// Starts the protocol with a serialized token challenge.
+    ///
+    /// # Errors
+    /// Returns an error if the token challenge cannot be deserialized.
+    pub fn from_bytes(challenge: &[u8]) -> Result<Self, ProtocolError> {
+        TokenChallenge::deserialize(challenge)
+            .map(Self::new)
+            .map_err(|_| ProtocolError::InvalidTokenChallenge)
+    }
+
+    /// Returns the token challenge.
+    #[must_use]
+    pub const fn challenge(&self) -> &TokenChallenge {
+        &self.challenge
+    }
+
+    /// Creates a token request for `count` tokens and returns it serialized,
+    /// ready to be sent to the issuer.
+    ///
+    /// # Errors
+    /// Returns an error if the token request cannot be created.
+    pub fn send_request<C: IssuanceClient>(
+        self,
+        client: &C,
+        count: u16,
+    ) -> Result<(RequestSent<'_, C>, Vec<u8>), ProtocolError> {
+        let (token_request, token_state) = client.token_request(&self.challenge, count)?;
+        let token_request = token_request
+            .tls_serialize_detached()
+            .map_err(|_| ProtocolError::TokenRequestFailed)?;
+        Ok((
+            RequestSent {
+                client,
+                token_state,
+            },
+            token_request,
+        ))
+    }
+}
+
+/// A token request was sent and the client waits for the token response.
+///
+/// The state cannot be cloned, so the client-side state of a request is
+/// used for exactly one response.
+pub struct RequestSent<'c, C: IssuanceClient> {
+    client: &'c C,
+    token_state: C::TokenState,
+}
+
+impl<C: IssuanceClient> fmt::Debug for RequestSent<'_, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestSent").finish_non_exhaustive()
+    }
+}
+
+impl<C: IssuanceClient> RequestSent<'_, C> {
+    /// Finalizes a serialized token response with the client that created
+    /// the request.
+    ///
+    /// # Errors
+    /// Returns an error if the token response is invalid.
+    pub fn receive_response(
+        self,
+        token_response: &[u8],
+    ) -> Result<TokensReady<C::Token>, ProtocolError> {
+        let token_response = C::TokenResponse::tls_deserialize(&mut &token_response[..])
+            .map_err(|_| ProtocolError::InvalidTokenResponse)?;
+        let tokens = self.client.tokens(token_response, &self.token_state)?;
+        Ok(TokensReady { tokens })
+    }
+}
+
+/// The tokens were issued.
+#[derive(Clone, Debug)]
+pub struct TokensReady<T> {
+    tokens: Vec<T>,
+}
+
+impl<T> TokensReady<T> {
+    /// Returns the tokens.
+    #[must_use]
+    pub fn tokens(&self) -> &[T] {
+        &self.tokens
+    }
+
+    /// Returns the tokens by value.
+    #[must_use]
+    pub fn into_tokens(self) -> Vec<T> {
+        self.tokens
+    }
+}
+
+#[cfg(feature = "p384")]
+impl IssuanceClient for private_tokens::client::Client {
+    type TokenRequest = private_tokens::TokenRequest;
+    type TokenResponse = private_tokens::TokenResponse;
+    type TokenState = private_tokens::client::TokenState;
+    type Token = private_tokens::PrivateToken;
+
+    fn token_request(
+        &self,
+        challenge: &TokenChallenge,
+        count: u16,
+    ) -> Result<(Self::TokenRequest, Self::TokenState), ProtocolError> {
+        if count != 1 {
+            return Err(ProtocolError::InvalidTokenCount);
+        }
+        self.issue_token_request(challenge)
+            .map_err(|_| ProtocolError::TokenRequestFailed)
+    }
+
+    fn tokens(
+        &self,
+        token_response: Self::TokenResponse,
+        token_state: &Self::TokenState,
+    ) -> Result<Vec<Self::Token>, ProtocolError> {
+        self.issue_token(&token_response, token_state)
+            .map(|token| vec![token])
+            .map_err(|_| ProtocolError::InvalidTokenResponse)
+    }
+}
+
+impl IssuanceClient for public_tokens::client::Client {
+    type TokenRequest = public_tokens::TokenRequest;
+    type TokenResponse = public_tokens::TokenResponse;
+    type TokenState = public_tokens::client::TokenState;
+    type Token = Token<U256>;
+
+    fn token_request(
+        &self,
+        challenge: &TokenChallenge,
+        count: u16,
+    ) -> Result<(Self::TokenRequest, Self::TokenState), ProtocolError> {
+        if count != 1 {
+            return Err(ProtocolError::InvalidTokenCount);
+        }
+        self.create_token_request(&mut OsRng, challenge.clone())
+            .map_err(|_| ProtocolError::TokenRequestFailed)
+    }
+
+    fn tokens(
+        &self,
+        token_response: Self::TokenResponse,
+        token_state: &Self::TokenState,
+    ) -> Result<Vec<Self::Token>, ProtocolError> {
+        self.issue_token(token_response, token_state)
+            .map(|token| vec![token])
+            .map_err(|_| ProtocolError::InvalidTokenResponse)
+    }
+}
+
+#[cfg(feature = "p384")]
+impl IssuanceClient for batched_tokens_p384::client::Client {
+    type TokenRequest = batched_tokens_p384::TokenRequest;
+    type TokenResponse = batched_tokens_p384::TokenResponse;
+    type TokenState = Vec<batched_tokens_p384::client::TokenState>;
+    type Token = batched_tokens_p384::BatchedToken;
+
+    fn token_request(
+        &self,
+        challenge: &TokenChallenge,
+        count: u16,
+    ) -> Result<(Self::TokenRequest, Self::TokenState), ProtocolError> {
+        if count == 0 {
+            return Err(ProtocolError::InvalidTokenCount);
+        }
+        self.issue_token_request(challenge, count)
+            .map_err(|_| ProtocolError::TokenRequestFailed)
+    }
+
+    fn tokens(
+        &self,
+        token_response: Self::TokenResponse,
+        token_state: &Self::TokenState,
+    ) -> Result<Vec<Self::Token>, ProtocolError> {
+        self.issue_tokens(&token_response, token_state)
+            .map_err(|_| ProtocolError::InvalidTokenResponse)
+    }
+}
+
+#[cfg(feature = "ristretto255")]
+impl IssuanceClient for batched_tokens_ristretto255::client::Client {
+    type TokenRequest = batched_tokens_ristretto255::TokenRequest;
+    type TokenResponse = batched_tokens_ristretto255::TokenResponse;
+    type TokenState = Vec<batched_tokens_ristretto255::client::TokenState>;
+    type Token = batched_tokens_ristretto255::BatchedToken;
+
+    fn token_request(
+        &self,
+        challenge: &TokenChallenge,
+        count: u16,
+    ) -> Result<(Self::TokenRequest, Self::TokenState), ProtocolError> {
+        if count == 0 {
+            return Err(ProtocolError::InvalidTokenCount);
+        }
+        self.issue_token_request(challenge, count)
+            .map_err(|_| ProtocolError::TokenRequestFailed)
+    }
+
+    fn tokens(
+        &self,
+        token_response: Self::TokenResponse,
+        token_state: &Self::TokenState,
+    ) -> Result<Vec<Self::Token>, ProtocolError> {
+        self.issue_tokens(&token_response, token_state)
+            .map_err(|_| ProtocolError::InvalidTokenResponse)
+    }
+}
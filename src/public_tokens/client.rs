@@ -4,9 +4,11 @@ use blind_rsa_signatures::{BlindSignature, BlindingResult, Options, PublicKey};
 use generic_array::{typenum::U256, GenericArray};
 use rand::{CryptoRng, RngCore};
 use thiserror::Error;
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
 use crate::{
     auth::{authenticate::TokenChallenge, authorize::Token},
+    issuer_directory::TokenKeyDirectory,
     ChallengeDigest, TokenInput, TokenKeyId, TokenType,
 };
 
@@ -22,8 +24,19 @@ pub struct TokenState {
     challenge_digest: ChallengeDigest,
 }
 
+impl Drop for TokenState {
+    fn drop(&mut self) {
+        // The token input wipes itself.
+        self.blinding_result.secret.0.zeroize();
+        self.challenge_digest.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for TokenState {}
+
 /// Errors that can occur when issuing token requests.
 #[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum IssueTokenRequestError {
     #[error("Token blinding error")]
     /// Error when blinding the token.
@@ -31,10 +44,15 @@ pub enum IssueTokenRequestError {
     #[error("Invalid TokenChallenge")]
     /// Error when the token challenge is invalid.
     InvalidTokenChallenge,
+    #[error("Token key does not match a current issuer directory entry")]
+    /// Error when the public key is not listed as current in the issuer
+    /// directory.
+    KeyNotInDirectory,
 }
 
 /// Errors that can occur when issuing tokens.
 #[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum IssueTokenError {
     #[error("Invalid TokenResponse")]
     /// Error when the token response is invalid.
@@ -46,6 +64,7 @@ pub enum IssueTokenError {
 pub struct Client {
     token_key_id: TokenKeyId,
     public_key: PublicKey,
+    directory: Option<TokenKeyDirectory>,
 }
 
 impl Client {
@@ -57,17 +76,48 @@ impl Client {
         Self {
             token_key_id,
             public_key,
+            directory: None,
         }
     }
 
+    /// Sets the issuer directory the public key is checked against before
+    /// blinding. Token requests are refused if the directory does not list
+    /// the key as current, so that an issuance endpoint cannot single out a
+    /// client by serving it a unique key.
+    #[must_use]
+    pub fn with_directory(mut self, directory: TokenKeyDirectory) -> Self {
+        self.directory = Some(directory);
+        self
+    }
+
     /// Issue a token request.
     ///
     /// # Errors
     /// Returns an error if the challenge is invalid.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(truncated_token_key_id = truncate_token_key_id(&self.token_key_id)),
+            err(level = "debug")
+        )
+    )]
     pub fn issue_token_request<R: RngCore + CryptoRng>(
         &mut self,
         rng: &mut R,
         challenge: TokenChallenge,
+    ) -> Result<(TokenRequest, TokenState), IssueTokenRequestError> {
+        self.create_token_request(rng, challenge)
+    }
+
+    /// Creates a token request through a shared borrow, so that the
+    /// protocol states can keep the client borrowed while a request is
+    /// outstanding.
+    pub(crate) fn create_token_request<R: RngCore + CryptoRng>(
+        &self,
+        rng: &mut R,
+        challenge: TokenChallenge,
     ) -> Result<(TokenRequest, TokenState), IssueTokenRequestError> {
         let mut nonce: Nonce = [0u8; 32];
         rng.fill_bytes(&mut nonce);
@@ -76,6 +126,12 @@ impl Client {
             .digest()
             .map_err(|_| IssueTokenRequestError::InvalidTokenChallenge)?;
 
+        if let Some(directory) = &self.directory {
+            directory
+                .check_key(TokenType::PublicToken, &self.token_key_id)
+                .map_err(|_| IssueTokenRequestError::KeyNotInDirectory)?;
+        }
+
         // nonce = random(32)
         // challenge_digest = SHA256(challenge)
         // token_input = concat(0x0002, nonce, challenge_digest, token_key_id)
@@ -91,12 +147,13 @@ impl Client {
         let options = Options::default();
         let blinding_result = self
             .public_key
-            .blind(rng, token_input.serialize(), false, &options)
+            .blind(rng, token_input.to_bytes(), false, &options)
             .map_err(|_| IssueTokenRequestError::BlindingError)?;
 
-        debug_assert!(blinding_result.blind_msg.len() == NK);
-        let mut blinded_msg = [0u8; NK];
-        blinded_msg.copy_from_slice(blinding_result.blind_msg.as_slice());
+        // The blinded message has the size of the modulus, which is only NK
+        // bytes for 2048-bit keys
+        let blinded_msg = <[u8; NK]>::try_from(blinding_result.blind_msg.as_slice())
+            .map_err(|_| IssueTokenRequestError::BlindingError)?;
 
         let token_request = TokenRequest {
             token_type: TokenType::PublicToken,
@@ -116,13 +173,22 @@ impl Client {
     ///
     /// # Errors
     /// Returns an error if the token response is invalid.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(truncated_token_key_id = truncate_token_key_id(&self.token_key_id)),
+            err(level = "debug")
+        )
+    )]
     pub fn issue_token(
         &self,
         token_response: TokenResponse,
         token_state: &TokenState,
     ) -> Result<Token<U256>, IssueTokenError> {
         // authenticator = rsabssa_finalize(pkI, nonce, blind_sig, blind_inv)
-        let token_input = token_state.token_input.serialize();
+        let token_input = token_state.token_input.to_bytes();
         let options = Options::default();
         let blind_sig = BlindSignature(token_response.blind_sig.to_vec());
         let signature = self
@@ -136,7 +202,8 @@ impl Client {
             )
             .map_err(|_| IssueTokenError::InvalidTokenResponse)?;
         let authenticator: GenericArray<u8, U256> =
-            GenericArray::clone_from_slice(&signature[0..256]);
+            GenericArray::from_exact_iter(signature.iter().copied())
+                .ok_or(IssueTokenError::InvalidTokenResponse)?;
         Ok(Token::new(
             TokenType::PublicToken,
             token_state.token_input.nonce,
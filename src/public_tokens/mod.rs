@@ -23,7 +23,9 @@ pub fn public_key_to_truncated_token_key_id(public_key: &PublicKey) -> Truncated
     truncate_token_key_id(&public_key_to_token_key_id(public_key))
 }
 
-fn public_key_to_token_key_id(public_key: &PublicKey) -> TokenKeyId {
+/// Converts a public key to a token key ID, the SHA-256 digest of the
+/// serialized public key.
+pub fn public_key_to_token_key_id(public_key: &PublicKey) -> TokenKeyId {
     let public_key = serialize_public_key(public_key);
 
     Sha256::digest(public_key).into()
@@ -1,25 +1,47 @@
 //! Server-side implementation of Publicly Verifiable Token protocol.
 
+use std::{sync::Arc, time::Duration};
+
 use async_trait::async_trait;
+#[cfg(feature = "pem")]
+use blind_rsa_signatures::{reexports::rsa::PublicKeyParts, SecretKey};
 use blind_rsa_signatures::{KeyPair, Options, PublicKey, Signature};
 use generic_array::ArrayLength;
-use rand::{rngs::OsRng, CryptoRng, RngCore};
+use rand::{CryptoRng, RngCore};
 use thiserror::Error;
 
-use crate::{auth::authorize::Token, NonceStore, TokenInput, TokenType, TruncatedTokenKeyId};
+use crate::{
+    auth::authorize::Token,
+    clock::{Clock, ServerClock},
+    concurrency::ConcurrencyLimit,
+    config::{ConfigError, ServerConfig},
+    issuer_directory::TokenKey,
+    metrics::{Metrics, ServerMetrics},
+    policy::{check_policy, AcceptAll, PolicyRejection, RedemptionPolicy},
+    webhooks::RedemptionOutcome,
+    DoubleSpendEvent, DoubleSpendObserver, KeyStoreError, KeyValidity, MaybeSendSync, NonceStore,
+    ReadinessError, RedemptionErrors, ServerRng, TokenInput, TokenType, TruncatedTokenKeyId,
+};
+#[cfg(feature = "pem")]
+use crate::{private_keys::PrivateKeyError, SecretString, SecretVec};
 
 use super::{public_key_to_token_key_id, truncate_token_key_id, TokenRequest, TokenResponse, NK};
 
 /// Errors that can occur when creating a keypair.
 #[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum CreateKeypairError {
     #[error("Seed is too long")]
     /// Error when the seed is too long.
     SeedError,
+    #[error(transparent)]
+    /// Error when the key store fails.
+    KeyStore(#[from] KeyStoreError),
 }
 
 /// Errors that can occur when issuing the token response.
 #[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum IssueTokenResponseError {
     #[error("Key ID not found")]
     /// Error when the key ID is not found.
@@ -30,10 +52,21 @@ pub enum IssueTokenResponseError {
     #[error("Invalid toke type")]
     /// Error when the token type is invalid.
     InvalidTokenType,
+    #[error("Too many concurrent requests for the key")]
+    /// Error when the key is at its concurrency limit.
+    TooManyRequests,
+    #[error("Key is not valid at this time")]
+    /// Error when the key is used before its not-before time or from its
+    /// not-after time on.
+    KeyNotValid,
+    #[error(transparent)]
+    /// Error when the key store fails.
+    KeyStore(#[from] KeyStoreError),
 }
 
 /// Errors that can occur when redeeming the token.
 #[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum RedeemTokenError {
     #[error("Key ID not found")]
     /// Error when the key ID is not found.
@@ -44,87 +77,695 @@ pub enum RedeemTokenError {
     #[error("The token is invalid")]
     /// Error when the token is invalid.
     InvalidToken,
+    #[error("Rejected by redemption policy: {0}")]
+    /// Error when the redemption policy rejects the token.
+    PolicyRejected(#[from] PolicyRejection),
+    #[error("Key expired")]
+    /// Error when the token is redeemed after the not-after time of its key
+    /// and the redemption grace period.
+    KeyExpired,
+    #[error(transparent)]
+    /// Error when the key store fails.
+    KeyStore(#[from] KeyStoreError),
+}
+
+/// Classifies the result of a redemption for metrics.
+fn redemption_outcome(result: &Result<(), RedeemTokenError>) -> RedemptionOutcome {
+    match result {
+        Ok(()) => RedemptionOutcome::Redeemed,
+        Err(RedeemTokenError::DoubleSpending) => RedemptionOutcome::DoubleSpending,
+        Err(_) => RedemptionOutcome::Rejected,
+    }
 }
 
 /// Minimal trait for a key store to store key material on the server-side. Note
 /// that the store requires inner mutability.
-#[async_trait]
-
-pub trait IssuerKeyStore: Send + Sync {
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+pub trait IssuerKeyStore: MaybeSendSync {
     /// Inserts a keypair with a given `truncated_token_key_id` into the key store.
-    async fn insert(&self, truncated_token_key_id: TruncatedTokenKeyId, server: KeyPair);
-    /// Returns a keypair with a given `truncated_token_key_id` from the key store.
-    async fn get(&self, truncated_token_key_id: &TruncatedTokenKeyId) -> Option<KeyPair>;
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        server: KeyPair,
+    ) -> Result<(), KeyStoreError>;
+    /// Returns a keypair with a given `truncated_token_key_id` from the key
+    /// store, or `None` if the key store does not contain it.
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<KeyPair>, KeyStoreError>;
+    /// Removes the keypair with a given `truncated_token_key_id` from the key
+    /// store. Returns `true` if the key store contained it.
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError>;
+    /// Returns the truncated token key IDs of all keypairs in the key store.
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError>;
+    /// Returns `true` if the key store contains a keypair with a given
+    /// `truncated_token_key_id`.
+    async fn contains(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(self.get(truncated_token_key_id).await?.is_some())
+    }
+    /// Loads the key material ahead of the first request, e.g. from a
+    /// database or a key management service. The default does nothing.
+    async fn preload(&self) {}
+    /// Records the validity period of the keypair with a given
+    /// `truncated_token_key_id`, possibly before the keypair is inserted. The
+    /// default fails, for key stores that cannot record validity periods.
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        let _ = (truncated_token_key_id, validity);
+        Err(KeyStoreError::Backend(
+            "key validity periods are not supported".to_string(),
+        ))
+    }
+    /// Returns the validity period of the keypair with a given
+    /// `truncated_token_key_id`. Keys without a recorded validity period,
+    /// and all keys of the default, are valid without bounds.
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        let _ = truncated_token_key_id;
+        Ok(KeyValidity::default())
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<S: IssuerKeyStore + ?Sized> IssuerKeyStore for Box<S> {
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        server: KeyPair,
+    ) -> Result<(), KeyStoreError> {
+        (**self).insert(truncated_token_key_id, server).await
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<KeyPair>, KeyStoreError> {
+        (**self).get(truncated_token_key_id).await
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        (**self).remove(truncated_token_key_id).await
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        (**self).list_key_ids().await
+    }
+
+    async fn contains(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        (**self).contains(truncated_token_key_id).await
+    }
+
+    async fn preload(&self) {
+        (**self).preload().await
+    }
+
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        (**self)
+            .set_validity(truncated_token_key_id, validity)
+            .await
+    }
+
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        (**self).validity(truncated_token_key_id).await
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<S: IssuerKeyStore + ?Sized> IssuerKeyStore for Arc<S> {
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        server: KeyPair,
+    ) -> Result<(), KeyStoreError> {
+        (**self).insert(truncated_token_key_id, server).await
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<KeyPair>, KeyStoreError> {
+        (**self).get(truncated_token_key_id).await
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        (**self).remove(truncated_token_key_id).await
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        (**self).list_key_ids().await
+    }
+
+    async fn contains(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        (**self).contains(truncated_token_key_id).await
+    }
+
+    async fn preload(&self) {
+        (**self).preload().await
+    }
+
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        (**self)
+            .set_validity(truncated_token_key_id, validity)
+            .await
+    }
+
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        (**self).validity(truncated_token_key_id).await
+    }
 }
 
 /// Minimal trait for a key store to store key material on the server-side. Note
 /// that the store requires inner mutability.
-#[async_trait]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
 pub trait OriginKeyStore {
     /// Inserts a keypair with a given `truncated_token_key_id` into the key store.
-    async fn insert(&self, truncated_token_key_id: TruncatedTokenKeyId, server: PublicKey);
-    /// Returns a keypair with a given `truncated_token_key_id` from the key store.
-    async fn get(&self, truncated_token_key_id: &TruncatedTokenKeyId) -> Option<PublicKey>;
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        server: PublicKey,
+    ) -> Result<(), KeyStoreError>;
+    /// Returns a keypair with a given `truncated_token_key_id` from the key
+    /// store, or `None` if the key store does not contain it.
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<PublicKey>, KeyStoreError>;
+    /// Removes the keypair with a given `truncated_token_key_id` from the key
+    /// store. Returns `true` if the key store contained it.
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError>;
+    /// Returns the truncated token key IDs of all keypairs in the key store.
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError>;
+    /// Returns `true` if the key store contains a keypair with a given
+    /// `truncated_token_key_id`.
+    async fn contains(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(self.get(truncated_token_key_id).await?.is_some())
+    }
+    /// Records the validity period of the public key with a given
+    /// `truncated_token_key_id`, e.g. as published in the issuer directory.
+    /// The default fails, for key stores that cannot record validity periods.
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        let _ = (truncated_token_key_id, validity);
+        Err(KeyStoreError::Backend(
+            "key validity periods are not supported".to_string(),
+        ))
+    }
+    /// Returns the validity period of the public key with a given
+    /// `truncated_token_key_id`. Keys without a recorded validity period,
+    /// and all keys of the default, are valid without bounds.
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        let _ = truncated_token_key_id;
+        Ok(KeyValidity::default())
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<S: OriginKeyStore + Send + Sync + ?Sized> OriginKeyStore for Box<S> {
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        server: PublicKey,
+    ) -> Result<(), KeyStoreError> {
+        (**self).insert(truncated_token_key_id, server).await
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<PublicKey>, KeyStoreError> {
+        (**self).get(truncated_token_key_id).await
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        (**self).remove(truncated_token_key_id).await
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        (**self).list_key_ids().await
+    }
+
+    async fn contains(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        (**self).contains(truncated_token_key_id).await
+    }
+
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        (**self)
+            .set_validity(truncated_token_key_id, validity)
+            .await
+    }
+
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        (**self).validity(truncated_token_key_id).await
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<S: OriginKeyStore + Send + Sync + ?Sized> OriginKeyStore for Arc<S> {
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        server: PublicKey,
+    ) -> Result<(), KeyStoreError> {
+        (**self).insert(truncated_token_key_id, server).await
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<PublicKey>, KeyStoreError> {
+        (**self).get(truncated_token_key_id).await
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        (**self).remove(truncated_token_key_id).await
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        (**self).list_key_ids().await
+    }
+
+    async fn contains(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        (**self).contains(truncated_token_key_id).await
+    }
+
+    async fn set_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        (**self)
+            .set_validity(truncated_token_key_id, validity)
+            .await
+    }
+
+    async fn validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        (**self).validity(truncated_token_key_id).await
+    }
 }
 
-/// Serializes a keypair into a DER-encoded PKCS#8 document.
+/// Serializes a public key into the DER-encoded SubjectPublicKeyInfo with the
+/// RSASSA-PSS OID that issuer directories and `token-key` challenge
+/// attributes carry.
 #[must_use]
+#[allow(clippy::unwrap_used)]
 pub fn serialize_public_key(public_key: &PublicKey) -> Vec<u8> {
+    // Encoding a valid RSA public key does not fail
     public_key.to_spki(Some(&Options::default())).unwrap()
 }
 
+/// Deserializes a public key from a DER-encoded SubjectPublicKeyInfo.
+///
+/// # Errors
+///
+/// This function will return an error if the slice is not a valid public key.
+pub fn deserialize_public_key(slice: &[u8]) -> Result<PublicKey, blind_rsa_signatures::Error> {
+    PublicKey::from_spki(slice, Some(&Options::default()))
+}
+
 const KEYSIZE_IN_BITS: usize = 2048;
 const KEYSIZE_IN_BYTES: usize = KEYSIZE_IN_BITS / 8;
 
 /// Server-side implementation of Publicly Verifiable Token protocol for
 /// issuers.
 #[derive(Default, Debug)]
-pub struct IssuerServer {}
+pub struct IssuerServer {
+    concurrency_limit: Option<ConcurrencyLimit>,
+    rng: ServerRng,
+    clock: ServerClock,
+    metrics: ServerMetrics,
+}
 
 impl IssuerServer {
     /// Creates a new server.
     #[must_use]
     pub const fn new() -> Self {
-        Self {}
+        Self {
+            concurrency_limit: None,
+            rng: ServerRng::os(),
+            clock: ServerClock::system(),
+            metrics: ServerMetrics::none(),
+        }
+    }
+
+    /// Creates a new server from a configuration.
+    ///
+    /// # Errors
+    /// Returns an error if the configuration is invalid or does not accept
+    /// publicly verifiable tokens.
+    pub fn from_config(config: &ServerConfig) -> Result<Self, ConfigError> {
+        config.validate_for(TokenType::PublicToken)?;
+        let mut server = Self::new();
+        if let Some(max_in_flight_per_key) = config.max_in_flight_per_key() {
+            server = server.with_concurrency_limit(ConcurrencyLimit::new(max_in_flight_per_key));
+        }
+        Ok(server)
+    }
+
+    /// Limits the number of concurrent evaluations per key. Requests for a
+    /// key that is at its limit fail with
+    /// [`IssueTokenResponseError::TooManyRequests`].
+    #[must_use]
+    pub fn with_concurrency_limit(mut self, concurrency_limit: ConcurrencyLimit) -> Self {
+        self.concurrency_limit = Some(concurrency_limit);
+        self
+    }
+
+    /// Draws the blind signature randomness from `rng` instead of the OS
+    /// random number generator, e.g. to reproduce test vectors.
+    #[must_use]
+    pub fn with_rng<R: RngCore + CryptoRng + Send + 'static>(mut self, rng: R) -> Self {
+        self.rng = ServerRng::new(rng);
+        self
+    }
+
+    /// Reads the time that key validity periods are checked against from
+    /// `clock` instead of the system clock.
+    #[must_use]
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = ServerClock::new(clock);
+        self
+    }
+
+    /// Reports every issuance to `metrics`.
+    #[must_use]
+    pub fn with_metrics<M: Metrics + 'static>(mut self, metrics: M) -> Self {
+        self.metrics = ServerMetrics::new(metrics);
+        self
     }
 
     /// Creates a new keypair and inserts it into the key store.
     ///
     /// # Errors
     /// Returns an error if creating the keypair fails.
-    pub async fn create_keypair<IKS: IssuerKeyStore, R: RngCore + CryptoRng>(
+    pub async fn create_keypair<IKS: IssuerKeyStore + ?Sized, R: RngCore + CryptoRng>(
         &self,
         rng: &mut R,
         key_store: &IKS,
+    ) -> Result<KeyPair, CreateKeypairError> {
+        self.create_keypair_internal(rng, key_store, None).await
+    }
+
+    /// Creates a new keypair that is only used within `validity` and inserts
+    /// it into the key store. The validity period is recorded before the
+    /// keypair, so that the keypair is never used outside of it.
+    ///
+    /// # Errors
+    /// Returns an error if creating the keypair fails or the key store
+    /// cannot record validity periods.
+    pub async fn create_keypair_with_validity<
+        IKS: IssuerKeyStore + ?Sized,
+        R: RngCore + CryptoRng,
+    >(
+        &self,
+        rng: &mut R,
+        key_store: &IKS,
+        validity: KeyValidity,
+    ) -> Result<KeyPair, CreateKeypairError> {
+        self.create_keypair_internal(rng, key_store, Some(validity))
+            .await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(truncated_token_key_id = tracing::field::Empty),
+            err(level = "debug")
+        )
+    )]
+    async fn create_keypair_internal<IKS: IssuerKeyStore + ?Sized, R: RngCore + CryptoRng>(
+        &self,
+        rng: &mut R,
+        key_store: &IKS,
+        validity: Option<KeyValidity>,
     ) -> Result<KeyPair, CreateKeypairError> {
         let key_pair =
             KeyPair::generate(rng, KEYSIZE_IN_BITS).map_err(|_| CreateKeypairError::SeedError)?;
         let truncated_token_key_id =
             truncate_token_key_id(&public_key_to_token_key_id(&key_pair.pk));
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("truncated_token_key_id", truncated_token_key_id);
+        if let Some(validity) = validity {
+            key_store
+                .set_validity(truncated_token_key_id, validity)
+                .await?;
+        }
         key_store
             .insert(truncated_token_key_id, key_pair.clone())
-            .await;
+            .await?;
         Ok(key_pair)
     }
 
+    /// Preloads the key store and checks that every key in
+    /// `truncated_token_key_ids` is present, so that deployments can hold back
+    /// traffic until issuance will succeed.
+    ///
+    /// # Errors
+    /// Returns an error for the first key that is not ready.
+    pub async fn ready<IKS: IssuerKeyStore + ?Sized>(
+        &self,
+        key_store: &IKS,
+        truncated_token_key_ids: &[TruncatedTokenKeyId],
+    ) -> Result<(), ReadinessError> {
+        key_store.preload().await;
+        for truncated_token_key_id in truncated_token_key_ids {
+            key_store
+                .get(truncated_token_key_id)
+                .await?
+                .ok_or(ReadinessError::KeyIdNotFound(*truncated_token_key_id))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the issuer directory entry of the key in the key store, or
+    /// `None` if the key is not present. `not_before` is the time from which
+    /// on the key is used, in seconds since the Unix epoch.
+    ///
+    /// # Errors
+    /// Returns an error if the key store fails.
+    pub async fn token_key<IKS: IssuerKeyStore + ?Sized>(
+        &self,
+        key_store: &IKS,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        not_before: Option<u64>,
+    ) -> Result<Option<TokenKey>, KeyStoreError> {
+        let Some(key_pair) = key_store.get(&truncated_token_key_id).await? else {
+            return Ok(None);
+        };
+        Ok(Some(TokenKey::new(
+            TokenType::PublicToken,
+            &serialize_public_key(&key_pair.pk),
+            not_before,
+        )))
+    }
+
     /// Issues a new token response.
     ///
     /// # Errors
     /// Returns an error if the token request is invalid.
-    pub async fn issue_token_response<IKS: IssuerKeyStore>(
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                token_type = ?token_request.token_type,
+                truncated_token_key_id = token_request.truncated_token_key_id,
+            ),
+            err(level = "debug")
+        )
+    )]
+    pub async fn issue_token_response<IKS: IssuerKeyStore + ?Sized>(
+        &self,
+        key_store: &IKS,
+        token_request: TokenRequest,
+    ) -> Result<TokenResponse, IssueTokenResponseError> {
+        let token_type = token_request.token_type;
+        let truncated_token_key_id = token_request.truncated_token_key_id;
+        let result = self
+            .issue_token_response_inner(key_store, token_request)
+            .await;
+        self.metrics
+            .issuance(token_type, truncated_token_key_id, 1, &result);
+        result
+    }
+
+    async fn issue_token_response_inner<IKS: IssuerKeyStore + ?Sized>(
         &self,
         key_store: &IKS,
         token_request: TokenRequest,
     ) -> Result<TokenResponse, IssueTokenResponseError> {
-        let rng = &mut OsRng;
+        let rng = &mut self.rng.clone();
         if token_request.token_type != TokenType::PublicToken {
             return Err(IssueTokenResponseError::InvalidTokenType);
         }
+        let _permit = self
+            .concurrency_limit
+            .as_ref()
+            .map(|limit| {
+                limit
+                    .try_acquire(token_request.truncated_token_key_id)
+                    .ok_or(IssueTokenResponseError::TooManyRequests)
+            })
+            .transpose()?;
         let key_pair = key_store
             .get(&token_request.truncated_token_key_id)
-            .await
+            .await?
             .ok_or(IssueTokenResponseError::KeyIdNotFound)?;
+        if !key_store
+            .validity(&token_request.truncated_token_key_id)
+            .await?
+            .allows_issuance(self.clock.unix_time())
+        {
+            return Err(IssueTokenResponseError::KeyNotValid);
+        }
+
+        Self::blind_sign(rng, &key_pair, token_request)
+    }
 
+    /// Issues a token response with a keypair the caller manages itself,
+    /// e.g. in a hardware security device or a static configuration, without
+    /// going through a key store.
+    ///
+    /// # Errors
+    /// Returns an error if the token request is invalid or was not made for
+    /// the public key of `key_pair`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                token_type = ?token_request.token_type,
+                truncated_token_key_id = token_request.truncated_token_key_id,
+            ),
+            err(level = "debug")
+        )
+    )]
+    pub fn issue_token_response_with_key(
+        &self,
+        key_pair: &KeyPair,
+        token_request: TokenRequest,
+    ) -> Result<TokenResponse, IssueTokenResponseError> {
+        let token_type = token_request.token_type;
+        let truncated_token_key_id = token_request.truncated_token_key_id;
+        let result = self.issue_token_response_with_key_inner(key_pair, token_request);
+        self.metrics
+            .issuance(token_type, truncated_token_key_id, 1, &result);
+        result
+    }
+
+    fn issue_token_response_with_key_inner(
+        &self,
+        key_pair: &KeyPair,
+        token_request: TokenRequest,
+    ) -> Result<TokenResponse, IssueTokenResponseError> {
+        let rng = &mut self.rng.clone();
+        if token_request.token_type != TokenType::PublicToken {
+            return Err(IssueTokenResponseError::InvalidTokenType);
+        }
+        if truncate_token_key_id(&public_key_to_token_key_id(&key_pair.pk))
+            != token_request.truncated_token_key_id
+        {
+            return Err(IssueTokenResponseError::KeyIdNotFound);
+        }
+        let _permit = self
+            .concurrency_limit
+            .as_ref()
+            .map(|limit| {
+                limit
+                    .try_acquire(token_request.truncated_token_key_id)
+                    .ok_or(IssueTokenResponseError::TooManyRequests)
+            })
+            .transpose()?;
+
+        Self::blind_sign(rng, key_pair, token_request)
+    }
+
+    fn blind_sign<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        key_pair: &KeyPair,
+        token_request: TokenRequest,
+    ) -> Result<TokenResponse, IssueTokenResponseError> {
         // blind_sig = rsabssa_blind_sign(skI, TokenRequest.blinded_msg)
         let options = Options::default();
         let blind_signature = key_pair
@@ -132,42 +773,294 @@ impl IssuerServer {
             .blind_sign(rng, token_request.blinded_msg, &options)
             .map_err(|_| IssueTokenResponseError::InvalidTokenRequest)?;
 
-        debug_assert!(blind_signature.len() == NK);
-        let mut blind_sig = [0u8; NK];
-        blind_sig.copy_from_slice(blind_signature.as_slice());
+        let blind_sig = <[u8; NK]>::try_from(blind_signature.as_slice())
+            .map_err(|_| IssueTokenResponseError::InvalidTokenRequest)?;
 
         Ok(TokenResponse { blind_sig })
     }
 
     /// Sets the given keypair.
+    ///
+    /// # Errors
+    /// Returns an error if the key store fails.
     #[cfg(feature = "kat")]
-    pub async fn set_keypair<IKS: IssuerKeyStore>(&self, key_store: &IKS, key_pair: KeyPair) {
+    pub async fn set_keypair<IKS: IssuerKeyStore + ?Sized>(
+        &self,
+        key_store: &IKS,
+        key_pair: KeyPair,
+    ) -> Result<(), KeyStoreError> {
+        self.insert_keypair(key_store, key_pair).await
+    }
+
+    #[cfg(any(feature = "kat", feature = "pem"))]
+    async fn insert_keypair<IKS: IssuerKeyStore + ?Sized>(
+        &self,
+        key_store: &IKS,
+        key_pair: KeyPair,
+    ) -> Result<(), KeyStoreError> {
         let truncated_token_key_id =
             truncate_token_key_id(&public_key_to_token_key_id(&key_pair.pk));
-        key_store.insert(truncated_token_key_id, key_pair).await;
+        key_store.insert(truncated_token_key_id, key_pair).await
+    }
+
+    /// Exports the private key of a keypair in the key store as a
+    /// DER-encoded PKCS#8 document.
+    ///
+    /// # Errors
+    /// Returns an error if the keypair is not present, cannot be encoded or
+    /// the key store fails.
+    #[cfg(feature = "pem")]
+    pub async fn export_private_key<IKS: IssuerKeyStore + ?Sized>(
+        &self,
+        key_store: &IKS,
+        truncated_token_key_id: TruncatedTokenKeyId,
+    ) -> Result<SecretVec<u8>, PrivateKeyError> {
+        let key_pair = key_store
+            .get(&truncated_token_key_id)
+            .await?
+            .ok_or(PrivateKeyError::KeyIdNotFound)?;
+        key_pair
+            .sk
+            .to_der()
+            .map(SecretVec::new)
+            .map_err(|_| PrivateKeyError::InvalidKey)
+    }
+
+    /// Exports the private key of a keypair in the key store as a
+    /// PEM-encoded PKCS#8 document.
+    ///
+    /// # Errors
+    /// Returns an error if the keypair is not present, cannot be encoded or
+    /// the key store fails.
+    #[cfg(feature = "pem")]
+    pub async fn export_private_key_pem<IKS: IssuerKeyStore + ?Sized>(
+        &self,
+        key_store: &IKS,
+        truncated_token_key_id: TruncatedTokenKeyId,
+    ) -> Result<SecretString, PrivateKeyError> {
+        let key_pair = key_store
+            .get(&truncated_token_key_id)
+            .await?
+            .ok_or(PrivateKeyError::KeyIdNotFound)?;
+        key_pair
+            .sk
+            .to_pem()
+            .map(SecretString::new)
+            .map_err(|_| PrivateKeyError::InvalidKey)
+    }
+
+    /// Imports a private key from a DER-encoded PKCS#8 or PKCS#1 document
+    /// into the key store.
+    ///
+    /// # Errors
+    /// Returns an error if the document does not contain a valid RSA private
+    /// key or the key store fails.
+    #[cfg(feature = "pem")]
+    pub async fn import_private_key<IKS: IssuerKeyStore + ?Sized>(
+        &self,
+        key_store: &IKS,
+        der: &[u8],
+    ) -> Result<PublicKey, PrivateKeyError> {
+        let sk = SecretKey::from_der(der).map_err(|_| PrivateKeyError::InvalidKey)?;
+        self.insert_private_key(key_store, sk).await
+    }
+
+    /// Imports a private key from a PEM-encoded PKCS#8 or PKCS#1 document
+    /// into the key store.
+    ///
+    /// # Errors
+    /// Returns an error if the document does not contain a valid RSA private
+    /// key or the key store fails.
+    #[cfg(feature = "pem")]
+    pub async fn import_private_key_pem<IKS: IssuerKeyStore + ?Sized>(
+        &self,
+        key_store: &IKS,
+        pem: &str,
+    ) -> Result<PublicKey, PrivateKeyError> {
+        let sk = SecretKey::from_pem(pem).map_err(|_| PrivateKeyError::InvalidKey)?;
+        self.insert_private_key(key_store, sk).await
+    }
+
+    #[cfg(feature = "pem")]
+    async fn insert_private_key<IKS: IssuerKeyStore + ?Sized>(
+        &self,
+        key_store: &IKS,
+        sk: SecretKey,
+    ) -> Result<PublicKey, PrivateKeyError> {
+        // Publicly verifiable tokens use 2048 bit keys only
+        if sk.0.size() != KEYSIZE_IN_BYTES {
+            return Err(PrivateKeyError::InvalidKey);
+        }
+        let pk = sk.public_key().map_err(|_| PrivateKeyError::InvalidKey)?;
+        self.insert_keypair(key_store, KeyPair { pk: pk.clone(), sk })
+            .await?;
+        Ok(pk)
     }
 }
 
 /// Server-side implementation of Publicly Verifiable Token protocol for
 /// origins.
 #[derive(Default, Debug)]
-pub struct OriginServer {}
+pub struct OriginServer {
+    redemption_errors: RedemptionErrors,
+    clock: ServerClock,
+    redemption_grace_period: Duration,
+    metrics: ServerMetrics,
+    double_spend_observer: Option<DoubleSpendObserver>,
+}
 
 impl OriginServer {
     /// Creates a new server.
     pub fn new() -> Self {
-        Self {}
+        Self {
+            redemption_errors: RedemptionErrors::Detailed,
+            clock: ServerClock::system(),
+            redemption_grace_period: Duration::ZERO,
+            metrics: ServerMetrics::none(),
+            double_spend_observer: None,
+        }
+    }
+
+    /// Creates a new server from a configuration.
+    ///
+    /// # Errors
+    /// Returns an error if the configuration is invalid or does not accept
+    /// publicly verifiable tokens.
+    pub fn from_config(config: &ServerConfig) -> Result<Self, ConfigError> {
+        config.validate_for(TokenType::PublicToken)?;
+        Ok(Self::new().with_redemption_errors(config.redemption_errors()))
+    }
+
+    /// Sets how much detail redemption errors reveal. For publicly verifiable
+    /// tokens, [`RedemptionErrors::Uniform`] makes the errors uniform, but
+    /// does not equalize the timing of unknown key IDs.
+    #[must_use]
+    pub const fn with_redemption_errors(mut self, redemption_errors: RedemptionErrors) -> Self {
+        self.redemption_errors = redemption_errors;
+        self
+    }
+
+    /// Reads the time that key validity periods are checked against from
+    /// `clock` instead of the system clock.
+    #[must_use]
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = ServerClock::new(clock);
+        self
+    }
+
+    /// Keeps redeeming tokens for `redemption_grace_period` after the
+    /// not-after time of their key, so that tokens issued shortly before a
+    /// key expired can still be spent. Defaults to no grace period.
+    #[must_use]
+    pub const fn with_redemption_grace_period(mut self, redemption_grace_period: Duration) -> Self {
+        self.redemption_grace_period = redemption_grace_period;
+        self
+    }
+
+    /// Reports every redemption to `metrics`.
+    #[must_use]
+    pub fn with_metrics<M: Metrics + 'static>(mut self, metrics: M) -> Self {
+        self.metrics = ServerMetrics::new(metrics);
+        self
+    }
+
+    /// Reports every token that is rejected as double spent to
+    /// `double_spend_observer`.
+    #[must_use]
+    pub fn with_double_spend_observer(
+        mut self,
+        double_spend_observer: DoubleSpendObserver,
+    ) -> Self {
+        self.double_spend_observer = Some(double_spend_observer);
+        self
     }
 
     /// Redeems a token.
     ///
     /// # Errors
     /// Returns an error if the token is invalid.
-    pub async fn redeem_token<OKS: OriginKeyStore, NS: NonceStore, Nk: ArrayLength<u8>>(
+    pub async fn redeem_token<
+        OKS: OriginKeyStore + Sync + ?Sized,
+        NS: NonceStore + ?Sized,
+        Nk: ArrayLength<u8>,
+    >(
+        &self,
+        key_store: &OKS,
+        nonce_store: &NS,
+        token: Token<Nk>,
+    ) -> Result<(), RedeemTokenError> {
+        self.redeem_token_with_policy(key_store, nonce_store, token, &AcceptAll, &())
+            .await
+    }
+
+    /// Redeems a token if `policy` accepts it. The policy is consulted with
+    /// `metadata` after the token was verified and before its nonce is
+    /// recorded, so a rejected token is not spent.
+    ///
+    /// # Errors
+    /// Returns an error if the token is invalid or the policy rejects it.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                token_type = ?token.token_type(),
+                truncated_token_key_id = truncate_token_key_id(token.token_key_id()),
+            ),
+            err(level = "debug")
+        )
+    )]
+    pub async fn redeem_token_with_policy<
+        OKS: OriginKeyStore + Sync + ?Sized,
+        NS: NonceStore + ?Sized,
+        Nk: ArrayLength<u8>,
+        P: RedemptionPolicy<M> + ?Sized,
+        M: Sync + ?Sized,
+    >(
         &self,
         key_store: &OKS,
         nonce_store: &NS,
         token: Token<Nk>,
+        policy: &P,
+        metadata: &M,
+    ) -> Result<(), RedeemTokenError> {
+        let token_type = token.token_type();
+        let token_key_id = *token.token_key_id();
+        let challenge_digest = *token.challenge_digest();
+        let result = self
+            .redeem_token_with_policy_inner(key_store, nonce_store, token, policy, metadata)
+            .await;
+        self.metrics.redemption(
+            token_type,
+            truncate_token_key_id(&token_key_id),
+            redemption_outcome(&result),
+        );
+        if let (Err(RedeemTokenError::DoubleSpending), Some(double_spend_observer)) =
+            (&result, &self.double_spend_observer)
+        {
+            double_spend_observer.observe(&DoubleSpendEvent {
+                token_type,
+                token_key_id,
+                challenge_digest,
+            });
+        }
+        result
+    }
+
+    async fn redeem_token_with_policy_inner<
+        OKS: OriginKeyStore + Sync + ?Sized,
+        NS: NonceStore + ?Sized,
+        Nk: ArrayLength<u8>,
+        P: RedemptionPolicy<M> + ?Sized,
+        M: Sync + ?Sized,
+    >(
+        &self,
+        key_store: &OKS,
+        nonce_store: &NS,
+        token: Token<Nk>,
+        policy: &P,
+        metadata: &M,
     ) -> Result<(), RedeemTokenError> {
         if token.token_type() != TokenType::PublicToken {
             return Err(RedeemTokenError::InvalidToken);
@@ -187,16 +1080,108 @@ impl OriginServer {
 
         let public_key = key_store
             .get(&truncate_token_key_id(token.token_key_id()))
-            .await
-            .ok_or(RedeemTokenError::KeyIdNotFound)?;
+            .await?
+            .ok_or(match self.redemption_errors {
+                RedemptionErrors::Detailed => RedeemTokenError::KeyIdNotFound,
+                RedemptionErrors::Uniform => RedeemTokenError::InvalidToken,
+            })?;
+        if !key_store
+            .validity(&truncate_token_key_id(token.token_key_id()))
+            .await?
+            .allows_redemption(
+                self.clock.unix_time(),
+                self.redemption_grace_period.as_secs(),
+            )
+        {
+            return Err(match self.redemption_errors {
+                RedemptionErrors::Detailed => RedeemTokenError::KeyExpired,
+                RedemptionErrors::Uniform => RedeemTokenError::InvalidToken,
+            });
+        }
 
         let options = Options::default();
         let signature = Signature(token.authenticator().to_vec());
 
         signature
-            .verify(&public_key, None, token_input.serialize(), &options)
+            .verify(&public_key, None, token_input.to_bytes(), &options)
             .map_err(|_| RedeemTokenError::InvalidToken)?;
-        nonce_store.insert(token.nonce()).await;
+        check_policy(
+            policy,
+            &token,
+            truncate_token_key_id(token.token_key_id()),
+            metadata,
+        )
+        .await?;
+        if !nonce_store.try_insert(token.nonce()).await {
+            return Err(RedeemTokenError::DoubleSpending);
+        }
         Ok(())
     }
+
+    /// Verifies a token with a public key the caller manages itself, without
+    /// going through a key store. The nonce is neither checked nor recorded,
+    /// so the caller is responsible for detecting double spending.
+    ///
+    /// # Errors
+    /// Returns an error if the token is invalid or was not issued under
+    /// `public_key`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                token_type = ?token.token_type(),
+                truncated_token_key_id = truncate_token_key_id(token.token_key_id()),
+            ),
+            err(level = "debug")
+        )
+    )]
+    pub fn redeem_token_with_key<Nk: ArrayLength<u8>>(
+        &self,
+        public_key: &PublicKey,
+        token: Token<Nk>,
+    ) -> Result<(), RedeemTokenError> {
+        let token_type = token.token_type();
+        let truncated_token_key_id = truncate_token_key_id(token.token_key_id());
+        let result = self.redeem_token_with_key_inner(public_key, token);
+        self.metrics.redemption(
+            token_type,
+            truncated_token_key_id,
+            redemption_outcome(&result),
+        );
+        result
+    }
+
+    fn redeem_token_with_key_inner<Nk: ArrayLength<u8>>(
+        &self,
+        public_key: &PublicKey,
+        token: Token<Nk>,
+    ) -> Result<(), RedeemTokenError> {
+        if token.token_type() != TokenType::PublicToken {
+            return Err(RedeemTokenError::InvalidToken);
+        }
+        if token.authenticator().len() != KEYSIZE_IN_BYTES {
+            return Err(RedeemTokenError::InvalidToken);
+        }
+        if public_key_to_token_key_id(public_key) != *token.token_key_id() {
+            return Err(match self.redemption_errors {
+                RedemptionErrors::Detailed => RedeemTokenError::KeyIdNotFound,
+                RedemptionErrors::Uniform => RedeemTokenError::InvalidToken,
+            });
+        }
+        let token_input = TokenInput::new(
+            token.token_type(),
+            token.nonce(),
+            *token.challenge_digest(),
+            *token.token_key_id(),
+        );
+
+        let options = Options::default();
+        let signature = Signature(token.authenticator().to_vec());
+
+        signature
+            .verify(public_key, None, token_input.to_bytes(), &options)
+            .map_err(|_| RedeemTokenError::InvalidToken)
+    }
 }
@@ -0,0 +1,155 @@
+//! # Redis challenge store
+//!
+//! A [`ChallengeStore`] backed by Redis, so that all replicas of an origin
+//! share the outstanding challenges and a token can be redeemed at a
+//! different replica than the one that issued its challenge.
+//!
+//! Each challenge is stored as its own key that Redis drops when the
+//! challenge expires. Single-use challenges are taken with `GETDEL`, so of
+//! two concurrent redemptions for the same challenge only one gets it. Both
+//! `GETDEL` and absolute expiries require Redis 6.2 or later. A challenge
+//! that is recorded again only replaces the stored one if it expires later,
+//! which a Lua script checks atomically.
+
+use std::fmt;
+
+use async_trait::async_trait;
+use redis::{aio::ConnectionManager, RedisError};
+use thiserror::Error;
+
+use crate::{ChallengeDigest, ChallengeRecord, ChallengeStore};
+
+const DEFAULT_KEY_PREFIX: &[u8] = b"privacypass:challenge:";
+
+// Replaces the record in KEYS[1] with ARGV[1], expiring at ARGV[2], unless
+// the stored record expires later.
+const INSERT_SCRIPT: &str = r"
+local recorded = redis.call('GET', KEYS[1])
+if recorded then
+    local expires_at = tonumber(string.match(recorded, '^(%d+):'))
+    if expires_at and expires_at >= tonumber(ARGV[2]) then
+        return 0
+    end
+end
+redis.call('SET', KEYS[1], ARGV[1], 'EXAT', ARGV[2])
+return 1
+";
+
+/// Errors that can occur when connecting a Redis challenge store.
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum RedisChallengeStoreError {
+    #[error("Redis error")]
+    /// Error when the URL is invalid or the server cannot be reached.
+    Redis(#[from] RedisError),
+}
+
+/// Challenge store that is shared between origin replicas through Redis.
+///
+/// The store is fail-closed: if Redis cannot be reached, every challenge is
+/// reported as unknown, so that no token is redeemed. Challenges that cannot
+/// be written for the same reason are lost.
+#[derive(Clone)]
+pub struct RedisChallengeStore {
+    connection: ConnectionManager,
+    key_prefix: Vec<u8>,
+}
+
+impl fmt::Debug for RedisChallengeStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisChallengeStore")
+            .field("key_prefix", &String::from_utf8_lossy(&self.key_prefix))
+            .finish_non_exhaustive()
+    }
+}
+
+impl RedisChallengeStore {
+    /// Connects to the Redis server at `url`, e.g. `redis://127.0.0.1/`.
+    /// The connection is re-established automatically when it drops.
+    ///
+    /// # Errors
+    /// Returns an error if the URL is invalid or the server cannot be
+    /// reached.
+    pub async fn connect(url: &str) -> Result<Self, RedisChallengeStoreError> {
+        let client = redis::Client::open(url)?;
+        Ok(Self::new(ConnectionManager::new(client).await?))
+    }
+
+    /// Creates a store on an existing connection. Challenges are stored
+    /// under the key prefix `privacypass:challenge:`.
+    #[must_use]
+    pub fn new(connection: ConnectionManager) -> Self {
+        Self {
+            connection,
+            key_prefix: DEFAULT_KEY_PREFIX.to_vec(),
+        }
+    }
+
+    /// Sets the prefix of the Redis keys, so that several stores can share
+    /// one Redis database.
+    #[must_use]
+    pub fn with_key_prefix(mut self, key_prefix: impl Into<Vec<u8>>) -> Self {
+        self.key_prefix = key_prefix.into();
+        self
+    }
+
+    fn key(&self, challenge_digest: &ChallengeDigest) -> Vec<u8> {
+        [self.key_prefix.as_slice(), challenge_digest].concat()
+    }
+
+    async fn store(
+        &self,
+        challenge_digest: &ChallengeDigest,
+        record: ChallengeRecord,
+    ) -> Result<(), RedisError> {
+        // Redis rejects an expiry of zero
+        redis::Script::new(INSERT_SCRIPT)
+            .key(self.key(challenge_digest))
+            .arg(encode_record(record))
+            .arg(record.expires_at.max(1))
+            .invoke_async::<_, u8>(&mut self.connection.clone())
+            .await?;
+        Ok(())
+    }
+
+    async fn load(
+        &self,
+        command: &str,
+        challenge_digest: &ChallengeDigest,
+    ) -> Result<Option<ChallengeRecord>, RedisError> {
+        let value = redis::cmd(command)
+            .arg(self.key(challenge_digest))
+            .query_async::<_, Option<String>>(&mut self.connection.clone())
+            .await?;
+        Ok(value.as_deref().and_then(decode_record))
+    }
+}
+
+/// Encodes a record as `<expires_at>:<single_use>`, e.g. `1700000060:1`.
+fn encode_record(record: ChallengeRecord) -> String {
+    format!("{}:{}", record.expires_at, u8::from(record.single_use))
+}
+
+fn decode_record(value: &str) -> Option<ChallengeRecord> {
+    let (expires_at, single_use) = value.split_once(':')?;
+    Some(ChallengeRecord::new(
+        expires_at.parse().ok()?,
+        single_use == "1",
+    ))
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl ChallengeStore for RedisChallengeStore {
+    async fn insert(&self, challenge_digest: ChallengeDigest, record: ChallengeRecord) {
+        let _ = self.store(&challenge_digest, record).await;
+    }
+
+    async fn get(&self, challenge_digest: &ChallengeDigest) -> Option<ChallengeRecord> {
+        self.load("GET", challenge_digest).await.ok().flatten()
+    }
+
+    async fn take(&self, challenge_digest: &ChallengeDigest) -> Option<ChallengeRecord> {
+        self.load("GETDEL", challenge_digest).await.ok().flatten()
+    }
+}
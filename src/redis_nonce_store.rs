@@ -0,0 +1,146 @@
+//! # Redis nonce store
+//!
+//! A [`NonceStore`] backed by Redis, so that all instances of a clustered
+//! origin share the set of redeemed nonces and a token redeemed at one
+//! instance is rejected as double spending by all others.
+//!
+//! Each nonce is stored as its own key with `SET ... NX`, so of two
+//! concurrent redemptions of the same token only one can record its nonce.
+//! Keys can be given a TTL after which Redis drops them, which re-enables
+//! double spending of their tokens, see [`RedisNonceStore::with_ttl`].
+
+use std::{fmt, time::Duration};
+
+use async_trait::async_trait;
+use redis::{aio::ConnectionManager, RedisError};
+use thiserror::Error;
+
+use crate::{Nonce, NonceStore};
+
+const DEFAULT_KEY_PREFIX: &[u8] = b"privacypass:nonce:";
+
+/// Errors that can occur when connecting a Redis nonce store.
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum RedisNonceStoreError {
+    #[error("Redis error")]
+    /// Error when the URL is invalid or the server cannot be reached.
+    Redis(#[from] RedisError),
+}
+
+/// Nonce store that is shared between servers through Redis.
+///
+/// The store is fail-closed: if Redis cannot be reached, every nonce is
+/// reported as redeemed. Nonces that cannot be written for the same reason
+/// are lost.
+///
+/// **With a TTL, double spending is possible again once Redis drops a
+/// nonce**, because redemption does not reject tokens by age.
+#[derive(Clone)]
+pub struct RedisNonceStore {
+    connection: ConnectionManager,
+    key_prefix: Vec<u8>,
+    ttl: Option<Duration>,
+}
+
+impl fmt::Debug for RedisNonceStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisNonceStore")
+            .field("key_prefix", &String::from_utf8_lossy(&self.key_prefix))
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RedisNonceStore {
+    /// Connects to the Redis server at `url`, e.g. `redis://127.0.0.1/`.
+    /// The connection is re-established automatically when it drops.
+    ///
+    /// # Errors
+    /// Returns an error if the URL is invalid or the server cannot be
+    /// reached.
+    pub async fn connect(url: &str) -> Result<Self, RedisNonceStoreError> {
+        let client = redis::Client::open(url)?;
+        Ok(Self::new(ConnectionManager::new(client).await?))
+    }
+
+    /// Creates a store on an existing connection. Nonces are stored under
+    /// the key prefix `privacypass:nonce:` and never expire.
+    #[must_use]
+    pub fn new(connection: ConnectionManager) -> Self {
+        Self {
+            connection,
+            key_prefix: DEFAULT_KEY_PREFIX.to_vec(),
+            ttl: None,
+        }
+    }
+
+    /// Sets the prefix of the Redis keys, so that several stores can share
+    /// one Redis database.
+    #[must_use]
+    pub fn with_key_prefix(mut self, key_prefix: impl Into<Vec<u8>>) -> Self {
+        self.key_prefix = key_prefix.into();
+        self
+    }
+
+    /// Sets the time after which Redis drops a stored nonce, unless it was
+    /// inserted with its own TTL. Only set a TTL if the application rejects
+    /// tokens that are older than it, e.g. through challenges that expire
+    /// sooner.
+    #[must_use]
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    fn key(&self, nonce: &Nonce) -> Vec<u8> {
+        [self.key_prefix.as_slice(), nonce].concat()
+    }
+
+    async fn contains(&self, nonce: &Nonce) -> Result<bool, RedisError> {
+        redis::cmd("EXISTS")
+            .arg(self.key(nonce))
+            .query_async(&mut self.connection.clone())
+            .await
+    }
+
+    /// Returns `true` if the nonce was not stored yet.
+    async fn store(&self, nonce: &Nonce, ttl: Option<Duration>) -> Result<bool, RedisError> {
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(self.key(nonce)).arg(1u8).arg("NX");
+        if let Some(ttl) = ttl {
+            // Redis rejects a TTL of zero
+            let millis = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+            cmd.arg("PX").arg(millis.max(1));
+        }
+        // `SET ... NX` replies nil if the nonce is already stored
+        let reply = cmd
+            .query_async::<_, Option<String>>(&mut self.connection.clone())
+            .await?;
+        Ok(reply.is_some())
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl NonceStore for RedisNonceStore {
+    async fn exists(&self, nonce: &Nonce) -> bool {
+        self.contains(nonce).await.unwrap_or(true)
+    }
+
+    async fn insert(&self, nonce: Nonce) {
+        let _ = self.store(&nonce, self.ttl).await;
+    }
+
+    async fn try_insert(&self, nonce: Nonce) -> bool {
+        self.store(&nonce, self.ttl).await.unwrap_or(false)
+    }
+
+    async fn insert_with_ttl(&self, nonce: Nonce, ttl: Duration) {
+        let _ = self.store(&nonce, Some(ttl)).await;
+    }
+
+    async fn try_insert_with_ttl(&self, nonce: Nonce, ttl: Duration) -> bool {
+        self.store(&nonce, Some(ttl)).await.unwrap_or(false)
+    }
+}
@@ -0,0 +1,82 @@
+//! # Serde support for wire structs
+//!
+//! With the `serde-wire` feature, the token requests, token responses,
+//! tokens and token challenges of all token types implement
+//! [`serde::Serialize`] and [`serde::Deserialize`], so that they can be
+//! stored in JSON documents or job queues. Each struct is represented as a
+//! base64url string of its wire encoding, the same encoding that the HTTP
+//! authentication headers use. Deserialization accepts padded and unpadded
+//! strings and rejects trailing bytes.
+//!
+//! Key material is not covered: public keys are published through the
+//! [`TokenKey`](crate::issuer_directory::TokenKey) entries of the issuer
+//! directory, which implement serde already.
+//!
+//! The client-side issuance states of the batched token types are covered
+//! the same way, so that a client can persist the state of an outstanding
+//! token request and finish issuance after a restart.
+
+use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+use generic_array::ArrayLength;
+use serde::{de::Error as _, ser::Error as _, Deserializer, Serializer};
+use voprf::Group;
+
+use crate::{
+    arbitrary_batched_tokens::{BatchTokenRequest, BatchTokenResponse},
+    auth::{authenticate::TokenChallenge, authorize::Token, URL_SAFE_LENIENT},
+    batched_tokens, public_tokens,
+};
+
+fn serialize<T: tls_codec::Serialize, S: Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let bytes = value.tls_serialize_detached().map_err(S::Error::custom)?;
+    serializer.serialize_str(&URL_SAFE.encode(bytes))
+}
+
+fn deserialize<'de, T: tls_codec::Deserialize, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    let encoded = <String as serde::Deserialize>::deserialize(deserializer)?;
+    let bytes = URL_SAFE_LENIENT.decode(encoded).map_err(D::Error::custom)?;
+    let mut bytes = bytes.as_slice();
+    let value = T::tls_deserialize(&mut bytes).map_err(D::Error::custom)?;
+    if !bytes.is_empty() {
+        return Err(D::Error::custom("trailing bytes after serialized data"));
+    }
+    Ok(value)
+}
+
+macro_rules! impl_serde {
+    ($ty:ty $(, $param:ident: $bound:path)?) => {
+        impl<$($param: $bound)?> serde::Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serialize(self, serializer)
+            }
+        }
+
+        impl<'de, $($param: $bound)?> serde::Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                deserialize(deserializer)
+            }
+        }
+    };
+}
+
+impl_serde!(TokenChallenge);
+impl_serde!(Token<Nk>, Nk: ArrayLength<u8>);
+impl_serde!(batched_tokens::TokenRequest<G>, G: Group);
+impl_serde!(batched_tokens::TokenResponse<G>, G: Group);
+impl_serde!(public_tokens::TokenRequest);
+impl_serde!(public_tokens::TokenResponse);
+impl_serde!(BatchTokenRequest);
+impl_serde!(BatchTokenResponse);
+#[cfg(feature = "p384")]
+impl_serde!(crate::private_tokens::TokenRequest);
+#[cfg(feature = "p384")]
+impl_serde!(crate::private_tokens::TokenResponse);
+#[cfg(feature = "p384")]
+impl_serde!(crate::batched_tokens_p384::client::IssuanceState);
+#[cfg(feature = "ristretto255")]
+impl_serde!(crate::batched_tokens_ristretto255::client::IssuanceState);
@@ -0,0 +1,225 @@
+//! # Embedded nonce store
+//!
+//! A [`NonceStore`] backed by a `sled` database, so that origins that run as
+//! a single process keep their redeemed nonces across restarts and crashes.
+//! Without a persistent store a restarted origin accepts every token that
+//! was redeemed before the restart again.
+//!
+//! Each nonce is a key of a `sled` tree whose value is the expiry of the
+//! nonce, if any. Inserts update the key atomically, so of two concurrent
+//! redemptions of the same token only one can record its nonce, and every
+//! write is flushed to disk before it completes.
+
+use std::{
+    fmt,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use thiserror::Error;
+
+use crate::{
+    clock::{Clock, SystemClock},
+    Nonce, NonceStore,
+};
+
+const DEFAULT_TREE: &str = "privacypass_nonces";
+
+/// Errors that can occur when opening or pruning an embedded nonce store.
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum SledNonceStoreError {
+    #[error("Database error")]
+    /// Error when the database cannot be opened, read or written.
+    Sled(#[from] sled::Error),
+}
+
+/// Nonce store that keeps the redeemed nonces in an embedded `sled`
+/// database.
+///
+/// The store is fail-closed: if the database cannot be read, every nonce is
+/// reported as redeemed. Nonces that cannot be written for the same reason
+/// are lost. Expired nonces are no longer reported as redeemed and are
+/// removed by [`prune`](Self::prune).
+///
+/// **An expired nonce no longer prevents its token from being spent
+/// again.** Without a TTL nonces never expire; with one, the application has
+/// to reject tokens older than the TTL itself.
+pub struct SledNonceStore<C = SystemClock> {
+    tree: sled::Tree,
+    ttl: Option<Duration>,
+    clock: C,
+}
+
+impl<C> fmt::Debug for SledNonceStore<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SledNonceStore")
+            .field("len", &self.tree.len())
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SledNonceStore {
+    /// Opens the database at `path`, creating it if it does not exist yet.
+    /// Nonces are stored in the tree `privacypass_nonces` and never expire.
+    ///
+    /// # Errors
+    /// Returns an error if the database cannot be opened, e.g. because
+    /// another process holds it open.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SledNonceStoreError> {
+        let db = sled::open(path)?;
+        Ok(Self::new(db.open_tree(DEFAULT_TREE)?))
+    }
+
+    /// Creates a store on a tree of an open database, so that applications
+    /// can keep other data in the same database. Nonces never expire.
+    #[must_use]
+    pub fn new(tree: sled::Tree) -> Self {
+        Self::new_with_clock(tree, SystemClock)
+    }
+}
+
+impl<C> SledNonceStore<C> {
+    /// Creates a store on a tree of an open database whose nonces expire as
+    /// measured by `clock`.
+    #[must_use]
+    pub fn new_with_clock(tree: sled::Tree, clock: C) -> Self {
+        Self {
+            tree,
+            ttl: None,
+            clock,
+        }
+    }
+
+    /// Sets the time after which a stored nonce expires, unless it was
+    /// inserted with its own TTL. Tokens can be double spent after that time,
+    /// see the type docs.
+    #[must_use]
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Returns the number of stored nonces, including expired nonces that
+    /// have not been pruned yet.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Returns `true` if the store holds no nonces.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+}
+
+/// Returns the time in milliseconds since the Unix epoch.
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, duration_millis)
+}
+
+fn duration_millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Returns `true` if the nonce with the stored `value` has not expired at
+/// `now`. Nonces without an expiry are stored with an empty value.
+fn is_live(value: &[u8], now: u64) -> bool {
+    <[u8; 8]>::try_from(value).map_or(true, |expiry| u64::from_be_bytes(expiry) > now)
+}
+
+/// Returns `true` if a nonce stored as `value` expires after one stored as
+/// `current`.
+fn outlives(value: &[u8], current: &[u8]) -> bool {
+    match (<[u8; 8]>::try_from(value), <[u8; 8]>::try_from(current)) {
+        (_, Err(_)) => false,
+        (Err(_), Ok(_)) => true,
+        (Ok(value), Ok(current)) => u64::from_be_bytes(value) > u64::from_be_bytes(current),
+    }
+}
+
+impl<C: Clock> SledNonceStore<C> {
+    /// Removes all expired nonces and returns how many were removed.
+    ///
+    /// # Errors
+    /// Returns an error if the database cannot be read or written.
+    pub async fn prune(&self) -> Result<usize, SledNonceStoreError> {
+        let now = unix_millis(self.clock.now());
+        let mut pruned = 0;
+        for entry in self.tree.iter() {
+            let (nonce, value) = entry?;
+            // A nonce that was inserted again in the meantime is kept
+            if !is_live(&value, now)
+                && self
+                    .tree
+                    .compare_and_swap(nonce, Some(value), None::<&[u8]>)?
+                    .is_ok()
+            {
+                pruned += 1;
+            }
+        }
+        self.tree.flush_async().await?;
+        Ok(pruned)
+    }
+
+    fn contains(&self, nonce: &Nonce) -> sled::Result<bool> {
+        let now = unix_millis(self.clock.now());
+        Ok(self
+            .tree
+            .get(nonce)?
+            .is_some_and(|value| is_live(&value, now)))
+    }
+
+    /// Returns `true` if the nonce was not stored yet or had expired. If
+    /// `replace` is set, the expiry of a stored nonce is extended as well,
+    /// but never shortened.
+    async fn store(
+        &self,
+        nonce: &Nonce,
+        ttl: Option<Duration>,
+        replace: bool,
+    ) -> sled::Result<bool> {
+        let now = unix_millis(self.clock.now());
+        let value = ttl.map_or_else(Vec::new, |ttl| {
+            now.saturating_add(duration_millis(ttl))
+                .to_be_bytes()
+                .to_vec()
+        });
+        let previous = self.tree.fetch_and_update(nonce, |current| match current {
+            // A live nonce keeps its later expiry
+            Some(current) if is_live(current, now) && !(replace && outlives(&value, current)) => {
+                Some(current.to_vec())
+            }
+            _ => Some(value.clone()),
+        })?;
+        self.tree.flush_async().await?;
+        Ok(!previous.is_some_and(|previous| is_live(&previous, now)))
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<C: Clock> NonceStore for SledNonceStore<C> {
+    async fn exists(&self, nonce: &Nonce) -> bool {
+        self.contains(nonce).unwrap_or(true)
+    }
+
+    async fn insert(&self, nonce: Nonce) {
+        let _ = self.store(&nonce, self.ttl, true).await;
+    }
+
+    async fn try_insert(&self, nonce: Nonce) -> bool {
+        self.store(&nonce, self.ttl, false).await.unwrap_or(false)
+    }
+
+    async fn insert_with_ttl(&self, nonce: Nonce, ttl: Duration) {
+        let _ = self.store(&nonce, Some(ttl), true).await;
+    }
+
+    async fn try_insert_with_ttl(&self, nonce: Nonce, ttl: Duration) -> bool {
+        self.store(&nonce, Some(ttl), false).await.unwrap_or(false)
+    }
+}
@@ -0,0 +1,624 @@
+//! # SQL stores
+//!
+//! [`SqlxKeyStore`] and [`SqlxNonceStore`] keep keys and redeemed nonces in
+//! a Postgres or SQLite database through `sqlx`, so that issuers keep their
+//! keys across restarts and all instances of a clustered origin share the
+//! set of redeemed nonces. Postgres requires the `sqlx-postgres` feature,
+//! SQLite the `sqlx-sqlite` feature.
+//!
+//! The tables are created by [`SqlxKeyStore::migrate`] and
+//! [`SqlxNonceStore::migrate`]. Both run the same idempotent schema from the
+//! `migrations` directory of the crate, which applications that manage
+//! their schema themselves can copy into their own migrations instead.
+//!
+//! The key store implements the key store trait of every token type for the
+//! matching key type, like the in-memory key store, e.g.
+//! `SqlxKeyStore<VoprfServer<Ristretto255>>` for batched Ristretto255
+//! tokens. Private keys are stored unencrypted; the database has to be
+//! protected accordingly. Key validity periods are kept in a table of their
+//! own, so that they can be recorded before their key.
+
+use std::{fmt, marker::PhantomData, time::Duration};
+
+use async_trait::async_trait;
+#[cfg(feature = "sqlx-postgres")]
+use sqlx::PgPool;
+#[cfg(feature = "sqlx-sqlite")]
+use sqlx::SqlitePool;
+use sqlx::{Executor, Row};
+use thiserror::Error;
+
+use crate::{
+    clock::{Clock, SystemClock},
+    KeyStoreError, KeyValidity, Nonce, NonceStore, TruncatedTokenKeyId,
+};
+
+#[cfg(feature = "sqlx-postgres")]
+const POSTGRES_SCHEMA: &str = include_str!("../migrations/postgres/0001_privacypass.sql");
+#[cfg(feature = "sqlx-sqlite")]
+const SQLITE_SCHEMA: &str = include_str!("../migrations/sqlite/0001_privacypass.sql");
+
+const DEFAULT_KEY_SET: &str = "default";
+
+/// Errors that can occur when setting up or maintaining a SQL store.
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum SqlxStoreError {
+    #[error("Database error")]
+    /// Error when a statement fails or the database cannot be reached.
+    Database(#[from] sqlx::Error),
+}
+
+#[derive(Clone)]
+enum SqlxPool {
+    #[cfg(feature = "sqlx-postgres")]
+    Postgres(PgPool),
+    #[cfg(feature = "sqlx-sqlite")]
+    Sqlite(SqlitePool),
+}
+
+impl SqlxPool {
+    const fn backend(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "sqlx-postgres")]
+            Self::Postgres(_) => "postgres",
+            #[cfg(feature = "sqlx-sqlite")]
+            Self::Sqlite(_) => "sqlite",
+        }
+    }
+
+    async fn migrate(&self) -> Result<(), SqlxStoreError> {
+        match self {
+            #[cfg(feature = "sqlx-postgres")]
+            Self::Postgres(pool) => {
+                pool.execute(POSTGRES_SCHEMA).await?;
+            }
+            #[cfg(feature = "sqlx-sqlite")]
+            Self::Sqlite(pool) => {
+                pool.execute(SQLITE_SCHEMA).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs `$body` with `$pool` bound to the pool of the backend. The
+/// statements are the same for both backends, but their types are not.
+macro_rules! with_pool {
+    ($pool:expr, |$conn:ident| $body:expr) => {
+        match $pool {
+            #[cfg(feature = "sqlx-postgres")]
+            SqlxPool::Postgres($conn) => $body,
+            #[cfg(feature = "sqlx-sqlite")]
+            SqlxPool::Sqlite($conn) => $body,
+        }
+    };
+}
+
+fn key_store_error(error: sqlx::Error) -> KeyStoreError {
+    match error {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed => {
+            KeyStoreError::Unavailable
+        }
+        error => KeyStoreError::Backend(error.to_string()),
+    }
+}
+
+/// Key material that can be stored in a [`SqlxKeyStore`].
+trait SqlxKey: Sized {
+    fn to_bytes(&self) -> Result<Vec<u8>, KeyStoreError>;
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, KeyStoreError>;
+}
+
+macro_rules! impl_voprf_key {
+    ($group:ty) => {
+        impl SqlxKey for voprf::VoprfServer<$group> {
+            fn to_bytes(&self) -> Result<Vec<u8>, KeyStoreError> {
+                Ok(self.serialize().to_vec())
+            }
+
+            fn from_bytes(bytes: &[u8]) -> Result<Self, KeyStoreError> {
+                Self::deserialize(bytes)
+                    .map_err(|_| KeyStoreError::Backend("Invalid VOPRF key".to_string()))
+            }
+        }
+    };
+}
+
+#[cfg(feature = "p384")]
+impl_voprf_key!(p384::NistP384);
+#[cfg(feature = "ristretto255")]
+impl_voprf_key!(voprf::Ristretto255);
+
+impl SqlxKey for blind_rsa_signatures::KeyPair {
+    fn to_bytes(&self) -> Result<Vec<u8>, KeyStoreError> {
+        self.sk
+            .to_der()
+            .map_err(|_| KeyStoreError::Backend("Invalid RSA key".to_string()))
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, KeyStoreError> {
+        let invalid = |_| KeyStoreError::Backend("Invalid RSA key".to_string());
+        let sk = blind_rsa_signatures::SecretKey::from_der(bytes).map_err(invalid)?;
+        let pk = sk.public_key().map_err(invalid)?;
+        Ok(Self { pk, sk })
+    }
+}
+
+impl SqlxKey for blind_rsa_signatures::PublicKey {
+    fn to_bytes(&self) -> Result<Vec<u8>, KeyStoreError> {
+        Ok(crate::public_tokens::server::serialize_public_key(self))
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, KeyStoreError> {
+        crate::public_tokens::server::deserialize_public_key(bytes)
+            .map_err(|_| KeyStoreError::Backend("Invalid RSA public key".to_string()))
+    }
+}
+
+/// Key store that keeps the keys in a SQL database.
+pub struct SqlxKeyStore<K> {
+    pool: SqlxPool,
+    key_set: String,
+    key: PhantomData<fn() -> K>,
+}
+
+impl<K> Clone for SqlxKeyStore<K> {
+    fn clone(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            key_set: self.key_set.clone(),
+            key: PhantomData,
+        }
+    }
+}
+
+impl<K> fmt::Debug for SqlxKeyStore<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SqlxKeyStore")
+            .field("backend", &self.pool.backend())
+            .field("key_set", &self.key_set)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K> SqlxKeyStore<K> {
+    fn new(pool: SqlxPool) -> Self {
+        Self {
+            pool,
+            key_set: DEFAULT_KEY_SET.to_string(),
+            key: PhantomData,
+        }
+    }
+
+    /// Creates a key store in a Postgres database. Keys are stored in the
+    /// key set `default`.
+    #[cfg(feature = "sqlx-postgres")]
+    #[must_use]
+    pub fn postgres(pool: PgPool) -> Self {
+        Self::new(SqlxPool::Postgres(pool))
+    }
+
+    /// Creates a key store in a SQLite database. Keys are stored in the key
+    /// set `default`.
+    #[cfg(feature = "sqlx-sqlite")]
+    #[must_use]
+    pub fn sqlite(pool: SqlitePool) -> Self {
+        Self::new(SqlxPool::Sqlite(pool))
+    }
+
+    /// Sets the name of the key set, so that several key stores, e.g. of
+    /// different token types or issuers, can share one table.
+    #[must_use]
+    pub fn with_key_set(mut self, key_set: impl Into<String>) -> Self {
+        self.key_set = key_set.into();
+        self
+    }
+
+    /// Creates the tables of the SQL stores if they do not exist yet.
+    ///
+    /// # Errors
+    /// Returns an error if the schema cannot be applied.
+    pub async fn migrate(&self) -> Result<(), SqlxStoreError> {
+        self.pool.migrate().await
+    }
+
+    async fn remove_key(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        with_pool!(&self.pool, |pool| sqlx::query(
+            "DELETE FROM privacypass_key_validities WHERE key_set = $1 AND key_id = $2",
+        )
+        .bind(&self.key_set)
+        .bind(i16::from(*truncated_token_key_id))
+        .execute(pool)
+        .await
+        .map(|_| ()))
+        .map_err(key_store_error)?;
+        let result = with_pool!(&self.pool, |pool| sqlx::query(
+            "DELETE FROM privacypass_keys WHERE key_set = $1 AND key_id = $2",
+        )
+        .bind(&self.key_set)
+        .bind(i16::from(*truncated_token_key_id))
+        .execute(pool)
+        .await
+        .map(|result| result.rows_affected()))
+        .map_err(key_store_error)?;
+        Ok(result > 0)
+    }
+
+    async fn key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        let key_ids = with_pool!(&self.pool, |pool| sqlx::query(
+            "SELECT key_id FROM privacypass_keys WHERE key_set = $1 ORDER BY key_id",
+        )
+        .bind(&self.key_set)
+        .fetch_all(pool)
+        .await
+        .and_then(|rows| rows
+            .iter()
+            .map(|row| row.try_get::<i16, _>(0))
+            .collect::<Result<Vec<_>, _>>()))
+        .map_err(key_store_error)?;
+        key_ids
+            .into_iter()
+            .map(|key_id| {
+                TruncatedTokenKeyId::try_from(key_id)
+                    .map_err(|_| KeyStoreError::Backend(format!("Invalid key ID {key_id}")))
+            })
+            .collect()
+    }
+
+    async fn set_key_validity(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        validity: KeyValidity,
+    ) -> Result<(), KeyStoreError> {
+        let not_before = validity.not_before.map(unix_seconds).transpose()?;
+        let not_after = validity.not_after.map(unix_seconds).transpose()?;
+        let redeemable_until = validity.redeemable_until.map(unix_seconds).transpose()?;
+        with_pool!(&self.pool, |pool| sqlx::query(
+            "INSERT INTO privacypass_key_validities \
+             (key_set, key_id, not_before, not_after, redeemable_until) \
+             VALUES ($1, $2, $3, $4, $5) ON CONFLICT (key_set, key_id) DO UPDATE \
+             SET not_before = excluded.not_before, not_after = excluded.not_after, \
+             redeemable_until = excluded.redeemable_until",
+        )
+        .bind(&self.key_set)
+        .bind(i16::from(truncated_token_key_id))
+        .bind(not_before)
+        .bind(not_after)
+        .bind(redeemable_until)
+        .execute(pool)
+        .await
+        .map(|_| ()))
+        .map_err(key_store_error)
+    }
+
+    async fn key_validity(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<KeyValidity, KeyStoreError> {
+        let bounds = with_pool!(&self.pool, |pool| sqlx::query(
+            "SELECT not_before, not_after, redeemable_until FROM privacypass_key_validities \
+             WHERE key_set = $1 AND key_id = $2",
+        )
+        .bind(&self.key_set)
+        .bind(i16::from(*truncated_token_key_id))
+        .fetch_optional(pool)
+        .await
+        .and_then(|row| row
+            .map(|row| Ok::<_, sqlx::Error>((
+                row.try_get::<Option<i64>, _>(0)?,
+                row.try_get::<Option<i64>, _>(1)?,
+                row.try_get::<Option<i64>, _>(2)?
+            )))
+            .transpose()))
+        .map_err(key_store_error)?;
+        let Some((not_before, not_after, redeemable_until)) = bounds else {
+            return Ok(KeyValidity::default());
+        };
+        let from_unix_seconds = |secs: i64| {
+            u64::try_from(secs)
+                .map_err(|_| KeyStoreError::Backend(format!("Invalid validity bound {secs}")))
+        };
+        Ok(KeyValidity {
+            not_before: not_before.map(from_unix_seconds).transpose()?,
+            not_after: not_after.map(from_unix_seconds).transpose()?,
+            redeemable_until: redeemable_until.map(from_unix_seconds).transpose()?,
+        })
+    }
+}
+
+fn unix_seconds(secs: u64) -> Result<i64, KeyStoreError> {
+    i64::try_from(secs)
+        .map_err(|_| KeyStoreError::Backend(format!("Invalid validity bound {secs}")))
+}
+
+impl<K> SqlxKeyStore<K> {
+    async fn insert_key(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        key: &K,
+    ) -> Result<(), KeyStoreError>
+    where
+        K: SqlxKey,
+    {
+        let key_material = key.to_bytes()?;
+        with_pool!(&self.pool, |pool| sqlx::query(
+            "INSERT INTO privacypass_keys (key_set, key_id, key_material) VALUES ($1, $2, $3) \
+             ON CONFLICT (key_set, key_id) DO UPDATE SET key_material = excluded.key_material",
+        )
+        .bind(&self.key_set)
+        .bind(i16::from(truncated_token_key_id))
+        .bind(key_material.as_slice())
+        .execute(pool)
+        .await
+        .map(|_| ()))
+        .map_err(key_store_error)
+    }
+
+    async fn get_key(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<K>, KeyStoreError>
+    where
+        K: SqlxKey,
+    {
+        let key_material = with_pool!(&self.pool, |pool| sqlx::query(
+            "SELECT key_material FROM privacypass_keys WHERE key_set = $1 AND key_id = $2",
+        )
+        .bind(&self.key_set)
+        .bind(i16::from(*truncated_token_key_id))
+        .fetch_optional(pool)
+        .await
+        .and_then(|row| row.map(|row| row.try_get::<Vec<u8>, _>(0)).transpose()))
+        .map_err(key_store_error)?;
+        key_material.as_deref().map(K::from_bytes).transpose()
+    }
+}
+
+macro_rules! impl_key_store {
+    ($key_store:path, $key:ty) => {
+        #[cfg_attr(feature = "send", async_trait)]
+        #[cfg_attr(not(feature = "send"), async_trait(?Send))]
+        impl $key_store for SqlxKeyStore<$key> {
+            async fn insert(
+                &self,
+                truncated_token_key_id: TruncatedTokenKeyId,
+                key: $key,
+            ) -> Result<(), KeyStoreError> {
+                self.insert_key(truncated_token_key_id, &key).await
+            }
+
+            async fn get(
+                &self,
+                truncated_token_key_id: &TruncatedTokenKeyId,
+            ) -> Result<Option<$key>, KeyStoreError> {
+                self.get_key(truncated_token_key_id).await
+            }
+
+            async fn remove(
+                &self,
+                truncated_token_key_id: &TruncatedTokenKeyId,
+            ) -> Result<bool, KeyStoreError> {
+                self.remove_key(truncated_token_key_id).await
+            }
+
+            async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+                self.key_ids().await
+            }
+
+            async fn set_validity(
+                &self,
+                truncated_token_key_id: TruncatedTokenKeyId,
+                validity: KeyValidity,
+            ) -> Result<(), KeyStoreError> {
+                self.set_key_validity(truncated_token_key_id, validity)
+                    .await
+            }
+
+            async fn validity(
+                &self,
+                truncated_token_key_id: &TruncatedTokenKeyId,
+            ) -> Result<KeyValidity, KeyStoreError> {
+                self.key_validity(truncated_token_key_id).await
+            }
+        }
+    };
+}
+
+#[cfg(feature = "p384")]
+impl_key_store!(
+    crate::private_tokens::server::PrivateKeyStore,
+    voprf::VoprfServer<p384::NistP384>
+);
+#[cfg(feature = "p384")]
+impl_key_store!(
+    crate::batched_tokens_p384::server::BatchedKeyStore,
+    voprf::VoprfServer<p384::NistP384>
+);
+#[cfg(feature = "ristretto255")]
+impl_key_store!(
+    crate::batched_tokens_ristretto255::server::BatchedKeyStore,
+    voprf::VoprfServer<voprf::Ristretto255>
+);
+impl_key_store!(
+    crate::public_tokens::server::IssuerKeyStore,
+    blind_rsa_signatures::KeyPair
+);
+impl_key_store!(
+    crate::public_tokens::server::OriginKeyStore,
+    blind_rsa_signatures::PublicKey
+);
+
+/// Nonce store that keeps the redeemed nonces in a SQL database.
+///
+/// The store is fail-closed: if the database cannot be reached, every nonce
+/// is reported as redeemed. Nonces that cannot be written for the same
+/// reason are lost. Expired nonces are no longer reported as redeemed and
+/// are deleted by [`prune`](Self::prune).
+///
+/// **Tokens whose nonce expired can be redeemed a second time.** Nonces only
+/// expire if a TTL is set, which is safe only where the application rejects
+/// tokens older than the TTL by other means.
+#[derive(Clone)]
+pub struct SqlxNonceStore {
+    pool: SqlxPool,
+    ttl: Option<Duration>,
+}
+
+impl fmt::Debug for SqlxNonceStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SqlxNonceStore")
+            .field("backend", &self.pool.backend())
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns the time in milliseconds since the Unix epoch.
+fn unix_millis(time: Duration) -> i64 {
+    i64::try_from(time.as_millis()).unwrap_or(i64::MAX)
+}
+
+fn now() -> Duration {
+    SystemClock
+        .now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+impl SqlxNonceStore {
+    /// Creates a nonce store in a Postgres database. Nonces never expire.
+    #[cfg(feature = "sqlx-postgres")]
+    #[must_use]
+    pub fn postgres(pool: PgPool) -> Self {
+        Self {
+            pool: SqlxPool::Postgres(pool),
+            ttl: None,
+        }
+    }
+
+    /// Creates a nonce store in a SQLite database. Nonces never expire.
+    #[cfg(feature = "sqlx-sqlite")]
+    #[must_use]
+    pub fn sqlite(pool: SqlitePool) -> Self {
+        Self {
+            pool: SqlxPool::Sqlite(pool),
+            ttl: None,
+        }
+    }
+
+    /// Sets the time after which a stored nonce expires, unless it was
+    /// inserted with its own TTL. See the type docs for the double spending
+    /// this allows.
+    #[must_use]
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Creates the tables of the SQL stores if they do not exist yet.
+    ///
+    /// # Errors
+    /// Returns an error if the schema cannot be applied.
+    pub async fn migrate(&self) -> Result<(), SqlxStoreError> {
+        self.pool.migrate().await
+    }
+
+    /// Deletes all expired nonces and returns how many were deleted.
+    ///
+    /// # Errors
+    /// Returns an error if the statement fails.
+    pub async fn prune(&self) -> Result<u64, SqlxStoreError> {
+        let now = unix_millis(now());
+        let deleted = with_pool!(&self.pool, |pool| sqlx::query(
+            "DELETE FROM privacypass_nonces WHERE expires_at <= $1",
+        )
+        .bind(now)
+        .execute(pool)
+        .await?
+        .rows_affected());
+        Ok(deleted)
+    }
+
+    async fn contains(&self, nonce: &Nonce) -> Result<bool, sqlx::Error> {
+        let now = unix_millis(now());
+        with_pool!(&self.pool, |pool| sqlx::query(
+            "SELECT 1 FROM privacypass_nonces \
+             WHERE nonce = $1 AND (expires_at IS NULL OR expires_at > $2)",
+        )
+        .bind(nonce.as_slice())
+        .bind(now)
+        .fetch_optional(pool)
+        .await
+        .map(|row| row.is_some()))
+    }
+
+    /// Returns `true` if the nonce was not stored yet or had expired. If
+    /// `replace` is set, the expiry of a stored nonce is extended as well,
+    /// but never shortened.
+    async fn store(
+        &self,
+        nonce: &Nonce,
+        ttl: Option<Duration>,
+        replace: bool,
+    ) -> Result<bool, sqlx::Error> {
+        let now = now();
+        let expires_at = ttl.map(|ttl| unix_millis(now.saturating_add(ttl)));
+        let now = unix_millis(now);
+        if replace {
+            return with_pool!(&self.pool, |pool| sqlx::query(
+                "INSERT INTO privacypass_nonces (nonce, expires_at) VALUES ($1, $2) \
+                 ON CONFLICT (nonce) DO UPDATE SET expires_at = excluded.expires_at \
+                 WHERE privacypass_nonces.expires_at IS NOT NULL \
+                 AND (excluded.expires_at IS NULL \
+                 OR excluded.expires_at > privacypass_nonces.expires_at)",
+            )
+            .bind(nonce.as_slice())
+            .bind(expires_at)
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected() > 0));
+        }
+        // A stored nonce is only overwritten once it expired, so that a
+        // changed row means the nonce was not redeemed yet
+        with_pool!(&self.pool, |pool| sqlx::query(
+            "INSERT INTO privacypass_nonces (nonce, expires_at) VALUES ($1, $2) \
+             ON CONFLICT (nonce) DO UPDATE SET expires_at = excluded.expires_at \
+             WHERE privacypass_nonces.expires_at <= $3",
+        )
+        .bind(nonce.as_slice())
+        .bind(expires_at)
+        .bind(now)
+        .execute(pool)
+        .await
+        .map(|result| result.rows_affected() > 0))
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl NonceStore for SqlxNonceStore {
+    async fn exists(&self, nonce: &Nonce) -> bool {
+        self.contains(nonce).await.unwrap_or(true)
+    }
+
+    async fn insert(&self, nonce: Nonce) {
+        let _ = self.store(&nonce, self.ttl, true).await;
+    }
+
+    async fn try_insert(&self, nonce: Nonce) -> bool {
+        self.store(&nonce, self.ttl, false).await.unwrap_or(false)
+    }
+
+    async fn insert_with_ttl(&self, nonce: Nonce, ttl: Duration) {
+        let _ = self.store(&nonce, Some(ttl), true).await;
+    }
+
+    async fn try_insert_with_ttl(&self, nonce: Nonce, ttl: Duration) -> bool {
+        self.store(&nonce, Some(ttl), false).await.unwrap_or(false)
+    }
+}
@@ -0,0 +1,117 @@
+//! Test vectors of batched Ristretto255 tokens.
+
+use voprf::{Group, Ristretto255};
+
+use crate::{
+    auth::authenticate::TokenChallenge,
+    batched_tokens_ristretto255::{
+        client::Client,
+        server::{serialize_public_key, BatchedKeyStore, Server},
+        NE,
+    },
+    Nonce, NonceStore, Serialize, TokenType,
+};
+
+use super::{check, parse, BatchedTokenTestVector, TestVectorError};
+
+/// Test vectors of the Go implementation of the batched tokens draft.
+pub const VECTORS: &str =
+    include_str!("../../tests/kat_vectors/batched_ristretto255_vectors_go.json");
+
+/// Returns the embedded test vectors.
+///
+/// # Errors
+/// Returns an error if the vectors cannot be parsed.
+pub fn vectors() -> Result<Vec<BatchedTokenTestVector>, TestVectorError> {
+    parse(VECTORS)
+}
+
+/// Runs a test vector with the given stores. The key store should not hold a
+/// key with the truncated token key ID of the vector yet.
+///
+/// # Errors
+/// Returns an error if a step fails or its output does not match the vector.
+pub async fn run_test_vector<BKS: BatchedKeyStore + ?Sized, NS: NonceStore + ?Sized>(
+    vector: &BatchedTokenTestVector,
+    key_store: &BKS,
+    nonce_store: &NS,
+) -> Result<(), TestVectorError> {
+    if vector.nonces.len() != vector.blinds.len() || vector.nonces.len() != vector.tokens.len() {
+        return Err(TestVectorError::InvalidInput("number of nonces"));
+    }
+    let server = Server::new();
+    let public_key = server
+        .set_key(key_store, &vector.sk_s)
+        .await
+        .map_err(|_| TestVectorError::StepFailed("Setting the key"))?;
+    check(
+        &serialize_public_key(public_key),
+        &vector.pk_s,
+        "Public key",
+    )?;
+
+    let token_challenge = TokenChallenge::deserialize(&vector.token_challenge)
+        .map_err(|_| TestVectorError::InvalidInput("token challenge"))?;
+    check(
+        &token_challenge.token_type(),
+        &TokenType::BatchedTokenRistretto255,
+        "Token type",
+    )?;
+    let nonces = vector
+        .nonces
+        .iter()
+        .map(|nonce| Nonce::try_from(nonce.0.as_slice()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| TestVectorError::InvalidInput("nonce"))?;
+    let blinds = vector
+        .blinds
+        .iter()
+        .map(|blind| Ristretto255::deserialize_scalar(&blind.0))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| TestVectorError::InvalidInput("blind"))?;
+
+    let client = Client::new(public_key);
+    let (token_request, token_states) = client
+        .issue_token_request_with_params(&token_challenge, nonces, blinds)
+        .map_err(|_| TestVectorError::StepFailed("Issuing the token request"))?;
+    check(
+        &token_request
+            .tls_serialize_detached()
+            .map_err(|_| TestVectorError::StepFailed("Serializing the token request"))?,
+        &vector.token_request,
+        "Token request",
+    )?;
+
+    let token_response = server
+        .issue_token_response(key_store, token_request)
+        .await
+        .map_err(|_| TestVectorError::StepFailed("Issuing the token response"))?;
+    let serialized_response = token_response
+        .tls_serialize_detached()
+        .map_err(|_| TestVectorError::StepFailed("Serializing the token response"))?;
+    // The proof is randomized, only the evaluated elements are compared
+    let elements = NE * vector.nonces.len();
+    check(
+        &serialized_response.get(..elements),
+        &vector.token_response.get(..elements),
+        "Token response",
+    )?;
+
+    let tokens = client
+        .issue_tokens(&token_response, &token_states)
+        .map_err(|_| TestVectorError::StepFailed("Finalizing the tokens"))?;
+    for (token, expected) in tokens.into_iter().zip(&vector.tokens) {
+        check(
+            &token
+                .tls_serialize_detached()
+                .map_err(|_| TestVectorError::StepFailed("Serializing the token"))?,
+            &expected.0,
+            "Token",
+        )?;
+        server
+            .redeem_token(key_store, nonce_store, token)
+            .await
+            .map_err(|_| TestVectorError::StepFailed("Redeeming the token"))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! # Test vectors
+//!
+//! Runs known-answer test vectors through the client and server of a token
+//! type, so that implementers can validate their key and nonce stores and
+//! their ciphersuite wiring against the specification. Each submodule
+//! embeds the vectors of its token type and offers a `run_test_vector`
+//! function that takes the stores to test:
+//!
+//! - [`private_tokens`] embeds the vectors of the issuance protocols draft
+//!   for privately verifiable tokens.
+//! - [`batched_tokens_ristretto255`] embeds the batched token vectors of the
+//!   Go implementation of the batched tokens draft.
+//! - [`batched_tokens_p384`] embeds the batched P-384 token vectors of this
+//!   crate, as the draft has none.
+//!
+//! A vector sets the server key, blinds its nonces with its blinds and
+//! checks the token request, the evaluated elements of the token response
+//! and the tokens against the expected values. Proofs are randomized and
+//! therefore not compared. Finally every token is redeemed with the given
+//! nonce store.
+//!
+//! The module needs the `kat` feature, which enables deterministic blinding.
+
+#[cfg(feature = "p384")]
+pub mod batched_tokens_p384;
+#[cfg(feature = "ristretto255")]
+pub mod batched_tokens_ristretto255;
+#[cfg(feature = "p384")]
+pub mod private_tokens;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Errors that can occur when running a test vector.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TestVectorError {
+    #[error("The test vectors cannot be parsed")]
+    /// Error when the JSON document of the vectors is invalid.
+    Parse,
+    #[error("Invalid {0} in test vector")]
+    /// Error when an input of the vector cannot be decoded.
+    InvalidInput(&'static str),
+    #[error("{0} failed")]
+    /// Error when a protocol step returned an error.
+    StepFailed(&'static str),
+    #[error("{0} does not match the test vector")]
+    /// Error when an output differs from the expected value.
+    Mismatch(&'static str),
+}
+
+/// Test vector of the batched token types.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct BatchedTokenTestVector {
+    /// Serialized private key of the issuer.
+    #[serde(with = "hex", alias = "skS")]
+    pub sk_s: Vec<u8>,
+    /// Serialized public key of the issuer.
+    #[serde(with = "hex", alias = "pkS")]
+    pub pk_s: Vec<u8>,
+    /// Serialized token challenge.
+    #[serde(with = "hex")]
+    pub token_challenge: Vec<u8>,
+    /// Nonces of the tokens.
+    pub nonces: Vec<HexBytes>,
+    /// Serialized blinds of the tokens.
+    pub blinds: Vec<HexBytes>,
+    /// Expected serialized token request.
+    #[serde(with = "hex")]
+    pub token_request: Vec<u8>,
+    /// Expected serialized token response.
+    #[serde(with = "hex")]
+    pub token_response: Vec<u8>,
+    /// Expected serialized tokens.
+    pub tokens: Vec<HexBytes>,
+}
+
+/// Test vector of privately verifiable tokens.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct PrivateTokenTestVector {
+    /// Serialized private key of the issuer.
+    #[serde(with = "hex", alias = "skS")]
+    pub sk_s: Vec<u8>,
+    /// Serialized public key of the issuer.
+    #[serde(with = "hex", alias = "pkS")]
+    pub pk_s: Vec<u8>,
+    /// Serialized token challenge.
+    #[serde(with = "hex")]
+    pub token_challenge: Vec<u8>,
+    /// Nonce of the token.
+    #[serde(with = "hex")]
+    pub nonce: Vec<u8>,
+    /// Serialized blind of the token.
+    #[serde(with = "hex")]
+    pub blind: Vec<u8>,
+    /// Expected serialized token request.
+    #[serde(with = "hex")]
+    pub token_request: Vec<u8>,
+    /// Expected serialized token response.
+    #[serde(with = "hex")]
+    pub token_response: Vec<u8>,
+    /// Expected serialized token.
+    #[serde(with = "hex")]
+    pub token: Vec<u8>,
+}
+
+/// Byte string that is hex encoded in test vectors.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct HexBytes(#[serde(with = "hex")] pub Vec<u8>);
+
+/// Parses a JSON list of test vectors.
+fn parse<V: for<'de> Deserialize<'de>>(vectors: &str) -> Result<Vec<V>, TestVectorError> {
+    serde_json::from_str(vectors.trim()).map_err(|_| TestVectorError::Parse)
+}
+
+/// Returns an error if `actual` differs from `expected`.
+fn check<T: PartialEq + ?Sized>(
+    actual: &T,
+    expected: &T,
+    what: &'static str,
+) -> Result<(), TestVectorError> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(TestVectorError::Mismatch(what))
+    }
+}
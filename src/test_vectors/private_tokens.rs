@@ -0,0 +1,102 @@
+//! Test vectors of privately verifiable tokens.
+
+use p384::NistP384;
+use voprf::Group;
+
+use crate::{
+    auth::authenticate::TokenChallenge,
+    private_tokens::{
+        client::Client,
+        server::{serialize_public_key, PrivateKeyStore, Server},
+        NE,
+    },
+    Nonce, NonceStore, Serialize, TokenType,
+};
+
+use super::{check, parse, PrivateTokenTestVector, TestVectorError};
+
+/// Test vectors of the issuance protocols draft.
+pub const VECTORS: &str = include_str!("../../tests/kat_vectors/private_vectors.json");
+
+/// Returns the embedded test vectors.
+///
+/// # Errors
+/// Returns an error if the vectors cannot be parsed.
+pub fn vectors() -> Result<Vec<PrivateTokenTestVector>, TestVectorError> {
+    parse(VECTORS)
+}
+
+/// Runs a test vector with the given stores. The key store should not hold a
+/// key with the truncated token key ID of the vector yet.
+///
+/// # Errors
+/// Returns an error if a step fails or its output does not match the vector.
+pub async fn run_test_vector<PKS: PrivateKeyStore + ?Sized, NS: NonceStore + ?Sized>(
+    vector: &PrivateTokenTestVector,
+    key_store: &PKS,
+    nonce_store: &NS,
+) -> Result<(), TestVectorError> {
+    let server = Server::new();
+    let public_key = server
+        .set_key(key_store, &vector.sk_s)
+        .await
+        .map_err(|_| TestVectorError::StepFailed("Setting the key"))?;
+    check(
+        &serialize_public_key(public_key),
+        &vector.pk_s,
+        "Public key",
+    )?;
+
+    let token_challenge = TokenChallenge::deserialize(&vector.token_challenge)
+        .map_err(|_| TestVectorError::InvalidInput("token challenge"))?;
+    check(
+        &token_challenge.token_type(),
+        &TokenType::PrivateToken,
+        "Token type",
+    )?;
+    let nonce = Nonce::try_from(vector.nonce.as_slice())
+        .map_err(|_| TestVectorError::InvalidInput("nonce"))?;
+    let blind = NistP384::deserialize_scalar(&vector.blind)
+        .map_err(|_| TestVectorError::InvalidInput("blind"))?;
+
+    let client = Client::new(public_key);
+    let (token_request, token_state) = client
+        .issue_token_request_with_params(&token_challenge, nonce, blind)
+        .map_err(|_| TestVectorError::StepFailed("Issuing the token request"))?;
+    check(
+        &token_request
+            .tls_serialize_detached()
+            .map_err(|_| TestVectorError::StepFailed("Serializing the token request"))?,
+        &vector.token_request,
+        "Token request",
+    )?;
+
+    let token_response = server
+        .issue_token_response(key_store, token_request)
+        .await
+        .map_err(|_| TestVectorError::StepFailed("Issuing the token response"))?;
+    let serialized_response = token_response
+        .tls_serialize_detached()
+        .map_err(|_| TestVectorError::StepFailed("Serializing the token response"))?;
+    // The proof is randomized, only the evaluated element is compared
+    check(
+        &serialized_response.get(..NE),
+        &vector.token_response.get(..NE),
+        "Token response",
+    )?;
+
+    let token = client
+        .issue_token(&token_response, &token_state)
+        .map_err(|_| TestVectorError::StepFailed("Finalizing the token"))?;
+    check(
+        &token
+            .tls_serialize_detached()
+            .map_err(|_| TestVectorError::StepFailed("Serializing the token"))?,
+        &vector.token,
+        "Token",
+    )?;
+    server
+        .redeem_token(key_store, nonce_store, token)
+        .await
+        .map_err(|_| TestVectorError::StepFailed("Redeeming the token"))
+}
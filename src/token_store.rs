@@ -0,0 +1,183 @@
+//! # Client-side token cache
+//!
+//! Clients that fetch tokens ahead of time, e.g. a batch of tokens for an
+//! origin, keep the unspent tokens in a [`TokenStore`] and take a matching
+//! token from it when an origin sends a [`TokenChallenge`]. Tokens are
+//! indexed by [`TokenStoreKey`], i.e. by token type, issuer and origin info
+//! of the challenge they were issued for, and are only handed back for a
+//! challenge with the same digest, since the token is bound to it.
+//! [`MemoryTokenStore`] keeps the tokens in memory.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use async_trait::async_trait;
+use generic_array::ArrayLength;
+
+use crate::{
+    auth::{authenticate::TokenChallenge, authorize::Token},
+    MaybeSendSync, TokenType,
+};
+
+/// Index of the tokens in a token store.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TokenStoreKey {
+    /// Token type of the challenge.
+    pub token_type: TokenType,
+    /// Issuer name of the challenge.
+    pub issuer_name: String,
+    /// Origin info of the challenge.
+    pub origin_info: Vec<String>,
+}
+
+impl TokenStoreKey {
+    /// Returns the key of the tokens that can answer `challenge`.
+    #[must_use]
+    pub fn from_challenge(challenge: &TokenChallenge) -> Self {
+        Self {
+            token_type: challenge.token_type(),
+            issuer_name: challenge.issuer_name(),
+            origin_info: challenge.origin_info(),
+        }
+    }
+}
+
+/// Minimal trait for a client-side store of unspent tokens. Note that the
+/// store requires inner mutability.
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+pub trait TokenStore<Nk: ArrayLength<u8>>: MaybeSendSync {
+    /// Stores tokens that were issued for `challenge`.
+    async fn insert(&self, challenge: &TokenChallenge, tokens: Vec<Token<Nk>>);
+    /// Removes and returns a token that answers `challenge`, if there is
+    /// one.
+    async fn take(&self, challenge: &TokenChallenge) -> Option<Token<Nk>>;
+    /// Returns the number of tokens that answer `challenge`, e.g. to decide
+    /// when to fetch more.
+    async fn count(&self, challenge: &TokenChallenge) -> usize;
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<Nk: ArrayLength<u8>, S: TokenStore<Nk> + ?Sized> TokenStore<Nk> for Box<S> {
+    async fn insert(&self, challenge: &TokenChallenge, tokens: Vec<Token<Nk>>) {
+        (**self).insert(challenge, tokens).await
+    }
+
+    async fn take(&self, challenge: &TokenChallenge) -> Option<Token<Nk>> {
+        (**self).take(challenge).await
+    }
+
+    async fn count(&self, challenge: &TokenChallenge) -> usize {
+        (**self).count(challenge).await
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<Nk: ArrayLength<u8>, S: TokenStore<Nk> + ?Sized> TokenStore<Nk> for Arc<S> {
+    async fn insert(&self, challenge: &TokenChallenge, tokens: Vec<Token<Nk>>) {
+        (**self).insert(challenge, tokens).await
+    }
+
+    async fn take(&self, challenge: &TokenChallenge) -> Option<Token<Nk>> {
+        (**self).take(challenge).await
+    }
+
+    async fn count(&self, challenge: &TokenChallenge) -> usize {
+        (**self).count(challenge).await
+    }
+}
+
+/// Token store that keeps the tokens in memory.
+pub struct MemoryTokenStore<Nk: ArrayLength<u8>> {
+    tokens: Mutex<HashMap<TokenStoreKey, Vec<Token<Nk>>>>,
+}
+
+impl<Nk: ArrayLength<u8>> Default for MemoryTokenStore<Nk> {
+    fn default() -> Self {
+        Self {
+            tokens: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<Nk: ArrayLength<u8>> fmt::Debug for MemoryTokenStore<Nk> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryTokenStore")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl<Nk: ArrayLength<u8>> MemoryTokenStore<Nk> {
+    /// Creates an empty token store.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of stored tokens.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.tokens
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .map(Vec::len)
+            .sum()
+    }
+
+    /// Returns `true` if the store holds no tokens.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<Nk: ArrayLength<u8>> TokenStore<Nk> for MemoryTokenStore<Nk> {
+    async fn insert(&self, challenge: &TokenChallenge, tokens: Vec<Token<Nk>>) {
+        self.tokens
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(TokenStoreKey::from_challenge(challenge))
+            .or_default()
+            .extend(tokens);
+    }
+
+    async fn take(&self, challenge: &TokenChallenge) -> Option<Token<Nk>> {
+        let digest = challenge.digest().ok()?;
+        let key = TokenStoreKey::from_challenge(challenge);
+        let mut tokens = self.tokens.lock().unwrap_or_else(PoisonError::into_inner);
+        let entry = tokens.get_mut(&key)?;
+        let index = entry
+            .iter()
+            .position(|token| *token.challenge_digest() == digest)?;
+        let token = entry.swap_remove(index);
+        if entry.is_empty() {
+            tokens.remove(&key);
+        }
+        Some(token)
+    }
+
+    async fn count(&self, challenge: &TokenChallenge) -> usize {
+        let Ok(digest) = challenge.digest() else {
+            return 0;
+        };
+        self.tokens
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&TokenStoreKey::from_challenge(challenge))
+            .map_or(0, |tokens| {
+                tokens
+                    .iter()
+                    .filter(|token| *token.challenge_digest() == digest)
+                    .count()
+            })
+    }
+}
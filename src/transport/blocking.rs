@@ -0,0 +1,112 @@
+//! Blocking HTTP transport based on `ureq`.
+
+use std::io::Read;
+
+use async_trait::async_trait;
+
+use crate::webhooks::{WebhookTransport, EVENT_MEDIA_TYPE, SIGNATURE_HEADER};
+
+use super::{
+    FetchedDocument, IssuanceTransport, TransportError, MAX_RESPONSE_LENGTH,
+    TOKEN_REQUEST_MEDIA_TYPE, TOKEN_RESPONSE_MEDIA_TYPE,
+};
+
+/// Blocking HTTP transport.
+///
+/// The [`IssuanceTransport`] methods perform the request on the calling
+/// thread and only return once the response is complete. Applications
+/// without an async runtime can drive them with
+/// `futures::executor::block_on`. Async applications should use a
+/// non-blocking transport instead, or call this one from a thread that is
+/// allowed to block.
+#[derive(Clone, Debug)]
+pub struct UreqTransport {
+    agent: ureq::Agent,
+}
+
+impl Default for UreqTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UreqTransport {
+    /// Creates a new transport with a default agent.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_agent(ureq::Agent::new())
+    }
+
+    /// Creates a new transport from a configured agent, e.g. one with
+    /// timeouts or a proxy.
+    #[must_use]
+    pub const fn with_agent(agent: ureq::Agent) -> Self {
+        Self { agent }
+    }
+
+    fn body(response: Result<ureq::Response, ureq::Error>) -> Result<Vec<u8>, TransportError> {
+        let response = response.map_err(|error| match error {
+            ureq::Error::Status(status, _) => TransportError::Status(status),
+            ureq::Error::Transport(_) => TransportError::Unreachable,
+        })?;
+        let mut body = Vec::new();
+        response
+            .into_reader()
+            .take(MAX_RESPONSE_LENGTH)
+            .read_to_end(&mut body)
+            .map_err(|_| TransportError::InvalidResponse)?;
+        Ok(body)
+    }
+}
+
+#[cfg_attr(not(all(target_arch = "wasm32", target_os = "unknown")), async_trait)]
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), async_trait(?Send))]
+impl IssuanceTransport for UreqTransport {
+    async fn send_token_request(
+        &self,
+        issuer_request_uri: &str,
+        token_request: &[u8],
+    ) -> Result<Vec<u8>, TransportError> {
+        Self::body(
+            self.agent
+                .post(issuer_request_uri)
+                .set("Content-Type", TOKEN_REQUEST_MEDIA_TYPE)
+                .set("Accept", TOKEN_RESPONSE_MEDIA_TYPE)
+                .send_bytes(token_request),
+        )
+    }
+
+    async fn fetch(&self, uri: &str) -> Result<Vec<u8>, TransportError> {
+        Self::body(self.agent.get(uri).call())
+    }
+
+    async fn fetch_document(&self, uri: &str) -> Result<FetchedDocument, TransportError> {
+        let response = self.agent.get(uri).call();
+        let cache_control = response
+            .as_ref()
+            .ok()
+            .and_then(|response| response.header("Cache-Control"))
+            .map(str::to_string);
+        Ok(FetchedDocument::new(Self::body(response)?, cache_control))
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl WebhookTransport for UreqTransport {
+    async fn post_event(
+        &self,
+        url: &str,
+        signature: &str,
+        body: &[u8],
+    ) -> Result<(), TransportError> {
+        Self::body(
+            self.agent
+                .post(url)
+                .set("Content-Type", EVENT_MEDIA_TYPE)
+                .set(SIGNATURE_HEADER, signature)
+                .send_bytes(body),
+        )
+        .map(|_| ())
+    }
+}
@@ -0,0 +1,271 @@
+//! Caching client for the issuer directory.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{
+    auth::authenticate::Challenge,
+    clock::{Clock, SystemClock},
+    issuer_directory::{DirectoryError, TokenKey, TokenKeyDirectory},
+    TokenKeyId, TokenType,
+};
+
+use super::{directory_uri, IssuanceTransport, TransportError};
+
+/// Default of [`DirectoryClient::with_min_refresh_interval`], in seconds.
+const DEFAULT_MIN_REFRESH_INTERVAL: u64 = 10;
+
+/// Errors that can occur when checking a token key with a
+/// [`DirectoryClient`].
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DirectoryClientError {
+    #[error(transparent)]
+    /// The directory could not be fetched or parsed.
+    Transport(#[from] TransportError),
+    #[error(transparent)]
+    /// The directory does not list a matching key that is valid now.
+    Directory(#[from] DirectoryError),
+}
+
+/// A parsed directory, the time until which it may be reused and the time
+/// it was last fetched, or last claimed for a forced refresh, in seconds
+/// since the Unix epoch.
+struct CachedDirectory {
+    directory: Arc<TokenKeyDirectory>,
+    expires_at: u64,
+    refreshed_at: u64,
+}
+
+/// Client that fetches the issuer directory of one issuer and caches it.
+///
+/// The directory is reused for as long as the `max-age` directive of its
+/// `Cache-Control` header allows. Directories served with `no-store` or
+/// `no-cache` are not reused. Directories served without a `max-age` are
+/// reused for the default max age, which is zero unless set with
+/// [`with_default_max_age`](Self::with_default_max_age).
+///
+/// When a cached directory does not list a key, e.g. because the issuer
+/// rotated its keys since the directory was fetched, the directory is
+/// fetched again before the key is rejected. Such forced refreshes happen at
+/// most once per [minimum refresh
+/// interval](Self::with_min_refresh_interval), so that clients presenting
+/// unknown key IDs cannot make the client fetch the directory on every
+/// request.
+pub struct DirectoryClient<T, C = SystemClock> {
+    transport: T,
+    directory_uri: String,
+    clock: C,
+    default_max_age: u64,
+    min_refresh_interval: u64,
+    cached: Mutex<Option<CachedDirectory>>,
+}
+
+impl<T, C> fmt::Debug for DirectoryClient<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DirectoryClient")
+            .field("directory_uri", &self.directory_uri)
+            .field("default_max_age", &self.default_max_age)
+            .field("min_refresh_interval", &self.min_refresh_interval)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: IssuanceTransport> DirectoryClient<T> {
+    /// Creates a client for the directory of an issuer origin such as
+    /// `https://issuer.example.net`, fetched with `transport`.
+    #[must_use]
+    pub fn new(transport: T, issuer_origin: &str) -> Self {
+        Self::new_with_clock(transport, issuer_origin, SystemClock)
+    }
+}
+
+impl<T: IssuanceTransport, C: Clock> DirectoryClient<T, C> {
+    /// Creates a client whose cached directory expires as measured by
+    /// `clock`, and whose keys are checked against the time of `clock`.
+    #[must_use]
+    pub fn new_with_clock(transport: T, issuer_origin: &str, clock: C) -> Self {
+        Self {
+            transport,
+            directory_uri: directory_uri(issuer_origin),
+            clock,
+            default_max_age: 0,
+            min_refresh_interval: DEFAULT_MIN_REFRESH_INTERVAL,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Sets for how long a directory that was served without a `max-age`
+    /// directive is reused.
+    #[must_use]
+    pub fn with_default_max_age(mut self, default_max_age: Duration) -> Self {
+        self.default_max_age = default_max_age.as_secs();
+        self
+    }
+
+    /// Sets how old a cached directory has to be before a key it does not
+    /// list makes the client fetch it again. Defaults to 10 seconds; unknown
+    /// keys are rejected from the cached directory until then.
+    #[must_use]
+    pub fn with_min_refresh_interval(mut self, min_refresh_interval: Duration) -> Self {
+        self.min_refresh_interval = min_refresh_interval.as_secs();
+        self
+    }
+
+    /// Returns the directory, from the cache if it is still fresh.
+    ///
+    /// # Errors
+    /// Returns an error if the directory has to be fetched and the transport
+    /// fails or the directory cannot be parsed.
+    pub async fn directory(&self) -> Result<Arc<TokenKeyDirectory>, TransportError> {
+        match self.cached_directory(self.clock.unix_time()) {
+            Some(directory) => Ok(directory),
+            None => self.refresh().await,
+        }
+    }
+
+    /// Fetches the directory and replaces the cached one.
+    ///
+    /// # Errors
+    /// Returns an error if the transport fails or the directory cannot be
+    /// parsed. The cached directory is kept in that case.
+    pub async fn refresh(&self) -> Result<Arc<TokenKeyDirectory>, TransportError> {
+        let document = self.transport.fetch_document(&self.directory_uri).await?;
+        let directory: TokenKeyDirectory =
+            serde_json::from_slice(document.body()).map_err(|_| TransportError::InvalidResponse)?;
+        let directory = Arc::new(directory);
+        let max_age = document
+            .cache_control()
+            .and_then(max_age)
+            .unwrap_or(self.default_max_age);
+        let now = self.clock.unix_time();
+        *self.lock() = Some(CachedDirectory {
+            directory: directory.clone(),
+            expires_at: now.saturating_add(max_age),
+            refreshed_at: now,
+        });
+        Ok(directory)
+    }
+
+    /// Drops the cached directory, so that the next call fetches it again.
+    pub fn invalidate(&self) {
+        *self.lock() = None;
+    }
+
+    /// Checks that the directory lists a key with the given token type and
+    /// token key ID that is valid now, see
+    /// [`TokenKeyDirectory::check_key_at`]. If a cached directory that is at
+    /// least as old as the minimum refresh interval does not list the key,
+    /// the directory is fetched again and checked once more.
+    ///
+    /// # Errors
+    /// Returns an error if the directory cannot be fetched, no entry matches
+    /// or the matching entry is not valid yet.
+    pub async fn check_key(
+        &self,
+        token_type: TokenType,
+        token_key_id: &TokenKeyId,
+    ) -> Result<TokenKey, DirectoryClientError> {
+        let now = self.clock.unix_time();
+        let (directory, cached) = match self.cached_directory(now) {
+            Some(directory) => (directory, true),
+            None => (self.refresh().await?, false),
+        };
+        match directory.check_key_at(token_type, token_key_id, now) {
+            Err(DirectoryError::KeyNotFound) if cached && self.claim_forced_refresh(now) => {
+                let directory = self.refresh().await?;
+                let token_key =
+                    directory.check_key_at(token_type, token_key_id, self.clock.unix_time())?;
+                Ok(token_key.clone())
+            }
+            result => Ok(result?.clone()),
+        }
+    }
+
+    /// Checks that the directory lists the token key of a challenge, see
+    /// [`check_key`](Self::check_key).
+    ///
+    /// # Errors
+    /// Returns an error if the directory cannot be fetched, no entry matches
+    /// or the matching entry is not valid yet.
+    pub async fn check_challenge(
+        &self,
+        challenge: &Challenge,
+    ) -> Result<TokenKey, DirectoryClientError> {
+        let token_key_id: TokenKeyId = Sha256::digest(challenge.token_key()).into();
+        self.check_key(challenge.token_challenge().token_type(), &token_key_id)
+            .await
+    }
+
+    fn cached_directory(&self, now: u64) -> Option<Arc<TokenKeyDirectory>> {
+        self.lock()
+            .as_ref()
+            .filter(|cached| cached.expires_at > now)
+            .map(|cached| cached.directory.clone())
+    }
+
+    /// Returns `true` if the cached directory is old enough to be fetched
+    /// again for an unknown key, and marks it as refreshed now, so that
+    /// concurrent requests for unknown keys do not all fetch it.
+    fn claim_forced_refresh(&self, now: u64) -> bool {
+        let mut cached = self.lock();
+        let Some(cached) = cached.as_mut() else {
+            return false;
+        };
+        if now
+            < cached
+                .refreshed_at
+                .saturating_add(self.min_refresh_interval)
+        {
+            return false;
+        }
+        cached.refreshed_at = now;
+        true
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<CachedDirectory>> {
+        self.cached.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Returns for how many seconds a response with the `Cache-Control` header
+/// `cache_control` may be reused, or `None` if the header does not say.
+/// Responses with an invalid `max-age` are treated as stale.
+fn max_age(cache_control: &str) -> Option<u64> {
+    let mut max_age = None;
+    for directive in cache_control.split(',') {
+        let (name, value) = directive
+            .split_once('=')
+            .map_or((directive, None), |(name, value)| (name, Some(value)));
+        let name = name.trim();
+        if name.eq_ignore_ascii_case("no-store") || name.eq_ignore_ascii_case("no-cache") {
+            return Some(0);
+        }
+        if name.eq_ignore_ascii_case("max-age") && max_age.is_none() {
+            max_age = Some(
+                value
+                    .and_then(|value| value.trim().trim_matches('"').parse().ok())
+                    .unwrap_or(0),
+            );
+        }
+    }
+    max_age
+}
+
+#[test]
+fn cache_control_max_age() {
+    assert_eq!(max_age("max-age=3600"), Some(3600));
+    assert_eq!(max_age("public, Max-Age=\"60\", must-revalidate"), Some(60));
+    assert_eq!(max_age("max-age=60, max-age=120"), Some(60));
+    assert_eq!(max_age("max-age=soon"), Some(0));
+    assert_eq!(max_age("max-age=60, no-store"), Some(0));
+    assert_eq!(max_age("no-cache"), Some(0));
+    assert_eq!(max_age("public"), None);
+    assert_eq!(max_age(""), None);
+}
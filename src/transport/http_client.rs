@@ -0,0 +1,177 @@
+//! Async HTTP transport based on `reqwest`.
+
+use async_trait::async_trait;
+use reqwest::header::{ACCEPT, CACHE_CONTROL, CONTENT_TYPE};
+
+use crate::{
+    issuer_directory::TokenKeyDirectory,
+    webhooks::{WebhookTransport, EVENT_MEDIA_TYPE, SIGNATURE_HEADER},
+    Deserialize, Serialize,
+};
+
+use super::{
+    fetch_directory, send_token_request, FetchedDocument, IssuanceTransport, TransportError,
+    MAX_RESPONSE_LENGTH, TOKEN_REQUEST_MEDIA_TYPE, TOKEN_RESPONSE_MEDIA_TYPE,
+};
+
+/// Non-blocking HTTP transport.
+///
+/// Besides the [`IssuanceTransport`] methods, it offers one method per step
+/// of the issuance round trip: [`fetch_issuer_directory`] and
+/// [`request_tokens`]. Token responses are only accepted with the
+/// [`TOKEN_RESPONSE_MEDIA_TYPE`] content type.
+///
+/// [`fetch_issuer_directory`]: ReqwestTransport::fetch_issuer_directory
+/// [`request_tokens`]: ReqwestTransport::request_tokens
+#[derive(Clone, Debug, Default)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+impl ReqwestTransport {
+    /// Creates a new transport with a default client.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new transport from a configured client, e.g. one with
+    /// timeouts or a proxy.
+    #[must_use]
+    pub const fn with_client(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+
+    /// Fetches and parses the issuer directory of an issuer origin such as
+    /// `https://issuer.example.net`, see [`fetch_directory`].
+    ///
+    /// # Errors
+    /// Returns an error if the issuer cannot be reached or the directory
+    /// cannot be parsed.
+    pub async fn fetch_issuer_directory(
+        &self,
+        issuer_origin: &str,
+    ) -> Result<TokenKeyDirectory, TransportError> {
+        fetch_directory(self, issuer_origin).await
+    }
+
+    /// Sends a token request to the issuer request URI of the directory and
+    /// deserializes the token response, see [`send_token_request`].
+    ///
+    /// # Errors
+    /// Returns an error if the request cannot be serialized, the issuer
+    /// cannot be reached or the response cannot be deserialized.
+    pub async fn request_tokens<Request, Response>(
+        &self,
+        issuer_request_uri: &str,
+        token_request: &Request,
+    ) -> Result<Response, TransportError>
+    where
+        Request: Serialize + Sync,
+        Response: Deserialize,
+    {
+        send_token_request(self, issuer_request_uri, token_request).await
+    }
+
+    /// Reads the body of a successful response. If `media_type` is given,
+    /// the response must have that content type.
+    async fn body(
+        response: Result<reqwest::Response, reqwest::Error>,
+        media_type: Option<&str>,
+    ) -> Result<Vec<u8>, TransportError> {
+        let mut response = response.map_err(|_| TransportError::Unreachable)?;
+        if !response.status().is_success() {
+            return Err(TransportError::Status(response.status().as_u16()));
+        }
+        if let Some(media_type) = media_type {
+            let content_type = response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split(';').next())
+                .map(str::trim);
+            if !content_type
+                .is_some_and(|content_type| content_type.eq_ignore_ascii_case(media_type))
+            {
+                return Err(TransportError::InvalidResponse);
+            }
+        }
+        let mut body = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|_| TransportError::InvalidResponse)?
+        {
+            let length = u64::try_from(body.len() + chunk.len()).unwrap_or(u64::MAX);
+            if length > MAX_RESPONSE_LENGTH {
+                return Err(TransportError::InvalidResponse);
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+}
+
+#[cfg_attr(not(all(target_arch = "wasm32", target_os = "unknown")), async_trait)]
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), async_trait(?Send))]
+impl IssuanceTransport for ReqwestTransport {
+    async fn send_token_request(
+        &self,
+        issuer_request_uri: &str,
+        token_request: &[u8],
+    ) -> Result<Vec<u8>, TransportError> {
+        Self::body(
+            self.client
+                .post(issuer_request_uri)
+                .header(CONTENT_TYPE, TOKEN_REQUEST_MEDIA_TYPE)
+                .header(ACCEPT, TOKEN_RESPONSE_MEDIA_TYPE)
+                .body(token_request.to_vec())
+                .send()
+                .await,
+            Some(TOKEN_RESPONSE_MEDIA_TYPE),
+        )
+        .await
+    }
+
+    async fn fetch(&self, uri: &str) -> Result<Vec<u8>, TransportError> {
+        Self::body(self.client.get(uri).send().await, None).await
+    }
+
+    async fn fetch_document(&self, uri: &str) -> Result<FetchedDocument, TransportError> {
+        let response = self.client.get(uri).send().await;
+        let cache_control = response
+            .as_ref()
+            .ok()
+            .and_then(|response| response.headers().get(CACHE_CONTROL))
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        Ok(FetchedDocument::new(
+            Self::body(response, None).await?,
+            cache_control,
+        ))
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl WebhookTransport for ReqwestTransport {
+    async fn post_event(
+        &self,
+        url: &str,
+        signature: &str,
+        body: &[u8],
+    ) -> Result<(), TransportError> {
+        Self::body(
+            self.client
+                .post(url)
+                .header(CONTENT_TYPE, EVENT_MEDIA_TYPE)
+                .header(SIGNATURE_HEADER, signature)
+                .body(body.to_vec())
+                .send()
+                .await,
+            None,
+        )
+        .await
+        .map(|_| ())
+    }
+}
@@ -0,0 +1,125 @@
+//! Transport for testing client integrations without network access.
+
+use std::collections::HashMap;
+#[cfg(feature = "send")]
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+#[cfg(feature = "send")]
+use crate::dispatch::{DispatchError, MultiTypeServer};
+use crate::issuer_directory::TokenKeyDirectory;
+
+use super::{directory_uri, FetchedDocument, IssuanceTransport, TransportError};
+
+/// Transport that serves canned documents and token responses, or routes
+/// token requests to an in-process `MultiTypeServer`. Requests to unknown
+/// URIs fail with status 404.
+///
+/// Routing to a server requires the `send` feature, since the transport
+/// futures must be `Send`.
+#[derive(Clone, Debug, Default)]
+pub struct MockTransport {
+    documents: HashMap<String, Vec<u8>>,
+    cache_controls: HashMap<String, String>,
+    token_responses: HashMap<String, Vec<u8>>,
+    #[cfg(feature = "send")]
+    servers: HashMap<String, Arc<MultiTypeServer>>,
+}
+
+impl MockTransport {
+    /// Creates a new transport that does not serve anything yet.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves `document` at `uri`.
+    #[must_use]
+    pub fn with_document(mut self, uri: &str, document: Vec<u8>) -> Self {
+        self.documents.insert(uri.to_string(), document);
+        self
+    }
+
+    /// Serves the document at `uri` with the `Cache-Control` header
+    /// `cache_control`.
+    #[must_use]
+    pub fn with_cache_control(mut self, uri: &str, cache_control: &str) -> Self {
+        self.cache_controls
+            .insert(uri.to_string(), cache_control.to_string());
+        self
+    }
+
+    /// Serves `directory` at the well-known directory URI of `issuer_origin`.
+    #[must_use]
+    pub fn with_directory(self, issuer_origin: &str, directory: &TokenKeyDirectory) -> Self {
+        // Serializing a directory only involves strings and integers and
+        // cannot fail.
+        let document = serde_json::to_vec(directory).unwrap_or_default();
+        self.with_document(&directory_uri(issuer_origin), document)
+    }
+
+    /// Answers every token request sent to `issuer_request_uri` with the
+    /// canned `token_response`.
+    #[must_use]
+    pub fn with_token_response(
+        mut self,
+        issuer_request_uri: &str,
+        token_response: Vec<u8>,
+    ) -> Self {
+        self.token_responses
+            .insert(issuer_request_uri.to_string(), token_response);
+        self
+    }
+
+    /// Routes token requests sent to `issuer_request_uri` to `server`.
+    /// Canned token responses for the same URI take precedence.
+    #[cfg(feature = "send")]
+    #[must_use]
+    pub fn with_server(mut self, issuer_request_uri: &str, server: Arc<MultiTypeServer>) -> Self {
+        self.servers.insert(issuer_request_uri.to_string(), server);
+        self
+    }
+}
+
+#[cfg_attr(not(all(target_arch = "wasm32", target_os = "unknown")), async_trait)]
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), async_trait(?Send))]
+impl IssuanceTransport for MockTransport {
+    async fn send_token_request(
+        &self,
+        issuer_request_uri: &str,
+        token_request: &[u8],
+    ) -> Result<Vec<u8>, TransportError> {
+        if let Some(token_response) = self.token_responses.get(issuer_request_uri) {
+            return Ok(token_response.clone());
+        }
+        #[cfg(feature = "send")]
+        if let Some(server) = self.servers.get(issuer_request_uri) {
+            return server
+                .issue_token_response(token_request)
+                .await
+                .map_err(|error| match error {
+                    DispatchError::UnsupportedTokenType(_) => TransportError::Status(415),
+                    DispatchError::KeyStore(_) => TransportError::Status(503),
+                    _ => TransportError::Status(400),
+                });
+        }
+        #[cfg(not(feature = "send"))]
+        let _ = token_request;
+        Err(TransportError::Status(404))
+    }
+
+    async fn fetch(&self, uri: &str) -> Result<Vec<u8>, TransportError> {
+        self.documents
+            .get(uri)
+            .cloned()
+            .ok_or(TransportError::Status(404))
+    }
+
+    async fn fetch_document(&self, uri: &str) -> Result<FetchedDocument, TransportError> {
+        Ok(FetchedDocument::new(
+            self.fetch(uri).await?,
+            self.cache_controls.get(uri).cloned(),
+        ))
+    }
+}
@@ -0,0 +1,185 @@
+//! # Issuance transport
+//!
+//! The protocol clients only produce and consume messages. An
+//! [`IssuanceTransport`] moves those messages between the client and the
+//! issuer, which keeps the crate independent of any particular HTTP client
+//! and lets applications route issuance through proxies, OHTTP relays or
+//! whatever their environment offers.
+//!
+//! With the `ureq` feature, [`UreqTransport`] provides a blocking HTTP
+//! transport for command line tools and applications without an async
+//! runtime. With the `reqwest` feature, [`ReqwestTransport`] provides a
+//! non-blocking HTTP transport for async applications. [`MockTransport`] serves canned responses or an in-process
+//! server, so that applications can test their client integration offline.
+//! [`RecordingTransport`] records real exchanges to fixture files that
+//! [`ReplayTransport`] replays in deterministic regression tests.
+//!
+//! A [`DirectoryClient`] caches the issuer directory for as long as the
+//! issuer's `Cache-Control` header allows and re-fetches it, at a limited
+//! rate, when a challenge names a key the cached copy does not list yet. A
+//! [`MultiIssuerClient`] splits a token count across several equivalent
+//! issuers and keeps their tokens apart in a token store.
+
+#[cfg(feature = "ureq")]
+mod blocking;
+mod directory;
+#[cfg(feature = "reqwest")]
+mod http_client;
+mod mock;
+mod record;
+mod sourcing;
+
+use async_trait::async_trait;
+use thiserror::Error;
+
+use crate::{
+    issuer_directory::{TokenKeyDirectory, WELL_KNOWN_PATH},
+    Deserialize, Serialize,
+};
+
+#[cfg(feature = "ureq")]
+pub use blocking::UreqTransport;
+pub use directory::{DirectoryClient, DirectoryClientError};
+#[cfg(feature = "reqwest")]
+pub use http_client::ReqwestTransport;
+pub use mock::MockTransport;
+pub use record::{RecordingTransport, ReplayTransport};
+pub use sourcing::{IssuerSource, MultiIssuerClient, SourcingError, SourcingReport};
+
+/// Media type of serialized token requests.
+pub const TOKEN_REQUEST_MEDIA_TYPE: &str = "application/private-token-request";
+
+/// Media type of serialized token responses.
+pub const TOKEN_RESPONSE_MEDIA_TYPE: &str = "application/private-token-response";
+
+/// Upper bound for the size of a response body. A batched token response
+/// with the maximum number of P-384 elements stays well below it.
+#[cfg(any(feature = "ureq", feature = "reqwest"))]
+const MAX_RESPONSE_LENGTH: u64 = 8 * 1024 * 1024;
+
+/// Errors that can occur when exchanging messages with an issuer.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TransportError {
+    #[error("Issuer unreachable")]
+    /// The issuer could not be reached.
+    Unreachable,
+    #[error("Issuer responded with status {0}")]
+    /// The issuer responded with an unsuccessful status.
+    Status(u16),
+    #[error("Invalid request")]
+    /// The request could not be serialized.
+    InvalidRequest,
+    #[error("Invalid response")]
+    /// The response could not be deserialized.
+    InvalidResponse,
+}
+
+/// A fetched document and the caching directives it was served with.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FetchedDocument {
+    body: Vec<u8>,
+    cache_control: Option<String>,
+}
+
+impl FetchedDocument {
+    /// Creates a document from a response body and the value of its
+    /// `Cache-Control` header, if any.
+    #[must_use]
+    pub const fn new(body: Vec<u8>, cache_control: Option<String>) -> Self {
+        Self {
+            body,
+            cache_control,
+        }
+    }
+
+    /// Returns the response body.
+    #[must_use]
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Returns the value of the `Cache-Control` header, if any.
+    #[must_use]
+    pub fn cache_control(&self) -> Option<&str> {
+        self.cache_control.as_deref()
+    }
+
+    /// Returns the response body.
+    #[must_use]
+    pub fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+}
+
+/// Moves serialized messages between a client and an issuer.
+///
+/// In browsers, i.e. on `wasm32-unknown-unknown`, the returned futures are
+/// not required to be `Send`, so that transports can await JavaScript
+/// promises.
+#[cfg_attr(not(all(target_arch = "wasm32", target_os = "unknown")), async_trait)]
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), async_trait(?Send))]
+pub trait IssuanceTransport: Send + Sync {
+    /// Sends a serialized token request to `issuer_request_uri` with the
+    /// [`TOKEN_REQUEST_MEDIA_TYPE`] content type and returns the body of the
+    /// response.
+    async fn send_token_request(
+        &self,
+        issuer_request_uri: &str,
+        token_request: &[u8],
+    ) -> Result<Vec<u8>, TransportError>;
+
+    /// Fetches the document at `uri`, e.g. the issuer directory.
+    async fn fetch(&self, uri: &str) -> Result<Vec<u8>, TransportError>;
+
+    /// Fetches the document at `uri` together with its `Cache-Control`
+    /// header. The default implementation calls [`fetch`](Self::fetch) and
+    /// reports no caching directives.
+    async fn fetch_document(&self, uri: &str) -> Result<FetchedDocument, TransportError> {
+        Ok(FetchedDocument::new(self.fetch(uri).await?, None))
+    }
+}
+
+/// Returns the URI of the issuer directory of an issuer origin such as
+/// `https://issuer.example.net`.
+#[must_use]
+pub fn directory_uri(issuer_origin: &str) -> String {
+    format!("{}{WELL_KNOWN_PATH}", issuer_origin.trim_end_matches('/'))
+}
+
+/// Fetches and parses the issuer directory of an issuer origin.
+///
+/// # Errors
+/// Returns an error if the transport fails or the directory cannot be parsed.
+pub async fn fetch_directory<T: IssuanceTransport + ?Sized>(
+    transport: &T,
+    issuer_origin: &str,
+) -> Result<TokenKeyDirectory, TransportError> {
+    let directory = transport.fetch(&directory_uri(issuer_origin)).await?;
+    serde_json::from_slice(&directory).map_err(|_| TransportError::InvalidResponse)
+}
+
+/// Sends a token request to the issuer and deserializes the token response.
+///
+/// # Errors
+/// Returns an error if the request cannot be serialized, the transport fails
+/// or the response cannot be deserialized.
+pub async fn send_token_request<T, Request, Response>(
+    transport: &T,
+    issuer_request_uri: &str,
+    token_request: &Request,
+) -> Result<Response, TransportError>
+where
+    T: IssuanceTransport + ?Sized,
+    Request: Serialize + Sync,
+    Response: Deserialize,
+{
+    let token_request = token_request
+        .tls_serialize_detached()
+        .map_err(|_| TransportError::InvalidRequest)?;
+    let token_response = transport
+        .send_token_request(issuer_request_uri, &token_request)
+        .await?;
+    Response::tls_deserialize(&mut token_response.as_slice())
+        .map_err(|_| TransportError::InvalidResponse)
+}
@@ -0,0 +1,209 @@
+//! Transports that record exchanges to fixture files and replay them.
+
+use std::{
+    collections::VecDeque,
+    fs, io,
+    path::Path,
+    sync::{Mutex, PoisonError},
+};
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::{Deserialize, Serialize};
+
+use super::{FetchedDocument, IssuanceTransport, TransportError};
+
+/// Kind of a recorded exchange.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ExchangeKind {
+    TokenRequest,
+    Fetch,
+}
+
+/// A recorded exchange. Messages are base64 encoded.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Exchange {
+    kind: ExchangeKind,
+    uri: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    request: String,
+    response: String,
+}
+
+/// Decorator that records the successful exchanges of another transport, so
+/// that they can be saved to a fixture file and replayed with
+/// [`ReplayTransport`]. Failed exchanges are passed through without being
+/// recorded.
+#[derive(Debug)]
+pub struct RecordingTransport<T> {
+    inner: T,
+    exchanges: Mutex<Vec<Exchange>>,
+}
+
+impl<T: IssuanceTransport> RecordingTransport<T> {
+    /// Creates a new recorder around `inner`.
+    #[must_use]
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            exchanges: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the recorded exchanges as a JSON fixture.
+    #[must_use]
+    pub fn fixture(&self) -> String {
+        let exchanges = self
+            .exchanges
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        // Exchanges only consist of strings and cannot fail to serialize.
+        serde_json::to_string_pretty(&*exchanges).unwrap_or_default()
+    }
+
+    /// Writes the recorded exchanges to a fixture file.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.fixture())
+    }
+
+    /// Returns the inner transport.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn record(&self, exchange: Exchange) {
+        self.exchanges
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(exchange);
+    }
+}
+
+#[cfg_attr(not(all(target_arch = "wasm32", target_os = "unknown")), async_trait)]
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), async_trait(?Send))]
+impl<T: IssuanceTransport> IssuanceTransport for RecordingTransport<T> {
+    async fn send_token_request(
+        &self,
+        issuer_request_uri: &str,
+        token_request: &[u8],
+    ) -> Result<Vec<u8>, TransportError> {
+        let token_response = self
+            .inner
+            .send_token_request(issuer_request_uri, token_request)
+            .await?;
+        self.record(Exchange {
+            kind: ExchangeKind::TokenRequest,
+            uri: issuer_request_uri.to_string(),
+            request: STANDARD.encode(token_request),
+            response: STANDARD.encode(&token_response),
+        });
+        Ok(token_response)
+    }
+
+    async fn fetch(&self, uri: &str) -> Result<Vec<u8>, TransportError> {
+        let document = self.inner.fetch(uri).await?;
+        self.record(Exchange {
+            kind: ExchangeKind::Fetch,
+            uri: uri.to_string(),
+            request: String::new(),
+            response: STANDARD.encode(&document),
+        });
+        Ok(document)
+    }
+
+    async fn fetch_document(&self, uri: &str) -> Result<FetchedDocument, TransportError> {
+        let document = self.inner.fetch_document(uri).await?;
+        self.record(Exchange {
+            kind: ExchangeKind::Fetch,
+            uri: uri.to_string(),
+            request: String::new(),
+            response: STANDARD.encode(document.body()),
+        });
+        Ok(document)
+    }
+}
+
+/// Transport that replays exchanges from a fixture written by
+/// [`RecordingTransport`].
+///
+/// Each recorded exchange is replayed once, in recording order per URI.
+/// Token requests are matched by URI only, because their content depends on
+/// fresh blinds. The replayed token responses therefore only finalize if the
+/// client reproduces the recorded blinds, e.g. with the
+/// `issue_token_request_with_params` constructors of the `kat` feature.
+/// Requests without a matching recorded exchange fail with
+/// [`TransportError::Unreachable`].
+/// Documents are replayed without their `Cache-Control` header.
+#[derive(Debug)]
+pub struct ReplayTransport {
+    exchanges: Mutex<VecDeque<Exchange>>,
+}
+
+impl ReplayTransport {
+    /// Creates a new transport from a JSON fixture.
+    ///
+    /// # Errors
+    /// Returns an error if the fixture is malformed.
+    pub fn from_fixture(fixture: &str) -> io::Result<Self> {
+        let exchanges = serde_json::from_str::<VecDeque<Exchange>>(fixture)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        Ok(Self {
+            exchanges: Mutex::new(exchanges),
+        })
+    }
+
+    /// Creates a new transport from a fixture file.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or is malformed.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_fixture(&fs::read_to_string(path)?)
+    }
+
+    /// Returns the number of exchanges that have not been replayed yet.
+    #[must_use]
+    pub fn remaining(&self) -> usize {
+        self.exchanges
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    fn replay(&self, kind: ExchangeKind, uri: &str) -> Result<Vec<u8>, TransportError> {
+        let mut exchanges = self
+            .exchanges
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let position = exchanges
+            .iter()
+            .position(|exchange| exchange.kind == kind && exchange.uri == uri)
+            .ok_or(TransportError::Unreachable)?;
+        let exchange = exchanges
+            .remove(position)
+            .ok_or(TransportError::Unreachable)?;
+        STANDARD
+            .decode(exchange.response)
+            .map_err(|_| TransportError::InvalidResponse)
+    }
+}
+
+#[cfg_attr(not(all(target_arch = "wasm32", target_os = "unknown")), async_trait)]
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), async_trait(?Send))]
+impl IssuanceTransport for ReplayTransport {
+    async fn send_token_request(
+        &self,
+        issuer_request_uri: &str,
+        _token_request: &[u8],
+    ) -> Result<Vec<u8>, TransportError> {
+        self.replay(ExchangeKind::TokenRequest, issuer_request_uri)
+    }
+
+    async fn fetch(&self, uri: &str) -> Result<Vec<u8>, TransportError> {
+        self.replay(ExchangeKind::Fetch, uri)
+    }
+}
@@ -0,0 +1,287 @@
+//! Sourcing tokens from several equivalent issuers.
+
+use std::{collections::HashMap, fmt};
+
+use futures::future::join_all;
+use generic_array::ArrayLength;
+use thiserror::Error;
+
+use crate::{
+    auth::{authenticate::TokenChallenge, authorize::Token},
+    dynamic::DynClient,
+    protocol::ProtocolError,
+    token_store::TokenStore,
+    TokenType,
+};
+
+use super::{IssuanceTransport, TransportError};
+
+/// Errors that can occur when sourcing tokens from several issuers.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SourcingError {
+    #[error("No challenge names a configured issuer")]
+    /// None of the challenges names an issuer of the client with its token
+    /// type.
+    NoMatchingIssuer,
+    #[error(transparent)]
+    /// The token request could not be created or the token response could
+    /// not be finalized.
+    Protocol(#[from] ProtocolError),
+    #[error(transparent)]
+    /// The issuer could not be reached or rejected the token request.
+    Transport(#[from] TransportError),
+    #[error("Invalid token")]
+    /// A finalized token does not match the authenticator size of the store.
+    InvalidToken,
+}
+
+/// An issuer that tokens can be sourced from.
+pub struct IssuerSource {
+    issuer_name: String,
+    issuer_request_uri: String,
+    client: Box<dyn DynClient>,
+}
+
+impl fmt::Debug for IssuerSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IssuerSource")
+            .field("issuer_name", &self.issuer_name)
+            .field("issuer_request_uri", &self.issuer_request_uri)
+            .field("token_type", &self.client.token_type())
+            .finish()
+    }
+}
+
+impl IssuerSource {
+    /// Creates a source for the issuer `issuer_name`, the issuer name of its
+    /// challenges, whose token requests are sent to `issuer_request_uri` and
+    /// created with `client`, e.g. one returned by
+    /// [`dyn_client`](crate::dynamic::dyn_client).
+    #[must_use]
+    pub fn new(issuer_name: &str, issuer_request_uri: &str, client: Box<dyn DynClient>) -> Self {
+        Self {
+            issuer_name: issuer_name.to_string(),
+            issuer_request_uri: issuer_request_uri.to_string(),
+            client,
+        }
+    }
+
+    /// Returns the issuer name.
+    #[must_use]
+    pub fn issuer_name(&self) -> &str {
+        &self.issuer_name
+    }
+
+    /// Returns the challenge of `challenges` that names this issuer and its
+    /// token type.
+    fn challenge<'a>(&self, challenges: &'a [TokenChallenge]) -> Option<&'a TokenChallenge> {
+        challenges.iter().find(|challenge| {
+            challenge.token_type() == self.client.token_type()
+                && challenge.issuer_name() == self.issuer_name
+        })
+    }
+}
+
+/// Outcome of sourcing tokens from several issuers.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SourcingReport {
+    issued: HashMap<String, usize>,
+    failures: Vec<(String, SourcingError)>,
+}
+
+impl SourcingReport {
+    /// Returns the number of tokens that were stored.
+    #[must_use]
+    pub fn issued(&self) -> usize {
+        self.issued.values().sum()
+    }
+
+    /// Returns the number of tokens that were stored from the issuer
+    /// `issuer_name`.
+    #[must_use]
+    pub fn issued_by(&self, issuer_name: &str) -> usize {
+        self.issued.get(issuer_name).copied().unwrap_or(0)
+    }
+
+    /// Returns the issuers that failed, together with their errors.
+    #[must_use]
+    pub fn failures(&self) -> &[(String, SourcingError)] {
+        &self.failures
+    }
+}
+
+/// Client that splits a token count across several equivalent issuers, i.e.
+/// issuers that the same origins trust, so that tokens can still be fetched
+/// when one of them is unavailable.
+///
+/// Tokens are bound to a challenge that names their issuer, so an origin
+/// that trusts several issuers sends one challenge per issuer. The tokens of
+/// each issuer are stored for its challenge, which keeps the tokens of
+/// different issuers apart in the [`TokenStore`], and the
+/// [`SourcingReport`] tells how many tokens each issuer produced.
+pub struct MultiIssuerClient<T> {
+    transport: T,
+    sources: Vec<IssuerSource>,
+}
+
+impl<T> fmt::Debug for MultiIssuerClient<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MultiIssuerClient")
+            .field("sources", &self.sources)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: IssuanceTransport> MultiIssuerClient<T> {
+    /// Creates a client without issuers that sends its token requests with
+    /// `transport`.
+    #[must_use]
+    pub const fn new(transport: T) -> Self {
+        Self {
+            transport,
+            sources: Vec::new(),
+        }
+    }
+
+    /// Adds an issuer to source tokens from.
+    #[must_use]
+    pub fn with_issuer(mut self, source: IssuerSource) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    /// Fetches `count` tokens from the issuers named by `challenges` and
+    /// stores them in `store`. The count is split evenly across the issuers
+    /// and requested from them concurrently. The share of an issuer that
+    /// fails is requested from the remaining ones, until `count` tokens are
+    /// stored or every issuer failed.
+    ///
+    /// # Errors
+    /// Returns an error if no challenge names one of the issuers. Failures of
+    /// single issuers are reported in the [`SourcingReport`].
+    pub async fn fetch_tokens<Nk, S>(
+        &self,
+        challenges: &[TokenChallenge],
+        count: u16,
+        store: &S,
+    ) -> Result<SourcingReport, SourcingError>
+    where
+        Nk: ArrayLength<u8>,
+        S: TokenStore<Nk> + ?Sized,
+    {
+        let mut candidates: Vec<(&IssuerSource, &TokenChallenge)> = self
+            .sources
+            .iter()
+            .filter_map(|source| Some((source, source.challenge(challenges)?)))
+            .collect();
+        if candidates.is_empty() {
+            return Err(SourcingError::NoMatchingIssuer);
+        }
+
+        let mut report = SourcingReport::default();
+        let mut remaining = count;
+        while remaining > 0 && !candidates.is_empty() {
+            let shares = split(remaining, candidates.len());
+            let results = join_all(candidates.iter().zip(&shares).map(
+                |(&(source, challenge), &share)| self.fetch_from::<Nk>(source, challenge, share),
+            ))
+            .await;
+
+            let mut available = Vec::with_capacity(candidates.len());
+            for ((candidate, share), (tokens, error)) in
+                candidates.into_iter().zip(shares).zip(results)
+            {
+                let (source, challenge) = candidate;
+                let issued = u16::try_from(tokens.len()).unwrap_or(u16::MAX);
+                remaining = remaining.saturating_sub(issued);
+                *report.issued.entry(source.issuer_name.clone()).or_default() += tokens.len();
+                if !tokens.is_empty() {
+                    store.insert(challenge, tokens).await;
+                }
+                match error {
+                    Some(error) => report.failures.push((source.issuer_name.clone(), error)),
+                    // Issuers that delivered less than asked are not asked again
+                    None if issued == share => available.push(candidate),
+                    None => {}
+                }
+            }
+            candidates = available;
+        }
+        Ok(report)
+    }
+
+    /// Fetches `count` tokens from one issuer. Returns the tokens that were
+    /// fetched before an error, if any.
+    async fn fetch_from<Nk: ArrayLength<u8>>(
+        &self,
+        source: &IssuerSource,
+        challenge: &TokenChallenge,
+        count: u16,
+    ) -> (Vec<Token<Nk>>, Option<SourcingError>) {
+        let token_type = source.client.token_type();
+        let mut tokens = Vec::with_capacity(usize::from(count));
+        let mut remaining = count;
+        while remaining > 0 {
+            let batch = remaining.min(max_batch_size(token_type));
+            match self.fetch_batch(source, challenge, batch).await {
+                Ok(batch_tokens) => tokens.extend(batch_tokens),
+                Err(error) => return (tokens, Some(error)),
+            }
+            remaining -= batch;
+        }
+        (tokens, None)
+    }
+
+    async fn fetch_batch<Nk: ArrayLength<u8>>(
+        &self,
+        source: &IssuerSource,
+        challenge: &TokenChallenge,
+        count: u16,
+    ) -> Result<Vec<Token<Nk>>, SourcingError> {
+        let token_type = source.client.token_type();
+        let (token_request, token_state) = source.client.issue_token_request(challenge, count)?;
+        let token_response = self
+            .transport
+            .send_token_request(&source.issuer_request_uri, &token_request)
+            .await?;
+        source
+            .client
+            .issue_tokens(&token_response, token_state)?
+            .iter()
+            .map(|token| {
+                Token::from_bytes_for_type(token_type, token)
+                    .map_err(|_| SourcingError::InvalidToken)
+            })
+            .collect()
+    }
+}
+
+/// Returns the number of tokens a single token request of `token_type` can
+/// ask for.
+const fn max_batch_size(token_type: TokenType) -> u16 {
+    match token_type {
+        TokenType::PrivateToken | TokenType::PublicToken => 1,
+        TokenType::BatchedTokenRistretto255
+        | TokenType::BatchedTokenP384
+        | TokenType::BatchedTokenRistretto255Final => u16::MAX,
+    }
+}
+
+/// Splits `count` into `parts` shares that differ by at most one.
+fn split(count: u16, parts: usize) -> Vec<u16> {
+    let parts_u16 = u16::try_from(parts).unwrap_or(u16::MAX);
+    (0..parts)
+        .map(|index| {
+            let index = u16::try_from(index).unwrap_or(u16::MAX);
+            count / parts_u16 + u16::from(index < count % parts_u16)
+        })
+        .collect()
+}
+
+#[test]
+fn split_shares() {
+    assert_eq!(split(5, 3), [2, 2, 1]);
+    assert_eq!(split(1, 3), [1, 0, 0]);
+    assert_eq!(split(6, 2), [3, 3]);
+}
@@ -0,0 +1,376 @@
+//! # Unblinding outside of voprf
+//!
+//! Experimental, behind the `experimental-unblinding` feature. Clients
+//! normally finalize token responses with voprf's `batch_finalize`, which
+//! verifies the proof and unblinds the whole batch at once. Skipping the
+//! proof verification and finalizing streamed token responses element by
+//! element both need steps that voprf does not expose, so they are
+//! implemented here:
+//!
+//! - [`finalize_unverified`] reads the blind from the serialized voprf
+//!   client state, whose layout voprf does not document.
+//! - [`verify_proof`] reimplements the batched DLEQ proof verification of
+//!   RFC 9497.
+//!
+//! Both are checked against voprf and the RFC 9497 test vectors, but have to
+//! be revisited with every voprf release.
+
+use sha2::{
+    digest::{core_api::BlockSizeUser, FixedOutput, HashMarker, Output},
+    Digest,
+};
+use subtle::ConstantTimeEq;
+use typenum::{IsLess, IsLessOrEqual, Unsigned, U256};
+use voprf::Group;
+
+#[cfg(test)]
+use rand::rngs::OsRng;
+
+/// Finalizes a VOPRF evaluation without verifying the proof:
+///
+/// ```text
+/// unblinded_element = evaluated_element * blind^-1
+/// hash_input = I2OSP(len(input), 2) || input ||
+///              I2OSP(len(unblinded_element), 2) || unblinded_element ||
+///              "Finalize"
+/// output = Hash(hash_input)
+/// ```
+///
+/// `serialized_client` is the serialized VOPRF client state, which starts
+/// with the blind. voprf does not expose the blind otherwise;
+/// `voprf_client_layout` checks the layout.
+pub(crate) fn finalize_unverified<G: Group, H: Digest>(
+    serialized_client: &[u8],
+    input: &[u8],
+    evaluated_element: &[u8],
+) -> Option<Output<H>> {
+    let blind = G::deserialize_scalar(serialized_client.get(..G::ScalarLen::USIZE)?).ok()?;
+    let evaluated_element = G::deserialize_elem(evaluated_element).ok()?;
+    let unblinded_element = G::serialize_elem(evaluated_element * &G::invert_scalar(blind));
+
+    let mut hasher = H::new();
+    hasher.update(u16::try_from(input.len()).ok()?.to_be_bytes());
+    hasher.update(input);
+    hasher.update(u16::try_from(unblinded_element.len()).ok()?.to_be_bytes());
+    hasher.update(unblinded_element);
+    hasher.update(b"Finalize");
+    Some(hasher.finalize())
+}
+
+/// Verifies the batched DLEQ proof of a VOPRF evaluation (RFC 9497, section
+/// 2.2.2) without finalizing the evaluated elements:
+///
+/// ```text
+/// (M, Z) = ComputeComposites(public_key, blinded_elements, evaluated_elements)
+/// t2 = s * generator + c * public_key
+/// t3 = s * M + c * Z
+/// verified = c == HashToScalar(public_key || M || Z || t2 || t3 || "Challenge")
+/// ```
+///
+/// `identifier` is the identifier of the ciphersuite, e.g.
+/// `ristretto255-SHA512`, and `proof` the serialized `c || s`.
+pub(crate) fn verify_proof<G: Group, H>(
+    identifier: &[u8],
+    public_key: G::Elem,
+    blinded_elements: &[G::Elem],
+    evaluated_elements: &[G::Elem],
+    proof: &[u8],
+) -> bool
+where
+    H: Digest + BlockSizeUser + Default + FixedOutput + HashMarker,
+    H::OutputSize: IsLess<U256> + IsLessOrEqual<H::BlockSize>,
+{
+    verify_proof_inner::<G, H>(
+        identifier,
+        public_key,
+        blinded_elements,
+        evaluated_elements,
+        proof,
+    )
+    .unwrap_or(false)
+}
+
+fn verify_proof_inner<G: Group, H>(
+    identifier: &[u8],
+    public_key: G::Elem,
+    blinded_elements: &[G::Elem],
+    evaluated_elements: &[G::Elem],
+    proof: &[u8],
+) -> Option<bool>
+where
+    H: Digest + BlockSizeUser + Default + FixedOutput + HashMarker,
+    H::OutputSize: IsLess<U256> + IsLessOrEqual<H::BlockSize>,
+{
+    if blinded_elements.len() != evaluated_elements.len() || proof.len() != 2 * G::ScalarLen::USIZE
+    {
+        return None;
+    }
+    let (c, s) = proof.split_at(G::ScalarLen::USIZE);
+    let c = G::deserialize_scalar(c).ok()?;
+    let s = G::deserialize_scalar(s).ok()?;
+
+    // VOPRF mode
+    let context_string = [b"OPRFV1-\x01-".as_slice(), identifier].concat();
+    let hash_to_scalar_dst: [&[u8]; 2] = [b"HashToScalar-", &context_string];
+    let elem_len = u16::try_from(G::ElemLen::USIZE).ok()?.to_be_bytes();
+    let public_key_bytes = G::serialize_elem(public_key);
+
+    // ComputeComposites
+    let seed_dst = [b"Seed-".as_slice(), &context_string].concat();
+    let seed = <H as Digest>::new()
+        .chain_update(elem_len)
+        .chain_update(&public_key_bytes)
+        .chain_update(u16::try_from(seed_dst.len()).ok()?.to_be_bytes())
+        .chain_update(&seed_dst)
+        .finalize();
+    let seed_len = u16::try_from(seed.len()).ok()?.to_be_bytes();
+    let mut m = G::identity_elem();
+    let mut z = G::identity_elem();
+    for (index, (blinded_element, evaluated_element)) in
+        blinded_elements.iter().zip(evaluated_elements).enumerate()
+    {
+        let index = u16::try_from(index).ok()?.to_be_bytes();
+        let d = G::hash_to_scalar::<H>(
+            &[
+                &seed_len,
+                &seed,
+                &index,
+                &elem_len,
+                &G::serialize_elem(*blinded_element),
+                &elem_len,
+                &G::serialize_elem(*evaluated_element),
+                b"Composite",
+            ],
+            &hash_to_scalar_dst,
+        )
+        .ok()?;
+        m = *blinded_element * &d + &m;
+        z = *evaluated_element * &d + &z;
+    }
+
+    let t2 = G::base_elem() * &s + &(public_key * &c);
+    let t3 = m * &s + &(z * &c);
+    let expected_c = G::hash_to_scalar::<H>(
+        &[
+            &elem_len,
+            &public_key_bytes,
+            &elem_len,
+            &G::serialize_elem(m),
+            &elem_len,
+            &G::serialize_elem(z),
+            &elem_len,
+            &G::serialize_elem(t2),
+            &elem_len,
+            &G::serialize_elem(t3),
+            b"Challenge",
+        ],
+        &hash_to_scalar_dst,
+    )
+    .ok()?;
+    Some(bool::from(expected_c.ct_eq(&c)))
+}
+
+#[cfg(feature = "ristretto255")]
+#[test]
+fn voprf_proof_verification() {
+    use voprf::{Ristretto255, VoprfClient, VoprfServer};
+
+    let server = VoprfServer::<Ristretto255>::new(&mut OsRng).unwrap();
+    let blinds = (0..3u8)
+        .map(|input| VoprfClient::<Ristretto255>::blind(&[input], &mut OsRng).unwrap())
+        .collect::<Vec<_>>();
+    let blinded_elements = blinds
+        .iter()
+        .map(|blind| blind.message.clone())
+        .collect::<Vec<_>>();
+    let prepared = server
+        .batch_blind_evaluate_prepare(blinded_elements.iter())
+        .collect::<Vec<_>>();
+    let result = server
+        .batch_blind_evaluate_finish(&mut OsRng, blinded_elements.iter(), &prepared)
+        .unwrap();
+    let proof = result.proof.serialize();
+
+    let elems = |serialized: Vec<Vec<u8>>| {
+        serialized
+            .iter()
+            .map(|element| Ristretto255::deserialize_elem(element).unwrap())
+            .collect::<Vec<_>>()
+    };
+    let blinded = elems(
+        blinded_elements
+            .iter()
+            .map(|element| element.serialize().to_vec())
+            .collect(),
+    );
+    let evaluated = elems(
+        result
+            .messages
+            .map(|element| element.serialize().to_vec())
+            .collect(),
+    );
+    let verify = |blinded: &[_], evaluated: &[_], proof: &[u8]| {
+        verify_proof::<Ristretto255, sha2::Sha512>(
+            b"ristretto255-SHA512",
+            server.get_public_key(),
+            blinded,
+            evaluated,
+            proof,
+        )
+    };
+    assert!(verify(&blinded, &evaluated, &proof));
+    // Swapped elements, a truncated batch and a modified proof are rejected
+    let mut swapped = evaluated.clone();
+    swapped.swap(0, 1);
+    assert!(!verify(&blinded, &swapped, &proof));
+    assert!(!verify(&blinded[..2], &evaluated[..2], &proof));
+    let mut modified = proof.to_vec();
+    modified[40] ^= 1;
+    assert!(!verify(&blinded, &evaluated, &modified));
+}
+
+#[cfg(feature = "ristretto255")]
+#[test]
+fn voprf_client_layout() {
+    use voprf::{Ristretto255, VoprfClient, VoprfServer};
+
+    let server = VoprfServer::<Ristretto255>::new(&mut OsRng).unwrap();
+    let blind = VoprfClient::<Ristretto255>::blind(b"input", &mut OsRng).unwrap();
+    let serialized_client = blind.state.serialize();
+    // The blind followed by the blinded element
+    assert_eq!(serialized_client.len(), 64);
+    assert_eq!(serialized_client[32..], blind.message.serialize()[..]);
+
+    let result = server.blind_evaluate(&mut OsRng, &blind.message);
+    let output = blind
+        .state
+        .finalize(
+            b"input",
+            &result.message,
+            &result.proof,
+            server.get_public_key(),
+        )
+        .unwrap();
+    assert_eq!(
+        finalize_unverified::<Ristretto255, sha2::Sha512>(
+            &serialized_client,
+            b"input",
+            &result.message.serialize(),
+        ),
+        Some(output)
+    );
+}
+
+#[cfg(test)]
+#[derive(serde::Deserialize)]
+struct Rfc9497Suite {
+    suite: String,
+    #[serde(with = "hex")]
+    pk_s: Vec<u8>,
+    vectors: Vec<Rfc9497Vector>,
+}
+
+#[cfg(test)]
+#[derive(serde::Deserialize)]
+struct Rfc9497Vector {
+    inputs: Vec<String>,
+    blinds: Vec<String>,
+    blinded_elements: Vec<String>,
+    evaluated_elements: Vec<String>,
+    #[serde(with = "hex")]
+    proof: Vec<u8>,
+    outputs: Vec<String>,
+}
+
+/// Checks the proof verification and the unverified finalization against
+/// the VOPRF mode test vectors of RFC 9497 of the group `G`. `client`
+/// deserializes and serializes a voprf client of the suite.
+#[cfg(test)]
+fn check_rfc9497_vectors<G: Group, H>(suite: &Rfc9497Suite, client: impl Fn(&[u8]) -> Vec<u8>)
+where
+    H: Digest + BlockSizeUser + Default + FixedOutput + HashMarker,
+    H::OutputSize: IsLess<U256> + IsLessOrEqual<H::BlockSize>,
+{
+    let decode = |values: &[String]| {
+        values
+            .iter()
+            .map(|value| hex::decode(value).unwrap())
+            .collect::<Vec<_>>()
+    };
+    let elems = |values: &[String]| {
+        decode(values)
+            .iter()
+            .map(|element| G::deserialize_elem(element).unwrap())
+            .collect::<Vec<_>>()
+    };
+    let public_key = G::deserialize_elem(&suite.pk_s).unwrap();
+    for vector in &suite.vectors {
+        let blinded = elems(&vector.blinded_elements);
+        let evaluated = elems(&vector.evaluated_elements);
+        assert!(verify_proof::<G, H>(
+            suite.suite.as_bytes(),
+            public_key,
+            &blinded,
+            &evaluated,
+            &vector.proof,
+        ));
+        if blinded.len() > 1 {
+            let mut swapped = evaluated.clone();
+            swapped.swap(0, 1);
+            assert!(!verify_proof::<G, H>(
+                suite.suite.as_bytes(),
+                public_key,
+                &blinded,
+                &swapped,
+                &vector.proof,
+            ));
+        }
+
+        // The client state is serialized by voprf, so the layout that
+        // `finalize_unverified` reads is checked here as well
+        let blinded_elements = decode(&vector.blinded_elements);
+        let evaluated_elements = decode(&vector.evaluated_elements);
+        for (index, blind) in decode(&vector.blinds).iter().enumerate() {
+            let serialized_client = client(&[blind.as_slice(), &blinded_elements[index]].concat());
+            let output = finalize_unverified::<G, H>(
+                &serialized_client,
+                &hex::decode(&vector.inputs[index]).unwrap(),
+                &evaluated_elements[index],
+            )
+            .unwrap();
+            assert_eq!(hex::encode(output), vector.outputs[index]);
+        }
+    }
+}
+
+#[cfg(any(feature = "p384", feature = "ristretto255"))]
+#[test]
+fn voprf_rfc9497_vectors() {
+    let suites: Vec<Rfc9497Suite> = serde_json::from_str(include_str!(
+        "../tests/kat_vectors/voprf_rfc9497_vectors.json"
+    ))
+    .unwrap();
+    for suite in &suites {
+        match suite.suite.as_str() {
+            #[cfg(feature = "ristretto255")]
+            "ristretto255-SHA512" => {
+                check_rfc9497_vectors::<voprf::Ristretto255, sha2::Sha512>(suite, |bytes| {
+                    voprf::VoprfClient::<voprf::Ristretto255>::deserialize(bytes)
+                        .unwrap()
+                        .serialize()
+                        .to_vec()
+                })
+            }
+            #[cfg(feature = "p384")]
+            "P384-SHA384" => {
+                check_rfc9497_vectors::<p384::NistP384, sha2::Sha384>(suite, |bytes| {
+                    voprf::VoprfClient::<p384::NistP384>::deserialize(bytes)
+                        .unwrap()
+                        .serialize()
+                        .to_vec()
+                })
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! # Redemption webhooks
+//!
+//! A [`WebhookDispatcher`] notifies external systems, such as billing or
+//! analytics, of redemptions by POSTing a [`RedemptionEvent`] as JSON to
+//! every configured URL. Events only contain the truncated token key ID, the
+//! token type, the outcome and a timestamp, so they cannot be linked to the
+//! token, its nonce or the challenge it was issued for.
+//!
+//! Every body is signed with HMAC-SHA256 under a shared secret. The
+//! signature is sent in the [`SIGNATURE_HEADER`] header, and receivers check
+//! it together with the timestamp of the event with [`verify_event`]. Failed
+//! deliveries are retried with exponential backoff.
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::{
+    auth::URL_SAFE_LENIENT,
+    clock::{Clock, SystemClock},
+    transport::TransportError,
+    ExposeSecret, MaybeSendSync, SecretVec, TokenType, TruncatedTokenKeyId,
+};
+
+/// Header that carries the base64url encoded HMAC-SHA256 signature of the
+/// body.
+pub const SIGNATURE_HEADER: &str = "PrivacyPass-Signature";
+
+/// Media type of webhook bodies.
+pub const EVENT_MEDIA_TYPE: &str = "application/json";
+
+/// Errors that can occur when dispatching an event.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WebhookError {
+    #[error("Event could not be serialized")]
+    /// The event could not be serialized.
+    Serialization,
+    #[error("Event could not be signed")]
+    /// The event could not be signed with the secret.
+    Signing,
+    #[error("Delivery to {url} failed: {error}")]
+    /// The event could not be delivered to a URL after all attempts.
+    Delivery {
+        /// URL the event was sent to.
+        url: String,
+        /// Error of the last attempt.
+        error: TransportError,
+    },
+}
+
+/// Outcome of a redemption.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RedemptionOutcome {
+    /// The token was redeemed.
+    Redeemed,
+    /// The token had already been redeemed.
+    DoubleSpending,
+    /// The token was rejected for another reason.
+    Rejected,
+}
+
+/// Event that is sent to the webhook URLs.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RedemptionEvent {
+    /// Truncated token key ID of the key the token was issued under.
+    pub truncated_token_key_id: TruncatedTokenKeyId,
+    /// Token type code point.
+    pub token_type: u16,
+    /// Outcome of the redemption.
+    pub outcome: RedemptionOutcome,
+    /// Time of the redemption in seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl RedemptionEvent {
+    /// Creates an event for a redemption that happened now.
+    #[must_use]
+    pub fn new(
+        token_type: TokenType,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        outcome: RedemptionOutcome,
+    ) -> Self {
+        Self::new_with_clock(token_type, truncated_token_key_id, outcome, &SystemClock)
+    }
+
+    /// Creates an event for a redemption that happened at the current time
+    /// of `clock`.
+    #[must_use]
+    pub fn new_with_clock<C: Clock + ?Sized>(
+        token_type: TokenType,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        outcome: RedemptionOutcome,
+        clock: &C,
+    ) -> Self {
+        Self {
+            truncated_token_key_id,
+            token_type: token_type as u16,
+            outcome,
+            timestamp: clock.unix_time(),
+        }
+    }
+}
+
+/// Sends webhook requests.
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+pub trait WebhookTransport: MaybeSendSync {
+    /// POSTs `body` to `url` with the [`EVENT_MEDIA_TYPE`] content type and
+    /// `signature` in the [`SIGNATURE_HEADER`] header. Any status other than
+    /// 2xx is an error.
+    async fn post_event(
+        &self,
+        url: &str,
+        signature: &str,
+        body: &[u8],
+    ) -> Result<(), TransportError>;
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<T: WebhookTransport + ?Sized> WebhookTransport for Arc<T> {
+    async fn post_event(
+        &self,
+        url: &str,
+        signature: &str,
+        body: &[u8],
+    ) -> Result<(), TransportError> {
+        (**self).post_event(url, signature, body).await
+    }
+}
+
+/// Dispatches signed redemption events to webhook URLs.
+pub struct WebhookDispatcher<T> {
+    transport: T,
+    secret: SecretVec<u8>,
+    urls: Vec<String>,
+    max_attempts: usize,
+    initial_backoff: Duration,
+}
+
+impl<T> std::fmt::Debug for WebhookDispatcher<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebhookDispatcher")
+            .field("urls", &self.urls)
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_backoff", &self.initial_backoff)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: WebhookTransport> WebhookDispatcher<T> {
+    /// Creates a dispatcher without URLs that signs events with `secret`. A
+    /// delivery is attempted up to 3 times, with a backoff of 1 second that
+    /// doubles after every failed attempt.
+    #[must_use]
+    pub fn new(transport: T, secret: SecretVec<u8>) -> Self {
+        Self {
+            transport,
+            secret,
+            urls: Vec::new(),
+            max_attempts: 3,
+            initial_backoff: Duration::from_secs(1),
+        }
+    }
+
+    /// Adds a URL that events are sent to.
+    #[must_use]
+    pub fn with_url(mut self, url: &str) -> Self {
+        self.urls.push(url.to_string());
+        self
+    }
+
+    /// Sets the number of delivery attempts per URL. At least one attempt
+    /// is always made.
+    #[must_use]
+    pub const fn with_max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Sets the backoff after the first failed attempt.
+    #[must_use]
+    pub const fn with_initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Sends the event to all URLs. Applications that must not wait for the
+    /// deliveries spawn the returned future.
+    ///
+    /// # Errors
+    /// Returns an error if the event cannot be serialized, or for the first
+    /// URL the event could not be delivered to. Deliveries to the other URLs
+    /// are attempted regardless.
+    pub async fn dispatch(&self, event: &RedemptionEvent) -> Result<(), WebhookError> {
+        let body = serde_json::to_vec(event).map_err(|_| WebhookError::Serialization)?;
+        let signature = sign(self.secret.expose_secret(), &body)?;
+        let mut result = Ok(());
+        for url in &self.urls {
+            if let Err(error) = self.deliver(url, &signature, &body).await {
+                if result.is_ok() {
+                    result = Err(WebhookError::Delivery {
+                        url: url.clone(),
+                        error,
+                    });
+                }
+            }
+        }
+        result
+    }
+
+    async fn deliver(&self, url: &str, signature: &str, body: &[u8]) -> Result<(), TransportError> {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            match self.transport.post_event(url, signature, body).await {
+                Ok(()) => return Ok(()),
+                Err(error) if attempt >= self.max_attempts => return Err(error),
+                Err(_) => {
+                    tokio::time::sleep(backoff).await;
+                    backoff = backoff.saturating_mul(2);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+fn sign(secret: &[u8], body: &[u8]) -> Result<String, WebhookError> {
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret) else {
+        return Err(WebhookError::Signing);
+    };
+    mac.update(body);
+    Ok(URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+}
+
+/// Checks the signature of a webhook body and that the timestamp of its
+/// event lies within `max_age` of the current time, and returns the event.
+/// `max_age` should cover the backoff of all delivery attempts.
+#[must_use]
+pub fn verify_event(
+    secret: &[u8],
+    body: &[u8],
+    signature: &str,
+    max_age: Duration,
+) -> Option<RedemptionEvent> {
+    verify_event_with_clock(secret, body, signature, max_age, &SystemClock)
+}
+
+/// Checks a webhook body like [`verify_event`], against the current time of
+/// `clock`.
+#[must_use]
+pub fn verify_event_with_clock<C: Clock + ?Sized>(
+    secret: &[u8],
+    body: &[u8],
+    signature: &str,
+    max_age: Duration,
+    clock: &C,
+) -> Option<RedemptionEvent> {
+    if !verify_signature(secret, body, signature) {
+        return None;
+    }
+    let event: RedemptionEvent = serde_json::from_slice(body).ok()?;
+    (clock.unix_time().abs_diff(event.timestamp) <= max_age.as_secs()).then_some(event)
+}
+
+/// Checks the signature of a webhook body in constant time.
+///
+/// The timestamp of the event is not checked, so a body that was captured
+/// once can be replayed indefinitely. Receivers that use this function must
+/// check the timestamp themselves, or use [`verify_event`] instead.
+#[must_use]
+pub fn verify_signature(secret: &[u8], body: &[u8], signature: &str) -> bool {
+    let Ok(signature) = URL_SAFE_LENIENT.decode(signature) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
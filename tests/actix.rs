@@ -0,0 +1,92 @@
+mod batched_memory_stores;
+
+use batched_memory_stores::*;
+
+use std::sync::Arc;
+
+use actix_web::{
+    http::{
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+        StatusCode,
+    },
+    test, web, App,
+};
+use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+use privacypass::{
+    auth::authenticate::TokenChallenge,
+    batched_tokens_ristretto255,
+    dispatch::{MultiTypeServer, TokenTypeHandler},
+    dynamic::{dyn_client, BatchedRistretto255Server, DynServer},
+    integrations::{
+        actix::{RedeemedToken, RedeemedTokenConfig},
+        ChallengeTemplate,
+    },
+};
+
+async fn protected(token: RedeemedToken) -> String {
+    hex::encode(token.challenge_digest())
+}
+
+#[actix_web::test]
+async fn actix_redeemed_token_extractor() {
+    let issuer = BatchedRistretto255Server::new(
+        batched_tokens_ristretto255::server::Server::new(),
+        MemoryKeyStoreRistretto255::default(),
+        MemoryNonceStore::default(),
+    );
+    let token_type = issuer.token_type();
+    let public_key = issuer.create_keypair().await.unwrap();
+    let issuer = Arc::new(issuer);
+    let mut server = MultiTypeServer::new();
+    server.register(token_type as u16, issuer.clone()).unwrap();
+
+    let config = RedeemedTokenConfig::new(
+        Arc::new(server),
+        vec![ChallengeTemplate::new(
+            token_type,
+            "issuer.example.com",
+            &public_key,
+        )],
+    );
+    let app = test::init_service(
+        App::new()
+            .app_data(config)
+            .route("/", web::get().to(protected)),
+    )
+    .await;
+
+    // Requests without a token are challenged
+    let response = test::call_service(&app, test::TestRequest::get().to_request()).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().contains_key(WWW_AUTHENTICATE));
+
+    // Requests with a valid token reach the handler, a spent token is rejected
+    let client = dyn_client(token_type, &public_key).unwrap();
+    let challenge = TokenChallenge::new(
+        token_type,
+        "issuer.example.com",
+        None,
+        &["origin.example.com".to_string()],
+    );
+    let (token_request, token_state) = client.issue_token_request(&challenge, 1).unwrap();
+    let token_response = issuer.issue_token_response(&token_request).await.unwrap();
+    let tokens = client.issue_tokens(&token_response, token_state).unwrap();
+    let authorization = format!("PrivateToken token={}", URL_SAFE.encode(&tokens[0]));
+
+    let request = test::TestRequest::get()
+        .insert_header((AUTHORIZATION, authorization.clone()))
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert_eq!(body, hex::encode(challenge.digest().unwrap()));
+
+    let request = test::TestRequest::get()
+        .insert_header((AUTHORIZATION, authorization))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Apps without a configuration fail closed
+    let app = test::init_service(App::new().route("/", web::get().to(protected))).await;
+    let response = test::call_service(&app, test::TestRequest::get().to_request()).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
@@ -0,0 +1,42 @@
+use async_trait::async_trait;
+use privacypass::{
+    attestation::{AttestationError, AttestationVerifier, AttestedTokenRequest},
+    Deserialize, Serialize,
+};
+
+/// Accepts attestations that equal the request's first byte and signatures
+/// that equal the attestation reversed.
+struct ToyVerifier;
+
+#[async_trait]
+impl AttestationVerifier for ToyVerifier {
+    async fn verify(
+        &self,
+        token_request: &[u8],
+        attestation: &[u8],
+        signature: &[u8],
+    ) -> Result<(), AttestationError> {
+        if attestation != &token_request[..1] {
+            return Err(AttestationError::InvalidAttestation);
+        }
+        if signature.iter().rev().ne(attestation.iter()) {
+            return Err(AttestationError::InvalidSignature);
+        }
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn attested_token_request() {
+    let request = AttestedTokenRequest::new(7u8, &[7], &[7]);
+    let bytes = request.tls_serialize_detached().unwrap();
+    let request = AttestedTokenRequest::<u8>::tls_deserialize(&mut bytes.as_slice()).unwrap();
+    assert_eq!(request.attestation(), &[7]);
+    assert_eq!(request.verify(&ToyVerifier).await, Ok(7u8));
+
+    let request = AttestedTokenRequest::new(7u8, &[8], &[8]);
+    assert_eq!(
+        request.verify(&ToyVerifier).await,
+        Err(AttestationError::InvalidAttestation)
+    );
+}
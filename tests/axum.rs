@@ -0,0 +1,120 @@
+mod batched_memory_stores;
+
+use batched_memory_stores::*;
+
+use std::sync::Arc;
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{header::CONTENT_TYPE, Request, StatusCode},
+};
+use privacypass::{
+    auth::authenticate::TokenChallenge,
+    batched_tokens_ristretto255,
+    dispatch::MultiTypeServer,
+    dynamic::{dyn_client, BatchedRistretto255Server, DynServer},
+    integrations::axum::IssuerService,
+    issuer_directory::{TokenKey, TokenKeyDirectory, WELL_KNOWN_PATH},
+    problem_details::ProblemDetails,
+};
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn axum_issuer_service() {
+    let issuer = BatchedRistretto255Server::new(
+        batched_tokens_ristretto255::server::Server::new(),
+        MemoryKeyStoreRistretto255::default(),
+        MemoryNonceStore::default(),
+    );
+    let token_type = issuer.token_type();
+    let public_key = issuer.create_keypair().await.unwrap();
+    let mut server = MultiTypeServer::new();
+    server
+        .register(token_type as u16, Arc::new(issuer))
+        .unwrap();
+    let directory = TokenKeyDirectory::new(
+        "https://issuer.example.com/issue",
+        vec![TokenKey::new(token_type, &public_key, None)],
+    );
+    let service =
+        IssuerService::new(Arc::new(server), directory.clone()).with_max_request_size(256);
+    assert_eq!(service.token_request_path(), "/issue");
+    let router = service.router();
+
+    // The directory is served at the well-known path
+    let response = router
+        .clone()
+        .oneshot(Request::get(WELL_KNOWN_PATH).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(
+        serde_json::from_slice::<TokenKeyDirectory>(&body).unwrap(),
+        directory
+    );
+
+    let client = dyn_client(token_type, &public_key).unwrap();
+    let challenge = TokenChallenge::new(
+        token_type,
+        "issuer.example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let token_request_to = |token_request: Vec<u8>, content_type: &str| {
+        Request::post("/issue")
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from(token_request))
+            .unwrap()
+    };
+
+    // Token requests are answered with token responses
+    let (token_request, token_state) = client.issue_token_request(&challenge, 2).unwrap();
+    let response = router
+        .clone()
+        .oneshot(token_request_to(
+            token_request.clone(),
+            "application/private-token-request",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[CONTENT_TYPE],
+        "application/private-token-response"
+    );
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(client.issue_tokens(&body, token_state).unwrap().len(), 2);
+
+    // Wrong content types, oversized and invalid token requests are rejected
+    let response = router
+        .clone()
+        .oneshot(token_request_to(token_request, "application/octet-stream"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let (token_request, _) = client.issue_token_request(&challenge, 10).unwrap();
+    let response = router
+        .clone()
+        .oneshot(token_request_to(
+            token_request,
+            "application/private-token-request",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let response = router
+        .oneshot(token_request_to(
+            vec![0x7A, 0x7A],
+            "application/private-token-request",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let problem: ProblemDetails = serde_json::from_slice(&body).unwrap();
+    assert_eq!(problem.code(), "unsupported-token-type");
+    assert_eq!(problem.detail(), Some("0x7a7a"));
+}
@@ -2,10 +2,11 @@ mod batched_memory_stores;
 
 use batched_memory_stores::*;
 
+use futures::{StreamExt, TryStreamExt};
 use privacypass::{
     auth::authenticate::TokenChallenge,
-    batched_tokens_p384::{client::*, server::*},
-    TokenType,
+    batched_tokens_p384::{client::*, server::*, TokenResponse, TokenResponsePart},
+    ProofVerification, TokenType, VoprfError,
 };
 
 #[tokio::test]
@@ -69,3 +70,208 @@ async fn batched_tokens_p384_cycle() {
         );
     }
 }
+
+#[tokio::test]
+async fn batched_tokens_p384_stream() {
+    let nr = 10;
+
+    let key_store = MemoryKeyStoreP384::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::new(public_key);
+
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenP384,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) = client.issue_token_request(&challenge, nr).unwrap();
+
+    // Server: Stream the TokenResponse part by part
+    let parts = server
+        .issue_token_response_stream(&key_store, token_request)
+        .await
+        .unwrap()
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+    assert_eq!(parts.len(), nr as usize + 2);
+    assert!(matches!(
+        parts.last(),
+        Some(TokenResponsePart::Proof(proof)) if proof.len() == 96
+    ));
+
+    // Client: The concatenated parts form a regular TokenResponse
+    let bytes = parts
+        .iter()
+        .flat_map(|part| part.serialize())
+        .collect::<Vec<_>>();
+    let token_response = TokenResponse::try_from_bytes(&bytes).unwrap();
+    let tokens = client.issue_tokens(&token_response, &token_states).unwrap();
+    assert_eq!(tokens.len(), nr as usize);
+}
+
+#[tokio::test]
+async fn batched_tokens_p384_incremental_finalization() {
+    let nr = 10;
+
+    let key_store = MemoryKeyStoreP384::default();
+    let nonce_store = MemoryNonceStore::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenP384,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+
+    for client in [
+        Client::new(public_key),
+        Client::new(public_key).with_proof_verification(ProofVerification::SkipForTrustedIssuer),
+    ] {
+        let (token_request, token_states) = client.issue_token_request(&challenge, nr).unwrap();
+        let mut parts = server
+            .issue_token_response_stream(&key_store, token_request)
+            .await
+            .unwrap();
+
+        // Client: Feed the parts as they arrive
+        let mut finalization = client.begin_issue_tokens(&token_states);
+        while let Some(part) = parts.next().await {
+            finalization.push(part.unwrap()).unwrap();
+        }
+        let tokens = finalization.finish().unwrap();
+        assert_eq!(tokens.len(), nr as usize);
+
+        for token in tokens {
+            assert!(server
+                .redeem_token(&key_store, &nonce_store, token)
+                .await
+                .is_ok());
+        }
+    }
+}
+
+#[tokio::test]
+async fn batched_tokens_p384_incremental_finalization_rejects_invalid_proof() {
+    let key_store = MemoryKeyStoreP384::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::new(public_key);
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenP384,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) = client.issue_token_request(&challenge, 3).unwrap();
+    let mut parts = server
+        .issue_token_response_stream(&key_store, token_request)
+        .await
+        .unwrap()
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+
+    // Swapping two evaluated elements keeps every element valid on its own,
+    // but the proof no longer covers them in this order
+    parts.swap(1, 2);
+    let mut finalization = client.begin_issue_tokens(&token_states);
+    for part in parts {
+        finalization.push(part).unwrap();
+    }
+    assert!(matches!(
+        finalization.finish(),
+        Err(IssueTokenError::Voprf(VoprfError::ProofVerification))
+    ));
+}
+
+#[tokio::test]
+async fn batched_tokens_p384_skip_proof_verification() {
+    let nr = 3;
+
+    let key_store = MemoryKeyStoreP384::default();
+    let nonce_store = MemoryNonceStore::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let verifying_client = Client::new(public_key);
+    let trusting_client =
+        Client::new(public_key).with_proof_verification(ProofVerification::SkipForTrustedIssuer);
+
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenP384,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) =
+        trusting_client.issue_token_request(&challenge, nr).unwrap();
+    let parts = server
+        .issue_token_response_stream(&key_store, token_request)
+        .await
+        .unwrap()
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+
+    // Both clients unblind a valid response to the same tokens
+    let bytes = parts
+        .iter()
+        .flat_map(|part| part.serialize())
+        .collect::<Vec<_>>();
+    let token_response = TokenResponse::try_from_bytes(&bytes).unwrap();
+    let verified_tokens = verifying_client
+        .issue_tokens(&token_response, &token_states)
+        .unwrap();
+    let trusted_tokens = trusting_client
+        .issue_tokens(&token_response, &token_states)
+        .unwrap();
+    for (verified, trusted) in verified_tokens.iter().zip(&trusted_tokens) {
+        assert_eq!(verified.authenticator(), trusted.authenticator());
+    }
+    for token in trusted_tokens {
+        assert!(server
+            .redeem_token(&key_store, &nonce_store, token)
+            .await
+            .is_ok());
+    }
+
+    // A reordered response is only noticed at redemption time
+    let (token_request, token_states) =
+        trusting_client.issue_token_request(&challenge, nr).unwrap();
+    let mut parts = server
+        .issue_token_response_stream(&key_store, token_request)
+        .await
+        .unwrap()
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+    parts.swap(1, 2);
+    let bytes = parts
+        .iter()
+        .flat_map(|part| part.serialize())
+        .collect::<Vec<_>>();
+    let token_response = TokenResponse::try_from_bytes(&bytes).unwrap();
+    assert!(matches!(
+        verifying_client.issue_tokens(&token_response, &token_states),
+        Err(IssueTokenError::Voprf(VoprfError::ProofVerification))
+    ));
+    let tokens = trusting_client
+        .issue_tokens(&token_response, &token_states)
+        .unwrap();
+    for token in &tokens[..2] {
+        assert_eq!(
+            server
+                .redeem_token(&key_store, &nonce_store, token.clone())
+                .await,
+            Err(RedeemTokenError::InvalidToken)
+        );
+    }
+    assert!(server
+        .redeem_token(&key_store, &nonce_store, tokens[2].clone())
+        .await
+        .is_ok());
+}
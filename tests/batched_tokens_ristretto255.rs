@@ -2,11 +2,25 @@ mod batched_memory_stores;
 
 use batched_memory_stores::*;
 
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use futures::{StreamExt, TryStreamExt};
 use privacypass::{
     auth::authenticate::TokenChallenge,
-    batched_tokens_ristretto255::{client::*, server::*},
-    TokenType,
+    batched_tokens_ristretto255::{
+        client::*, public_key_to_token_key_id, public_key_to_truncated_token_key_id, server::*,
+        BatchedToken, TokenRequest, TokenResponse, TokenResponsePart,
+    },
+    config::{ConfigError, ServerConfig},
+    evaluator::{BlindEvaluation, BlindEvaluator, BlindEvaluatorError},
+    policy::{PolicyRejection, RedemptionContext, RedemptionPolicy},
+    CodePoints, Deserialize, DoubleSpendEvent, DoubleSpendObserver, KeyStoreError, NonceStore,
+    ProofVerification, ReadinessError, SecretVec, Serialize, ServerRng, TokenType,
+    TruncatedTokenKeyId, VoprfError,
 };
+use rand::{rngs::StdRng, SeedableRng};
+use voprf::{Ristretto255, VoprfServer};
 
 #[tokio::test]
 async fn batched_tokens_ristretto255_cycle() {
@@ -69,3 +83,1037 @@ async fn batched_tokens_ristretto255_cycle() {
         );
     }
 }
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_code_points() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let nonce_store = MemoryNonceStore::default();
+
+    // Server: Create a server that is transitioning to the final code points
+    let server = Server::with_code_points(CodePoints::Transitional);
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255Final,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+
+    // Both draft and final clients are accepted
+    for code_points in [CodePoints::Draft, CodePoints::Final] {
+        let client = Client::with_code_points(public_key, code_points);
+        let (token_request, token_states) = client.issue_token_request(&challenge, 2).unwrap();
+        let token_response = server
+            .issue_token_response(&key_store, token_request)
+            .await
+            .unwrap();
+        let tokens = client.issue_tokens(&token_response, &token_states).unwrap();
+        for token in tokens {
+            assert_eq!(
+                token.token_type(),
+                code_points.emit(TokenType::BatchedTokenRistretto255)
+            );
+            assert!(server
+                .redeem_token(&key_store, &nonce_store, token)
+                .await
+                .is_ok());
+        }
+    }
+
+    // A server that only speaks the draft code points rejects final ones
+    let draft_server = Server::new();
+    let client = Client::with_code_points(public_key, CodePoints::Final);
+    let (token_request, _token_states) = client.issue_token_request(&challenge, 1).unwrap();
+    assert_eq!(
+        draft_server
+            .issue_token_response(&key_store, token_request)
+            .await
+            .err(),
+        Some(IssueTokenResponseError::InvalidTokenType)
+    );
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_injected_rng() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let nonce_store = MemoryNonceStore::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::new(public_key);
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+
+    // Nonces and blinds are drawn from the injected generator only
+    let (token_request, token_states) = client
+        .issue_token_request_with_rng(&mut StdRng::seed_from_u64(7), &challenge, 3)
+        .unwrap();
+    let (same_token_request, _) = client
+        .issue_token_request_with_rng(&mut StdRng::seed_from_u64(7), &challenge, 3)
+        .unwrap();
+    assert_eq!(
+        token_request.tls_serialize_detached().unwrap(),
+        same_token_request.tls_serialize_detached().unwrap()
+    );
+
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+    for token in client.issue_tokens(&token_response, &token_states).unwrap() {
+        assert!(server
+            .redeem_token(&key_store, &nonce_store, token)
+            .await
+            .is_ok());
+    }
+
+    // Servers with the same injected generator derive the same keys
+    let mut public_keys = Vec::new();
+    for _ in 0..2 {
+        let server = Server::new().with_rng(StdRng::seed_from_u64(7));
+        let key_store = MemoryKeyStoreRistretto255::default();
+        public_keys.push(server.create_keypair(&key_store).await.unwrap());
+    }
+    assert_eq!(public_keys[0], public_keys[1]);
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_token_request_builder() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let nonce_store = MemoryNonceStore::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::new(public_key);
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+
+    // Precomputed nonces end up in the tokens
+    let nonces = vec![[1; 32], [2; 32]];
+    let (token_request, issuance_state) = client
+        .token_request_builder(&challenge)
+        .nonces(nonces.clone())
+        .build()
+        .unwrap();
+    assert_eq!(token_request.nr(), 2);
+
+    // The state is kept across the call to the issuer
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+    let tokens = client
+        .issue_tokens_with_state(&token_response, issuance_state)
+        .unwrap();
+    assert_eq!(
+        tokens.iter().map(|token| token.nonce()).collect::<Vec<_>>(),
+        nonces
+    );
+    for token in tokens {
+        assert!(server
+            .redeem_token(&key_store, &nonce_store, token)
+            .await
+            .is_ok());
+    }
+
+    // One token is requested by default, otherwise `count` random ones
+    let (token_request, _) = client.token_request_builder(&challenge).build().unwrap();
+    assert_eq!(token_request.nr(), 1);
+    let (token_request, _) = client
+        .token_request_builder(&challenge)
+        .count(3)
+        .build_with_rng(&mut StdRng::seed_from_u64(7))
+        .unwrap();
+    assert_eq!(token_request.nr(), 3);
+
+    assert_eq!(
+        client
+            .token_request_builder(&challenge)
+            .count(3)
+            .nonces(nonces)
+            .build()
+            .unwrap_err(),
+        IssueTokenRequestError::NonceCountMismatch
+    );
+    assert_eq!(
+        client
+            .token_request_builder(&challenge)
+            .count(0)
+            .build()
+            .unwrap_err(),
+        IssueTokenRequestError::EmptyBatch
+    );
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_persisted_issuance_state() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let nonce_store = MemoryNonceStore::default();
+    let server = Server::with_code_points(CodePoints::Final);
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255Final,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let client = Client::with_code_points(public_key, CodePoints::Final);
+
+    let (token_request, issuance_state) = client
+        .token_request_builder(&challenge)
+        .count(3)
+        .build()
+        .unwrap();
+    let serialized = issuance_state.tls_serialize_detached().unwrap();
+    assert_eq!(serialized.len(), 2 + 3 * (32 + 32 + 2 + 32 + 32 + 32));
+    drop(issuance_state);
+
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+
+    // The restored state finalizes the response like the original
+    let issuance_state = IssuanceState::try_from_bytes(&serialized).unwrap();
+    assert_eq!(issuance_state.tls_serialize_detached().unwrap(), serialized);
+    let tokens = client
+        .issue_tokens_with_state(&token_response, issuance_state)
+        .unwrap();
+    assert_eq!(tokens.len(), 3);
+    for token in tokens {
+        assert_eq!(token.token_type(), TokenType::BatchedTokenRistretto255Final);
+        assert!(server
+            .redeem_token(&key_store, &nonce_store, token)
+            .await
+            .is_ok());
+    }
+
+    // Truncated, extended, empty and foreign states are rejected
+    assert!(IssuanceState::try_from_bytes(&serialized[..serialized.len() - 1]).is_err());
+    assert!(IssuanceState::try_from_bytes(&[serialized.as_slice(), &[0]].concat()).is_err());
+    assert!(IssuanceState::try_from_bytes(&[0, 0]).is_err());
+    let mut foreign = serialized.clone();
+    foreign[2 + 64..2 + 66].copy_from_slice(&(TokenType::PrivateToken as u16).to_be_bytes());
+    assert!(IssuanceState::try_from_bytes(&foreign).is_err());
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_stream() {
+    let nr = 10;
+
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::new(public_key);
+
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) = client.issue_token_request(&challenge, nr).unwrap();
+
+    // Server: Stream the TokenResponse part by part
+    let parts = server
+        .issue_token_response_stream(&key_store, token_request)
+        .await
+        .unwrap()
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+    assert_eq!(parts.len(), nr as usize + 2);
+    assert!(matches!(
+        parts.last(),
+        Some(TokenResponsePart::Proof(proof)) if proof.len() == 64
+    ));
+
+    // Client: The concatenated parts form a regular TokenResponse
+    let bytes = parts
+        .iter()
+        .flat_map(|part| part.serialize())
+        .collect::<Vec<_>>();
+    let token_response = TokenResponse::try_from_bytes(&bytes).unwrap();
+    let tokens = client.issue_tokens(&token_response, &token_states).unwrap();
+    assert_eq!(tokens.len(), nr as usize);
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_stream_matches_response() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::new(public_key);
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, _) = client
+        .issue_token_request_with_rng(&mut StdRng::seed_from_u64(7), &challenge, 3)
+        .unwrap();
+    let (same_token_request, _) = client
+        .issue_token_request_with_rng(&mut StdRng::seed_from_u64(7), &challenge, 3)
+        .unwrap();
+
+    // The streamed evaluated elements are those of a regular response, only
+    // the randomized proof differs
+    let streamed = server
+        .issue_token_response_stream(&key_store, token_request)
+        .await
+        .unwrap()
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap()
+        .iter()
+        .flat_map(|part| part.serialize())
+        .collect::<Vec<_>>();
+    let regular = server
+        .issue_token_response(&key_store, same_token_request)
+        .await
+        .unwrap()
+        .tls_serialize_detached()
+        .unwrap();
+    assert_eq!(streamed.len(), regular.len());
+    assert_eq!(streamed[..2 + 3 * 32], regular[..2 + 3 * 32]);
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_incremental_finalization() {
+    let nr = 10;
+
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let nonce_store = MemoryNonceStore::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+
+    for client in [
+        Client::new(public_key),
+        Client::new(public_key).with_proof_verification(ProofVerification::SkipForTrustedIssuer),
+    ] {
+        let (token_request, token_states) = client.issue_token_request(&challenge, nr).unwrap();
+        let mut parts = server
+            .issue_token_response_stream(&key_store, token_request)
+            .await
+            .unwrap();
+
+        // Client: Feed the parts as they arrive
+        let mut finalization = client.begin_issue_tokens(&token_states);
+        while let Some(part) = parts.next().await {
+            finalization.push(part.unwrap()).unwrap();
+        }
+        let tokens = finalization.finish().unwrap();
+        assert_eq!(tokens.len(), nr as usize);
+
+        for token in tokens {
+            assert!(server
+                .redeem_token(&key_store, &nonce_store, token)
+                .await
+                .is_ok());
+        }
+    }
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_incremental_finalization_with_state() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let nonce_store = MemoryNonceStore::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::new(public_key);
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+
+    // The blinded elements of a restored state are checked by the proof
+    let (token_request, issuance_state) = client
+        .token_request_builder(&challenge)
+        .count(3)
+        .build()
+        .unwrap();
+    let issuance_state =
+        IssuanceState::try_from_bytes(&issuance_state.tls_serialize_detached().unwrap()).unwrap();
+    let mut parts = server
+        .issue_token_response_stream(&key_store, token_request)
+        .await
+        .unwrap();
+
+    let mut finalization = client.begin_issue_tokens_with_state(issuance_state);
+    while let Some(part) = parts.next().await {
+        finalization.push(part.unwrap()).unwrap();
+    }
+    let tokens = finalization.finish().unwrap();
+    assert_eq!(tokens.len(), 3);
+    for token in tokens {
+        assert!(server
+            .redeem_token(&key_store, &nonce_store, token)
+            .await
+            .is_ok());
+    }
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_incremental_finalization_rejects_invalid_proof() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::new(public_key);
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) = client.issue_token_request(&challenge, 3).unwrap();
+    let mut parts = server
+        .issue_token_response_stream(&key_store, token_request)
+        .await
+        .unwrap()
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+
+    // Swapping two evaluated elements keeps every element valid on its own,
+    // but the proof no longer covers them in this order
+    parts.swap(1, 2);
+    let mut finalization = client.begin_issue_tokens(&token_states);
+    for part in parts {
+        finalization.push(part).unwrap();
+    }
+    assert!(matches!(
+        finalization.finish(),
+        Err(IssueTokenError::Voprf(VoprfError::ProofVerification))
+    ));
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_dyn_stores() {
+    // Server: Select the stores at runtime behind trait objects
+    let key_store: Arc<dyn BatchedKeyStore> = Arc::new(MemoryKeyStoreRistretto255::default());
+    let nonce_store: Box<dyn NonceStore> = Box::new(MemoryNonceStore::default());
+
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::new(public_key);
+
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) = client.issue_token_request(&challenge, 1).unwrap();
+    let token_response = server
+        .issue_token_response(&*key_store, token_request)
+        .await
+        .unwrap();
+    let tokens = client.issue_tokens(&token_response, &token_states).unwrap();
+
+    assert!(server
+        .redeem_token(&key_store, &nonce_store, tokens[0].clone())
+        .await
+        .is_ok());
+    assert_eq!(
+        server
+            .redeem_token(&*key_store, &*nonce_store, tokens[0].clone())
+            .await,
+        Err(RedeemTokenError::DoubleSpending)
+    );
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_proof_verification() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let other_public_key = server
+        .create_keypair(&MemoryKeyStoreRistretto255::default())
+        .await
+        .unwrap();
+
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) = Client::new(public_key)
+        .issue_token_request(&challenge, 2)
+        .unwrap();
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+
+    // Client: A proof under another key is reported as such
+    assert_eq!(
+        Client::new(other_public_key)
+            .issue_tokens(&token_response, &token_states)
+            .err(),
+        Some(IssueTokenError::Voprf(VoprfError::ProofVerification))
+    );
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_issuance_errors() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let server = Server::new().with_max_batch_size(2);
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::new(public_key);
+
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+
+    // Batches above the limit are rejected
+    let (token_request, _) = client.issue_token_request(&challenge, 3).unwrap();
+    assert_eq!(
+        server
+            .issue_token_response(&key_store, token_request)
+            .await
+            .err(),
+        Some(IssueTokenResponseError::BatchTooLarge(3))
+    );
+
+    // The index of an undecodable blinded element is reported. The elements
+    // follow the token type, the truncated key ID and the length prefix.
+    let (token_request, _) = client.issue_token_request(&challenge, 2).unwrap();
+    let mut bytes = token_request.tls_serialize_detached().unwrap();
+    let second_element = 5 + 32;
+    bytes[second_element..second_element + 32].fill(0xFF);
+    let token_request = TokenRequest::tls_deserialize(&mut bytes.as_slice()).unwrap();
+    assert_eq!(
+        server
+            .issue_token_response(&key_store, token_request)
+            .await
+            .err(),
+        Some(IssueTokenResponseError::InvalidBlindedElement(1))
+    );
+
+    // Empty batches are refused by the client and rejected by the server
+    assert_eq!(
+        client.issue_token_request(&challenge, 0).err(),
+        Some(IssueTokenRequestError::EmptyBatch)
+    );
+    let mut bytes = bytes[..3].to_vec();
+    bytes.extend_from_slice(&[0, 0]);
+    let token_request = TokenRequest::tls_deserialize(&mut bytes.as_slice()).unwrap();
+    assert_eq!(token_request.nr(), 0);
+    assert_eq!(
+        server
+            .issue_token_response(&key_store, token_request)
+            .await
+            .err(),
+        Some(IssueTokenResponseError::EmptyBatch)
+    );
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_readiness() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let server = Server::new();
+
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let truncated_token_key_id = public_key_to_truncated_token_key_id(&public_key);
+    assert_eq!(
+        server.ready(&key_store, &[truncated_token_key_id]).await,
+        Ok(())
+    );
+
+    let missing_key_id = truncated_token_key_id.wrapping_add(1);
+    assert_eq!(
+        server
+            .ready(&key_store, &[truncated_token_key_id, missing_key_id])
+            .await,
+        Err(ReadinessError::KeyIdNotFound(missing_key_id))
+    );
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_token_key() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let server = Server::new();
+
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let truncated_token_key_id = public_key_to_truncated_token_key_id(&public_key);
+    let token_key = server
+        .token_key(&key_store, truncated_token_key_id, Some(1_700_000_000))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        token_key.token_type(),
+        TokenType::BatchedTokenRistretto255 as u16
+    );
+    assert_eq!(
+        token_key.token_key(),
+        Some(serialize_public_key(public_key))
+    );
+    assert_eq!(
+        token_key.token_key_id(),
+        Some(public_key_to_token_key_id(&public_key))
+    );
+    assert_eq!(
+        token_key.token_key_id().map(|id| id[31]),
+        Some(truncated_token_key_id)
+    );
+    assert_eq!(token_key.not_before(), Some(1_700_000_000));
+
+    assert!(server
+        .token_key(&key_store, truncated_token_key_id.wrapping_add(1), None)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_keypair_with_seed() {
+    let server = Server::new();
+    let seed = || SecretVec::new(vec![7u8; 32]);
+
+    let replica_a = MemoryKeyStoreRistretto255::default();
+    let replica_b = MemoryKeyStoreRistretto255::default();
+    let public_key_a = server
+        .create_keypair_with_seed(&replica_a, &seed(), b"epoch 1")
+        .await
+        .unwrap();
+    let public_key_b = server
+        .create_keypair_with_seed(&replica_b, &seed(), b"epoch 1")
+        .await
+        .unwrap();
+    assert_eq!(public_key_a, public_key_b);
+
+    let public_key_c = server
+        .create_keypair_with_seed(&replica_a, &seed(), b"epoch 2")
+        .await
+        .unwrap();
+    assert_ne!(public_key_a, public_key_c);
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_key_store_operations() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let server = Server::new();
+
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let truncated_token_key_id = public_key_to_truncated_token_key_id(&public_key);
+    assert_eq!(
+        key_store.list_key_ids().await,
+        Ok(vec![truncated_token_key_id])
+    );
+    assert_eq!(key_store.contains(&truncated_token_key_id).await, Ok(true));
+
+    let client = Client::new(public_key);
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, _) = client.issue_token_request(&challenge, 1).unwrap();
+
+    assert_eq!(key_store.remove(&truncated_token_key_id).await, Ok(true));
+    assert_eq!(key_store.remove(&truncated_token_key_id).await, Ok(false));
+    assert_eq!(key_store.contains(&truncated_token_key_id).await, Ok(false));
+    assert_eq!(
+        server
+            .issue_token_response(&key_store, token_request)
+            .await
+            .err(),
+        Some(IssueTokenResponseError::KeyIdNotFound)
+    );
+}
+
+struct UnavailableKeyStore;
+
+#[async_trait]
+impl BatchedKeyStore for UnavailableKeyStore {
+    async fn insert(
+        &self,
+        _truncated_token_key_id: TruncatedTokenKeyId,
+        _server: VoprfServer<Ristretto255>,
+    ) -> Result<(), KeyStoreError> {
+        Err(KeyStoreError::Unavailable)
+    }
+
+    async fn get(
+        &self,
+        _truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<VoprfServer<Ristretto255>>, KeyStoreError> {
+        Err(KeyStoreError::Unavailable)
+    }
+
+    async fn remove(
+        &self,
+        _truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Err(KeyStoreError::Unavailable)
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        Err(KeyStoreError::Unavailable)
+    }
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_key_store_failure() {
+    let server = Server::new();
+    assert_eq!(
+        server.create_keypair(&UnavailableKeyStore).await.err(),
+        Some(CreateKeypairError::KeyStore(KeyStoreError::Unavailable))
+    );
+    assert_eq!(
+        server.ready(&UnavailableKeyStore, &[1]).await,
+        Err(ReadinessError::KeyStore(KeyStoreError::Unavailable))
+    );
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_from_config() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let config = ServerConfig::new()
+        .with_code_points(CodePoints::Final)
+        .with_max_batch_size(2);
+    let server = Server::from_config(&config).unwrap();
+    assert_eq!(server.code_points(), CodePoints::Final);
+
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::with_code_points(public_key, CodePoints::Final);
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255Final,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, _) = client.issue_token_request(&challenge, 3).unwrap();
+    assert_eq!(
+        server
+            .issue_token_response(&key_store, token_request)
+            .await
+            .err(),
+        Some(IssueTokenResponseError::BatchTooLarge(3))
+    );
+
+    // The configuration must accept the code point the server emits
+    let config = config.with_accepted_token_types(vec![TokenType::BatchedTokenRistretto255]);
+    assert_eq!(
+        Server::from_config(&config).err(),
+        Some(ConfigError::TokenTypeNotAccepted(
+            TokenType::BatchedTokenRistretto255Final
+        ))
+    );
+}
+
+/// Only accepts tokens for clients on a paid plan.
+struct PlanPolicy;
+
+#[async_trait]
+impl RedemptionPolicy<str> for PlanPolicy {
+    async fn check(&self, context: &RedemptionContext<'_, str>) -> Result<(), PolicyRejection> {
+        match context.metadata() {
+            "paid" => Ok(()),
+            _ => Err(PolicyRejection::new("plan")),
+        }
+    }
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_redemption_policy() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let nonce_store = MemoryNonceStore::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::new(public_key);
+
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) = client.issue_token_request(&challenge, 1).unwrap();
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+    let token = client
+        .issue_tokens(&token_response, &token_states)
+        .unwrap()
+        .remove(0);
+
+    // A rejected token is not spent
+    assert_eq!(
+        server
+            .redeem_token_with_policy(&key_store, &nonce_store, token.clone(), &PlanPolicy, "free")
+            .await,
+        Err(RedeemTokenError::PolicyRejected(PolicyRejection::new(
+            "plan"
+        )))
+    );
+    assert!(!nonce_store.exists(&token.nonce()).await);
+
+    assert_eq!(
+        server
+            .redeem_token_with_policy(&key_store, &nonce_store, token.clone(), &PlanPolicy, "paid")
+            .await,
+        Ok(())
+    );
+    assert_eq!(
+        server
+            .redeem_token_with_policy(&key_store, &nonce_store, token, &PlanPolicy, "paid")
+            .await,
+        Err(RedeemTokenError::DoubleSpending)
+    );
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_with_key() {
+    // The key material is managed outside of a key store
+    let voprf_server =
+        voprf::VoprfServer::<voprf::Ristretto255>::new(&mut rand::rngs::OsRng).unwrap();
+    let server = Server::new();
+    let client = Client::new(voprf_server.get_public_key());
+
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) = client.issue_token_request(&challenge, 2).unwrap();
+    let token_response = server
+        .issue_token_response_with_key(&voprf_server, token_request)
+        .unwrap();
+    let tokens = client.issue_tokens(&token_response, &token_states).unwrap();
+    for token in tokens.clone() {
+        assert_eq!(server.redeem_token_with_key(&voprf_server, token), Ok(()));
+    }
+
+    // Requests and tokens for another key are rejected
+    let other_server =
+        voprf::VoprfServer::<voprf::Ristretto255>::new(&mut rand::rngs::OsRng).unwrap();
+    let (token_request, _) = client.issue_token_request(&challenge, 1).unwrap();
+    assert_eq!(
+        server
+            .issue_token_response_with_key(&other_server, token_request)
+            .err(),
+        Some(IssueTokenResponseError::KeyIdNotFound)
+    );
+    assert_eq!(
+        server.redeem_token_with_key(&other_server, tokens[0].clone()),
+        Err(RedeemTokenError::KeyIdNotFound)
+    );
+}
+
+/// Evaluator standing in for an HSM that holds the private key.
+struct RemoteEvaluator {
+    server: VoprfServer<Ristretto255>,
+    available: bool,
+    drop_element: bool,
+}
+
+#[async_trait]
+impl BlindEvaluator for RemoteEvaluator {
+    fn public_key(&self) -> Vec<u8> {
+        self.server.public_key()
+    }
+
+    async fn blind_evaluate(
+        &self,
+        rng: &mut ServerRng,
+        blinded_elements: &[&[u8]],
+    ) -> Result<BlindEvaluation, BlindEvaluatorError> {
+        if !self.available {
+            return Err(BlindEvaluatorError::Backend("HSM unreachable".to_string()));
+        }
+        let mut evaluation =
+            BlindEvaluator::blind_evaluate(&self.server, rng, blinded_elements).await?;
+        if self.drop_element {
+            evaluation.evaluated_elements.pop();
+        }
+        Ok(evaluation)
+    }
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_with_evaluator() {
+    let mut evaluator = RemoteEvaluator {
+        server: VoprfServer::<Ristretto255>::new(&mut rand::rngs::OsRng).unwrap(),
+        available: true,
+        drop_element: false,
+    };
+    let server = Server::new();
+    let client = Client::new(evaluator.server.get_public_key());
+
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) = client.issue_token_request(&challenge, 3).unwrap();
+    let token_response = server
+        .issue_token_response_with_evaluator(&evaluator, token_request)
+        .await
+        .unwrap();
+    let tokens = client.issue_tokens(&token_response, &token_states).unwrap();
+    for token in tokens {
+        assert_eq!(
+            server.redeem_token_with_key(&evaluator.server, token),
+            Ok(())
+        );
+    }
+
+    evaluator.drop_element = true;
+    let (token_request, _) = client.issue_token_request(&challenge, 2).unwrap();
+    assert_eq!(
+        server
+            .issue_token_response_with_evaluator(&evaluator, token_request)
+            .await
+            .err(),
+        Some(IssueTokenResponseError::Evaluator(
+            BlindEvaluatorError::InvalidEvaluation
+        ))
+    );
+
+    evaluator.available = false;
+    let (token_request, _) = client.issue_token_request(&challenge, 1).unwrap();
+    assert!(matches!(
+        server
+            .issue_token_response_with_evaluator(&evaluator, token_request)
+            .await,
+        Err(IssueTokenResponseError::Evaluator(
+            BlindEvaluatorError::Backend(_)
+        ))
+    ));
+
+    // Requests for another key are rejected before the evaluator is called
+    let other_client = Client::new(
+        VoprfServer::<Ristretto255>::new(&mut rand::rngs::OsRng)
+            .unwrap()
+            .get_public_key(),
+    );
+    let (token_request, _) = other_client.issue_token_request(&challenge, 1).unwrap();
+    assert_eq!(
+        server
+            .issue_token_response_with_evaluator(&evaluator, token_request)
+            .await
+            .err(),
+        Some(IssueTokenResponseError::KeyIdNotFound)
+    );
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_concurrent_redemption() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let nonce_store = MemoryNonceStore::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+
+    let client = Client::new(public_key);
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) = client.issue_token_request(&challenge, 1).unwrap();
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+    let token = client
+        .issue_tokens(&token_response, &token_states)
+        .unwrap()
+        .remove(0);
+
+    // Even if both redemptions pass the early nonce check, only one of them
+    // may record the nonce and succeed
+    let (first, second) = tokio::join!(
+        server.redeem_token(&key_store, &nonce_store, token.clone()),
+        server.redeem_token(&key_store, &nonce_store, token.clone()),
+    );
+    let mut results = [first, second];
+    results.sort_by_key(Result::is_err);
+    assert_eq!(results, [Ok(()), Err(RedeemTokenError::DoubleSpending)]);
+
+    assert!(!nonce_store.try_insert(token.nonce()).await);
+}
+
+#[tokio::test]
+async fn batched_tokens_ristretto255_double_spend_observer() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let nonce_store = MemoryNonceStore::default();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    let server = Server::new().with_double_spend_observer(DoubleSpendObserver::new(move |event| {
+        recorded.lock().unwrap().push(*event);
+    }));
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::new(public_key);
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) = client.issue_token_request(&challenge, 2).unwrap();
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+    let tokens = client.issue_tokens(&token_response, &token_states).unwrap();
+
+    // Only the replay is reported, with the key ID and challenge of the token
+    server
+        .redeem_token(&key_store, &nonce_store, tokens[0].clone())
+        .await
+        .unwrap();
+    assert!(events.lock().unwrap().is_empty());
+    assert_eq!(
+        server
+            .redeem_token(&key_store, &nonce_store, tokens[0].clone())
+            .await,
+        Err(RedeemTokenError::DoubleSpending)
+    );
+    assert_eq!(
+        *events.lock().unwrap(),
+        [DoubleSpendEvent {
+            token_type: TokenType::BatchedTokenRistretto255,
+            token_key_id: public_key_to_token_key_id(&public_key),
+            challenge_digest: challenge.digest().unwrap(),
+        }]
+    );
+
+    // Other rejections are not reported
+    let mut forged = tokens[1].tls_serialize_detached().unwrap();
+    let last = forged.len() - 1;
+    forged[last] ^= 1;
+    let forged = BatchedToken::tls_deserialize(&mut forged.as_slice()).unwrap();
+    assert_eq!(
+        server.redeem_token(&key_store, &nonce_store, forged).await,
+        Err(RedeemTokenError::InvalidToken)
+    );
+    assert_eq!(events.lock().unwrap().len(), 1);
+}
@@ -0,0 +1,74 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_trait::async_trait;
+use privacypass::{
+    bloom_nonce_store::BloomNonceStore, memory_stores::MemoryNonceStore, Nonce, NonceStore,
+};
+
+/// Nonce store that counts the lookups that reach it.
+#[derive(Default)]
+struct CountingNonceStore {
+    nonces: MemoryNonceStore,
+    lookups: AtomicUsize,
+}
+
+#[async_trait]
+impl NonceStore for CountingNonceStore {
+    async fn exists(&self, nonce: &Nonce) -> bool {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        self.nonces.exists(nonce).await
+    }
+
+    async fn insert(&self, nonce: Nonce) {
+        self.nonces.insert(nonce).await;
+    }
+
+    async fn try_insert(&self, nonce: Nonce) -> bool {
+        self.nonces.try_insert(nonce).await
+    }
+}
+
+fn nonce(i: u32) -> Nonce {
+    let mut nonce = [0; 32];
+    nonce[..4].copy_from_slice(&i.to_be_bytes());
+    nonce
+}
+
+#[tokio::test]
+async fn bloom_nonce_store_filters_lookups() {
+    let nonce_store = BloomNonceStore::new(CountingNonceStore::default(), 1_000, 0.01);
+
+    for i in 0..1_000 {
+        assert!(nonce_store.try_insert(nonce(i)).await);
+    }
+    for i in 0..1_000 {
+        assert!(nonce_store.exists(&nonce(i)).await);
+        assert!(!nonce_store.try_insert(nonce(i)).await);
+    }
+    let lookups = nonce_store.inner().lookups.swap(0, Ordering::Relaxed);
+    assert_eq!(lookups, 1_000);
+
+    // Only false positives of the filter reach the backing store
+    for i in 1_000..11_000 {
+        assert!(!nonce_store.exists(&nonce(i)).await);
+    }
+    let lookups = nonce_store.inner().lookups.load(Ordering::Relaxed);
+    assert!(lookups < 300, "{lookups} lookups reached the backing store");
+}
+
+#[tokio::test]
+async fn bloom_nonce_store_backing_store_decides() {
+    let inner = CountingNonceStore::default();
+    // Redeemed elsewhere, e.g. by another instance of the origin
+    inner.insert(nonce(1)).await;
+    let nonce_store = BloomNonceStore::new(inner, 100, 0.01);
+
+    // The filter does not know the nonce, but the backing store rejects it
+    assert!(!nonce_store.exists(&nonce(1)).await);
+    assert!(!nonce_store.try_insert(nonce(1)).await);
+    assert!(nonce_store.exists(&nonce(1)).await);
+
+    nonce_store.clear();
+    assert!(!nonce_store.exists(&nonce(1)).await);
+    assert!(nonce_store.into_inner().exists(&nonce(1)).await);
+}
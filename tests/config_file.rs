@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use privacypass::{
+    config::ConfigError,
+    config_file::{ConfigFileError, IssuerConfig},
+    CodePoints, RedemptionErrors, TokenType,
+};
+
+const CONFIG: &str = r#"
+accepted-token-types = [2, 0x5]
+code-points = "final"
+max-batch-size = 100
+redemption-errors = "uniform"
+rotation-interval = 86400
+grace-period = 300
+
+[stores]
+key-store-url = "postgres://issuer@db/keys"
+"#;
+
+#[test]
+fn config_file() {
+    let config = IssuerConfig::from_toml_and_env(CONFIG, []).unwrap();
+    assert_eq!(
+        config.server.accepted_token_types(),
+        [
+            TokenType::PublicToken,
+            TokenType::BatchedTokenRistretto255Final
+        ]
+    );
+    assert_eq!(config.server.code_points(), CodePoints::Final);
+    assert_eq!(config.server.max_batch_size(), Some(100));
+    assert_eq!(config.server.max_in_flight_per_key(), None);
+    assert_eq!(config.server.redemption_errors(), RedemptionErrors::Uniform);
+    assert_eq!(
+        config.server.rotation_interval(),
+        Some(Duration::from_secs(86400))
+    );
+    assert_eq!(config.server.grace_period(), Duration::from_secs(300));
+    assert_eq!(
+        config.stores.key_store_url.as_deref(),
+        Some("postgres://issuer@db/keys")
+    );
+    assert_eq!(config.stores.nonce_store_url, None);
+}
+
+#[test]
+fn config_file_env_overrides() {
+    let vars = [
+        ("PRIVACYPASS_MAX_BATCH_SIZE", "50"),
+        ("PRIVACYPASS_ACCEPTED_TOKEN_TYPES", "0x0001, 2"),
+        ("PRIVACYPASS_NONCE_STORE_URL", "redis://cache/0"),
+        ("HOME", "/root"),
+    ]
+    .map(|(name, value)| (name.to_string(), value.to_string()));
+    let config = IssuerConfig::from_toml_and_env(CONFIG, vars).unwrap();
+    assert_eq!(config.server.max_batch_size(), Some(50));
+    assert_eq!(
+        config.server.accepted_token_types(),
+        [TokenType::PrivateToken, TokenType::PublicToken]
+    );
+    assert_eq!(
+        config.stores.nonce_store_url.as_deref(),
+        Some("redis://cache/0")
+    );
+
+    let invalid = |name: &str, value: &str| {
+        IssuerConfig::from_toml_and_env(CONFIG, [(name.to_string(), value.to_string())])
+    };
+    assert!(matches!(
+        invalid("PRIVACYPASS_MAX_BATCH_SIZE", "many"),
+        Err(ConfigFileError::InvalidValue("max-batch-size"))
+    ));
+    assert!(matches!(
+        invalid("PRIVACYPASS_MAX_BATCH_SIZE", "0"),
+        Err(ConfigFileError::Config(ConfigError::InvalidMaxBatchSize))
+    ));
+    assert!(matches!(
+        invalid("PRIVACYPASS_CODE_POINTS", "latest"),
+        Err(ConfigFileError::InvalidValue("code-points"))
+    ));
+    assert!(matches!(
+        IssuerConfig::from_toml_and_env("max-batch-sizes = 1", []),
+        Err(ConfigFileError::Toml(_))
+    ));
+}
@@ -0,0 +1,130 @@
+mod batched_memory_stores;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use batched_memory_stores::*;
+use privacypass::{
+    auth::authenticate::TokenChallenge,
+    batched_tokens_ristretto255,
+    dispatch::{DispatchError, HandlerRegistry, MultiTypeServer, TokenTypeHandler},
+    dynamic::{dyn_client, BatchedRistretto255Server, DynServer},
+    CodePoints, TokenType,
+};
+
+struct Echo;
+
+#[async_trait]
+impl TokenTypeHandler for Echo {
+    async fn issue_token_response(&self, token_request: &[u8]) -> Result<Vec<u8>, DispatchError> {
+        Ok(token_request.to_vec())
+    }
+
+    async fn redeem_token(&self, _token: &[u8]) -> Result<(), DispatchError> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn dispatch_by_code_point() {
+    // Register a greased code point
+    let mut server = MultiTypeServer::new();
+    server.register(0x7A7A, Arc::new(Echo)).unwrap();
+    assert_eq!(
+        server.register(0x7A7A, Arc::new(Echo)),
+        Err(DispatchError::AlreadyRegistered(0x7A7A))
+    );
+    assert_eq!(server.code_points(), vec![0x7A7A]);
+
+    // Messages are routed by their first two bytes
+    assert_eq!(
+        server.issue_token_response(&[0x7A, 0x7A, 1]).await,
+        Ok(vec![0x7A, 0x7A, 1])
+    );
+
+    // Unknown code points and truncated messages are rejected
+    assert_eq!(
+        server.redeem_token(&[0x00, 0x01, 1]).await,
+        Err(DispatchError::UnsupportedTokenType(0x0001))
+    );
+    assert_eq!(
+        server.redeem_token(&[0x00]).await,
+        Err(DispatchError::MissingTokenType)
+    );
+}
+
+// A private extension that registers its token types with a registry
+fn register_extension(registry: &mut HandlerRegistry) -> Result<(), DispatchError> {
+    registry.register(0x7A7A, Arc::new(Echo))?;
+    registry.register(0x8B8B, Arc::new(Echo))
+}
+
+#[tokio::test]
+async fn dispatch_from_registry() {
+    let mut registry = HandlerRegistry::new();
+    register_extension(&mut registry).unwrap();
+    assert_eq!(
+        register_extension(&mut registry),
+        Err(DispatchError::AlreadyRegistered(0x7A7A))
+    );
+
+    // The server consults the handlers of the registry
+    let mut server = MultiTypeServer::from_registry(registry);
+    assert_eq!(server.code_points(), vec![0x7A7A, 0x8B8B]);
+    assert_eq!(server.redeem_token(&[0x8B, 0x8B]).await, Ok(()));
+
+    assert!(server.unregister(0x8B8B).is_some());
+    assert!(server.registry().get(0x8B8B).is_none());
+    assert_eq!(
+        server.redeem_token(&[0x8B, 0x8B]).await,
+        Err(DispatchError::UnsupportedTokenType(0x8B8B))
+    );
+}
+
+#[tokio::test]
+async fn dispatch_transitional_code_points() {
+    let transitional_server = || {
+        BatchedRistretto255Server::new(
+            batched_tokens_ristretto255::server::Server::with_code_points(CodePoints::Transitional),
+            MemoryKeyStoreRistretto255::default(),
+            MemoryNonceStore::default(),
+        )
+    };
+
+    // Servers are registered under all code points or none
+    let mut registry = HandlerRegistry::new();
+    registry.register(0xF91A, Arc::new(Echo)).unwrap();
+    assert_eq!(
+        registry.register_server(transitional_server()),
+        Err(DispatchError::AlreadyRegistered(0xF91A))
+    );
+    assert_eq!(registry.code_points(), vec![0xF91A]);
+
+    let server = transitional_server();
+    let public_key = server.create_keypair().await.unwrap();
+    let mut registry = HandlerRegistry::new();
+    registry.register_server(server).unwrap();
+    let server = MultiTypeServer::from_registry(registry);
+    assert_eq!(server.code_points(), vec![0x0005, 0xF91A]);
+
+    // Requests and tokens with the draft and the final code point are served
+    for token_type in [
+        TokenType::BatchedTokenRistretto255,
+        TokenType::BatchedTokenRistretto255Final,
+    ] {
+        let client = dyn_client(token_type, &public_key).unwrap();
+        let challenge = TokenChallenge::new(
+            token_type,
+            "issuer.example.com",
+            None,
+            &["origin.example.com".to_string()],
+        );
+        let (token_request, token_state) = client.issue_token_request(&challenge, 2).unwrap();
+        assert_eq!(token_request[..2], (token_type as u16).to_be_bytes());
+        let token_response = server.issue_token_response(&token_request).await.unwrap();
+        let tokens = client.issue_tokens(&token_response, token_state).unwrap();
+        for token in tokens {
+            assert_eq!(server.redeem_token(&token).await, Ok(()));
+        }
+    }
+}
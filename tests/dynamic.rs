@@ -0,0 +1,197 @@
+mod batched_memory_stores;
+
+use batched_memory_stores::*;
+
+use privacypass::{
+    arbitrary_batched_tokens::{BatchTokenRequest, BatchTokenResponse},
+    auth::authenticate::TokenChallenge,
+    batched_tokens_p384, batched_tokens_ristretto255,
+    dispatch::{DispatchError, HandlerRegistry, MultiTypeServer, TokenTypeHandler},
+    dynamic::{dyn_client, BatchedP384Server, BatchedRistretto255Server, DynError, DynServer},
+    protocol::ProtocolError,
+    CodePoints, Serialize, TokenType,
+};
+
+#[tokio::test]
+async fn dynamic_ciphersuite_selection() {
+    // Server: One server per ciphersuite, selected at runtime
+    let servers: Vec<Box<dyn DynServer>> = vec![
+        Box::new(BatchedP384Server::new(
+            batched_tokens_p384::server::Server::new(),
+            MemoryKeyStoreP384::default(),
+            MemoryNonceStore::default(),
+        )),
+        Box::new(BatchedRistretto255Server::new(
+            batched_tokens_ristretto255::server::Server::with_code_points(CodePoints::Final),
+            MemoryKeyStoreRistretto255::default(),
+            MemoryNonceStore::default(),
+        )),
+    ];
+    assert_eq!(servers[0].token_type(), TokenType::BatchedTokenP384);
+    assert_eq!(
+        servers[1].token_type(),
+        TokenType::BatchedTokenRistretto255Final
+    );
+
+    for server in &servers {
+        let token_type = server.token_type();
+        let public_key = server.create_keypair().await.unwrap();
+
+        // Client: Select the client from the token type and the public key
+        let client = dyn_client(token_type, &public_key).unwrap();
+        assert_eq!(client.token_type(), token_type);
+
+        let challenge = TokenChallenge::new(
+            token_type,
+            "example.com",
+            None,
+            &["example.com".to_string()],
+        );
+        let (token_request, token_state) = client.issue_token_request(&challenge, 3).unwrap();
+        let token_response = server.issue_token_response(&token_request).await.unwrap();
+        let tokens = client.issue_tokens(&token_response, token_state).unwrap();
+        assert_eq!(tokens.len(), 3);
+
+        for token in &tokens {
+            assert_eq!(server.redeem_token(token).await, Ok(()));
+            assert_eq!(
+                server.redeem_token(token).await,
+                Err(DispatchError::DoubleSpending)
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn dynamic_client_errors() {
+    let server = BatchedRistretto255Server::new(
+        batched_tokens_ristretto255::server::Server::new(),
+        MemoryKeyStoreRistretto255::default(),
+        MemoryNonceStore::default(),
+    );
+    let public_key = server.create_keypair().await.unwrap();
+
+    // Token types without a VOPRF ciphersuite and invalid keys are rejected
+    assert_eq!(
+        dyn_client(TokenType::PublicToken, &public_key).err(),
+        Some(DynError::UnsupportedTokenType(0x0002))
+    );
+    assert_eq!(
+        dyn_client(TokenType::BatchedTokenP384, &public_key).err(),
+        Some(DynError::InvalidPublicKey)
+    );
+
+    // A token state can only be finalized by a client of the same type
+    let client = dyn_client(TokenType::BatchedTokenRistretto255, &public_key).unwrap();
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, _) = client.issue_token_request(&challenge, 1).unwrap();
+    let token_response = server.issue_token_response(&token_request).await.unwrap();
+
+    let other_key = BatchedP384Server::new(
+        batched_tokens_p384::server::Server::new(),
+        MemoryKeyStoreP384::default(),
+        MemoryNonceStore::default(),
+    )
+    .create_keypair()
+    .await
+    .unwrap();
+    let other_client = dyn_client(TokenType::BatchedTokenP384, &other_key).unwrap();
+    let other_challenge = TokenChallenge::new(
+        TokenType::BatchedTokenP384,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (_, other_state) = other_client
+        .issue_token_request(&other_challenge, 1)
+        .unwrap();
+    assert_eq!(
+        client.issue_tokens(&token_response, other_state).err(),
+        Some(ProtocolError::TokenStateMismatch)
+    );
+}
+
+#[tokio::test]
+async fn dynamic_arbitrary_batched_tokens() {
+    let p384_server = BatchedP384Server::new(
+        batched_tokens_p384::server::Server::new(),
+        MemoryKeyStoreP384::default(),
+        MemoryNonceStore::default(),
+    );
+    let ristretto255_server = BatchedRistretto255Server::new(
+        batched_tokens_ristretto255::server::Server::new(),
+        MemoryKeyStoreRistretto255::default(),
+        MemoryNonceStore::default(),
+    );
+    let p384_client = dyn_client(
+        p384_server.token_type(),
+        &p384_server.create_keypair().await.unwrap(),
+    )
+    .unwrap();
+    let ristretto255_client = dyn_client(
+        ristretto255_server.token_type(),
+        &ristretto255_server.create_keypair().await.unwrap(),
+    )
+    .unwrap();
+
+    let mut registry = HandlerRegistry::new();
+    registry.register_server(p384_server).unwrap();
+    registry.register_server(ristretto255_server).unwrap();
+    let server = MultiTypeServer::from_registry(registry);
+
+    // Client: Top up both token types in one request. The last entry has a
+    // token type the issuer does not support.
+    let p384_challenge = TokenChallenge::new(
+        TokenType::BatchedTokenP384,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let ristretto255_challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (p384_request, p384_state) = p384_client.issue_token_request(&p384_challenge, 2).unwrap();
+    let (ristretto255_request, ristretto255_state) = ristretto255_client
+        .issue_token_request(&ristretto255_challenge, 3)
+        .unwrap();
+    let batch_token_request = BatchTokenRequest::new(vec![
+        p384_request,
+        ristretto255_request,
+        vec![0x7A, 0x7A, 0x00],
+    ]);
+    let bytes = batch_token_request.tls_serialize_detached().unwrap();
+
+    // Server: Answer every supported entry
+    let batch_token_request = BatchTokenRequest::try_from_bytes(&bytes).unwrap();
+    assert_eq!(batch_token_request.len(), 3);
+    let batch_token_response = server
+        .issue_batch_token_response(&batch_token_request)
+        .await;
+    let bytes = batch_token_response.tls_serialize_detached().unwrap();
+
+    // Client: Finalize the entries with the client of their token type
+    let batch_token_response = BatchTokenResponse::try_from_bytes(&bytes).unwrap();
+    let token_responses = batch_token_response.token_responses().collect::<Vec<_>>();
+    assert_eq!(token_responses.len(), 3);
+    assert!(token_responses[2].is_none());
+    let p384_tokens = p384_client
+        .issue_tokens(token_responses[0].unwrap(), p384_state)
+        .unwrap();
+    let ristretto255_tokens = ristretto255_client
+        .issue_tokens(token_responses[1].unwrap(), ristretto255_state)
+        .unwrap();
+    assert_eq!(p384_tokens.len(), 2);
+    assert_eq!(ristretto255_tokens.len(), 3);
+
+    for token in p384_tokens.iter().chain(&ristretto255_tokens) {
+        assert_eq!(server.redeem_token(token).await, Ok(()));
+    }
+}
@@ -13,6 +13,7 @@ use voprf::{derive_key, Group, Mode, Ristretto255};
 use privacypass::{
     auth::authenticate::TokenChallenge,
     batched_tokens_ristretto255::{client::*, server::*, NE},
+    SecretVec,
 };
 
 #[derive(Serialize, Deserialize)]
@@ -166,7 +167,7 @@ async fn write_kat_batched_token_ristretto255() {
         let info = b"PrivacyPass";
 
         let public_key = server
-            .create_keypair_with_params(&key_store, &seed, info)
+            .create_keypair_with_params(&key_store, &SecretVec::new(seed.to_vec()), info)
             .await
             .unwrap();
 
@@ -180,6 +181,8 @@ async fn write_kat_batched_token_ristretto255() {
         // Client: Create client
         let client = Client::new(public_key);
 
+        // `is_multiple_of` needs Rust 1.87
+        #[allow(clippy::manual_is_multiple_of)]
         let redemption_context = if OsRng.next_u32() % 2 == 0 {
             let mut bytes = [0u8; 32];
             OsRng.fill_bytes(&mut bytes);
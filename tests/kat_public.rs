@@ -69,6 +69,7 @@ async fn evaluate_kat(list: Vec<PublicTokenTestVector>) {
 
         // Serialize the public key and compare it
         assert_eq!(serialize_public_key(&pub_key), vector.pk_s);
+        assert_eq!(deserialize_public_key(&vector.pk_s).unwrap().0, pub_key.0);
 
         let keypair = KeyPair {
             sk: sec_key,
@@ -76,7 +77,10 @@ async fn evaluate_kat(list: Vec<PublicTokenTestVector>) {
         };
 
         // Issuer server: Set the keypair
-        issuer_server.set_keypair(&issuer_key_store, keypair).await;
+        issuer_server
+            .set_keypair(&issuer_key_store, keypair)
+            .await
+            .unwrap();
 
         // Origin key store: Set the public key
         origin_key_store
@@ -84,7 +88,8 @@ async fn evaluate_kat(list: Vec<PublicTokenTestVector>) {
                 public_key_to_truncated_token_key_id(&pub_key),
                 pub_key.clone(),
             )
-            .await;
+            .await
+            .unwrap();
 
         // Client: Create client
         let mut client = Client::new(pub_key);
@@ -167,7 +172,8 @@ async fn write_kat_public_token() {
         // Issuer server: Set the keypair
         issuer_server
             .set_keypair(&issuer_key_store, keypair.clone())
-            .await;
+            .await
+            .unwrap();
 
         // Origin key store: Set the public key
         origin_key_store
@@ -175,7 +181,8 @@ async fn write_kat_public_token() {
                 public_key_to_truncated_token_key_id(&keypair.pk),
                 keypair.pk.clone(),
             )
-            .await;
+            .await
+            .unwrap();
 
         // Client: Create client
         let mut client = Client::new(keypair.pk);
@@ -196,6 +203,8 @@ async fn write_kat_public_token() {
             blind.clone().to_vec(),
         );
 
+        // `is_multiple_of` needs Rust 1.87
+        #[allow(clippy::manual_is_multiple_of)]
         let redemption_context = if OsRng.next_u32() % 2 == 0 {
             let mut bytes = [0u8; 32];
             OsRng.fill_bytes(&mut bytes);
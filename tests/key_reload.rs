@@ -0,0 +1,143 @@
+mod batched_memory_stores;
+
+use batched_memory_stores::*;
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+use voprf::{Ristretto255, VoprfServer};
+
+use privacypass::{
+    auth::authenticate::TokenChallenge,
+    batched_tokens_ristretto255::{client::*, public_key_to_truncated_token_key_id, server::*},
+    key_reload::*,
+    KeyValidity, TokenType, TruncatedTokenKeyId,
+};
+
+#[derive(Default)]
+struct TestKeySource {
+    version: AtomicU64,
+    keys: Mutex<HashMap<TruncatedTokenKeyId, VoprfServer<Ristretto255>>>,
+}
+
+#[async_trait]
+impl KeySource for TestKeySource {
+    type Key = VoprfServer<Ristretto255>;
+
+    async fn version(&self) -> Result<u64, KeyReloadError> {
+        Ok(self.version.load(Ordering::SeqCst))
+    }
+
+    async fn load(&self) -> Result<HashMap<TruncatedTokenKeyId, Self::Key>, KeyReloadError> {
+        Ok(self.keys.lock().await.clone())
+    }
+}
+
+#[tokio::test]
+async fn key_reload() {
+    let source = Arc::new(TestKeySource::default());
+    let key_store = Arc::new(ReloadableKeyStore::new());
+    let watcher = KeyWatcher::new(source.clone(), key_store.clone());
+    let mut events = watcher.subscribe();
+    let nonce_store = MemoryNonceStore::default();
+    let server = Server::new();
+
+    // Create a key out of band and publish it in the backing store
+    let staging = ReloadableKeyStore::<VoprfServer<Ristretto255>>::new();
+    let public_key = server.create_keypair(&staging).await.unwrap();
+    let key_id = public_key_to_truncated_token_key_id(&public_key);
+    *source.keys.lock().await = (*staging.keys()).clone();
+    source.version.store(1, Ordering::SeqCst);
+
+    let event = watcher.poll().await.unwrap().unwrap();
+    assert_eq!(event.added, vec![key_id]);
+    assert!(event.removed.is_empty());
+    assert_eq!(events.recv().await.unwrap(), event);
+    assert_eq!(watcher.poll().await.unwrap(), None);
+
+    // The swapped in key serves requests
+    let client = Client::new(public_key);
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) = client.issue_token_request(&challenge, 1).unwrap();
+    let token_response = server
+        .issue_token_response(&*key_store, token_request)
+        .await
+        .unwrap();
+    let tokens = client.issue_tokens(&token_response, &token_states).unwrap();
+
+    // Remove the key from the backing store
+    source.keys.lock().await.clear();
+    source.version.store(2, Ordering::SeqCst);
+    let event = watcher.reload().await.unwrap();
+    assert_eq!(event.version, 2);
+    assert_eq!(event.removed, vec![key_id]);
+    assert_eq!(
+        server
+            .redeem_token(&*key_store, &nonce_store, tokens[0].clone())
+            .await,
+        Err(RedeemTokenError::KeyIdNotFound)
+    );
+}
+
+#[tokio::test]
+async fn key_reload_key_validity() {
+    let source = Arc::new(TestKeySource::default());
+    let key_store = Arc::new(ReloadableKeyStore::new());
+    let watcher = KeyWatcher::new(source.clone(), key_store.clone());
+    let server = Server::new();
+
+    let staging = ReloadableKeyStore::<VoprfServer<Ristretto255>>::new();
+    let public_key = server.create_keypair(&staging).await.unwrap();
+    let key_id = public_key_to_truncated_token_key_id(&public_key);
+    *source.keys.lock().await = (*staging.keys()).clone();
+    source.version.store(1, Ordering::SeqCst);
+
+    // Validity periods recorded ahead of a swap apply to the swapped in key
+    let validity = KeyValidity::new(None, Some(1));
+    BatchedKeyStore::set_validity(&*key_store, key_id, validity)
+        .await
+        .unwrap();
+    watcher.poll().await.unwrap().unwrap();
+    assert_eq!(
+        BatchedKeyStore::validity(&*key_store, &key_id)
+            .await
+            .unwrap(),
+        validity
+    );
+    let client = Client::new(public_key);
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, _) = client.issue_token_request(&challenge, 1).unwrap();
+    assert_eq!(
+        server
+            .issue_token_response(&*key_store, token_request)
+            .await
+            .unwrap_err(),
+        IssueTokenResponseError::KeyNotValid
+    );
+
+    // Removing the key forgets its validity period
+    assert!(BatchedKeyStore::remove(&*key_store, &key_id).await.unwrap());
+    assert_eq!(
+        BatchedKeyStore::validity(&*key_store, &key_id)
+            .await
+            .unwrap(),
+        KeyValidity::default()
+    );
+}
@@ -0,0 +1,44 @@
+mod batched_memory_stores;
+
+use batched_memory_stores::*;
+
+use privacypass::{
+    batched_tokens_ristretto255::server::Server,
+    loadgen::{run_batched_ristretto255, WorkloadConfig},
+};
+
+#[tokio::test]
+async fn loadgen_batched_ristretto255() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let nonce_store = MemoryNonceStore::default();
+    let server = Server::new();
+
+    let config = WorkloadConfig::new()
+        .with_seed(7)
+        .with_rounds(10)
+        .with_batch_sizes(vec![(1, 2), (5, 1)])
+        .with_duplicate_rate(0.5);
+    let rounds = config.rounds().unwrap();
+
+    let report = run_batched_ristretto255(&server, &key_store, &nonce_store, &config)
+        .await
+        .unwrap();
+
+    assert_eq!(report.rounds, 10);
+    assert_eq!(
+        report.tokens_issued,
+        rounds
+            .iter()
+            .map(|round| usize::from(round.batch_size))
+            .sum::<usize>()
+    );
+    assert_eq!(report.redemptions, report.tokens_issued);
+    assert_eq!(
+        report.rejected_duplicates,
+        rounds
+            .iter()
+            .map(|round| usize::from(round.duplicates))
+            .sum::<usize>()
+    );
+    assert_eq!(report.accepted_duplicates, 0);
+}
@@ -0,0 +1,477 @@
+use std::{sync::Arc, time::Duration};
+
+use blind_rsa_signatures::{KeyPair, PublicKey};
+use privacypass::{
+    auth::authenticate::TokenChallenge,
+    batched_tokens_ristretto255::{client::*, public_key_to_truncated_token_key_id, server::*},
+    clock::TestClock,
+    memory_stores::{
+        ExpiringMemoryNonceStore, MemoryKeyStore, MemoryNonceStore, ShardedMemoryNonceStore,
+    },
+    public_tokens, KeyValidity, Nonce, NonceStore, RedemptionErrors, TokenType,
+};
+use voprf::{Ristretto255, VoprfServer};
+
+#[tokio::test]
+async fn memory_stores_batched_tokens_ristretto255_cycle() {
+    let key_store = MemoryKeyStore::<VoprfServer<Ristretto255>>::new();
+    let nonce_store = MemoryNonceStore::new();
+
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    assert_eq!(
+        BatchedKeyStore::list_key_ids(&key_store)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+
+    let client = Client::new(public_key);
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) = client.issue_token_request(&challenge, 3).unwrap();
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+    let tokens = client.issue_tokens(&token_response, &token_states).unwrap();
+
+    for token in &tokens {
+        assert!(!nonce_store.exists(&token.nonce()).await);
+        assert!(server
+            .redeem_token(&key_store, &nonce_store, token.clone())
+            .await
+            .is_ok());
+        assert!(nonce_store.exists(&token.nonce()).await);
+        assert_eq!(
+            server
+                .redeem_token(&key_store, &nonce_store, token.clone())
+                .await,
+            Err(RedeemTokenError::DoubleSpending)
+        );
+    }
+
+    let key_ids = BatchedKeyStore::list_key_ids(&key_store).await.unwrap();
+    assert!(BatchedKeyStore::remove(&key_store, &key_ids[0])
+        .await
+        .unwrap());
+    assert!(!BatchedKeyStore::remove(&key_store, &key_ids[0])
+        .await
+        .unwrap());
+    assert!(BatchedKeyStore::list_key_ids(&key_store)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn memory_stores_expiring_nonce_store() {
+    let clock = Arc::new(TestClock::from_unix_time(1_000));
+    let nonce_store =
+        ExpiringMemoryNonceStore::new_with_clock(Duration::from_secs(60), clock.clone());
+
+    assert!(nonce_store.try_insert([1; 32]).await);
+    assert!(!nonce_store.try_insert([1; 32]).await);
+    nonce_store
+        .insert_with_ttl([2; 32], Duration::from_secs(600))
+        .await;
+    assert!(nonce_store.exists(&[1; 32]).await);
+    assert!(nonce_store.exists(&[2; 32]).await);
+
+    // The first nonce expires with the TTL of the store, the second one
+    // with its own
+    clock.advance(Duration::from_secs(61));
+    assert!(!nonce_store.exists(&[1; 32]).await);
+    assert!(nonce_store.exists(&[2; 32]).await);
+    assert_eq!(nonce_store.len(), 2);
+    assert_eq!(nonce_store.prune(), 1);
+    assert_eq!(nonce_store.len(), 1);
+
+    // An expired nonce can be inserted again
+    clock.advance(Duration::from_secs(600));
+    assert!(nonce_store.try_insert([2; 32]).await);
+    assert_eq!(nonce_store.prune(), 0);
+}
+
+#[tokio::test]
+async fn memory_stores_expiring_nonce_store_keeps_later_expiry() {
+    let clock = Arc::new(TestClock::from_unix_time(1_000));
+    let nonce_store =
+        ExpiringMemoryNonceStore::new_with_clock(Duration::from_secs(60), clock.clone());
+
+    // Inserting a redeemed nonce again with a shorter TTL does not unblock
+    // it early
+    nonce_store
+        .insert_with_ttl([1; 32], Duration::from_secs(600))
+        .await;
+    nonce_store
+        .insert_with_ttl([1; 32], Duration::from_secs(10))
+        .await;
+    nonce_store.insert([1; 32]).await;
+    clock.advance(Duration::from_secs(61));
+    assert!(nonce_store.exists(&[1; 32]).await);
+    assert!(!nonce_store.try_insert([1; 32]).await);
+
+    // but a longer TTL extends it
+    nonce_store
+        .insert_with_ttl([1; 32], Duration::from_secs(1_200))
+        .await;
+    clock.advance(Duration::from_secs(600));
+    assert!(nonce_store.exists(&[1; 32]).await);
+    clock.advance(Duration::from_secs(600));
+    assert!(!nonce_store.exists(&[1; 32]).await);
+}
+
+#[tokio::test]
+async fn memory_stores_key_validity() {
+    let key_store = MemoryKeyStore::<VoprfServer<Ristretto255>>::new();
+    let nonce_store = MemoryNonceStore::new();
+    let clock = Arc::new(TestClock::from_unix_time(1_000));
+    let server = Server::new()
+        .with_clock(clock.clone())
+        .with_redemption_grace_period(Duration::from_secs(60));
+    let public_key = server
+        .create_keypair_with_validity(&key_store, KeyValidity::new(Some(1_100), Some(1_200)))
+        .await
+        .unwrap();
+    let truncated_token_key_id = public_key_to_truncated_token_key_id(&public_key);
+    assert_eq!(
+        BatchedKeyStore::validity(&key_store, &truncated_token_key_id)
+            .await
+            .unwrap(),
+        KeyValidity::new(Some(1_100), Some(1_200))
+    );
+
+    let client = Client::new(public_key);
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let issue = |nr| {
+        let (token_request, token_states) = client.issue_token_request(&challenge, nr).unwrap();
+        let server = &server;
+        let key_store = &key_store;
+        let client = &client;
+        async move {
+            server
+                .issue_token_response(key_store, token_request)
+                .await
+                .map(|token_response| client.issue_tokens(&token_response, &token_states).unwrap())
+        }
+    };
+
+    // Tokens are only issued between not-before and not-after
+    assert_eq!(
+        issue(1).await.unwrap_err(),
+        IssueTokenResponseError::KeyNotValid
+    );
+    clock.advance(Duration::from_secs(100));
+    let tokens = issue(3).await.unwrap();
+    clock.advance(Duration::from_secs(100));
+    assert_eq!(
+        issue(1).await.unwrap_err(),
+        IssueTokenResponseError::KeyNotValid
+    );
+
+    // and redeemed until the end of the grace period
+    server
+        .redeem_token(&key_store, &nonce_store, tokens[0].clone())
+        .await
+        .unwrap();
+    clock.advance(Duration::from_secs(59));
+    server
+        .redeem_token(&key_store, &nonce_store, tokens[1].clone())
+        .await
+        .unwrap();
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(
+        server
+            .redeem_token(&key_store, &nonce_store, tokens[2].clone())
+            .await,
+        Err(RedeemTokenError::KeyExpired)
+    );
+    let uniform = Server::new()
+        .with_clock(clock.clone())
+        .with_redemption_grace_period(Duration::from_secs(60))
+        .with_redemption_errors(RedemptionErrors::Uniform);
+    assert_eq!(
+        uniform
+            .redeem_token(&key_store, &nonce_store, tokens[2].clone())
+            .await,
+        Err(RedeemTokenError::InvalidToken)
+    );
+
+    // Keys without a validity period do not expire, and removing a key
+    // forgets its validity period
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    assert_eq!(
+        BatchedKeyStore::validity(
+            &key_store,
+            &public_key_to_truncated_token_key_id(&public_key)
+        )
+        .await
+        .unwrap(),
+        KeyValidity::default()
+    );
+    BatchedKeyStore::remove(&key_store, &truncated_token_key_id)
+        .await
+        .unwrap();
+    assert_eq!(
+        BatchedKeyStore::validity(&key_store, &truncated_token_key_id)
+            .await
+            .unwrap(),
+        KeyValidity::default()
+    );
+}
+
+#[tokio::test]
+async fn memory_stores_key_rotation() {
+    let key_store = MemoryKeyStore::<VoprfServer<Ristretto255>>::new();
+    let nonce_store = MemoryNonceStore::new();
+    let clock = Arc::new(TestClock::from_unix_time(1_000));
+    // Without a redemption grace period
+    let server = Server::new().with_clock(clock.clone());
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let issue = |public_key, nr| {
+        let client = Client::new(public_key);
+        let (token_request, token_states) = client.issue_token_request(&challenge, nr).unwrap();
+        let server = &server;
+        let key_store = &key_store;
+        async move {
+            server
+                .issue_token_response(key_store, token_request)
+                .await
+                .map(|token_response| client.issue_tokens(&token_response, &token_states).unwrap())
+        }
+    };
+    let validity = |public_key| {
+        let key_store = &key_store;
+        async move {
+            BatchedKeyStore::validity(key_store, &public_key_to_truncated_token_key_id(&public_key))
+                .await
+                .unwrap()
+        }
+    };
+
+    let old_key = server.create_keypair(&key_store).await.unwrap();
+    let tokens = issue(old_key, 2).await.unwrap();
+    let staged_validity = KeyValidity::new(Some(10_000), None);
+    let staged_key = server
+        .create_keypair_with_validity(&key_store, staged_validity)
+        .await
+        .unwrap();
+
+    // The new key issues and the old one only redeems from now on, a key
+    // staged for later is left alone
+    clock.advance(Duration::from_secs(10));
+    let new_key = server
+        .rotate_keypair(&key_store, Duration::from_secs(3_600))
+        .await
+        .unwrap();
+    assert_eq!(
+        validity(old_key).await,
+        KeyValidity::new(None, Some(1_010)).with_redeemable_until(4_610)
+    );
+    assert_eq!(validity(staged_key).await, staged_validity);
+    assert_eq!(
+        issue(old_key, 1).await.unwrap_err(),
+        IssueTokenResponseError::KeyNotValid
+    );
+    let new_tokens = issue(new_key, 1).await.unwrap();
+    server
+        .redeem_token(&key_store, &nonce_store, tokens[0].clone())
+        .await
+        .unwrap();
+
+    // until the redemption window ends
+    clock.advance(Duration::from_secs(3_600));
+    assert_eq!(
+        server
+            .redeem_token(&key_store, &nonce_store, tokens[1].clone())
+            .await,
+        Err(RedeemTokenError::KeyExpired)
+    );
+    server
+        .redeem_token(&key_store, &nonce_store, new_tokens[0].clone())
+        .await
+        .unwrap();
+
+    // An expired key issues again when it is made the primary key, unknown
+    // keys are rejected
+    assert!(server
+        .set_primary_key(
+            &key_store,
+            public_key_to_truncated_token_key_id(&old_key),
+            Duration::from_secs(60),
+        )
+        .await
+        .unwrap());
+    assert!(issue(old_key, 1).await.is_ok());
+    assert_eq!(
+        issue(new_key, 1).await.unwrap_err(),
+        IssueTokenResponseError::KeyNotValid
+    );
+    let key_ids =
+        [old_key, new_key, staged_key].map(|key| public_key_to_truncated_token_key_id(&key));
+    let unknown_key_id = (0..=u8::MAX)
+        .find(|key_id| !key_ids.contains(key_id))
+        .unwrap();
+    assert!(!server
+        .set_primary_key(&key_store, unknown_key_id, Duration::from_secs(60))
+        .await
+        .unwrap());
+}
+
+fn nonce(a: u8, b: u8) -> Nonce {
+    let mut nonce = [0; 32];
+    nonce[0] = a;
+    nonce[1] = b;
+    nonce
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn memory_stores_sharded_nonce_store() {
+    let nonce_store = Arc::new(ShardedMemoryNonceStore::with_shards(16));
+
+    // Every nonce is inserted by two tasks concurrently, only one succeeds
+    let tasks = (0..8u8)
+        .map(|task| {
+            let nonce_store = nonce_store.clone();
+            tokio::spawn(async move {
+                let mut inserted = 0;
+                for i in 0..=255u8 {
+                    if nonce_store.try_insert(nonce(i, task / 2)).await {
+                        inserted += 1;
+                    }
+                }
+                inserted
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut inserted = 0;
+    for task in tasks {
+        inserted += task.await.unwrap();
+    }
+    assert_eq!(inserted, 4 * 256);
+    assert_eq!(nonce_store.len(), 4 * 256);
+
+    assert!(nonce_store.exists(&nonce(7, 3)).await);
+    assert!(!nonce_store.exists(&nonce(7, 4)).await);
+    nonce_store.insert([1; 32]).await;
+    assert!(!nonce_store.try_insert([1; 32]).await);
+}
+
+#[tokio::test]
+async fn memory_stores_public_key_validity() {
+    use public_tokens::{client::Client, public_key_to_truncated_token_key_id, server::*};
+
+    let rng = &mut rand::thread_rng();
+    let issuer_key_store = MemoryKeyStore::<KeyPair>::new();
+    let origin_key_store = MemoryKeyStore::<PublicKey>::new();
+    let nonce_store = MemoryNonceStore::new();
+    let clock = Arc::new(TestClock::from_unix_time(1_000));
+    let issuer_server = IssuerServer::new().with_clock(clock.clone());
+    let origin_server = OriginServer::new()
+        .with_clock(clock.clone())
+        .with_redemption_grace_period(Duration::from_secs(60));
+    let validity = KeyValidity::new(Some(1_100), Some(1_200));
+
+    let key_pair = issuer_server
+        .create_keypair_with_validity(rng, &issuer_key_store, validity)
+        .await
+        .unwrap();
+    let truncated_token_key_id = public_key_to_truncated_token_key_id(&key_pair.pk);
+    assert_eq!(
+        IssuerKeyStore::validity(&issuer_key_store, &truncated_token_key_id)
+            .await
+            .unwrap(),
+        validity
+    );
+    OriginKeyStore::insert(
+        &origin_key_store,
+        truncated_token_key_id,
+        key_pair.pk.clone(),
+    )
+    .await
+    .unwrap();
+    OriginKeyStore::set_validity(&origin_key_store, truncated_token_key_id, validity)
+        .await
+        .unwrap();
+
+    // Requests need a mutable client, so tokens are finalized by a second one
+    let mut client = Client::new(key_pair.pk.clone());
+    let finalizing_client = Client::new(key_pair.pk);
+    let challenge = TokenChallenge::new(
+        TokenType::PublicToken,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let mut issue = || {
+        let (token_request, token_state) =
+            client.issue_token_request(rng, challenge.clone()).unwrap();
+        let issuer_server = &issuer_server;
+        let issuer_key_store = &issuer_key_store;
+        let finalizing_client = &finalizing_client;
+        async move {
+            issuer_server
+                .issue_token_response(issuer_key_store, token_request)
+                .await
+                .map(|token_response| {
+                    finalizing_client
+                        .issue_token(token_response, &token_state)
+                        .unwrap()
+                })
+        }
+    };
+
+    // Tokens are only issued between not-before and not-after
+    assert_eq!(
+        issue().await.unwrap_err(),
+        IssueTokenResponseError::KeyNotValid
+    );
+    clock.advance(Duration::from_secs(100));
+    let first = issue().await.unwrap();
+    let second = issue().await.unwrap();
+    clock.advance(Duration::from_secs(100));
+    assert_eq!(
+        issue().await.unwrap_err(),
+        IssueTokenResponseError::KeyNotValid
+    );
+
+    // and redeemed until the end of the grace period
+    clock.advance(Duration::from_secs(59));
+    origin_server
+        .redeem_token(&origin_key_store, &nonce_store, first)
+        .await
+        .unwrap();
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(
+        origin_server
+            .redeem_token(&origin_key_store, &nonce_store, second.clone())
+            .await,
+        Err(RedeemTokenError::KeyExpired)
+    );
+    let uniform = OriginServer::new()
+        .with_clock(clock.clone())
+        .with_redemption_grace_period(Duration::from_secs(60))
+        .with_redemption_errors(RedemptionErrors::Uniform);
+    assert_eq!(
+        uniform
+            .redeem_token(&origin_key_store, &nonce_store, second)
+            .await,
+        Err(RedeemTokenError::InvalidToken)
+    );
+}
@@ -0,0 +1,139 @@
+use std::sync::{Arc, Mutex};
+
+use privacypass::{
+    auth::authenticate::TokenChallenge,
+    batched_tokens_ristretto255::{client::*, public_key_to_truncated_token_key_id, server::*},
+    memory_stores::{MemoryKeyStore, MemoryNonceStore},
+    metrics::{prometheus::PrometheusMetrics, Metrics},
+    webhooks::RedemptionOutcome,
+    TokenType, TruncatedTokenKeyId,
+};
+use prometheus::{Encoder, Registry, TextEncoder};
+use voprf::{Ristretto255, VoprfServer};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Event {
+    Issued(TokenType, TruncatedTokenKeyId, usize),
+    IssuanceFailed(TokenType, TruncatedTokenKeyId),
+    Redeemed(TokenType, TruncatedTokenKeyId, RedemptionOutcome),
+}
+
+#[derive(Default)]
+struct RecordingMetrics(Mutex<Vec<Event>>);
+
+impl Metrics for RecordingMetrics {
+    fn tokens_issued(
+        &self,
+        token_type: TokenType,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        count: usize,
+    ) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(Event::Issued(token_type, truncated_token_key_id, count));
+    }
+
+    fn issuance_failed(&self, token_type: TokenType, truncated_token_key_id: TruncatedTokenKeyId) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(Event::IssuanceFailed(token_type, truncated_token_key_id));
+    }
+
+    fn token_redeemed(
+        &self,
+        token_type: TokenType,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        outcome: RedemptionOutcome,
+    ) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(Event::Redeemed(token_type, truncated_token_key_id, outcome));
+    }
+}
+
+#[tokio::test]
+async fn metrics_batched_tokens_ristretto255() {
+    let metrics = Arc::new(RecordingMetrics::default());
+    let registry = Registry::new();
+    let server = Server::new().with_metrics(metrics.clone());
+    let prometheus_server = Server::new().with_metrics(PrometheusMetrics::new(&registry).unwrap());
+    let key_store = MemoryKeyStore::<VoprfServer<Ristretto255>>::new();
+    let nonce_store = MemoryNonceStore::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let key_id = public_key_to_truncated_token_key_id(&public_key);
+
+    let client = Client::new(public_key);
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) = client.issue_token_request(&challenge, 3).unwrap();
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+    let tokens = client.issue_tokens(&token_response, &token_states).unwrap();
+    let (token_request, _) = client.issue_token_request(&challenge, 3).unwrap();
+    prometheus_server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+
+    // A request for a key the server does not have
+    let other_public_key = VoprfServer::<Ristretto255>::new(&mut rand::rngs::OsRng)
+        .unwrap()
+        .get_public_key();
+    let other_key_id = public_key_to_truncated_token_key_id(&other_public_key);
+    let (token_request, _) = Client::new(other_public_key)
+        .issue_token_request(&challenge, 1)
+        .unwrap();
+    assert!(server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .is_err());
+
+    for _ in 0..2 {
+        let _ = server
+            .redeem_token(&key_store, &nonce_store, tokens[0].clone())
+            .await;
+    }
+    let _ = prometheus_server
+        .redeem_token(&key_store, &nonce_store, tokens[1].clone())
+        .await;
+
+    assert_eq!(
+        *metrics.0.lock().unwrap(),
+        vec![
+            Event::Issued(TokenType::BatchedTokenRistretto255, key_id, 3),
+            Event::IssuanceFailed(TokenType::BatchedTokenRistretto255, other_key_id),
+            Event::Redeemed(
+                TokenType::BatchedTokenRistretto255,
+                key_id,
+                RedemptionOutcome::Redeemed
+            ),
+            Event::Redeemed(
+                TokenType::BatchedTokenRistretto255,
+                key_id,
+                RedemptionOutcome::DoubleSpending
+            ),
+        ]
+    );
+
+    let mut exposition = Vec::new();
+    TextEncoder::new()
+        .encode(&registry.gather(), &mut exposition)
+        .unwrap();
+    let exposition = String::from_utf8(exposition).unwrap();
+    let token_type = TokenType::BatchedTokenRistretto255 as u16;
+    assert!(exposition.contains(&format!(
+        "privacypass_tokens_issued_total{{key_id=\"{key_id}\",token_type=\"{token_type}\"}} 3"
+    )));
+    assert!(exposition.contains(&format!(
+        "privacypass_redemptions_total{{key_id=\"{key_id}\",outcome=\"redeemed\",token_type=\"{token_type}\"}} 1"
+    )));
+}
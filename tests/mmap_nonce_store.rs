@@ -0,0 +1,114 @@
+use std::{
+    fs,
+    io::{Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+use privacypass::{
+    mmap_nonce_store::{MmapNonceStore, MmapNonceStoreError},
+    NonceStore,
+};
+
+fn store_path(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("privacypass-{name}-{}.nonces", std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[tokio::test]
+async fn mmap_nonce_store_shared() {
+    let path = store_path("shared");
+
+    // Two handles on the same file stand in for two worker processes
+    let worker1 = MmapNonceStore::open_with_capacity(&path, 4).unwrap();
+    let worker2 = MmapNonceStore::open(&path).unwrap();
+
+    assert!(!worker2.exists(&[0; 32]).await);
+    worker1.insert([0; 32]).await;
+    assert!(worker2.exists(&[0; 32]).await);
+
+    // Growing the table in one handle is picked up by the other
+    for i in 1..=100u8 {
+        worker1.insert([i; 32]).await;
+    }
+    for i in 0..=100u8 {
+        assert!(worker2.exists(&[i; 32]).await);
+    }
+    assert!(!worker2.exists(&[101; 32]).await);
+
+    // The nonces survive reopening the file
+    drop((worker1, worker2));
+    let reopened = MmapNonceStore::open(&path).unwrap();
+    assert!(reopened.exists(&[42; 32]).await);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn mmap_nonce_store_interrupted_grow() {
+    let path = store_path("interrupted");
+    let store = MmapNonceStore::open_with_capacity(&path, 4).unwrap();
+    store.insert([1; 32]).await;
+    store.insert([2; 32]).await;
+    drop(store);
+
+    // A process that crashed while growing the table leaves the file
+    // extended by a partially filled larger table
+    let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+    let len = file.metadata().unwrap().len();
+    file.set_len(len + 8 * 33).unwrap();
+    file.seek(SeekFrom::Start(len)).unwrap();
+    file.write_all(&[1; 1]).unwrap();
+    file.write_all(&[0xAA; 32]).unwrap();
+    drop(file);
+
+    // The smaller table is still used and the leftovers are discarded when
+    // the table grows
+    let store = MmapNonceStore::open(&path).unwrap();
+    assert!(store.exists(&[1; 32]).await);
+    assert!(store.exists(&[2; 32]).await);
+    assert!(!store.exists(&[0xAA; 32]).await);
+    for i in 3..=10u8 {
+        store.insert([i; 32]).await;
+    }
+    for i in 1..=10u8 {
+        assert!(store.exists(&[i; 32]).await);
+    }
+    assert!(!store.exists(&[0xAA; 32]).await);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn mmap_nonce_store_full_table() {
+    let path = store_path("full");
+    drop(MmapNonceStore::open_with_capacity(&path, 4).unwrap());
+
+    // A corrupted file whose table has no empty slot left
+    let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(SeekFrom::Start(64)).unwrap();
+    for _ in 0..4 {
+        file.write_all(&[1; 1]).unwrap();
+        file.write_all(&[0xBB; 32]).unwrap();
+    }
+    drop(file);
+
+    // Probing stops after every slot and the store fails closed
+    let store = MmapNonceStore::open(&path).unwrap();
+    assert!(store.exists(&[1; 32]).await);
+    assert!(!store.try_insert([1; 32]).await);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn mmap_nonce_store_invalid_file() {
+    let path = store_path("invalid");
+    fs::write(&path, b"not a nonce store").unwrap();
+    assert!(matches!(
+        MmapNonceStore::open(&path),
+        Err(MmapNonceStoreError::InvalidFile)
+    ));
+    fs::remove_file(&path).unwrap();
+}
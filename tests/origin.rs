@@ -0,0 +1,438 @@
+mod batched_memory_stores;
+
+use batched_memory_stores::*;
+
+use std::{sync::Arc, time::Duration};
+
+use blind_rsa_signatures::{KeyPair, PublicKey};
+use privacypass::{
+    auth::authenticate::TokenChallenge,
+    batched_tokens_ristretto255,
+    clock::{Clock, TestClock},
+    dispatch::{DispatchError, MultiTypeServer, TokenTypeHandler},
+    dynamic::{dyn_client, BatchedRistretto255Server, DynServer, PublicTokensOriginServer},
+    memory_stores::{ExpiringMemoryNonceStore, MemoryChallengeStore, MemoryKeyStore},
+    origin::{check_origin_name, Origin, OriginError},
+    public_tokens::{
+        self, public_key_to_truncated_token_key_id,
+        server::{IssuerServer, OriginKeyStore, OriginServer},
+    },
+    Serialize, TokenType,
+};
+use rand::{rngs::OsRng, thread_rng, Rng};
+
+#[tokio::test]
+async fn origin_redeems_tokens_for_its_challenges() {
+    let issuer = BatchedRistretto255Server::new(
+        batched_tokens_ristretto255::server::Server::new(),
+        MemoryKeyStoreRistretto255::default(),
+        MemoryNonceStore::default(),
+    );
+    let token_type = issuer.token_type();
+    let public_key = issuer.create_keypair().await.unwrap();
+    let issuer = Arc::new(issuer);
+    let mut server = MultiTypeServer::new();
+    server.register(token_type as u16, issuer.clone()).unwrap();
+    let clock = Arc::new(TestClock::from_unix_time(1_700_000_000));
+    let challenges = Arc::new(MemoryChallengeStore::new());
+    let origin = Origin::new_with_clock(
+        "issuer.example.com",
+        Arc::new(server),
+        challenges.clone(),
+        clock.clone(),
+    );
+    let origin_info = ["origin.example.com".to_string()];
+
+    let client = dyn_client(token_type, &public_key).unwrap();
+    let issue_tokens = |challenge: TokenChallenge, nr| {
+        let client = &client;
+        let issuer = issuer.clone();
+        async move {
+            let (token_request, token_state) = client.issue_token_request(&challenge, nr).unwrap();
+            let token_response = issuer.issue_token_response(&token_request).await.unwrap();
+            client.issue_tokens(&token_response, token_state).unwrap()
+        }
+    };
+
+    // A challenge without a redemption context is good for every token of a
+    // batch, and each token is redeemed once
+    let challenge = origin
+        .create_challenge(token_type, None, &origin_info, 60)
+        .await
+        .unwrap();
+    assert_eq!(challenges.len(), 1);
+    let tokens = issue_tokens(challenge, 2).await;
+    assert_eq!(origin.redeem_token(&tokens[0]).await, Ok(()));
+    assert_eq!(origin.redeem_token(&tokens[1]).await, Ok(()));
+    assert_eq!(challenges.len(), 1);
+    assert_eq!(
+        origin.redeem_token(&tokens[0]).await,
+        Err(OriginError::Dispatch(DispatchError::DoubleSpending))
+    );
+
+    // A challenge with a redemption context is good for one token, which is
+    // only taken once the token is accepted
+    let challenge = origin
+        .create_challenge(token_type, Some(OsRng.gen()), &origin_info, 60)
+        .await
+        .unwrap();
+    let mut tokens = issue_tokens(challenge, 2).await;
+    let mut forged = tokens[0].clone();
+    *forged.last_mut().unwrap() ^= 1;
+    assert_eq!(
+        origin.redeem_token(&forged).await,
+        Err(OriginError::Dispatch(DispatchError::InvalidToken))
+    );
+    assert_eq!(challenges.len(), 2);
+    assert_eq!(origin.redeem_token(&tokens[0]).await, Ok(()));
+    assert_eq!(challenges.len(), 1);
+    assert_eq!(
+        origin.redeem_token(&tokens.remove(1)).await,
+        Err(OriginError::UnknownChallenge)
+    );
+
+    // Tokens for a challenge the origin did not issue are rejected
+    let made_up = TokenChallenge::new(
+        token_type,
+        "issuer.example.com",
+        Some(OsRng.gen()),
+        &origin_info,
+    );
+    let tokens = issue_tokens(made_up, 1).await;
+    assert_eq!(
+        origin.redeem_token(&tokens[0]).await,
+        Err(OriginError::UnknownChallenge)
+    );
+
+    // Tokens for an expired challenge are rejected
+    let challenge = origin
+        .create_challenge(token_type, None, &origin_info, 60)
+        .await
+        .unwrap();
+    let tokens = issue_tokens(challenge, 1).await;
+    origin
+        .create_challenge(token_type, Some(OsRng.gen()), &origin_info, 60)
+        .await
+        .unwrap();
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(
+        origin.redeem_token(&tokens[0]).await,
+        Err(OriginError::UnknownChallenge)
+    );
+    // Expired challenges are pruned when the next one is created
+    assert_eq!(challenges.len(), 2);
+    origin
+        .create_challenge(token_type, Some(OsRng.gen()), &origin_info, 60)
+        .await
+        .unwrap();
+    assert_eq!(challenges.len(), 1);
+
+    // Interactive challenges are bound to a fresh redemption context and
+    // expire at the given time
+    let expires_at = clock.now() + Duration::from_secs(300);
+    let challenge = origin
+        .create_challenge_with_expiry(token_type, &origin_info, expires_at)
+        .await
+        .unwrap();
+    assert!(challenge.redemption_context().is_some());
+    let tokens = issue_tokens(challenge, 1).await;
+    let other = origin
+        .create_challenge_with_expiry(token_type, &origin_info, expires_at)
+        .await
+        .unwrap();
+    assert_ne!(other.redemption_context(), None);
+    let other_tokens = issue_tokens(other, 1).await;
+    clock.advance(Duration::from_secs(299));
+    assert_eq!(origin.redeem_token(&tokens[0]).await, Ok(()));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(
+        origin.redeem_token(&other_tokens[0]).await,
+        Err(OriginError::UnknownChallenge)
+    );
+
+    // Malformed tokens are rejected
+    assert_eq!(
+        origin.redeem_token(&[0; 8]).await,
+        Err(OriginError::Dispatch(DispatchError::InvalidToken))
+    );
+}
+
+#[tokio::test]
+async fn origin_keeps_nonces_until_single_use_challenges_expire() {
+    let clock = Arc::new(TestClock::from_unix_time(1_700_000_000));
+    let nonce_store = Arc::new(ExpiringMemoryNonceStore::new_with_clock(
+        Duration::from_secs(3600),
+        clock.clone(),
+    ));
+    let issuer = BatchedRistretto255Server::new(
+        batched_tokens_ristretto255::server::Server::new(),
+        MemoryKeyStoreRistretto255::default(),
+        nonce_store.clone(),
+    );
+    let token_type = issuer.token_type();
+    let public_key = issuer.create_keypair().await.unwrap();
+    let issuer = Arc::new(issuer);
+    let mut server = MultiTypeServer::new();
+    server.register(token_type as u16, issuer.clone()).unwrap();
+    let origin = Origin::new_with_clock(
+        "issuer.example.com",
+        Arc::new(server),
+        MemoryChallengeStore::new(),
+        clock.clone(),
+    );
+    let origin_info = ["origin.example.com".to_string()];
+    let client = dyn_client(token_type, &public_key).unwrap();
+
+    let mut tokens = Vec::new();
+    for redemption_context in [None, Some(OsRng.gen())] {
+        let challenge = origin
+            .create_challenge(token_type, redemption_context, &origin_info, 60)
+            .await
+            .unwrap();
+        let (token_request, token_state) = client.issue_token_request(&challenge, 1).unwrap();
+        let token_response = issuer.issue_token_response(&token_request).await.unwrap();
+        tokens.extend(client.issue_tokens(&token_response, token_state).unwrap());
+    }
+    clock.advance(Duration::from_secs(30));
+    for token in &tokens {
+        assert_eq!(origin.redeem_token(token).await, Ok(()));
+    }
+    assert_eq!(nonce_store.len(), 2);
+
+    // The nonce of the single-use challenge is kept until the challenge
+    // expires, the nonce of the shared challenge for the TTL of the store
+    clock.advance(Duration::from_secs(30));
+    assert_eq!(nonce_store.prune(), 1);
+    assert_eq!(nonce_store.len(), 1);
+}
+
+#[tokio::test]
+async fn origin_redeems_public_tokens() {
+    let issuer_key_store = MemoryKeyStore::<KeyPair>::new();
+    let origin_key_store = MemoryKeyStore::<PublicKey>::new();
+    let issuer = IssuerServer::new();
+    let key_pair = issuer
+        .create_keypair(&mut thread_rng(), &issuer_key_store)
+        .await
+        .unwrap();
+    OriginKeyStore::insert(
+        &origin_key_store,
+        public_key_to_truncated_token_key_id(&key_pair.pk),
+        key_pair.pk.clone(),
+    )
+    .await
+    .unwrap();
+    let mut server = MultiTypeServer::new();
+    server
+        .register(
+            TokenType::PublicToken as u16,
+            Arc::new(PublicTokensOriginServer::new(
+                OriginServer::new(),
+                origin_key_store,
+                MemoryNonceStore::default(),
+            )),
+        )
+        .unwrap();
+    let origin = Origin::new(
+        "issuer.example.com",
+        Arc::new(server),
+        MemoryChallengeStore::new(),
+    );
+    let origin_info = ["origin.example.com".to_string()];
+
+    let challenge = origin
+        .create_challenge(TokenType::PublicToken, None, &origin_info, 60)
+        .await
+        .unwrap();
+    let mut client = public_tokens::client::Client::new(key_pair.pk);
+    let (token_request, token_state) = client
+        .issue_token_request(&mut thread_rng(), challenge)
+        .unwrap();
+    let token_response = issuer
+        .issue_token_response(&issuer_key_store, token_request)
+        .await
+        .unwrap();
+    let token = client
+        .issue_token(token_response, &token_state)
+        .unwrap()
+        .tls_serialize_detached()
+        .unwrap();
+    assert_eq!(origin.redeem_token(&token).await, Ok(()));
+    assert_eq!(
+        origin.redeem_token(&token).await,
+        Err(OriginError::Dispatch(DispatchError::DoubleSpending))
+    );
+
+    // Challenges are only created for token types the origin can redeem
+    assert_eq!(
+        origin
+            .create_challenge(TokenType::PrivateToken, None, &origin_info, 60)
+            .await,
+        Err(OriginError::Dispatch(DispatchError::UnsupportedTokenType(
+            TokenType::PrivateToken as u16
+        )))
+    );
+}
+
+#[test]
+fn origin_name_check() {
+    let origin_info = [
+        "origin.example.com".to_string(),
+        "other.example.com".to_string(),
+        "[::1]".to_string(),
+    ];
+    let challenge = TokenChallenge::new(
+        TokenType::PrivateToken,
+        "issuer.example.com",
+        None,
+        &origin_info,
+    );
+    for authority in [
+        "origin.example.com",
+        "Origin.Example.COM:8443",
+        "origin.example.com.",
+        "user@other.example.com",
+        "[::1]:8080",
+    ] {
+        assert_eq!(
+            check_origin_name(&challenge, authority),
+            Ok(()),
+            "{authority}"
+        );
+    }
+    for authority in [
+        "evil.example.com",
+        "origin.example.com.evil.example",
+        "origin.example.com:https",
+        "",
+    ] {
+        assert_eq!(
+            check_origin_name(&challenge, authority),
+            Err(OriginError::OriginNameMismatch),
+            "{authority}"
+        );
+    }
+
+    // Challenges without origin info are valid for every origin
+    let challenge = TokenChallenge::new(TokenType::PrivateToken, "issuer.example.com", None, &[]);
+    assert_eq!(check_origin_name(&challenge, "evil.example.com"), Ok(()));
+}
+
+#[tokio::test]
+async fn origin_redeems_tokens_for_its_hostnames() {
+    let issuer = BatchedRistretto255Server::new(
+        batched_tokens_ristretto255::server::Server::new(),
+        MemoryKeyStoreRistretto255::default(),
+        MemoryNonceStore::default(),
+    );
+    let token_type = issuer.token_type();
+    let public_key = issuer.create_keypair().await.unwrap();
+    let issuer = Arc::new(issuer);
+    let mut server = MultiTypeServer::new();
+    server.register(token_type as u16, issuer.clone()).unwrap();
+    let challenges = Arc::new(MemoryChallengeStore::new());
+    let origin = Origin::new("issuer.example.com", Arc::new(server), challenges.clone());
+    let client = dyn_client(token_type, &public_key).unwrap();
+
+    let challenge = origin
+        .create_challenge(
+            token_type,
+            Some(OsRng.gen()),
+            &["origin.example.com".to_string()],
+            60,
+        )
+        .await
+        .unwrap();
+    let (token_request, token_state) = client.issue_token_request(&challenge, 1).unwrap();
+    let token_response = issuer.issue_token_response(&token_request).await.unwrap();
+    let tokens = client.issue_tokens(&token_response, token_state).unwrap();
+
+    // Tokens for another hostname are rejected without taking the challenge
+    assert_eq!(
+        origin
+            .redeem_token_for_authority(&tokens[0], &challenge, "tenant.example.com")
+            .await,
+        Err(OriginError::OriginNameMismatch)
+    );
+    assert_eq!(challenges.len(), 1);
+
+    // The challenge must be the one the token was issued for
+    let other = TokenChallenge::new(
+        token_type,
+        "issuer.example.com",
+        None,
+        &["tenant.example.com".to_string()],
+    );
+    assert_eq!(
+        origin
+            .redeem_token_for_authority(&tokens[0], &other, "tenant.example.com")
+            .await,
+        Err(OriginError::UnknownChallenge)
+    );
+    assert_eq!(
+        origin
+            .redeem_token_for_authority(&[0; 8], &challenge, "origin.example.com")
+            .await,
+        Err(OriginError::Dispatch(DispatchError::InvalidToken))
+    );
+
+    assert_eq!(
+        origin
+            .redeem_token_for_authority(&tokens[0], &challenge, "origin.example.com:443")
+            .await,
+        Ok(())
+    );
+    assert!(challenges.is_empty());
+}
+
+#[tokio::test]
+async fn origin_redeems_every_token_of_a_batch() {
+    let issuer = BatchedRistretto255Server::new(
+        batched_tokens_ristretto255::server::Server::new(),
+        MemoryKeyStoreRistretto255::default(),
+        MemoryNonceStore::default(),
+    );
+    let token_type = issuer.token_type();
+    let public_key = issuer.create_keypair().await.unwrap();
+    let issuer = Arc::new(issuer);
+    let mut server = MultiTypeServer::new();
+    server.register(token_type as u16, issuer.clone()).unwrap();
+    let clock = Arc::new(TestClock::from_unix_time(1_700_000_000));
+    let challenges = Arc::new(MemoryChallengeStore::new());
+    let origin = Origin::new_with_clock(
+        "issuer.example.com",
+        Arc::new(server),
+        challenges.clone(),
+        clock.clone(),
+    );
+    let origin_info = ["origin.example.com".to_string()];
+    let client = dyn_client(token_type, &public_key).unwrap();
+
+    let challenge = origin
+        .create_challenge(token_type, None, &origin_info, 300)
+        .await
+        .unwrap();
+    let (token_request, token_state) = client.issue_token_request(&challenge, 10).unwrap();
+    let token_response = issuer.issue_token_response(&token_request).await.unwrap();
+    let tokens = client.issue_tokens(&token_response, token_state).unwrap();
+
+    // Sending the same challenge again with a shorter max-age does not cut
+    // short the tokens already issued for it
+    let again = origin
+        .create_challenge(token_type, None, &origin_info, 60)
+        .await
+        .unwrap();
+    assert_eq!(again.digest().unwrap(), challenge.digest().unwrap());
+    assert_eq!(challenges.len(), 1);
+    clock.advance(Duration::from_secs(120));
+
+    for token in &tokens {
+        assert_eq!(origin.redeem_token(token).await, Ok(()));
+    }
+    for token in &tokens {
+        assert_eq!(
+            origin.redeem_token(token).await,
+            Err(OriginError::Dispatch(DispatchError::DoubleSpending))
+        );
+    }
+}
@@ -1,3 +1,6 @@
+// Each test crate uses only some of the stores.
+#![allow(dead_code)]
+
 use async_trait::async_trait;
 use p384::NistP384;
 use std::collections::{HashMap, HashSet};
@@ -5,7 +8,7 @@ use tokio::sync::Mutex;
 use voprf::*;
 
 use privacypass::private_tokens::server::*;
-use privacypass::{Nonce, NonceStore, TruncatedTokenKeyId};
+use privacypass::{KeyStoreError, Nonce, NonceStore, TruncatedTokenKeyId};
 
 #[derive(Default)]
 pub struct MemoryNonceStore {
@@ -23,6 +26,11 @@ impl NonceStore for MemoryNonceStore {
         let mut nonces = self.nonces.lock().await;
         nonces.insert(nonce);
     }
+
+    async fn try_insert(&self, nonce: Nonce) -> bool {
+        let mut nonces = self.nonces.lock().await;
+        nonces.insert(nonce)
+    }
 }
 
 #[derive(Default)]
@@ -36,15 +44,32 @@ impl PrivateKeyStore for MemoryKeyStore {
         &self,
         truncated_token_key_id: TruncatedTokenKeyId,
         server: VoprfServer<NistP384>,
-    ) {
+    ) -> Result<(), KeyStoreError> {
         let mut keys = self.keys.lock().await;
         keys.insert(truncated_token_key_id, server);
+        Ok(())
     }
 
     async fn get(
         &self,
         truncated_token_key_id: &TruncatedTokenKeyId,
-    ) -> Option<VoprfServer<NistP384>> {
-        self.keys.lock().await.get(truncated_token_key_id).cloned()
+    ) -> Result<Option<VoprfServer<NistP384>>, KeyStoreError> {
+        Ok(self.keys.lock().await.get(truncated_token_key_id).cloned())
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(self
+            .keys
+            .lock()
+            .await
+            .remove(truncated_token_key_id)
+            .is_some())
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        Ok(self.keys.lock().await.keys().copied().collect())
     }
 }
@@ -4,8 +4,13 @@ use private_memory_stores::*;
 
 use privacypass::{
     auth::authenticate::TokenChallenge,
+    issuer_directory::{TokenKey, TokenKeyDirectory},
     private_tokens::{client::*, server::*},
-    TokenType,
+    FinalizeObserver, IssuanceObserver, ProofVerification, RedemptionErrors, TokenType,
+};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
 };
 
 #[tokio::test]
@@ -58,3 +63,162 @@ async fn private_tokens_cycle() {
         Err(RedeemTokenError::DoubleSpending)
     );
 }
+
+#[tokio::test]
+async fn private_tokens_skip_proof_verification() {
+    let key_store = MemoryKeyStore::default();
+    let nonce_store = MemoryNonceStore::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+
+    let observed = Arc::new(AtomicUsize::new(0));
+    let counter = observed.clone();
+    let verifying_client = Client::new(public_key);
+    let trusting_client = Client::new(public_key)
+        .with_proof_verification(ProofVerification::SkipForTrustedIssuer)
+        .with_finalize_observer(FinalizeObserver::new(move |timings| {
+            assert!(!timings.proof_verified);
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+
+    let challenge = TokenChallenge::new(
+        TokenType::PrivateToken,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+
+    let (token_request, token_state) = trusting_client.issue_token_request(&challenge).unwrap();
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+
+    // Both modes derive the same authenticator from a valid response
+    let verified_token = verifying_client
+        .issue_token(&token_response, &token_state)
+        .unwrap();
+    let token = trusting_client
+        .issue_token(&token_response, &token_state)
+        .unwrap();
+    assert_eq!(token.authenticator(), verified_token.authenticator());
+    assert_eq!(observed.load(Ordering::SeqCst), 1);
+
+    assert!(server
+        .redeem_token(&key_store, &nonce_store, token)
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn private_tokens_directory_check() {
+    let key_store = MemoryKeyStore::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let other_key = server.create_keypair(&key_store).await.unwrap();
+
+    let challenge = TokenChallenge::new(
+        TokenType::PrivateToken,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+
+    let directory = TokenKeyDirectory::new(
+        "https://example.com/request",
+        vec![TokenKey::new(
+            TokenType::PrivateToken,
+            &serialize_public_key(public_key),
+            None,
+        )],
+    );
+
+    let client = Client::new(public_key).with_directory(directory.clone());
+    assert!(client.issue_token_request(&challenge).is_ok());
+
+    // A key that is not in the directory is refused
+    let client = Client::new(other_key).with_directory(directory);
+    assert_eq!(
+        client.issue_token_request(&challenge).unwrap_err(),
+        IssueTokenRequestError::KeyNotInDirectory
+    );
+}
+
+#[tokio::test]
+async fn private_tokens_uniform_redemption_errors() {
+    let key_store = MemoryKeyStore::default();
+    let empty_key_store = MemoryKeyStore::default();
+    let nonce_store = MemoryNonceStore::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::new(public_key);
+
+    let challenge = TokenChallenge::new(
+        TokenType::PrivateToken,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_state) = client.issue_token_request(&challenge).unwrap();
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+    let token = client.issue_token(&token_response, &token_state).unwrap();
+
+    // Detailed errors reveal the unknown key ID
+    assert_eq!(
+        server
+            .redeem_token(&empty_key_store, &nonce_store, token.clone())
+            .await,
+        Err(RedeemTokenError::KeyIdNotFound)
+    );
+
+    // Uniform errors do not
+    let server = Server::new().with_redemption_errors(RedemptionErrors::Uniform);
+    assert_eq!(
+        server
+            .redeem_token(&empty_key_store, &nonce_store, token.clone())
+            .await,
+        Err(RedeemTokenError::InvalidToken)
+    );
+
+    // Valid tokens still redeem exactly once
+    assert!(server
+        .redeem_token(&key_store, &nonce_store, token.clone())
+        .await
+        .is_ok());
+    assert_eq!(
+        server.redeem_token(&key_store, &nonce_store, token).await,
+        Err(RedeemTokenError::DoubleSpending)
+    );
+}
+
+#[tokio::test]
+async fn private_tokens_issuance_profiling() {
+    let key_store = MemoryKeyStore::default();
+
+    let observed = Arc::new(AtomicUsize::new(0));
+    let counter = observed.clone();
+    let server = Server::new().with_issuance_observer(IssuanceObserver::new(move |timings| {
+        assert!(timings.evaluate > std::time::Duration::ZERO);
+        counter.fetch_add(1, Ordering::SeqCst);
+    }));
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::new(public_key);
+
+    let challenge = TokenChallenge::new(
+        TokenType::PrivateToken,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+
+    let (token_request, token_state) = client.issue_token_request(&challenge).unwrap();
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+    client.issue_token(&token_response, &token_state).unwrap();
+    assert_eq!(observed.load(Ordering::SeqCst), 1);
+}
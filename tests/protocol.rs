@@ -0,0 +1,97 @@
+mod batched_memory_stores;
+
+use batched_memory_stores::*;
+
+use privacypass::{
+    auth::authenticate::TokenChallenge,
+    batched_tokens_ristretto255::{client::Client, server::Server, TokenRequest},
+    protocol::{ChallengeReceived, ProtocolError},
+    Deserialize, Serialize, TokenType,
+};
+
+#[tokio::test]
+async fn protocol_state_machine() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let nonce_store = MemoryNonceStore::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::new(public_key);
+
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+
+    // The embedder only moves bytes between the states and the issuer
+    let state = ChallengeReceived::from_bytes(&challenge.serialize().unwrap()).unwrap();
+    assert_eq!(state.challenge(), &challenge);
+    let (state, token_request) = state.send_request(&client, 3).unwrap();
+
+    let token_request = TokenRequest::tls_deserialize(&mut token_request.as_slice()).unwrap();
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap()
+        .tls_serialize_detached()
+        .unwrap();
+
+    let tokens = state
+        .receive_response(&token_response)
+        .unwrap()
+        .into_tokens();
+    assert_eq!(tokens.len(), 3);
+    for token in tokens {
+        server
+            .redeem_token(&key_store, &nonce_store, token)
+            .await
+            .unwrap();
+    }
+
+    // Invalid inputs are rejected at the transition that consumes them
+    assert_eq!(
+        ChallengeReceived::from_bytes(&[0x00]).unwrap_err(),
+        ProtocolError::InvalidTokenChallenge
+    );
+    assert_eq!(
+        ChallengeReceived::new(challenge.clone())
+            .send_request(&client, 0)
+            .unwrap_err(),
+        ProtocolError::InvalidTokenCount
+    );
+    let (state, _) = ChallengeReceived::new(challenge.clone())
+        .send_request(&client, 1)
+        .unwrap();
+    assert_eq!(
+        state.receive_response(&[0x00]).unwrap_err(),
+        ProtocolError::InvalidTokenResponse
+    );
+
+    // A response to another request of the same size does not finalize
+    let (first_state, first_request) = ChallengeReceived::new(challenge.clone())
+        .send_request(&client, 1)
+        .unwrap();
+    let (second_state, _) = ChallengeReceived::new(challenge)
+        .send_request(&client, 1)
+        .unwrap();
+    let first_request = TokenRequest::tls_deserialize(&mut first_request.as_slice()).unwrap();
+    let first_response = server
+        .issue_token_response(&key_store, first_request)
+        .await
+        .unwrap()
+        .tls_serialize_detached()
+        .unwrap();
+    assert_eq!(
+        second_state.receive_response(&first_response).unwrap_err(),
+        ProtocolError::InvalidTokenResponse
+    );
+    assert_eq!(
+        first_state
+            .receive_response(&first_response)
+            .unwrap()
+            .into_tokens()
+            .len(),
+        1
+    );
+}
@@ -1,9 +1,14 @@
+// Each test crate uses only some of the stores.
+#![allow(dead_code)]
+
 use std::collections::{HashMap, HashSet};
 use tokio::sync::Mutex;
 
 use async_trait::async_trait;
 use blind_rsa_signatures::{KeyPair, PublicKey};
-use privacypass::{public_tokens::server::*, Nonce, NonceStore, TruncatedTokenKeyId};
+use privacypass::{
+    public_tokens::server::*, KeyStoreError, Nonce, NonceStore, TruncatedTokenKeyId,
+};
 
 #[derive(Default)]
 pub struct MemoryNonceStore {
@@ -21,6 +26,11 @@ impl NonceStore for MemoryNonceStore {
         let mut nonces = self.nonces.lock().await;
         nonces.insert(nonce);
     }
+
+    async fn try_insert(&self, nonce: Nonce) -> bool {
+        let mut nonces = self.nonces.lock().await;
+        nonces.insert(nonce)
+    }
 }
 
 #[derive(Default)]
@@ -30,13 +40,37 @@ pub struct IssuerMemoryKeyStore {
 
 #[async_trait]
 impl IssuerKeyStore for IssuerMemoryKeyStore {
-    async fn insert(&self, truncated_token_key_id: TruncatedTokenKeyId, key_pair: KeyPair) {
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        key_pair: KeyPair,
+    ) -> Result<(), KeyStoreError> {
         let mut keys = self.keys.lock().await;
         keys.insert(truncated_token_key_id, key_pair);
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<KeyPair>, KeyStoreError> {
+        Ok(self.keys.lock().await.get(truncated_token_key_id).cloned())
     }
 
-    async fn get(&self, truncated_token_key_id: &TruncatedTokenKeyId) -> Option<KeyPair> {
-        self.keys.lock().await.get(truncated_token_key_id).cloned()
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(self
+            .keys
+            .lock()
+            .await
+            .remove(truncated_token_key_id)
+            .is_some())
+    }
+
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        Ok(self.keys.lock().await.keys().copied().collect())
     }
 }
 
@@ -47,12 +81,36 @@ pub struct OriginMemoryKeyStore {
 
 #[async_trait]
 impl OriginKeyStore for OriginMemoryKeyStore {
-    async fn insert(&self, truncated_token_key_id: TruncatedTokenKeyId, public_key: PublicKey) {
+    async fn insert(
+        &self,
+        truncated_token_key_id: TruncatedTokenKeyId,
+        public_key: PublicKey,
+    ) -> Result<(), KeyStoreError> {
         let mut keys = self.keys.lock().await;
         keys.insert(truncated_token_key_id, public_key);
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<Option<PublicKey>, KeyStoreError> {
+        Ok(self.keys.lock().await.get(truncated_token_key_id).cloned())
+    }
+
+    async fn remove(
+        &self,
+        truncated_token_key_id: &TruncatedTokenKeyId,
+    ) -> Result<bool, KeyStoreError> {
+        Ok(self
+            .keys
+            .lock()
+            .await
+            .remove(truncated_token_key_id)
+            .is_some())
     }
 
-    async fn get(&self, truncated_token_key_id: &TruncatedTokenKeyId) -> Option<PublicKey> {
-        self.keys.lock().await.get(truncated_token_key_id).cloned()
+    async fn list_key_ids(&self) -> Result<Vec<TruncatedTokenKeyId>, KeyStoreError> {
+        Ok(self.keys.lock().await.keys().copied().collect())
     }
 }
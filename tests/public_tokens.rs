@@ -35,7 +35,8 @@ async fn public_tokens_cycle() {
             public_key_to_truncated_token_key_id(&public_key),
             public_key.clone(),
         )
-        .await;
+        .await
+        .unwrap();
 
     // Client: Create client
     let mut client = Client::new(public_key);
@@ -0,0 +1,108 @@
+mod batched_memory_stores;
+
+use batched_memory_stores::*;
+
+use base64::Engine as _;
+
+use privacypass::{
+    auth::authenticate::TokenChallenge,
+    batched_tokens_ristretto255::{
+        client::*, server::*, BatchedToken, TokenRequest, TokenResponse,
+    },
+    Serialize, TokenType,
+};
+
+#[tokio::test]
+async fn serde_wire_round_trip() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::new(public_key);
+
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let json = serde_json::to_string(&challenge).unwrap();
+    assert_eq!(
+        serde_json::from_str::<TokenChallenge>(&json).unwrap(),
+        challenge
+    );
+
+    // Client: Queue the token request as JSON
+    let (token_request, token_states) = client.issue_token_request(&challenge, 2).unwrap();
+    let json = serde_json::to_string(&token_request).unwrap();
+    let token_request: TokenRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(token_request.nr(), 2);
+
+    // Server: Queue the token response as JSON
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+    let json = serde_json::to_value(&token_response).unwrap();
+    assert!(json.is_string());
+    let token_response: TokenResponse = serde_json::from_value(json).unwrap();
+
+    let tokens = client.issue_tokens(&token_response, &token_states).unwrap();
+    let json = serde_json::to_string(&tokens).unwrap();
+    let deserialized: Vec<BatchedToken> = serde_json::from_str(&json).unwrap();
+    for (token, deserialized) in tokens.iter().zip(deserialized) {
+        assert_eq!(
+            token.tls_serialize_detached().unwrap(),
+            deserialized.tls_serialize_detached().unwrap()
+        );
+        assert!(server
+            .redeem_token(&key_store, &MemoryNonceStore::default(), deserialized)
+            .await
+            .is_ok());
+    }
+
+    // Invalid base64 and trailing bytes are rejected
+    assert!(serde_json::from_str::<TokenChallenge>("\"%%\"").is_err());
+    let mut bytes = challenge.serialize().unwrap();
+    bytes.push(0);
+    let json =
+        serde_json::to_string(&base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes))
+            .unwrap();
+    assert!(serde_json::from_str::<TokenChallenge>(&json).is_err());
+}
+
+#[tokio::test]
+async fn serde_wire_issuance_state() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+
+    // Client: Persist the issuance state while the request is outstanding
+    let (token_request, issuance_state) = Client::new(public_key)
+        .token_request_builder(&challenge)
+        .count(2)
+        .build()
+        .unwrap();
+    let json = serde_json::to_string(&issuance_state).unwrap();
+    drop(issuance_state);
+
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+
+    // Client: Restore the state after a restart
+    let issuance_state: IssuanceState = serde_json::from_str(&json).unwrap();
+    let tokens = Client::new(public_key)
+        .issue_tokens_with_state(&token_response, issuance_state)
+        .unwrap();
+    assert_eq!(tokens.len(), 2);
+    for token in tokens {
+        assert_eq!(token.challenge_digest(), &challenge.digest().unwrap());
+    }
+}
@@ -0,0 +1,62 @@
+use std::{fs, path::PathBuf, sync::Arc, time::Duration};
+
+use privacypass::{clock::TestClock, sled_nonce_store::SledNonceStore, NonceStore};
+
+fn store_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("privacypass-{name}-{}.sled", std::process::id()));
+    let _ = fs::remove_dir_all(&path);
+    path
+}
+
+#[tokio::test]
+async fn sled_nonce_store_survives_restart() {
+    let path = store_path("restart");
+
+    let nonce_store = SledNonceStore::open(&path).unwrap();
+    assert!(!nonce_store.exists(&[0; 32]).await);
+    assert!(nonce_store.try_insert([0; 32]).await);
+    assert!(!nonce_store.try_insert([0; 32]).await);
+    nonce_store.insert([1; 32]).await;
+
+    // The nonces survive reopening the database
+    drop(nonce_store);
+    let nonce_store = SledNonceStore::open(&path).unwrap();
+    assert!(nonce_store.exists(&[0; 32]).await);
+    assert!(nonce_store.exists(&[1; 32]).await);
+    assert!(!nonce_store.try_insert([1; 32]).await);
+    assert_eq!(nonce_store.len(), 2);
+
+    drop(nonce_store);
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[tokio::test]
+async fn sled_nonce_store_ttl() {
+    let path = store_path("ttl");
+    let clock = Arc::new(TestClock::from_unix_time(1_000));
+    let db = sled::open(&path).unwrap();
+    let nonce_store =
+        SledNonceStore::new_with_clock(db.open_tree("nonces").unwrap(), clock.clone())
+            .with_ttl(Duration::from_secs(60));
+
+    assert!(nonce_store.try_insert([1; 32]).await);
+    nonce_store
+        .insert_with_ttl([2; 32], Duration::from_secs(600))
+        .await;
+
+    // The first nonce expires with the TTL of the store, the second one
+    // with its own
+    clock.advance(Duration::from_secs(61));
+    assert!(!nonce_store.exists(&[1; 32]).await);
+    assert!(nonce_store.exists(&[2; 32]).await);
+    assert_eq!(nonce_store.prune().await.unwrap(), 1);
+    assert_eq!(nonce_store.len(), 1);
+
+    // An expired nonce can be inserted again
+    clock.advance(Duration::from_secs(600));
+    assert!(nonce_store.try_insert([2; 32]).await);
+    assert_eq!(nonce_store.prune().await.unwrap(), 0);
+
+    drop((nonce_store, db));
+    fs::remove_dir_all(&path).unwrap();
+}
@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use blind_rsa_signatures::KeyPair;
+use privacypass::{
+    auth::authenticate::TokenChallenge,
+    batched_tokens_ristretto255::{client::*, server::*},
+    public_tokens::server::IssuerKeyStore,
+    sqlx_stores::{SqlxKeyStore, SqlxNonceStore},
+    KeyValidity, NonceStore, TokenType,
+};
+use rand::rngs::OsRng;
+use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+use voprf::{Ristretto255, VoprfServer};
+
+async fn sqlite_pool() -> SqlitePool {
+    // Every connection to an in-memory database opens its own database
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn sqlx_stores_batched_tokens_ristretto255_cycle() {
+    let pool = sqlite_pool().await;
+    let key_store = SqlxKeyStore::<VoprfServer<Ristretto255>>::sqlite(pool.clone());
+    let nonce_store = SqlxNonceStore::sqlite(pool);
+    key_store.migrate().await.unwrap();
+    // Migrating again is a no-op
+    nonce_store.migrate().await.unwrap();
+
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let key_ids = BatchedKeyStore::list_key_ids(&key_store).await.unwrap();
+    assert_eq!(key_ids.len(), 1);
+
+    let client = Client::new(public_key);
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) = client.issue_token_request(&challenge, 3).unwrap();
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+    let tokens = client.issue_tokens(&token_response, &token_states).unwrap();
+
+    for token in &tokens {
+        assert!(server
+            .redeem_token(&key_store, &nonce_store, token.clone())
+            .await
+            .is_ok());
+        assert!(nonce_store.exists(&token.nonce()).await);
+        assert_eq!(
+            server
+                .redeem_token(&key_store, &nonce_store, token.clone())
+                .await,
+            Err(RedeemTokenError::DoubleSpending)
+        );
+    }
+
+    // Key sets separate the keys of stores that share a table
+    let other_key_store = key_store.clone().with_key_set("other");
+    assert!(BatchedKeyStore::list_key_ids(&other_key_store)
+        .await
+        .unwrap()
+        .is_empty());
+
+    assert!(BatchedKeyStore::remove(&key_store, &key_ids[0])
+        .await
+        .unwrap());
+    assert!(!BatchedKeyStore::remove(&key_store, &key_ids[0])
+        .await
+        .unwrap());
+    assert!(BatchedKeyStore::get(&key_store, &key_ids[0])
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn sqlx_stores_rsa_key_pair() {
+    let key_store = SqlxKeyStore::<KeyPair>::sqlite(sqlite_pool().await);
+    key_store.migrate().await.unwrap();
+
+    let key_pair = KeyPair::generate(&mut OsRng, 2048).unwrap();
+    key_store.insert(7, key_pair.clone()).await.unwrap();
+    let stored = key_store.get(&7).await.unwrap().unwrap();
+    assert_eq!(stored.sk.to_der().unwrap(), key_pair.sk.to_der().unwrap());
+    assert_eq!(stored.pk, key_pair.pk);
+}
+
+#[tokio::test]
+async fn sqlx_stores_key_validity() {
+    let key_store = SqlxKeyStore::<KeyPair>::sqlite(sqlite_pool().await);
+    key_store.migrate().await.unwrap();
+    let validity = KeyValidity::new(Some(1_100), None).with_redeemable_until(1_300);
+
+    // Validity periods can be recorded before their key
+    assert_eq!(
+        key_store.validity(&7).await.unwrap(),
+        KeyValidity::default()
+    );
+    key_store.set_validity(7, validity).await.unwrap();
+    assert_eq!(key_store.validity(&7).await.unwrap(), validity);
+    let key_pair = KeyPair::generate(&mut OsRng, 2048).unwrap();
+    key_store.insert(7, key_pair).await.unwrap();
+    assert_eq!(key_store.validity(&7).await.unwrap(), validity);
+
+    // Key sets separate the validity periods, and removing a key forgets its
+    // validity period
+    let other_key_store = key_store.clone().with_key_set("other");
+    assert_eq!(
+        other_key_store.validity(&7).await.unwrap(),
+        KeyValidity::default()
+    );
+    assert!(key_store.remove(&7).await.unwrap());
+    assert_eq!(
+        key_store.validity(&7).await.unwrap(),
+        KeyValidity::default()
+    );
+}
+
+#[tokio::test]
+async fn sqlx_stores_nonce_ttl() {
+    let nonce_store = SqlxNonceStore::sqlite(sqlite_pool().await).with_ttl(Duration::ZERO);
+    nonce_store.migrate().await.unwrap();
+
+    // Nonces with the TTL of the store expire immediately
+    assert!(nonce_store.try_insert([1; 32]).await);
+    assert!(!nonce_store.exists(&[1; 32]).await);
+    assert!(nonce_store.try_insert([1; 32]).await);
+
+    nonce_store
+        .insert_with_ttl([2; 32], Duration::from_secs(600))
+        .await;
+    assert!(nonce_store.exists(&[2; 32]).await);
+    assert!(!nonce_store.try_insert([2; 32]).await);
+
+    assert_eq!(nonce_store.prune().await.unwrap(), 1);
+    assert!(nonce_store.exists(&[2; 32]).await);
+}
@@ -0,0 +1,72 @@
+mod batched_memory_stores;
+mod private_memory_stores;
+
+use privacypass::test_vectors::{
+    batched_tokens_p384, batched_tokens_ristretto255, private_tokens, TestVectorError,
+};
+
+#[tokio::test]
+async fn test_vectors_pass() {
+    for vector in private_tokens::vectors().unwrap() {
+        private_tokens::run_test_vector(
+            &vector,
+            &private_memory_stores::MemoryKeyStore::default(),
+            &private_memory_stores::MemoryNonceStore::default(),
+        )
+        .await
+        .unwrap();
+    }
+    for vector in batched_tokens_p384::vectors().unwrap() {
+        batched_tokens_p384::run_test_vector(
+            &vector,
+            &batched_memory_stores::MemoryKeyStoreP384::default(),
+            &batched_memory_stores::MemoryNonceStore::default(),
+        )
+        .await
+        .unwrap();
+    }
+    for vector in batched_tokens_ristretto255::vectors().unwrap() {
+        batched_tokens_ristretto255::run_test_vector(
+            &vector,
+            &batched_memory_stores::MemoryKeyStoreRistretto255::default(),
+            &batched_memory_stores::MemoryNonceStore::default(),
+        )
+        .await
+        .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_vectors_detect_mismatches() {
+    let mut vector = batched_tokens_ristretto255::vectors().unwrap().remove(0);
+    vector.tokens[0].0[40] ^= 1;
+    assert_eq!(
+        batched_tokens_ristretto255::run_test_vector(
+            &vector,
+            &batched_memory_stores::MemoryKeyStoreRistretto255::default(),
+            &batched_memory_stores::MemoryNonceStore::default(),
+        )
+        .await,
+        Err(TestVectorError::Mismatch("Token"))
+    );
+
+    // A nonce store that already saw the nonces rejects the tokens
+    let vector = private_tokens::vectors().unwrap().remove(0);
+    let nonce_store = private_memory_stores::MemoryNonceStore::default();
+    private_tokens::run_test_vector(
+        &vector,
+        &private_memory_stores::MemoryKeyStore::default(),
+        &nonce_store,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        private_tokens::run_test_vector(
+            &vector,
+            &private_memory_stores::MemoryKeyStore::default(),
+            &nonce_store,
+        )
+        .await,
+        Err(TestVectorError::StepFailed("Redeeming the token"))
+    );
+}
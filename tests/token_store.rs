@@ -0,0 +1,65 @@
+mod batched_memory_stores;
+
+use batched_memory_stores::*;
+
+use privacypass::{
+    auth::authenticate::TokenChallenge,
+    batched_tokens_ristretto255::{client::*, server::*},
+    token_store::{MemoryTokenStore, TokenStore},
+    TokenType,
+};
+use typenum::U64;
+
+#[tokio::test]
+async fn token_store_challenge_matching() {
+    let key_store = MemoryKeyStoreRistretto255::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let client = Client::new(public_key);
+
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "issuer.example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) = client.issue_token_request(&challenge, 3).unwrap();
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+    let tokens = client.issue_tokens(&token_response, &token_states).unwrap();
+
+    let token_store = MemoryTokenStore::<U64>::new();
+    token_store.insert(&challenge, tokens).await;
+    assert_eq!(token_store.count(&challenge).await, 3);
+
+    // Challenges for other origins or with a redemption context do not match
+    let other_origin = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "issuer.example.com",
+        None,
+        &["other.example.com".to_string()],
+    );
+    let with_context = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "issuer.example.com",
+        Some([1; 32]),
+        &["example.com".to_string()],
+    );
+    assert!(token_store.take(&other_origin).await.is_none());
+    assert!(token_store.take(&with_context).await.is_none());
+    assert_eq!(token_store.count(&with_context).await, 0);
+
+    // The tokens are handed out once each
+    for _ in 0..3 {
+        let token = token_store.take(&challenge).await.unwrap();
+        assert_eq!(token.challenge_digest(), &challenge.digest().unwrap());
+        assert!(server
+            .redeem_token(&key_store, &MemoryNonceStore::default(), token)
+            .await
+            .is_ok());
+    }
+    assert!(token_store.take(&challenge).await.is_none());
+    assert!(token_store.is_empty());
+}
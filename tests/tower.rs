@@ -0,0 +1,104 @@
+mod batched_memory_stores;
+
+use batched_memory_stores::*;
+
+use std::{convert::Infallible, sync::Arc};
+
+use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+use http::{
+    header::{AUTHORIZATION, WWW_AUTHENTICATE},
+    Request, Response, StatusCode,
+};
+use privacypass::{
+    auth::authenticate::{parse_www_authenticate_header, TokenChallenge},
+    batched_tokens_ristretto255,
+    dispatch::{MultiTypeServer, TokenTypeHandler},
+    dynamic::{dyn_client, BatchedRistretto255Server, DynServer},
+    integrations::{tower::TokenAuthLayer, ChallengeTemplate},
+};
+use tower::{service_fn, Layer, ServiceExt};
+
+#[tokio::test]
+async fn tower_token_auth_layer() {
+    let issuer = BatchedRistretto255Server::new(
+        batched_tokens_ristretto255::server::Server::new(),
+        MemoryKeyStoreRistretto255::default(),
+        MemoryNonceStore::default(),
+    );
+    let token_type = issuer.token_type();
+    let public_key = issuer.create_keypair().await.unwrap();
+    let issuer = Arc::new(issuer);
+    let mut server = MultiTypeServer::new();
+    server.register(token_type as u16, issuer.clone()).unwrap();
+
+    let layer = TokenAuthLayer::new(
+        Arc::new(server),
+        vec![
+            ChallengeTemplate::new(token_type, "issuer.example.com", &public_key)
+                .with_origin_info(vec!["origin.example.com".to_string()])
+                .with_max_age(60),
+        ],
+    );
+    let service = layer.layer(service_fn(|_: Request<String>| async {
+        Ok::<_, Infallible>(Response::new("protected".to_string()))
+    }));
+
+    // Requests without a token are challenged
+    let response = service
+        .clone()
+        .oneshot(Request::new(String::new()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let challenges = response.headers().get_all(WWW_AUTHENTICATE);
+    assert_eq!(challenges.iter().count(), 1);
+    let challenge = parse_www_authenticate_header(challenges.iter().next().unwrap()).unwrap();
+    assert_eq!(challenge[0].token_key(), public_key.as_slice());
+
+    // Every challenge has a fresh redemption context
+    let other = service
+        .clone()
+        .oneshot(Request::new(String::new()))
+        .await
+        .unwrap();
+    assert_ne!(
+        response.headers()[WWW_AUTHENTICATE],
+        other.headers()[WWW_AUTHENTICATE]
+    );
+
+    // Requests with a valid token pass, a spent token is rejected
+    let client = dyn_client(token_type, &public_key).unwrap();
+    let challenge = TokenChallenge::new(
+        token_type,
+        "issuer.example.com",
+        None,
+        &["origin.example.com".to_string()],
+    );
+    let (token_request, token_state) = client.issue_token_request(&challenge, 1).unwrap();
+    let token_response = issuer.issue_token_response(&token_request).await.unwrap();
+    let tokens = client.issue_tokens(&token_response, token_state).unwrap();
+    let authorized = || {
+        Request::builder()
+            .header(
+                AUTHORIZATION,
+                format!("PrivateToken token={}", URL_SAFE.encode(&tokens[0])),
+            )
+            .body(String::new())
+            .unwrap()
+    };
+
+    let response = service.clone().oneshot(authorized()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.into_body(), "protected");
+
+    let response = service.clone().oneshot(authorized()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Malformed tokens are rejected
+    let request = Request::builder()
+        .header(AUTHORIZATION, "PrivateToken token=AAAA")
+        .body(String::new())
+        .unwrap();
+    let response = service.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
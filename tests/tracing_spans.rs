@@ -0,0 +1,199 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use privacypass::{
+    auth::authenticate::TokenChallenge,
+    batched_tokens_ristretto255::{client::*, public_key_to_truncated_token_key_id, server::*},
+    memory_stores::{MemoryKeyStore, MemoryNonceStore},
+    TokenType,
+};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Metadata, Subscriber,
+};
+use tracing_core::span::Current;
+use voprf::{Ristretto255, VoprfServer};
+
+type Fields = HashMap<String, String>;
+
+struct FieldVisitor<'a>(&'a mut Fields);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}"));
+    }
+}
+
+#[derive(Default)]
+struct Recorded {
+    spans: HashMap<u64, (&'static Metadata<'static>, Fields)>,
+    events: Vec<Fields>,
+    // Entered spans, so that fields can be recorded on the current span
+    entered: Vec<(u64, &'static Metadata<'static>)>,
+}
+
+#[derive(Clone, Default)]
+struct RecordingSubscriber {
+    next_id: Arc<AtomicU64>,
+    recorded: Arc<Mutex<Recorded>>,
+}
+
+impl Subscriber for RecordingSubscriber {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attributes: &span::Attributes<'_>) -> span::Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut fields = Fields::new();
+        attributes.record(&mut FieldVisitor(&mut fields));
+        self.recorded
+            .lock()
+            .unwrap()
+            .spans
+            .insert(id, (attributes.metadata(), fields));
+        span::Id::from_u64(id)
+    }
+
+    fn record(&self, span: &span::Id, values: &span::Record<'_>) {
+        if let Some((_, fields)) = self
+            .recorded
+            .lock()
+            .unwrap()
+            .spans
+            .get_mut(&span.into_u64())
+        {
+            values.record(&mut FieldVisitor(fields));
+        }
+    }
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::new();
+        event.record(&mut FieldVisitor(&mut fields));
+        self.recorded.lock().unwrap().events.push(fields);
+    }
+
+    fn enter(&self, span: &span::Id) {
+        let mut recorded = self.recorded.lock().unwrap();
+        if let Some((metadata, _)) = recorded.spans.get(&span.into_u64()) {
+            let metadata = *metadata;
+            recorded.entered.push((span.into_u64(), metadata));
+        }
+    }
+
+    fn exit(&self, span: &span::Id) {
+        let mut recorded = self.recorded.lock().unwrap();
+        if let Some(index) = recorded
+            .entered
+            .iter()
+            .rposition(|(id, _)| *id == span.into_u64())
+        {
+            recorded.entered.remove(index);
+        }
+    }
+
+    fn current_span(&self) -> Current {
+        match self.recorded.lock().unwrap().entered.last() {
+            Some((id, metadata)) => Current::new(span::Id::from_u64(*id), metadata),
+            None => Current::none(),
+        }
+    }
+}
+
+impl RecordingSubscriber {
+    /// Returns the fields of all spans with the name.
+    fn spans(&self, name: &str) -> Vec<Fields> {
+        self.recorded
+            .lock()
+            .unwrap()
+            .spans
+            .values()
+            .filter(|(metadata, _)| metadata.name() == name)
+            .map(|(_, fields)| fields.clone())
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn tracing_batched_tokens_ristretto255() {
+    let subscriber = RecordingSubscriber::default();
+    let _guard = tracing::subscriber::set_default(subscriber.clone());
+
+    let server = Server::new();
+    let key_store = MemoryKeyStore::<VoprfServer<Ristretto255>>::new();
+    let nonce_store = MemoryNonceStore::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let key_id = public_key_to_truncated_token_key_id(&public_key).to_string();
+
+    let client = Client::new(public_key);
+    let challenge = TokenChallenge::new(
+        TokenType::BatchedTokenRistretto255,
+        "example.com",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_states) = client.issue_token_request(&challenge, 3).unwrap();
+    let token_response = server
+        .issue_token_response(&key_store, token_request)
+        .await
+        .unwrap();
+    let tokens = client.issue_tokens(&token_response, &token_states).unwrap();
+    for _ in 0..2 {
+        let _ = server
+            .redeem_token(&key_store, &nonce_store, tokens[0].clone())
+            .await;
+    }
+
+    let created = subscriber.spans("create_keypair_internal");
+    assert_eq!(created.len(), 1);
+    assert_eq!(created[0]["truncated_token_key_id"], key_id);
+
+    for name in [
+        "issue_token_request_internal",
+        "issue_token_response",
+        "issue_tokens",
+    ] {
+        let spans = subscriber.spans(name);
+        assert_eq!(spans.len(), 1, "{name}");
+        assert_eq!(spans[0]["truncated_token_key_id"], key_id, "{name}");
+        assert_eq!(spans[0]["batch_size"], "3", "{name}");
+    }
+
+    let redeemed = subscriber.spans("redeem_token_with_policy");
+    assert_eq!(redeemed.len(), 2);
+    assert!(redeemed
+        .iter()
+        .all(|fields| fields["truncated_token_key_id"] == key_id));
+    let double_spending = RedeemTokenError::DoubleSpending.to_string();
+    let recorded = subscriber.recorded.lock().unwrap();
+    assert!(recorded
+        .events
+        .iter()
+        .any(|fields| fields.get("error") == Some(&double_spending)));
+
+    // Nonces are never recorded
+    let nonces = tokens
+        .iter()
+        .map(|token| format!("{:?}", token.nonce()))
+        .collect::<Vec<_>>();
+    for fields in recorded
+        .spans
+        .values()
+        .map(|(_, fields)| fields)
+        .chain(&recorded.events)
+    {
+        for (name, value) in fields {
+            assert!(!name.contains("nonce"));
+            assert!(nonces.iter().all(|nonce| !value.contains(nonce.as_str())));
+        }
+    }
+}
@@ -0,0 +1,495 @@
+mod private_memory_stores;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use private_memory_stores::*;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use typenum::U48;
+
+use privacypass::{
+    auth::authenticate::{
+        build_www_authenticate_header, parse_www_authenticate_header, TokenChallenge,
+    },
+    clock::TestClock,
+    dispatch::{DispatchError, MultiTypeServer, TokenTypeHandler},
+    dynamic::dyn_client,
+    issuer_directory::{DirectoryError, TokenKey, TokenKeyDirectory},
+    private_tokens::{client::*, server::*, TokenRequest, TokenResponse},
+    token_store::{MemoryTokenStore, TokenStore},
+    transport::{
+        directory_uri, fetch_directory, send_token_request, DirectoryClient, DirectoryClientError,
+        FetchedDocument, IssuanceTransport, IssuerSource, MockTransport, MultiIssuerClient,
+        RecordingTransport, ReplayTransport, ReqwestTransport, SourcingError, TransportError,
+        TOKEN_RESPONSE_MEDIA_TYPE,
+    },
+    Deserialize, Serialize, TokenType,
+};
+
+const ISSUER_ORIGIN: &str = "https://issuer.example.net";
+const ISSUER_REQUEST_URI: &str = "https://issuer.example.net/request";
+
+/// Issues private tokens in-process.
+struct PrivateTokensHandler {
+    key_store: MemoryKeyStore,
+    server: Server,
+}
+
+#[async_trait]
+impl TokenTypeHandler for PrivateTokensHandler {
+    async fn issue_token_response(&self, token_request: &[u8]) -> Result<Vec<u8>, DispatchError> {
+        let token_request = TokenRequest::tls_deserialize(&mut &token_request[..])
+            .map_err(|_| DispatchError::InvalidTokenRequest)?;
+        let token_response = self
+            .server
+            .issue_token_response(&self.key_store, token_request)
+            .await
+            .map_err(|_| DispatchError::InvalidTokenRequest)?;
+        token_response
+            .tls_serialize_detached()
+            .map_err(|_| DispatchError::InvalidTokenRequest)
+    }
+
+    async fn redeem_token(&self, _token: &[u8]) -> Result<(), DispatchError> {
+        Err(DispatchError::InvalidToken)
+    }
+}
+
+/// Creates an in-process private token issuer and returns it with its
+/// serialized public key.
+async fn private_tokens_issuer() -> (Arc<MultiTypeServer>, Vec<u8>) {
+    let key_store = MemoryKeyStore::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let mut issuer = MultiTypeServer::new();
+    issuer
+        .register(
+            TokenType::PrivateToken as u16,
+            Arc::new(PrivateTokensHandler { key_store, server }),
+        )
+        .unwrap();
+    (Arc::new(issuer), serialize_public_key(public_key))
+}
+
+#[tokio::test]
+async fn transport_issuance() {
+    let key_store = MemoryKeyStore::default();
+    let server = Server::new();
+    let public_key = server.create_keypair(&key_store).await.unwrap();
+    let directory = TokenKeyDirectory::new(
+        ISSUER_REQUEST_URI,
+        vec![TokenKey::new(
+            TokenType::PrivateToken,
+            &serialize_public_key(public_key),
+            None,
+        )],
+    );
+    let mut issuer = MultiTypeServer::new();
+    issuer
+        .register(
+            TokenType::PrivateToken as u16,
+            Arc::new(PrivateTokensHandler { key_store, server }),
+        )
+        .unwrap();
+    let transport = MockTransport::new()
+        .with_directory(ISSUER_ORIGIN, &directory)
+        .with_server(ISSUER_REQUEST_URI, Arc::new(issuer));
+
+    // Trailing slashes of the origin are ignored
+    let directory = fetch_directory(&transport, "https://issuer.example.net/")
+        .await
+        .unwrap();
+    let client = Client::new(public_key).with_directory(directory.clone());
+
+    let challenge = TokenChallenge::new(
+        TokenType::PrivateToken,
+        "issuer.example.net",
+        None,
+        &["example.com".to_string()],
+    );
+    let (token_request, token_state) = client.issue_token_request(&challenge).unwrap();
+    let token_response: TokenResponse =
+        send_token_request(&transport, directory.issuer_request_uri(), &token_request)
+            .await
+            .unwrap();
+    assert!(client.issue_token(&token_response, &token_state).is_ok());
+
+    assert_eq!(
+        fetch_directory(&transport, "https://other.example.net")
+            .await
+            .unwrap_err(),
+        TransportError::Status(404)
+    );
+    assert_eq!(
+        transport
+            .send_token_request(ISSUER_REQUEST_URI, &[0x7A, 0x7A])
+            .await,
+        Err(TransportError::Status(415))
+    );
+}
+
+#[tokio::test]
+async fn transport_canned_responses() {
+    let transport = MockTransport::new()
+        .with_document("https://example.com/document", vec![1, 2, 3])
+        .with_token_response(ISSUER_REQUEST_URI, vec![4, 5, 6]);
+
+    assert_eq!(
+        transport.fetch("https://example.com/document").await,
+        Ok(vec![1, 2, 3])
+    );
+    assert_eq!(
+        transport.send_token_request(ISSUER_REQUEST_URI, &[]).await,
+        Ok(vec![4, 5, 6])
+    );
+    assert_eq!(
+        transport
+            .send_token_request("https://example.com/request", &[])
+            .await,
+        Err(TransportError::Status(404))
+    );
+
+    // A canned response that is not a token response is rejected
+    let result: Result<TokenResponse, _> =
+        send_token_request(&transport, ISSUER_REQUEST_URI, &0u8).await;
+    assert_eq!(result.unwrap_err(), TransportError::InvalidResponse);
+}
+
+#[tokio::test]
+async fn transport_record_replay() {
+    let transport = RecordingTransport::new(
+        MockTransport::new()
+            .with_document("https://example.com/document", vec![1, 2, 3])
+            .with_token_response(ISSUER_REQUEST_URI, vec![4, 5, 6]),
+    );
+    transport
+        .fetch("https://example.com/document")
+        .await
+        .unwrap();
+    transport
+        .send_token_request(ISSUER_REQUEST_URI, &[7])
+        .await
+        .unwrap();
+    // Failures are not recorded
+    assert!(transport.fetch("https://example.com/other").await.is_err());
+
+    let path = std::env::temp_dir().join("privacypass-transport-record-replay.json");
+    transport.save(&path).unwrap();
+    let replay = ReplayTransport::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(replay.remaining(), 2);
+
+    assert_eq!(
+        replay.send_token_request(ISSUER_REQUEST_URI, &[8]).await,
+        Ok(vec![4, 5, 6])
+    );
+    assert_eq!(
+        replay.fetch("https://example.com/document").await,
+        Ok(vec![1, 2, 3])
+    );
+
+    // Every exchange is replayed once
+    assert_eq!(replay.remaining(), 0);
+    assert_eq!(
+        replay.fetch("https://example.com/document").await,
+        Err(TransportError::Unreachable)
+    );
+    assert!(ReplayTransport::from_fixture("not json").is_err());
+}
+
+/// Mock issuer whose directory can be replaced and which counts fetches.
+#[derive(Clone, Default)]
+struct CountingTransport {
+    inner: Arc<Mutex<MockTransport>>,
+    fetches: Arc<AtomicUsize>,
+}
+
+impl CountingTransport {
+    fn serve(&self, directory: &TokenKeyDirectory, cache_control: Option<&str>) {
+        let mut transport = MockTransport::new().with_directory(ISSUER_ORIGIN, directory);
+        if let Some(cache_control) = cache_control {
+            transport = transport.with_cache_control(&directory_uri(ISSUER_ORIGIN), cache_control);
+        }
+        *self.inner.lock().unwrap() = transport;
+    }
+
+    fn fetches(&self) -> usize {
+        self.fetches.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl IssuanceTransport for CountingTransport {
+    async fn send_token_request(
+        &self,
+        issuer_request_uri: &str,
+        token_request: &[u8],
+    ) -> Result<Vec<u8>, TransportError> {
+        let inner = self.inner.lock().unwrap().clone();
+        inner
+            .send_token_request(issuer_request_uri, token_request)
+            .await
+    }
+
+    async fn fetch(&self, uri: &str) -> Result<Vec<u8>, TransportError> {
+        Ok(self.fetch_document(uri).await?.into_body())
+    }
+
+    async fn fetch_document(&self, uri: &str) -> Result<FetchedDocument, TransportError> {
+        self.fetches.fetch_add(1, Ordering::SeqCst);
+        let inner = self.inner.lock().unwrap().clone();
+        inner.fetch_document(uri).await
+    }
+}
+
+#[tokio::test]
+async fn transport_directory_client() {
+    let key_a = TokenKey::new(TokenType::PrivateToken, b"key a", None);
+    let key_b = TokenKey::new(TokenType::PrivateToken, b"key b", None);
+    let key_a_id = key_a.token_key_id().unwrap();
+    let key_b_id = key_b.token_key_id().unwrap();
+    let transport = CountingTransport::default();
+    transport.serve(
+        &TokenKeyDirectory::new(ISSUER_REQUEST_URI, vec![key_a.clone()]),
+        Some("public, max-age=60"),
+    );
+    let clock = Arc::new(TestClock::from_unix_time(1_700_000_000));
+    let client = DirectoryClient::new_with_clock(transport.clone(), ISSUER_ORIGIN, clock.clone());
+
+    // The directory is cached for the max-age of the response
+    assert_eq!(
+        client.check_key(TokenType::PrivateToken, &key_a_id).await,
+        Ok(key_a.clone())
+    );
+    assert!(client
+        .check_key(TokenType::PrivateToken, &key_a_id)
+        .await
+        .is_ok());
+    assert_eq!(transport.fetches(), 1);
+
+    // A key that the cached directory does not list triggers a re-fetch
+    // once the directory is older than the minimum refresh interval
+    transport.serve(
+        &TokenKeyDirectory::new(ISSUER_REQUEST_URI, vec![key_a.clone(), key_b.clone()]),
+        Some("max-age=60"),
+    );
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(
+        client.check_key(TokenType::PrivateToken, &key_b_id).await,
+        Ok(key_b.clone())
+    );
+    assert_eq!(transport.fetches(), 2);
+    // Repeated unknown keys are rejected from the cache until then
+    for _ in 0..3 {
+        assert_eq!(
+            client.check_key(TokenType::PrivateToken, &[0; 32]).await,
+            Err(DirectoryClientError::Directory(DirectoryError::KeyNotFound))
+        );
+    }
+    clock.advance(Duration::from_secs(9));
+    assert_eq!(
+        client.check_key(TokenType::PrivateToken, &[1; 32]).await,
+        Err(DirectoryClientError::Directory(DirectoryError::KeyNotFound))
+    );
+    assert_eq!(transport.fetches(), 2);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(
+        client.check_key(TokenType::PrivateToken, &[0; 32]).await,
+        Err(DirectoryClientError::Directory(DirectoryError::KeyNotFound))
+    );
+    assert_eq!(transport.fetches(), 3);
+    // Keys are only found for their own token type
+    assert_eq!(
+        client.check_key(TokenType::PublicToken, &key_a_id).await,
+        Err(DirectoryClientError::Directory(DirectoryError::KeyNotFound))
+    );
+    assert_eq!(transport.fetches(), 3);
+
+    // The token key of a challenge is checked against the directory
+    let challenge = TokenChallenge::new(
+        TokenType::PrivateToken,
+        "issuer.example.net",
+        None,
+        &["example.com".to_string()],
+    );
+    let (_, header) = build_www_authenticate_header(&challenge, b"key b", None).unwrap();
+    let challenges = parse_www_authenticate_header(&header).unwrap();
+    assert_eq!(client.check_challenge(&challenges[0]).await, Ok(key_b));
+    assert_eq!(transport.fetches(), 3);
+
+    // Expired directories are fetched again
+    clock.advance(Duration::from_secs(60));
+    client.directory().await.unwrap();
+    client.directory().await.unwrap();
+    assert_eq!(transport.fetches(), 4);
+    client.invalidate();
+    client.directory().await.unwrap();
+    assert_eq!(transport.fetches(), 5);
+
+    // Directories served with no-store are never reused
+    transport.serve(
+        &TokenKeyDirectory::new(ISSUER_REQUEST_URI, vec![key_a.clone()]),
+        Some("max-age=60, no-store"),
+    );
+    clock.advance(Duration::from_secs(60));
+    client.directory().await.unwrap();
+    client.directory().await.unwrap();
+    assert_eq!(transport.fetches(), 7);
+
+    // Directories served without a max-age use the default max-age
+    transport.serve(
+        &TokenKeyDirectory::new(ISSUER_REQUEST_URI, vec![key_a]),
+        None,
+    );
+    let client = DirectoryClient::new_with_clock(transport.clone(), ISSUER_ORIGIN, clock.clone())
+        .with_default_max_age(Duration::from_secs(30));
+    client.directory().await.unwrap();
+    clock.advance(Duration::from_secs(29));
+    client.directory().await.unwrap();
+    assert_eq!(transport.fetches(), 8);
+
+    // Transport failures are reported
+    let client = DirectoryClient::new(transport.clone(), "https://other.example.net");
+    assert_eq!(
+        client.check_key(TokenType::PrivateToken, &key_a_id).await,
+        Err(DirectoryClientError::Transport(TransportError::Status(404)))
+    );
+}
+
+/// Answers a single HTTP request on `listener` with `content_type` and
+/// `body`.
+async fn serve_once(listener: &TcpListener, content_type: &str, body: &[u8]) {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut request = Vec::new();
+    let mut buf = [0u8; 4096];
+    // Read the head and as much of the body as its length announces
+    loop {
+        let n = stream.read(&mut buf).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+        let text = String::from_utf8_lossy(&request).to_lowercase();
+        if let Some(end) = text.find("\r\n\r\n") {
+            let length = text
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .map_or(0, |length| length.trim().parse::<usize>().unwrap());
+            if request.len() >= end + 4 + length {
+                break;
+            }
+        }
+    }
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await.unwrap();
+    stream.write_all(body).await.unwrap();
+}
+
+#[tokio::test]
+async fn transport_reqwest() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let issuer_origin = format!("http://{}", listener.local_addr().unwrap());
+    let issuer_request_uri = format!("{issuer_origin}/request");
+    let transport = ReqwestTransport::new();
+
+    let directory = TokenKeyDirectory::new(&issuer_request_uri, vec![]);
+    let body = serde_json::to_vec(&directory).unwrap();
+    let (fetched, ()) = tokio::join!(
+        transport.fetch_issuer_directory(&issuer_origin),
+        serve_once(
+            &listener,
+            "application/private-token-issuer-directory",
+            &body
+        ),
+    );
+    assert_eq!(fetched.unwrap(), directory);
+
+    let (token_response, ()) = tokio::join!(
+        transport.send_token_request(&issuer_request_uri, &[1, 2, 3]),
+        serve_once(&listener, TOKEN_RESPONSE_MEDIA_TYPE, &[4, 5, 6]),
+    );
+    assert_eq!(token_response, Ok(vec![4, 5, 6]));
+
+    // Token responses must have the token response media type
+    let (token_response, ()) = tokio::join!(
+        transport.send_token_request(&issuer_request_uri, &[1, 2, 3]),
+        serve_once(&listener, "text/plain", &[4, 5, 6]),
+    );
+    assert_eq!(token_response, Err(TransportError::InvalidResponse));
+}
+
+#[tokio::test]
+async fn transport_multi_issuer_sourcing() {
+    let (issuer_a, public_key_a) = private_tokens_issuer().await;
+    let (issuer_b, public_key_b) = private_tokens_issuer().await;
+    let (_, public_key_c) = private_tokens_issuer().await;
+    let transport = MockTransport::new()
+        .with_server("https://a.example.net/request", issuer_a)
+        .with_server("https://b.example.net/request", issuer_b);
+    let source = |name: &str, public_key: &[u8]| {
+        IssuerSource::new(
+            name,
+            &format!("https://{name}/request"),
+            dyn_client(TokenType::PrivateToken, public_key).unwrap(),
+        )
+    };
+    // The third issuer is unavailable
+    let client = MultiIssuerClient::new(transport)
+        .with_issuer(source("a.example.net", &public_key_a))
+        .with_issuer(source("b.example.net", &public_key_b))
+        .with_issuer(source("c.example.net", &public_key_c));
+    let challenge = |issuer_name: &str| {
+        TokenChallenge::new(
+            TokenType::PrivateToken,
+            issuer_name,
+            None,
+            &["example.com".to_string()],
+        )
+    };
+    let challenges = [
+        challenge("a.example.net"),
+        challenge("b.example.net"),
+        challenge("c.example.net"),
+    ];
+    let store = MemoryTokenStore::<U48>::new();
+
+    // The share of the unavailable issuer is fetched from the others
+    let report = client.fetch_tokens(&challenges, 5, &store).await.unwrap();
+    assert_eq!(report.issued(), 5);
+    assert_eq!(report.issued_by("a.example.net"), 3);
+    assert_eq!(report.issued_by("b.example.net"), 2);
+    assert_eq!(report.issued_by("c.example.net"), 0);
+    assert_eq!(
+        report.failures(),
+        [(
+            "c.example.net".to_string(),
+            SourcingError::Transport(TransportError::Status(404))
+        )]
+    );
+
+    // The tokens of each issuer are kept for its own challenge
+    assert_eq!(store.count(&challenges[0]).await, 3);
+    assert_eq!(store.count(&challenges[1]).await, 2);
+    assert_eq!(store.count(&challenges[2]).await, 0);
+    assert_eq!(store.len(), 5);
+
+    // Challenges must name one of the issuers
+    assert_eq!(
+        client
+            .fetch_tokens(&[challenge("d.example.net")], 1, &store)
+            .await,
+        Err(SourcingError::NoMatchingIssuer)
+    );
+}
@@ -0,0 +1,114 @@
+use http::HeaderValue;
+use p384::NistP384;
+use privacypass::{
+    auth::{
+        authenticate::{parse_www_authenticate_header, TokenChallenge},
+        authorize::parse_authorization_header,
+    },
+    batched_tokens_p384, batched_tokens_ristretto255,
+    dynamic::{
+        dyn_client, BatchedP384Server, BatchedRistretto255Server, DynServer, PrivateTokensServer,
+    },
+    memory_stores::{MemoryKeyStore, MemoryNonceStore},
+    private_tokens, TokenType,
+};
+use typenum::U64;
+use voprf::{Ristretto255, VoprfServer};
+
+/// Truncations and single-byte corruptions of a valid message.
+fn mutations(bytes: &[u8]) -> Vec<Vec<u8>> {
+    let mut mutations = (0..bytes.len())
+        .map(|len| bytes[..len].to_vec())
+        .collect::<Vec<_>>();
+    for index in 0..bytes.len() {
+        for mask in [0x01, 0xFF] {
+            let mut mutation = bytes.to_vec();
+            mutation[index] ^= mask;
+            mutations.push(mutation);
+        }
+    }
+    mutations.push([bytes, &[0]].concat());
+    mutations
+}
+
+// Every public entry point that takes bytes from the network must return an
+// error for malformed input instead of panicking.
+#[tokio::test]
+async fn untrusted_input_does_not_panic() {
+    let servers: Vec<Box<dyn DynServer>> = vec![
+        Box::new(PrivateTokensServer::new(
+            private_tokens::server::Server::new(),
+            MemoryKeyStore::<VoprfServer<NistP384>>::new(),
+            MemoryNonceStore::new(),
+        )),
+        Box::new(BatchedP384Server::new(
+            batched_tokens_p384::server::Server::new(),
+            MemoryKeyStore::<VoprfServer<NistP384>>::new(),
+            MemoryNonceStore::new(),
+        )),
+        Box::new(BatchedRistretto255Server::new(
+            batched_tokens_ristretto255::server::Server::new(),
+            MemoryKeyStore::<VoprfServer<Ristretto255>>::new(),
+            MemoryNonceStore::new(),
+        )),
+    ];
+
+    for server in &servers {
+        let token_type = server.token_type();
+        let public_key = server.create_keypair().await.unwrap();
+        let client = dyn_client(token_type, &public_key).unwrap();
+        let challenge = TokenChallenge::new(
+            token_type,
+            "example.com",
+            None,
+            &["example.com".to_string()],
+        );
+
+        // Privately verifiable tokens are issued one at a time
+        let count = if token_type == TokenType::PrivateToken {
+            1
+        } else {
+            2
+        };
+        let (token_request, token_state) = client.issue_token_request(&challenge, count).unwrap();
+        for token_request in mutations(&token_request) {
+            let _ = server.issue_token_response(&token_request).await;
+        }
+
+        let token_response = server.issue_token_response(&token_request).await.unwrap();
+        for token_response in mutations(&token_response) {
+            let (_, token_state) = client.issue_token_request(&challenge, count).unwrap();
+            let _ = client.issue_tokens(&token_response, token_state);
+        }
+
+        let tokens = client.issue_tokens(&token_response, token_state).unwrap();
+        for token in mutations(&tokens[0]) {
+            let _ = server.redeem_token(&token).await;
+        }
+    }
+}
+
+#[test]
+fn untrusted_headers_do_not_panic() {
+    let inputs = [
+        "",
+        ",",
+        "PrivateToken",
+        "PrivateToken ",
+        "PrivateToken challenge",
+        "PrivateToken challenge=",
+        "PrivateToken challenge=\"",
+        "PrivateToken challenge=====, token-key=",
+        "PrivateToken token=AAAA",
+        "PrivateToken token=\u{7f}",
+        "PrivateToken challenge=AAAA, token-key=AAAA, max-age=99999999999999999999",
+        "PrivateToken token=, PrivateToken token=, PrivateToken",
+    ];
+    for input in inputs {
+        let Ok(value) = HeaderValue::from_str(input) else {
+            continue;
+        };
+        let _ = parse_www_authenticate_header(&value);
+        let _ = parse_authorization_header::<U64>(&value);
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use privacypass::{clock::TestClock, transport::TransportError, webhooks::*, SecretVec, TokenType};
+
+const SECRET: &[u8] = b"webhook secret";
+
+/// Records deliveries and fails the first `failures` attempts.
+#[derive(Default)]
+struct TestTransport {
+    failures: Mutex<usize>,
+    attempts: Mutex<usize>,
+    deliveries: Mutex<Vec<(String, String, Vec<u8>)>>,
+}
+
+#[async_trait]
+impl WebhookTransport for TestTransport {
+    async fn post_event(
+        &self,
+        url: &str,
+        signature: &str,
+        body: &[u8],
+    ) -> Result<(), TransportError> {
+        *self.attempts.lock().unwrap() += 1;
+        let mut failures = self.failures.lock().unwrap();
+        if *failures > 0 {
+            *failures -= 1;
+            return Err(TransportError::Status(503));
+        }
+        self.deliveries.lock().unwrap().push((
+            url.to_string(),
+            signature.to_string(),
+            body.to_vec(),
+        ));
+        Ok(())
+    }
+}
+
+fn test_dispatcher(failures: usize) -> (Arc<TestTransport>, WebhookDispatcher<Arc<TestTransport>>) {
+    let transport = Arc::new(TestTransport {
+        failures: Mutex::new(failures),
+        ..TestTransport::default()
+    });
+    let dispatcher = WebhookDispatcher::new(transport.clone(), SecretVec::new(SECRET.to_vec()))
+        .with_url("https://billing.example/redemptions")
+        .with_max_attempts(3)
+        .with_initial_backoff(Duration::ZERO);
+    (transport, dispatcher)
+}
+
+#[tokio::test]
+async fn webhooks_signed_event() {
+    let (transport, dispatcher) = test_dispatcher(0);
+    let event = RedemptionEvent::new(
+        TokenType::BatchedTokenRistretto255,
+        7,
+        RedemptionOutcome::Redeemed,
+    );
+    dispatcher.dispatch(&event).await.unwrap();
+
+    let deliveries = transport.deliveries.lock().unwrap();
+    let (url, signature, body) = &deliveries[0];
+    assert_eq!(url, "https://billing.example/redemptions");
+    assert!(verify_signature(SECRET, body, signature));
+    assert!(!verify_signature(b"other secret", body, signature));
+    assert_eq!(
+        serde_json::from_slice::<RedemptionEvent>(body).unwrap(),
+        event
+    );
+
+    // Only the key ID, token type, outcome and time are sent
+    let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+    let mut keys: Vec<_> = body.as_object().unwrap().keys().collect();
+    keys.sort();
+    assert_eq!(
+        keys,
+        [
+            "outcome",
+            "timestamp",
+            "token-type",
+            "truncated-token-key-id"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn webhooks_retry() {
+    // Two failed attempts are retried
+    let (transport, dispatcher) = test_dispatcher(2);
+    let event = RedemptionEvent::new(TokenType::PrivateToken, 3, RedemptionOutcome::Redeemed);
+    dispatcher.dispatch(&event).await.unwrap();
+    assert_eq!(*transport.attempts.lock().unwrap(), 3);
+    assert_eq!(transport.deliveries.lock().unwrap().len(), 1);
+
+    // Three failed attempts give up
+    let (transport, dispatcher) = test_dispatcher(3);
+    assert_eq!(
+        dispatcher.dispatch(&event).await,
+        Err(WebhookError::Delivery {
+            url: "https://billing.example/redemptions".to_string(),
+            error: TransportError::Status(503),
+        })
+    );
+    assert_eq!(*transport.attempts.lock().unwrap(), 3);
+    assert!(transport.deliveries.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn webhooks_event_freshness() {
+    let (transport, dispatcher) = test_dispatcher(0);
+    let clock = TestClock::from_unix_time(1_700_000_000);
+    let event = RedemptionEvent::new_with_clock(
+        TokenType::PrivateToken,
+        3,
+        RedemptionOutcome::Redeemed,
+        &clock,
+    );
+    dispatcher.dispatch(&event).await.unwrap();
+    let (_, signature, body) = transport.deliveries.lock().unwrap().remove(0);
+    let max_age = Duration::from_secs(300);
+
+    assert_eq!(
+        verify_event_with_clock(SECRET, &body, &signature, max_age, &clock),
+        Some(event)
+    );
+    assert_eq!(
+        verify_event_with_clock(b"other secret", &body, &signature, max_age, &clock),
+        None
+    );
+
+    // A replayed body is rejected once it is older than the window
+    clock.advance(Duration::from_secs(301));
+    assert!(verify_signature(SECRET, &body, &signature));
+    assert_eq!(
+        verify_event_with_clock(SECRET, &body, &signature, max_age, &clock),
+        None
+    );
+}